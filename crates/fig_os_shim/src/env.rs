@@ -98,6 +98,23 @@ impl Env {
         }
     }
 
+    /// Returns every environment variable whose name and value are valid unicode.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        use inner::Inner;
+        match &self.0 {
+            Inner::Real => env::vars_os()
+                .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+                .collect(),
+            Inner::Fake(fake) => fake
+                .lock()
+                .unwrap()
+                .vars
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
     /// Sets the environment variable `key` to the value `value` for the currently running
     /// process.
     ///
@@ -199,6 +216,12 @@ mod tests {
         assert_eq!(env.get("PATH").unwrap(), "/bin:/usr/bin");
         assert!(env.get_os("PATH").is_some());
         assert!(env.get("NON_EXISTENT").is_err());
+        let mut vars = env.vars();
+        vars.sort();
+        assert_eq!(vars, vec![
+            ("HOME".to_string(), "/home/user".to_string()),
+            ("PATH".to_string(), "/bin:/usr/bin".to_string())
+        ]);
     }
 
     #[test]
@@ -143,6 +143,23 @@ impl Fs {
         }
     }
 
+    pub fn read_sync(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        use inner::Inner;
+        match &self.0 {
+            Inner::Real => std::fs::read(path),
+            Inner::Chroot(root) => std::fs::read(append(root.path(), path)),
+            Inner::Fake(map) => {
+                let Ok(lock) = map.lock() else {
+                    return Err(io::Error::new(io::ErrorKind::Other, "poisoned lock"));
+                };
+                let Some(data) = lock.get(path.as_ref()) else {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "not found"));
+                };
+                Ok(data.clone())
+            },
+        }
+    }
+
     pub fn read_to_string_sync(&self, path: impl AsRef<Path>) -> io::Result<String> {
         use inner::Inner;
         match &self.0 {
@@ -340,6 +357,26 @@ impl Fs {
         }
     }
 
+    /// Queries the file system metadata for a path, following symbolic links.
+    ///
+    /// This is a proxy to [`tokio::fs::metadata`]
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user lacks permissions to perform `metadata` call on `path`.
+    /// * `path` does not exist.
+    pub async fn metadata(&self, path: impl AsRef<Path>) -> io::Result<std::fs::Metadata> {
+        use inner::Inner;
+        match &self.0 {
+            Inner::Real => fs::metadata(path).await,
+            Inner::Chroot(root) => fs::metadata(append(root.path(), path)).await,
+            Inner::Fake(_) => panic!("unimplemented"),
+        }
+    }
+
     /// Reads a symbolic link, returning the file that the link points to.
     ///
     /// This is a proxy to [`tokio::fs::read_link`].
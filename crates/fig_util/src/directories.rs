@@ -336,6 +336,11 @@ pub fn chat_profiles_dir<Ctx: FsProvider + EnvProvider>(ctx: &Ctx) -> Result<Pat
     Ok(home_dir_ctx(ctx)?.join(".aws").join("amazonq").join("profiles"))
 }
 
+/// The directory containing backups of the files changed by `q chat`, for the `/undo-file` command.
+pub fn chat_backups_dir<Ctx: FsProvider + EnvProvider>(ctx: &Ctx) -> Result<PathBuf> {
+    Ok(home_dir_ctx(ctx)?.join(".aws").join("amazonq").join("backups"))
+}
+
 /// The desktop app socket path
 ///
 /// - MacOS: `$TMPDIR/cwrun/desktop.sock`
@@ -22,4 +22,10 @@ pub struct Chat {
     /// '--trust-tools=fs_read,fs_write', trust no tools: '--trust-tools='
     #[arg(long, value_delimiter = ',', value_name = "TOOL_NAMES")]
     pub trust_tools: Option<Vec<String>>,
+    /// Don't write to the tool audit log, even if it is enabled in settings.
+    #[arg(long)]
+    pub no_audit: bool,
+    /// Print without colors, as when the NO_COLOR environment variable is set.
+    #[arg(long)]
+    pub no_color: bool,
 }
@@ -44,6 +44,21 @@ pub enum Command {
         subcommand: Option<ToolsSubcommand>,
     },
     Usage,
+    Expand,
+    Cache {
+        clear: bool,
+    },
+    /// Reverts the latest file change, or the latest change to `path`.
+    Undo {
+        path: Option<String>,
+    },
+    UndoFile {
+        path: String,
+    },
+    /// Turns plan mode on or off, or toggles it if `enabled` is `None`.
+    Plan {
+        enabled: Option<bool>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -705,6 +720,31 @@ impl Command {
                     }
                 },
                 "usage" => Self::Usage,
+                "expand" => match parts.get(1) {
+                    None | Some(&"last") => Self::Expand,
+                    Some(_) => return Err("Usage: /expand [last]".to_string()),
+                },
+                "cache" => match parts.get(1) {
+                    None => Self::Cache { clear: false },
+                    Some(&"clear") => Self::Cache { clear: true },
+                    Some(_) => return Err("Usage: /cache [clear]".to_string()),
+                },
+                "undo" => Self::Undo {
+                    path: parts
+                        .get(1..)
+                        .filter(|path| !path.is_empty())
+                        .map(|path| path.join(" ")),
+                },
+                "undo-file" => match parts.get(1..) {
+                    Some(path) if !path.is_empty() => Self::UndoFile { path: path.join(" ") },
+                    _ => return Err("Usage: /undo-file <path>".to_string()),
+                },
+                "plan" => match parts.get(1) {
+                    None => Self::Plan { enabled: None },
+                    Some(&"on") => Self::Plan { enabled: Some(true) },
+                    Some(&"off") => Self::Plan { enabled: Some(false) },
+                    Some(_) => return Err("Usage: /plan [on|off]".to_string()),
+                },
                 unknown_command => {
                     // If the command starts with a slash but isn't recognized,
                     // return an error instead of treating it as a prompt
@@ -928,6 +968,20 @@ mod tests {
                     subcommand: Some(HooksSubcommand::Help)
                 }),
             ),
+            ("/expand", Command::Expand),
+            ("/expand last", Command::Expand),
+            ("/cache", Command::Cache { clear: false }),
+            ("/cache clear", Command::Cache { clear: true }),
+            ("/undo", Command::Undo { path: None }),
+            ("/undo src/main.rs", Command::Undo {
+                path: Some("src/main.rs".to_string()),
+            }),
+            ("/undo-file src/main.rs", Command::UndoFile {
+                path: "src/main.rs".to_string(),
+            }),
+            ("/plan", Command::Plan { enabled: None }),
+            ("/plan on", Command::Plan { enabled: Some(true) }),
+            ("/plan off", Command::Plan { enabled: Some(false) }),
         ];
 
         for (input, parsed) in tests {
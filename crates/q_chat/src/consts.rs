@@ -16,4 +16,8 @@ pub const MAX_USER_MESSAGE_SIZE: usize = 600_000;
 /// In tokens
 pub const CONTEXT_WINDOW_SIZE: usize = 200_000;
 
+/// Fraction of the context window that tool specs and context files can take up at the start of a
+/// session before the user is warned.
+pub const INITIAL_CONTEXT_WARN_THRESHOLD: f64 = 0.6;
+
 pub const MAX_CHARS: usize = TokenCounter::token_to_chars(CONTEXT_WINDOW_SIZE); // Character-based warning threshold
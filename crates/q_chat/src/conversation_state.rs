@@ -46,6 +46,10 @@ use super::message::{
     UserMessageContent,
     build_env_state,
 };
+use super::result_framing::{
+    ResultFraming,
+    cancelled_tool_use_result,
+};
 use super::shared_writer::SharedWriter;
 use super::token_counter::{
     CharCount,
@@ -80,6 +84,9 @@ pub struct ConversationState {
     context_message_length: Option<usize>,
     /// Stores the latest conversation summary created by /compact
     latest_summary: Option<String>,
+    /// Changes the user made with commands, such as files reverted with /undo, to tell the model
+    /// about with the next prompt.
+    pending_notes: Vec<String>,
     updates: Option<SharedWriter>,
 }
 
@@ -129,6 +136,7 @@ impl ConversationState {
             context_manager,
             context_message_length: None,
             latest_summary: None,
+            pending_notes: Vec::new(),
             updates,
         }
     }
@@ -167,10 +175,28 @@ impl ConversationState {
             input
         };
 
-        let msg = UserMessage::new_prompt(input);
+        let msg = UserMessage::new_prompt(self.with_notes(input));
         self.next_message = Some(msg);
     }
 
+    /// Tells the model about `note` along with the next prompt. Notes are part of the prompt, so
+    /// that they stay in the history.
+    pub fn add_note(&mut self, note: String) {
+        self.pending_notes.push(note);
+    }
+
+    fn with_notes(&mut self, prompt: String) -> String {
+        if self.pending_notes.is_empty() {
+            return prompt;
+        }
+        let notes = self
+            .pending_notes
+            .drain(..)
+            .map(|note| format!("[{note}]\n"))
+            .collect::<String>();
+        format!("{notes}\n{prompt}")
+    }
+
     /// Sets the response message according to the currently set [Self::next_message].
     pub fn push_assistant_message(&mut self, message: AssistantMessage) {
         debug_assert!(self.next_message.is_some(), "next_message should exist");
@@ -262,10 +288,19 @@ impl ConversationState {
     }
 
     /// Sets the next user message with "cancelled" tool results.
-    pub fn abandon_tool_use(&mut self, tools_to_be_abandoned: Vec<QueuedTool>, deny_input: String) {
-        self.next_message = Some(UserMessage::new_cancelled_tool_uses(
-            Some(deny_input),
-            tools_to_be_abandoned.iter().map(|t| t.id.as_str()),
+    pub fn abandon_tool_use(
+        &mut self,
+        tools_to_be_abandoned: Vec<QueuedTool>,
+        deny_input: String,
+        framing: ResultFraming,
+    ) {
+        let tool_use_results = tools_to_be_abandoned
+            .iter()
+            .map(|t| cancelled_tool_use_result(framing, t))
+            .collect();
+        self.next_message = Some(UserMessage::new_cancelled_tool_use_results(
+            Some(self.with_notes(deny_input)),
+            tool_use_results,
         ));
     }
 
@@ -663,6 +698,7 @@ impl
             context_messages: context_chars.into(),
             user_messages: user_chars.into(),
             assistant_messages: assistant_chars.into(),
+            tool_specs: self.tools.char_count(),
         }
     }
 }
@@ -673,6 +709,8 @@ pub struct ConversationSize {
     pub context_messages: CharCount,
     pub user_messages: CharCount,
     pub assistant_messages: CharCount,
+    /// The tool specifications sent with every request.
+    pub tool_specs: CharCount,
 }
 
 /// Converts a list of user/assistant message pairs into a flattened list of ChatMessage.
@@ -894,6 +932,23 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_conversation_state_notes() {
+        let mut conversation_state =
+            ConversationState::new(Context::new_fake(), load_tools().unwrap(), None, None).await;
+        conversation_state.add_note("The user ran /undo".to_string());
+        conversation_state.set_next_user_message("hello".to_string()).await;
+        assert_eq!(
+            conversation_state.next_user_message().unwrap().prompt(),
+            Some("[The user ran /undo]\n\nhello")
+        );
+
+        // Notes are only sent once.
+        conversation_state.push_assistant_message(AssistantMessage::new_response(None, "hi".to_string()));
+        conversation_state.set_next_user_message("again".to_string()).await;
+        assert_eq!(conversation_state.next_user_message().unwrap().prompt(), Some("again"));
+    }
+
     #[tokio::test]
     async fn test_conversation_state_with_context_files() {
         let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
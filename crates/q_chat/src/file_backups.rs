@@ -0,0 +1,483 @@
+use std::path::{
+    Component,
+    Path,
+    PathBuf,
+};
+use std::time::Duration;
+
+use eyre::{
+    Result,
+    bail,
+};
+use fig_os_shim::Context;
+use fig_settings::Settings;
+use fig_util::directories::chat_backups_dir;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use tracing::warn;
+
+use super::tools::{
+    QueuedTool,
+    Tool,
+    format_path,
+    sanitize_path_tool_arg,
+};
+
+/// Setting enabling backups of the files changed by fs_write. On by default.
+pub const FILE_BACKUPS_ENABLED_SETTING: &str = "chat.fileBackups.enabled";
+/// Setting holding the total size in bytes of the backups kept for a session.
+pub const FILE_BACKUPS_MAX_BYTES_SETTING: &str = "chat.fileBackups.maxBytes";
+
+const DEFAULT_MAX_BYTES: i64 = 50 * 1024 * 1024;
+
+/// Backups left behind by sessions that didn't remove them, e.g. because they crashed, are removed
+/// at startup once they are this old.
+pub const STALE_SESSION_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Previous {
+    /// The write created the file.
+    Missing,
+    /// Copy of the file's previous contents.
+    Copy(PathBuf),
+    /// The file existed, but no copy of it could be kept.
+    NotKept,
+}
+
+#[derive(Debug)]
+struct Backup {
+    /// File that was written to.
+    target: PathBuf,
+    previous: Previous,
+    size: usize,
+    /// Hash of the file's contents before the write, or `None` if it didn't exist.
+    before_hash: Option<[u8; 32]>,
+    /// Hash of the file's contents just after the write, once it has run.
+    after_hash: Option<[u8; 32]>,
+}
+
+/// A journal of the files changed by fs_write this session, with copies taken just before each
+/// write, so that `/undo` and `/undo-file` can put them back.
+#[derive(Debug)]
+pub struct FileBackups {
+    enabled: bool,
+    max_bytes: usize,
+    /// Backups from oldest to newest.
+    backups: Vec<Backup>,
+    total_bytes: usize,
+    /// Sessions that backups were written for, whose directories are removed when the chat ends.
+    session_ids: Vec<String>,
+}
+
+impl Default for FileBackups {
+    fn default() -> Self {
+        Self::new(true, DEFAULT_MAX_BYTES as usize)
+    }
+}
+
+impl FileBackups {
+    pub fn new(enabled: bool, max_bytes: usize) -> Self {
+        Self {
+            enabled,
+            max_bytes,
+            backups: Vec::new(),
+            total_bytes: 0,
+            session_ids: Vec::new(),
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::new(
+            settings.get_bool_or(FILE_BACKUPS_ENABLED_SETTING, true),
+            settings
+                .get_int_or(FILE_BACKUPS_MAX_BYTES_SETTING, DEFAULT_MAX_BYTES)
+                .max(0) as usize,
+        )
+    }
+
+    /// Backs up the file that `tool` is about to write, if it is an fs_write. Returns a note for
+    /// the tool result saying where the backup is.
+    pub async fn save(&mut self, ctx: &Context, session_id: &str, tool: &QueuedTool) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let target = written_path(ctx, tool)?;
+        let fs = ctx.fs();
+        if !fs.exists(&target) {
+            self.push(target, Previous::Missing, None);
+            return None;
+        }
+
+        let contents = match fs.read(&target).await {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!(?err, ?target, "failed to read the file to back up");
+                self.push(target, Previous::NotKept, None);
+                return None;
+            },
+        };
+        let before_hash = Some(Sha256::digest(&contents).into());
+        if self.total_bytes + contents.len() > self.max_bytes {
+            self.push(target, Previous::NotKept, before_hash);
+            return Some(format!(
+                "No backup of the previous contents was kept, since the backups for this session have reached their limit of {} bytes.",
+                self.max_bytes
+            ));
+        }
+
+        let version = self.backups.iter().filter(|b| b.target == target).count() + 1;
+        let copy = match backup_path(ctx, session_id, &target, version) {
+            Ok(copy) => copy,
+            Err(err) => {
+                warn!(?err, "failed to find the backups directory");
+                self.push(target, Previous::NotKept, before_hash);
+                return None;
+            },
+        };
+        if !self.session_ids.iter().any(|id| id == session_id) {
+            self.session_ids.push(session_id.to_string());
+        }
+        if let Some(parent) = copy.parent() {
+            if let Err(err) = fs.create_dir_all(parent).await {
+                warn!(?err, ?parent, "failed to create the backups directory");
+                self.push(target, Previous::NotKept, before_hash);
+                return None;
+            }
+        }
+        if let Err(err) = fs.write(&copy, &contents).await {
+            warn!(?err, ?copy, "failed to write the backup");
+            self.push(target, Previous::NotKept, before_hash);
+            return None;
+        }
+
+        self.total_bytes += contents.len();
+        self.backups.push(Backup {
+            target,
+            previous: Previous::Copy(copy.clone()),
+            size: contents.len(),
+            before_hash,
+            after_hash: None,
+        });
+        Some(format!(
+            "The previous contents were backed up to {}, run /undo to restore them.",
+            copy.display()
+        ))
+    }
+
+    fn push(&mut self, target: PathBuf, previous: Previous, before_hash: Option<[u8; 32]>) {
+        self.backups.push(Backup {
+            target,
+            previous,
+            size: 0,
+            before_hash,
+            after_hash: None,
+        });
+    }
+
+    /// Records what the file that `tool` wrote to contains after it ran, so that `/undo` can tell
+    /// whether it changed since. Writes that left the file as it was are dropped from the journal.
+    pub async fn record(&mut self, ctx: &Context, tool: &QueuedTool) {
+        let Some(target) = written_path(ctx, tool) else {
+            return;
+        };
+        let Some(index) = self
+            .backups
+            .iter()
+            .rposition(|b| b.target == target && b.after_hash.is_none())
+        else {
+            return;
+        };
+        let after_hash = file_hash(ctx, &target).await;
+        if after_hash.is_some() && after_hash != self.backups[index].before_hash {
+            self.backups[index].after_hash = after_hash;
+            return;
+        }
+
+        let backup = self.backups.remove(index);
+        self.total_bytes -= backup.size;
+        if let Previous::Copy(copy) = &backup.previous {
+            if let Err(err) = ctx.fs().remove_file(copy).await {
+                warn!(?err, ?copy, "failed to remove the unneeded backup");
+            }
+        }
+    }
+
+    /// Reverts the latest write to `path`, or the latest write to any file if `path` is `None`.
+    /// Refuses if the file has changed since it was written. Returns the path of the reverted file
+    /// and a message describing what was restored.
+    pub async fn undo(&mut self, ctx: &Context, path: Option<&str>) -> Result<(String, String)> {
+        let target = path.map(|path| sanitize_path_tool_arg(ctx, path));
+        let Some(index) = self
+            .backups
+            .iter()
+            .rposition(|b| b.after_hash.is_some() && target.as_ref().is_none_or(|target| b.target == *target))
+        else {
+            match path {
+                Some(path) => bail!("No changes to {path} were made this session"),
+                None if !self.enabled => {
+                    bail!("No changes can be undone, since {FILE_BACKUPS_ENABLED_SETTING} is turned off")
+                },
+                None => bail!("No file changes were made this session"),
+            }
+        };
+
+        let backup = &self.backups[index];
+        let display_path = format_path(ctx.fs().chroot_path(ctx.env().current_dir()?), &backup.target);
+        if file_hash(ctx, &backup.target).await != backup.after_hash {
+            bail!(
+                "{display_path} was changed since it was last written to, either outside of this chat or by a later tool call, so it wasn't reverted. Run /undo-file {display_path} to restore the backup anyway"
+            );
+        }
+        let message = self.revert(ctx, index, &display_path).await?;
+        Ok((display_path, message))
+    }
+
+    /// Puts back the latest backup of `path`, even if the file changed since. A file that didn't
+    /// exist before it was written to is deleted. Returns a message describing what was restored.
+    pub async fn restore(&mut self, ctx: &Context, path: &str) -> Result<String> {
+        let target = sanitize_path_tool_arg(ctx, path);
+        let Some(index) = self.backups.iter().rposition(|b| b.target == target) else {
+            bail!("No backups of {path} were made this session");
+        };
+        self.revert(ctx, index, path).await
+    }
+
+    /// Deletes the backups of this session, which can't be restored once the chat ends.
+    pub async fn remove_session_backups(&mut self, ctx: &Context) {
+        let Ok(dir) = chat_backups_dir(ctx) else {
+            return;
+        };
+        for session_id in self.session_ids.drain(..) {
+            let session_dir = dir.join(session_id);
+            if let Err(err) = ctx.fs().remove_dir_all(&session_dir).await {
+                warn!(?err, ?session_dir, "failed to remove the session's backups");
+            }
+        }
+        self.backups.clear();
+        self.total_bytes = 0;
+    }
+
+    async fn revert(&mut self, ctx: &Context, index: usize, path: &str) -> Result<String> {
+        let fs = ctx.fs();
+        let target = &self.backups[index].target;
+        let message = match &self.backups[index].previous {
+            Previous::Copy(copy) => {
+                let contents = fs.read(copy).await?;
+                fs.write(target, contents).await?;
+                fs.remove_file(copy).await?;
+                format!("Restored {path} from {}", copy.display())
+            },
+            Previous::Missing => {
+                if fs.exists(target) {
+                    fs.remove_file(target).await?;
+                }
+                format!("Deleted {path}, since it didn't exist before it was written to")
+            },
+            Previous::NotKept => bail!("No backup of {path} was kept before it was last written to"),
+        };
+
+        let backup = self.backups.remove(index);
+        self.total_bytes -= backup.size;
+        Ok(message)
+    }
+}
+
+/// Deletes the backups of sessions that were last changed more than `max_age` ago.
+pub async fn prune_stale_sessions(ctx: &Context, max_age: Duration) {
+    let Ok(dir) = chat_backups_dir(ctx) else {
+        return;
+    };
+    let Ok(mut entries) = ctx.fs().read_dir(&dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let age = entry
+            .metadata()
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        if age.is_some_and(|age| age >= max_age) {
+            // The entry's path is already resolved, e.g. within a chroot.
+            if let Err(err) = tokio::fs::remove_dir_all(entry.path()).await {
+                warn!(?err, path = ?entry.path(), "failed to remove stale backups");
+            }
+        }
+    }
+}
+
+async fn file_hash(ctx: &Context, path: &Path) -> Option<[u8; 32]> {
+    ctx.fs()
+        .read(path)
+        .await
+        .ok()
+        .map(|contents| Sha256::digest(&contents).into())
+}
+
+/// The file that `tool` writes to, if it is an fs_write.
+fn written_path(ctx: &Context, tool: &QueuedTool) -> Option<PathBuf> {
+    match &tool.tool {
+        Tool::FsWrite(fs_write) if !fs_write.dry_run() => tool
+            .tool
+            .key_args()
+            .into_iter()
+            .find(|(key, _)| *key == "path")
+            .map(|(_, path)| sanitize_path_tool_arg(ctx, path)),
+        _ => None,
+    }
+}
+
+/// Where to keep the `version`th backup of `target`, e.g.
+/// `~/.aws/amazonq/backups/<session-id>/src/main.rs.1`. Files in the current directory are kept
+/// under their relative path, and others under their absolute path.
+fn backup_path(ctx: &Context, session_id: &str, target: &Path, version: usize) -> Result<PathBuf> {
+    let cwd = ctx.fs().chroot_path(ctx.env().current_dir()?);
+    let relative = target
+        .strip_prefix(&cwd)
+        .unwrap_or(target)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect::<PathBuf>();
+    let mut file_name = relative.into_os_string();
+    file_name.push(format!(".{version}"));
+    Ok(chat_backups_dir(ctx)?.join(session_id).join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::queued;
+
+    fn write(path: &str, file_text: &str) -> QueuedTool {
+        queued(
+            "1",
+            "fs_write",
+            serde_json::json!({ "command": "create", "path": path, "file_text": file_text }),
+        )
+    }
+
+    async fn run(ctx: &Context, backups: &mut FileBackups, tool: &QueuedTool) -> Option<String> {
+        let note = backups.save(ctx, "session", tool).await;
+        tool.tool.invoke(ctx, &mut std::io::sink()).await.unwrap();
+        backups.record(ctx, tool).await;
+        note
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().create_dir_all("/src").await.unwrap();
+        ctx.fs().write("/src/main.rs", "v1\n").await.unwrap();
+        let mut backups = FileBackups::default();
+
+        let note = run(&ctx, &mut backups, &write("/src/main.rs", "v2")).await.unwrap();
+        assert!(note.contains("/.aws/amazonq/backups/session/src/main.rs.1"), "{note}");
+        run(&ctx, &mut backups, &write("/src/main.rs", "v3")).await.unwrap();
+        assert_eq!(
+            ctx.fs()
+                .read_to_string("/home/testuser/.aws/amazonq/backups/session/src/main.rs.2")
+                .await
+                .unwrap(),
+            "v2\n"
+        );
+
+        // Each restore goes back one more write.
+        backups.restore(&ctx, "/src/main.rs").await.unwrap();
+        assert_eq!(ctx.fs().read_to_string("/src/main.rs").await.unwrap(), "v2\n");
+        backups.restore(&ctx, "/src/main.rs").await.unwrap();
+        assert_eq!(ctx.fs().read_to_string("/src/main.rs").await.unwrap(), "v1\n");
+        assert!(backups.restore(&ctx, "/src/main.rs").await.is_err());
+        assert_eq!(backups.total_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_undo() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/a.txt", "a1\n").await.unwrap();
+        ctx.fs().write("/b.txt", "b1\n").await.unwrap();
+        let mut backups = FileBackups::default();
+        assert!(backups.undo(&ctx, None).await.is_err());
+
+        run(&ctx, &mut backups, &write("/a.txt", "a2")).await;
+        run(&ctx, &mut backups, &write("/b.txt", "b2")).await;
+        // Writes that didn't change the file aren't journaled.
+        run(&ctx, &mut backups, &write("/b.txt", "b2")).await;
+        run(&ctx, &mut backups, &write("/new.txt", "new")).await;
+
+        // The latest change is undone first.
+        let (path, message) = backups.undo(&ctx, None).await.unwrap();
+        assert_eq!(path, "new.txt");
+        assert!(message.starts_with("Deleted"), "{message}");
+        assert!(!ctx.fs().exists("/new.txt"));
+
+        backups.undo(&ctx, Some("/a.txt")).await.unwrap();
+        assert_eq!(ctx.fs().read_to_string("/a.txt").await.unwrap(), "a1\n");
+        assert!(backups.undo(&ctx, Some("/a.txt")).await.is_err());
+
+        // Files changed again since they were written are left alone.
+        ctx.fs().write("/b.txt", "b3\n").await.unwrap();
+        let err = backups.undo(&ctx, None).await.unwrap_err().to_string();
+        assert!(err.contains("was changed since it was last written to"), "{err}");
+        assert_eq!(ctx.fs().read_to_string("/b.txt").await.unwrap(), "b3\n");
+        backups.restore(&ctx, "/b.txt").await.unwrap();
+        assert_eq!(ctx.fs().read_to_string("/b.txt").await.unwrap(), "b1\n");
+    }
+
+    #[tokio::test]
+    async fn test_restore_new_file() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut backups = FileBackups::default();
+
+        assert!(run(&ctx, &mut backups, &write("/new.txt", "hello")).await.is_none());
+        assert!(ctx.fs().exists("/new.txt"));
+        let message = backups.restore(&ctx, "/new.txt").await.unwrap();
+        assert!(message.starts_with("Deleted"), "{message}");
+        assert!(!ctx.fs().exists("/new.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_remove_backups() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/a.txt", "a1\n").await.unwrap();
+        let session_dir = "/home/testuser/.aws/amazonq/backups/session";
+        let other_dir = "/home/testuser/.aws/amazonq/backups/other";
+        ctx.fs().create_dir_all(other_dir).await.unwrap();
+        let mut backups = FileBackups::default();
+
+        run(&ctx, &mut backups, &write("/a.txt", "a2")).await.unwrap();
+        assert!(ctx.fs().exists(session_dir));
+        backups.remove_session_backups(&ctx).await;
+        assert!(!ctx.fs().exists(session_dir));
+        assert!(ctx.fs().exists(other_dir));
+        assert!(backups.undo(&ctx, None).await.is_err());
+
+        prune_stale_sessions(&ctx, STALE_SESSION_AGE).await;
+        assert!(ctx.fs().exists(other_dir));
+        prune_stale_sessions(&ctx, Duration::ZERO).await;
+        assert!(!ctx.fs().exists(other_dir));
+    }
+
+    #[tokio::test]
+    async fn test_backup_budget() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/a.txt", "12345").await.unwrap();
+        ctx.fs().write("/b.txt", "12345").await.unwrap();
+        let mut backups = FileBackups::new(true, 8);
+
+        assert!(
+            run(&ctx, &mut backups, &write("/a.txt", "a"))
+                .await
+                .unwrap()
+                .contains("backed up")
+        );
+        let note = run(&ctx, &mut backups, &write("/b.txt", "b")).await.unwrap();
+        assert!(note.contains("No backup"), "{note}");
+        assert!(backups.restore(&ctx, "/b.txt").await.is_err());
+
+        let mut disabled = FileBackups::new(false, 8);
+        assert!(run(&ctx, &mut disabled, &write("/a.txt", "a")).await.is_none());
+        assert!(disabled.restore(&ctx, "/a.txt").await.is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use eyre::{
+    Result,
+    bail,
+};
+use fig_os_shim::Context;
+use sha2::{
+    Digest,
+    Sha256,
+};
+
+use super::tools::fs_read::is_glob;
+use super::tools::{
+    QueuedTool,
+    Tool,
+    format_path,
+    sanitize_path_tool_arg,
+};
+
+/// Files larger than this are only hashed, so the external change can't be shown as a diff.
+const MAX_KEPT_BYTES: usize = 256 * 1024;
+/// Number of lines of the external change included in the error.
+const MAX_DIFF_LINES: usize = 50;
+
+#[derive(Debug)]
+struct Version {
+    hash: [u8; 32],
+    /// The contents the model last saw, if small enough to keep.
+    contents: Option<String>,
+}
+
+/// The contents of each file as of when the model last read or wrote it this session, so that a
+/// write built against stale contents isn't made after the file is changed outside of the chat.
+#[derive(Debug, Default)]
+pub struct FileVersions {
+    versions: HashMap<PathBuf, Version>,
+}
+
+impl FileVersions {
+    /// Records the current contents of the files that `tool` read or wrote, after it succeeded.
+    pub async fn record(&mut self, ctx: &Context, tool: &QueuedTool) {
+        let paths = match &tool.tool {
+            Tool::FsRead(fs_read) => fs_read.paths().into_iter().filter(|path| !is_glob(ctx, path)).collect(),
+            Tool::FsWrite(fs_write) if !fs_write.dry_run() => vec![fs_write.path()],
+            _ => return,
+        };
+        for path in paths {
+            let path = sanitize_path_tool_arg(ctx, path);
+            if !path.is_file() {
+                continue;
+            }
+            if let Ok(contents) = ctx.fs().read(&path).await {
+                self.versions.insert(path, Version {
+                    hash: Sha256::digest(&contents).into(),
+                    contents: (contents.len() <= MAX_KEPT_BYTES)
+                        .then(|| String::from_utf8(contents).ok())
+                        .flatten(),
+                });
+            }
+        }
+    }
+
+    /// Returns an error if `tool` writes to a file that has changed since the model last read it.
+    pub async fn check(&self, ctx: &Context, tool: &QueuedTool) -> Result<()> {
+        let Tool::FsWrite(fs_write) = &tool.tool else {
+            return Ok(());
+        };
+        if fs_write.dry_run() || fs_write.force() {
+            return Ok(());
+        }
+        let path = sanitize_path_tool_arg(ctx, fs_write.path());
+        let Some(version) = self.versions.get(&path) else {
+            return Ok(());
+        };
+        let current = match ctx.fs().read(&path).await {
+            Ok(current) => current,
+            // Deleted since it was read, in which case the write recreates it.
+            Err(_) => return Ok(()),
+        };
+        if <[u8; 32]>::from(Sha256::digest(&current)) == version.hash {
+            return Ok(());
+        }
+
+        let cwd = ctx.env().current_dir()?;
+        let display_path = format_path(&cwd, &path);
+        let diff = match (&version.contents, String::from_utf8(current)) {
+            (Some(old), Ok(new)) => external_change(&display_path, old, &new),
+            _ => String::new(),
+        };
+        bail!(
+            "{display_path} was changed outside of this chat since you last read it, so it wasn't modified. Read the file again and redo the change against its current contents. Only set `force` to true to overwrite the external change if the user asks to.{diff}"
+        )
+    }
+}
+
+/// Describes how the file changed since the model last saw it, as a unified diff.
+fn external_change(path: &str, old: &str, new: &str) -> String {
+    let unified = similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .header(&format!("{path} (last read)"), &format!("{path} (on disk)"))
+        .to_string();
+    let total_lines = unified.lines().count();
+    let mut diff = unified.lines().take(MAX_DIFF_LINES).collect::<Vec<_>>().join("\n");
+    if total_lines > MAX_DIFF_LINES {
+        diff.push_str(&format!("\n[{} more lines not shown]", total_lines - MAX_DIFF_LINES));
+    }
+    format!("\nThe external change was:\n{diff}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::queued;
+
+    fn replace(force: bool) -> QueuedTool {
+        queued(
+            "1",
+            "fs_write",
+            serde_json::json!({
+                "command": "str_replace",
+                "path": "/main.rs",
+                "old_str": "one",
+                "new_str": "two",
+                "force": force,
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_external_change() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/main.rs", "one\n").await.unwrap();
+        let mut versions = FileVersions::default();
+
+        // Files that weren't read can be written.
+        versions.check(&ctx, &replace(false)).await.unwrap();
+
+        versions
+            .record(
+                &ctx,
+                &queued(
+                    "1",
+                    "fs_read",
+                    serde_json::json!({ "mode": "Line", "path": "/main.rs" }),
+                ),
+            )
+            .await;
+        versions.check(&ctx, &replace(false)).await.unwrap();
+
+        ctx.fs().write("/main.rs", "one\nthree\n").await.unwrap();
+        let err = versions.check(&ctx, &replace(false)).await.unwrap_err().to_string();
+        assert!(err.contains("was changed outside of this chat"), "{err}");
+        assert!(err.contains("\n+three"), "{err}");
+        versions.check(&ctx, &replace(true)).await.unwrap();
+
+        // What the model wrote itself is up to date.
+        let write = replace(true);
+        write.tool.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        versions.record(&ctx, &write).await;
+        versions.check(&ctx, &replace(false)).await.unwrap();
+    }
+}
@@ -1,18 +1,24 @@
 use std::sync::Arc;
 
 use eyre::Result;
+use rustyline::EventHandler;
 use rustyline::error::ReadlineError;
-use rustyline::{
-    EventHandler,
-    KeyEvent,
-};
 
 use super::context::ContextManager;
+use super::keybindings::{
+    Action,
+    Key,
+    KeyBindings,
+};
 use super::prompt::rl;
 use super::skim_integration::SkimCommandSelector;
 
+/// Sequences a terminal wraps bracketed pastes in.
+pub const PASTE_START: &str = "\x1b[200~";
+pub const PASTE_END: &str = "\x1b[201~";
+
 #[derive(Debug)]
-pub struct InputSource(inner::Inner);
+pub struct InputSource(inner::Inner, Vec<Action>);
 
 mod inner {
     use rustyline::Editor;
@@ -27,37 +33,57 @@ mod inner {
         Mock {
             index: usize,
             lines: Vec<String>,
+            pasted: bool,
         },
     }
 }
 
 impl InputSource {
-    pub fn new() -> Result<Self> {
-        Ok(Self(inner::Inner::Readline(rl()?)))
+    pub fn new(bindings: &KeyBindings) -> Result<Self> {
+        let (rl, bound) = rl(bindings)?;
+        Ok(Self(inner::Inner::Readline(rl), bound))
     }
 
-    pub fn put_skim_command_selector(&mut self, context_manager: Arc<ContextManager>) {
+    /// Binds `key` to fuzzy search of commands and context files in `context_manager`.
+    pub fn put_skim_command_selector(&mut self, context_manager: Arc<ContextManager>, key: Key) {
         if let inner::Inner::Readline(rl) = &mut self.0 {
-            let key_char = match fig_settings::settings::get_string_opt("chat.skimCommandKey").as_deref() {
-                Some(key) if key.len() == 1 => key.chars().next().unwrap_or('k'),
-                _ => 'k', // Default to 'k' if setting is missing or invalid
-            };
             rl.bind_sequence(
-                KeyEvent::ctrl(key_char),
+                key.to_key_event(),
                 EventHandler::Conditional(Box::new(SkimCommandSelector::new(context_manager))),
             );
+            if !self.1.contains(&Action::FuzzySearch) {
+                self.1.push(Action::FuzzySearch);
+            }
         }
     }
 
+    /// The actions whose keys are bound in the line editor.
+    pub fn bound_actions(&self) -> &[Action] {
+        &self.1
+    }
+
+    /// Lines starting with [PASTE_START] are reported as bracketed pastes, with the markers
+    /// removed.
     #[allow(dead_code)]
     pub fn new_mock(lines: Vec<String>) -> Self {
-        Self(inner::Inner::Mock { index: 0, lines })
+        Self(
+            inner::Inner::Mock {
+                index: 0,
+                lines,
+                pasted: false,
+            },
+            Vec::new(),
+        )
     }
 
     pub fn read_line(&mut self, prompt: Option<&str>) -> Result<Option<String>, ReadlineError> {
         match &mut self.0 {
             inner::Inner::Readline(rl) => {
                 let prompt = prompt.unwrap_or_default();
+                // Only report pastes made while editing this line.
+                if let Some(helper) = rl.helper() {
+                    helper.take_pasted();
+                }
                 let curr_line = rl.readline(prompt);
                 match curr_line {
                     Ok(line) => {
@@ -68,13 +94,28 @@ impl InputSource {
                     Err(err) => Err(err),
                 }
             },
-            inner::Inner::Mock { index, lines } => {
+            inner::Inner::Mock { index, lines, pasted } => {
                 *index += 1;
-                Ok(lines.get(*index - 1).cloned())
+                let line = lines.get(*index - 1).cloned();
+                match line.as_deref().and_then(|line| line.strip_prefix(PASTE_START)) {
+                    Some(text) => {
+                        *pasted = true;
+                        Ok(Some(text.strip_suffix(PASTE_END).unwrap_or(text).to_string()))
+                    },
+                    None => Ok(line),
+                }
             },
         }
     }
 
+    /// Whether the last line read contained a bracketed paste from the terminal.
+    pub fn take_pasted(&mut self) -> bool {
+        match &mut self.0 {
+            inner::Inner::Readline(rl) => rl.helper().is_some_and(|h| h.take_pasted()),
+            inner::Inner::Mock { pasted, .. } => std::mem::take(pasted),
+        }
+    }
+
     // We're keeping this method for potential future use
     #[allow(dead_code)]
     pub fn set_buffer(&mut self, content: &str) {
@@ -101,4 +142,15 @@ mod tests {
         assert_eq!(input.read_line(None).unwrap().unwrap(), l3);
         assert!(input.read_line(None).unwrap().is_none());
     }
+
+    #[test]
+    fn test_mock_input_source_paste() {
+        let mut input = InputSource::new_mock(vec![format!("{PASTE_START}a\nb{PASTE_END}"), "c".to_string()]);
+
+        assert_eq!(input.read_line(None).unwrap().unwrap(), "a\nb");
+        assert!(input.take_pasted());
+        assert!(!input.take_pasted());
+        assert_eq!(input.read_line(None).unwrap().unwrap(), "c");
+        assert!(!input.take_pasted());
+    }
 }
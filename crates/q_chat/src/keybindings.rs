@@ -0,0 +1,495 @@
+//! Keyboard shortcuts for the chat prompt and the tool approval dialog.
+//!
+//! [BINDINGS] is the single table that both the key handling and the help output are built from,
+//! so the two can't drift apart. Each binding can be overridden with the
+//! `chat.keybindings.<name>` setting, e.g. `q settings chat.keybindings.fuzzySearch ctrl+x`.
+//!
+//! Cancelling works the same in every prompt: Esc, or Ctrl+C, cancels the prompt, e.g. rejecting
+//! the tool use being approved. Only the chat prompt exits, and only when pressed twice.
+
+use std::fmt;
+
+use crossterm::style::Stylize;
+use fig_settings::Settings;
+use rustyline::{
+    KeyCode,
+    KeyEvent,
+    Modifiers,
+};
+use tracing::warn;
+
+/// Where a binding is active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingContext {
+    /// The main chat prompt.
+    Prompt,
+    /// The "Allow this action?" dialog.
+    ToolApproval,
+    /// Every prompt.
+    All,
+}
+
+impl BindingContext {
+    /// Whether bindings in the two contexts are active at the same time.
+    pub fn overlaps(self, other: Self) -> bool {
+        self == other || self == Self::All || other == Self::All
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    InsertNewline,
+    InsertNewlineAlt,
+    FuzzySearch,
+    ApproveTool,
+    RejectTool,
+    TrustTool,
+    ShowFullDiff,
+    ShowShortcuts,
+    Cancel,
+}
+
+#[derive(Debug)]
+pub struct Binding {
+    pub action: Action,
+    pub context: BindingContext,
+    /// Suffix of the `chat.keybindings.` setting used to override this binding.
+    pub name: &'static str,
+    pub default: &'static str,
+    pub description: &'static str,
+    /// Whether the binding is a single character answer typed on its own line, rather than a key
+    /// chord handled by the line editor.
+    pub typed: bool,
+}
+
+impl Binding {
+    pub fn setting_key(&self) -> String {
+        format!("chat.keybindings.{}", self.name)
+    }
+}
+
+pub const BINDINGS: &[Binding] = &[
+    Binding {
+        action: Action::InsertNewline,
+        context: BindingContext::Prompt,
+        name: "insertNewline",
+        default: "ctrl+j",
+        description: "Insert new-line to provide multi-line prompt",
+        typed: false,
+    },
+    Binding {
+        action: Action::InsertNewlineAlt,
+        context: BindingContext::Prompt,
+        name: "insertNewlineAlt",
+        default: "alt+enter",
+        description: "Insert new-line (alternative binding)",
+        typed: false,
+    },
+    Binding {
+        action: Action::FuzzySearch,
+        context: BindingContext::Prompt,
+        name: "fuzzySearch",
+        default: "ctrl+k",
+        description: "Fuzzy search commands and context files. Use Tab to select multiple items.",
+        typed: false,
+    },
+    Binding {
+        action: Action::ApproveTool,
+        context: BindingContext::ToolApproval,
+        name: "approveTool",
+        default: "y",
+        description: "Allow this tool use",
+        typed: true,
+    },
+    Binding {
+        action: Action::RejectTool,
+        context: BindingContext::ToolApproval,
+        name: "rejectTool",
+        default: "n",
+        description: "Reject this tool use",
+        typed: true,
+    },
+    Binding {
+        action: Action::TrustTool,
+        context: BindingContext::ToolApproval,
+        name: "trustTool",
+        default: "t",
+        description: "Trust (always allow) this tool for the session",
+        typed: true,
+    },
+    Binding {
+        action: Action::ShowFullDiff,
+        context: BindingContext::ToolApproval,
+        name: "showFullDiff",
+        default: "d",
+        description: "Show the full diff of a file change that was summarized",
+        typed: true,
+    },
+    Binding {
+        action: Action::ShowShortcuts,
+        context: BindingContext::All,
+        name: "showShortcuts",
+        default: "?",
+        description: "Show these shortcuts",
+        typed: true,
+    },
+    Binding {
+        action: Action::Cancel,
+        context: BindingContext::All,
+        name: "cancel",
+        default: "esc",
+        description: "Cancel the prompt, same as ctrl + c. Pressed twice at the chat prompt, exits",
+        typed: false,
+    },
+];
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum KeyBindingError {
+    #[error("invalid key '{key}' for {setting}: {reason}")]
+    Invalid {
+        setting: String,
+        key: String,
+        reason: &'static str,
+    },
+    #[error("{setting} and {other} are both bound to '{key}'")]
+    Conflict {
+        setting: String,
+        other: String,
+        key: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyName {
+    Char(char),
+    Enter,
+    Esc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub name: KeyName,
+}
+
+impl Key {
+    /// Parses keys written like `ctrl+j`, `alt+enter`, `esc`, or `t`.
+    fn parse(s: &str) -> Result<Self, &'static str> {
+        let mut key = Self {
+            ctrl: false,
+            alt: false,
+            name: KeyName::Enter,
+        };
+        let mut parts = s.split('+').map(str::trim).peekable();
+        while let Some(part) = parts.next() {
+            let is_last = parts.peek().is_none();
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" if !is_last => key.ctrl = true,
+                "alt" | "meta" | "option" if !is_last => key.alt = true,
+                "enter" | "return" if is_last => key.name = KeyName::Enter,
+                "esc" | "escape" if is_last => key.name = KeyName::Esc,
+                _ if is_last => {
+                    let mut chars = part.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => key.name = KeyName::Char(c.to_ascii_lowercase()),
+                        _ => return Err("expected a single character, 'enter' or 'esc'"),
+                    }
+                },
+                _ => return Err("expected modifiers to be 'ctrl' or 'alt'"),
+            }
+        }
+        Ok(key)
+    }
+
+    fn has_modifier(&self) -> bool {
+        self.ctrl || self.alt
+    }
+
+    /// Whether the key is a chord that doesn't type anything, as line editor bindings must be.
+    fn is_chord(&self) -> bool {
+        self.has_modifier() || self.name == KeyName::Esc
+    }
+
+    pub fn to_key_event(self) -> KeyEvent {
+        let mut mods = Modifiers::NONE;
+        if self.ctrl {
+            mods |= Modifiers::CTRL;
+        }
+        if self.alt {
+            mods |= Modifiers::ALT;
+        }
+        match self.name {
+            KeyName::Char(c) => KeyEvent(KeyCode::Char(c), mods),
+            KeyName::Enter => KeyEvent(KeyCode::Enter, mods),
+            KeyName::Esc => KeyEvent(KeyCode::Esc, mods),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "ctrl + ")?;
+        }
+        if self.alt {
+            write!(f, "alt + ")?;
+        }
+        match self.name {
+            KeyName::Char(c) => write!(f, "{c}"),
+            KeyName::Enter => write!(f, "enter"),
+            KeyName::Esc => write!(f, "esc"),
+        }
+    }
+}
+
+/// The resolved key for every entry in [BINDINGS].
+#[derive(Debug, Clone)]
+pub struct KeyBindings(Vec<(&'static Binding, Key)>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self(
+            BINDINGS
+                .iter()
+                .map(|b| (b, Key::parse(b.default).expect("default keybindings are valid")))
+                .collect(),
+        )
+    }
+}
+
+impl KeyBindings {
+    /// Resolves the bindings from user settings, returning an error if an override is invalid or
+    /// conflicts with another binding in the same context.
+    pub fn from_settings(settings: &Settings) -> Result<Self, KeyBindingError> {
+        Self::from_overrides(|binding| {
+            settings.get_string_opt(binding.setting_key()).or_else(|| {
+                // Older setting that only changed the character used with ctrl.
+                match (binding.action, settings.get_string_opt("chat.skimCommandKey")) {
+                    (Action::FuzzySearch, Some(key)) if key.chars().count() == 1 => Some(format!("ctrl+{key}")),
+                    _ => None,
+                }
+            })
+        })
+    }
+
+    /// Same as [KeyBindings::from_settings], falling back to the defaults if the settings are
+    /// invalid.
+    pub fn load(settings: &Settings) -> Self {
+        Self::from_settings(settings).unwrap_or_else(|err| {
+            warn!(?err, "Invalid keybinding settings, using the defaults");
+            Self::default()
+        })
+    }
+
+    fn from_overrides(get: impl Fn(&Binding) -> Option<String>) -> Result<Self, KeyBindingError> {
+        let mut resolved: Vec<(&'static Binding, Key)> = Vec::with_capacity(BINDINGS.len());
+        for binding in BINDINGS {
+            let raw = get(binding).unwrap_or_else(|| binding.default.to_string());
+            let invalid = |reason| KeyBindingError::Invalid {
+                setting: binding.setting_key(),
+                key: raw.clone(),
+                reason,
+            };
+            let key = Key::parse(&raw).map_err(invalid)?;
+            if !binding.typed && !key.is_chord() {
+                return Err(invalid("prompt shortcuts need a ctrl or alt modifier"));
+            }
+            if binding.typed && (key.is_chord() || key.name == KeyName::Enter) {
+                return Err(invalid("typed answers must be a single character"));
+            }
+            if let Some((other, _)) = resolved.iter().find(|(other, other_key)| {
+                other.context.overlaps(binding.context) && other.typed == binding.typed && *other_key == key
+            }) {
+                return Err(KeyBindingError::Conflict {
+                    setting: binding.setting_key(),
+                    other: other.setting_key(),
+                    key: key.to_string(),
+                });
+            }
+            resolved.push((binding, key));
+        }
+        Ok(Self(resolved))
+    }
+
+    pub fn key(&self, action: Action) -> Key {
+        self.0
+            .iter()
+            .find(|(b, _)| b.action == action)
+            .map(|(_, key)| *key)
+            .expect("every action has a binding")
+    }
+
+    /// Returns the action answered by typing `input` at the prompt for `context`, case insensitive.
+    pub fn answer_action(&self, context: BindingContext, input: &str) -> Option<Action> {
+        let mut chars = input.trim().chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => c.to_ascii_lowercase(),
+            _ => return None,
+        };
+        self.0
+            .iter()
+            .find(|(b, key)| b.typed && b.context.overlaps(context) && key.name == KeyName::Char(c))
+            .map(|(b, _)| b.action)
+    }
+
+    /// Styled list of the shortcuts available in the given context, one per line.
+    pub fn help_text(&self, context: BindingContext) -> String {
+        self.0
+            .iter()
+            .filter(|(b, _)| b.context.overlaps(context))
+            .map(|(b, key)| {
+                format!(
+                    "{}{}\n",
+                    format!("{:<22}", key.to_string()).bold(),
+                    b.description.dark_grey()
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_bindings() {
+        let bindings = KeyBindings::default();
+        for binding in BINDINGS {
+            assert_eq!(BINDINGS.iter().filter(|b| b.action == binding.action).count(), 1);
+        }
+
+        assert_eq!(
+            bindings.key(Action::Cancel).to_key_event(),
+            KeyEvent(KeyCode::Esc, Modifiers::NONE)
+        );
+        assert_eq!(
+            bindings.key(Action::FuzzySearch).to_key_event(),
+            KeyEvent(KeyCode::Char('k'), Modifiers::CTRL)
+        );
+        assert_eq!(
+            bindings.answer_action(BindingContext::ToolApproval, "N"),
+            Some(Action::RejectTool)
+        );
+        assert_eq!(
+            bindings.answer_action(BindingContext::Prompt, "?"),
+            Some(Action::ShowShortcuts)
+        );
+        assert_eq!(bindings.answer_action(BindingContext::Prompt, "y"), None);
+    }
+
+    #[test]
+    fn test_key_parse() {
+        assert_eq!(
+            Key::parse("ctrl+j"),
+            Ok(Key {
+                ctrl: true,
+                alt: false,
+                name: KeyName::Char('j')
+            })
+        );
+        assert_eq!(
+            Key::parse("Alt + Enter"),
+            Ok(Key {
+                ctrl: false,
+                alt: true,
+                name: KeyName::Enter
+            })
+        );
+        assert!(Key::parse("ctrl+").is_err());
+        assert!(Key::parse("shift+k").is_err());
+        assert!(Key::parse("ctrl+kk").is_err());
+        assert_eq!(Key::parse("ctrl+j").unwrap().to_string(), "ctrl + j");
+    }
+
+    #[test]
+    fn test_overrides() {
+        let bindings = KeyBindings::from_overrides(|b| match b.action {
+            Action::FuzzySearch => Some("ctrl+x".to_string()),
+            Action::TrustTool => Some("a".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(bindings.key(Action::FuzzySearch).to_string(), "ctrl + x");
+        assert_eq!(
+            bindings.answer_action(BindingContext::ToolApproval, "A"),
+            Some(Action::TrustTool)
+        );
+        assert_eq!(bindings.answer_action(BindingContext::ToolApproval, "t"), None);
+        assert_eq!(
+            bindings.answer_action(BindingContext::ToolApproval, "y"),
+            Some(Action::ApproveTool)
+        );
+        assert_eq!(
+            bindings.answer_action(BindingContext::ToolApproval, "?"),
+            Some(Action::ShowShortcuts)
+        );
+        assert_eq!(bindings.answer_action(BindingContext::ToolApproval, "yes"), None);
+    }
+
+    #[test]
+    fn test_override_conflicts() {
+        let err = KeyBindings::from_overrides(|b| match b.action {
+            Action::FuzzySearch => Some("ctrl+j".to_string()),
+            _ => None,
+        })
+        .unwrap_err();
+        assert!(matches!(err, KeyBindingError::Conflict { .. }), "{err}");
+
+        let err = KeyBindings::from_overrides(|b| match b.action {
+            Action::TrustTool => Some("y".to_string()),
+            _ => None,
+        })
+        .unwrap_err();
+        assert!(matches!(err, KeyBindingError::Conflict { .. }), "{err}");
+
+        // Cancelling applies to every prompt, so its key can't be used in either.
+        let err = KeyBindings::from_overrides(|b| match b.action {
+            Action::FuzzySearch => Some("escape".to_string()),
+            _ => None,
+        })
+        .unwrap_err();
+        assert!(matches!(err, KeyBindingError::Conflict { .. }), "{err}");
+
+        // The same key is fine when the bindings are in different contexts.
+        assert!(
+            KeyBindings::from_overrides(|b| match b.action {
+                Action::FuzzySearch => Some("ctrl+y".to_string()),
+                _ => None,
+            })
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_override_invalid_for_context() {
+        let err = KeyBindings::from_overrides(|b| match b.action {
+            Action::FuzzySearch => Some("x".to_string()),
+            _ => None,
+        })
+        .unwrap_err();
+        assert!(matches!(err, KeyBindingError::Invalid { .. }), "{err}");
+
+        let err = KeyBindings::from_overrides(|b| match b.action {
+            Action::ApproveTool => Some("ctrl+y".to_string()),
+            _ => None,
+        })
+        .unwrap_err();
+        assert!(matches!(err, KeyBindingError::Invalid { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_legacy_skim_command_key() {
+        let settings = Settings::from_slice(&[("chat.skimCommandKey", "x".into())]);
+        let bindings = KeyBindings::from_settings(&settings).unwrap();
+        assert_eq!(bindings.key(Action::FuzzySearch).to_string(), "ctrl + x");
+
+        let settings = Settings::from_slice(&[
+            ("chat.skimCommandKey", "x".into()),
+            ("chat.keybindings.fuzzySearch", "alt+f".into()),
+        ]);
+        let bindings = KeyBindings::from_settings(&settings).unwrap();
+        assert_eq!(bindings.key(Action::FuzzySearch).to_string(), "alt + f");
+    }
+}
@@ -1,19 +1,28 @@
+mod audit_log;
 pub mod cli;
 mod command;
 mod consts;
 mod context;
 mod conversation_state;
+mod file_backups;
+mod file_versions;
 mod hooks;
 mod input_source;
+mod keybindings;
 mod message;
 mod parse;
 mod parser;
 mod prompt;
+mod result_cache;
+mod result_framing;
 mod shared_writer;
 mod skim_integration;
 mod token_counter;
+mod tool_usage;
 mod tools;
+mod transcript;
 pub mod util;
+mod watchdog;
 
 use std::borrow::Cow;
 use std::collections::{
@@ -30,17 +39,33 @@ use std::process::{
     ExitCode,
 };
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{
+    Duration,
+    Instant,
+};
 use std::{
     env,
     fs,
 };
 
+use audit_log::{
+    Approval,
+    AuditEntry,
+    AuditLog,
+    AuditStatus,
+    contains_secret,
+    redact_secrets,
+};
 use command::{
     Command,
     ToolsSubcommand,
 };
-use consts::CONTEXT_WINDOW_SIZE;
+use consts::{
+    CONTEXT_WINDOW_SIZE,
+    INITIAL_CONTEXT_WARN_THRESHOLD,
+    MAX_TOOL_RESPONSE_SIZE,
+    MAX_USER_MESSAGE_SIZE,
+};
 use context::ContextManager;
 use conversation_state::{
     ConversationState,
@@ -63,6 +88,7 @@ use eyre::{
     ErrReport,
     Result,
     bail,
+    eyre,
 };
 use fig_api_client::StreamingClient;
 use fig_api_client::clients::SendMessageOutput;
@@ -76,7 +102,12 @@ use fig_settings::{
     Settings,
     State,
 };
-use fig_util::CLI_BINARY_NAME;
+use fig_util::{
+    CLI_BINARY_NAME,
+    directories,
+};
+use file_backups::FileBackups;
+use file_versions::FileVersions;
 use hooks::{
     Hook,
     HookTrigger,
@@ -119,6 +150,11 @@ that may eventually reach memory constraints.
     )
 }
 use input_source::InputSource;
+use keybindings::{
+    Action,
+    BindingContext,
+    KeyBindings,
+};
 use parse::{
     ParseState,
     interpret_markdown,
@@ -128,6 +164,14 @@ use parser::{
     ResponseParser,
 };
 use regex::Regex;
+use result_cache::ResultCache;
+use result_framing::{
+    EnvelopeStatus,
+    ResultEnvelope,
+    ResultFraming,
+    cancelled_tool_use_result,
+    frame_result,
+};
 use serde_json::Map;
 use spinners::{
     Spinner,
@@ -135,19 +179,35 @@ use spinners::{
 };
 use thiserror::Error;
 use token_counter::{
+    CharCounter,
     TokenCount,
     TokenCounter,
 };
 use tokio::signal::unix::{
+    Signal,
     SignalKind,
     signal,
 };
+use tool_usage::ToolUsageStats;
+use tools::execute_bash::is_typed_back;
 use tools::gh_issue::GhIssueContext;
+use tools::sandbox::{
+    ALLOWED_PATHS_SETTING,
+    Sandbox,
+};
 use tools::{
+    ConcurrentResult,
+    InputSchema,
+    InvokeOutput,
+    OutputKind,
     QueuedTool,
     Tool,
+    ToolConcurrency,
     ToolPermissions,
     ToolSpec,
+    apply_unavailable_tools,
+    invoke_concurrently,
+    unavailable_tools,
 };
 use tracing::{
     debug,
@@ -155,12 +215,24 @@ use tracing::{
     trace,
     warn,
 };
+use transcript::{
+    ToolCallEvent,
+    TurnTranscript,
+};
 use util::{
     animate_output,
     play_notification_bell,
     region_check,
+    should_animate,
+    truncate_safe,
 };
 use uuid::Uuid;
+use watchdog::{
+    Component,
+    Heartbeats,
+    TerminalWriter,
+    spawn_watchdog,
+};
 use winnow::Partial;
 use winnow::stream::Offset;
 
@@ -240,45 +312,67 @@ const HELP_TEXT: &str = color_print::cstr! {"
   <em>clear</em>       <black!>Clear all files from current context [--global]</black!>
   <em>hooks</em>       <black!>View and manage context hooks</black!>
 <em>/usage</em>      <black!>Show current session's context window usage</black!>
+<em>/expand</em>     <black!>Show the tool calls hidden by the last collapsed summary</black!>
+<em>/cache</em>      <black!>Show cached read-only tool results [clear]</black!>
+<em>/undo</em>       <black!>Revert the last file change, if the file hasn't changed since [path]</black!>
+<em>/undo-file</em>  <black!>Restore a file from the backup taken before it was last changed</black!>
+<em>/plan</em>       <black!>Toggle plan mode, where file changes are proposed as diffs without being written [on|off]</black!>
 
 <cyan,em>Tips:</cyan,em>
 <em>!{command}</em>            <black!>Quickly execute a command in your current session</black!>
-<em>Ctrl(^) + j</em>           <black!>Insert new-line to provide multi-line prompt. Alternatively, [Alt(⌥) + Enter(⏎)]</black!>
-<em>Ctrl(^) + k</em>           <black!>Fuzzy search commands and context files. Use Tab to select multiple items.</black!>
-                      <black!>Change the keybind to ctrl+x with: q settings chat.skimCommandKey x (where x is any key)</black!>
+"};
+
+const KEYBINDINGS_HELP_FOOTER: &str = color_print::cstr! {"\
+                      <black!>Change a keybind with: q settings chat.keybindings.fuzzySearch ctrl+x</black!>
 
 "};
 
+/// Answers handled at the main chat prompt before the input is parsed.
+const PROMPT_ANSWERS: &[Action] = &[Action::ShowShortcuts];
+
+/// Answers handled at the tool approval prompt. Rejecting continues with the answer as the message.
+const APPROVAL_ANSWERS: &[Action] = &[
+    Action::ApproveTool,
+    Action::RejectTool,
+    Action::TrustTool,
+    Action::ShowFullDiff,
+    Action::ShowShortcuts,
+];
+
+/// How soon after cancelling a tool a second Ctrl+C exits the chat.
+const TOOL_CANCEL_EXIT_WINDOW: Duration = Duration::from_secs(2);
+
+/// How many lines of a multi-line paste are shown before asking whether to send it.
+const PASTE_PREVIEW_LINES: usize = 5;
+
 const RESPONSE_TIMEOUT_CONTENT: &str = "Response timed out - message took too long to generate";
 const TRUST_ALL_TEXT: &str = color_print::cstr! {"<green!>All tools are now trusted (<red!>!</red!>). Amazon Q will execute tools <bold>without</bold> asking for confirmation.\
 \nAgents can sometimes do unexpected things so understand the risks.</green!>"};
 
-pub async fn launch_chat(args: cli::Chat) -> Result<ExitCode> {
-    let trust_tools = args.trust_tools.map(|mut tools| {
+pub async fn launch_chat(mut args: cli::Chat) -> Result<ExitCode> {
+    args.trust_tools = args.trust_tools.map(|mut tools| {
         if tools.len() == 1 && tools[0].is_empty() {
             tools.pop();
         }
         tools
     });
-    chat(
-        args.input,
-        args.no_interactive,
-        args.accept_all,
-        args.profile,
-        args.trust_all_tools,
-        trust_tools,
-    )
-    .await
+    if args.no_color {
+        style::force_color_output(false);
+    }
+    chat(args).await
 }
 
-pub async fn chat(
-    input: Option<String>,
-    no_interactive: bool,
-    accept_all: bool,
-    profile: Option<String>,
-    trust_all_tools: bool,
-    trust_tools: Option<Vec<String>>,
-) -> Result<ExitCode> {
+pub async fn chat(args: cli::Chat) -> Result<ExitCode> {
+    let cli::Chat {
+        input,
+        no_interactive,
+        accept_all,
+        profile,
+        trust_all_tools,
+        trust_tools,
+        no_audit,
+        ..
+    } = args;
     if !fig_util::system_info::in_cloudshell() && !fig_auth::is_logged_in().await {
         bail!(
             "You are not logged in, please log in with {}",
@@ -360,13 +454,29 @@ pub async fn chat(
         }
     }
 
+    let settings = Settings::new();
+    let key_bindings = match KeyBindings::from_settings(&settings) {
+        Ok(key_bindings) => key_bindings,
+        Err(err) => {
+            if interactive {
+                queue!(
+                    output,
+                    style::SetForegroundColor(Color::Yellow),
+                    style::Print(format!("\nWarning: {err}. Using the default keybindings.\n")),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+            }
+            KeyBindings::default()
+        },
+    };
+
     let mut chat = ChatContext::new(
         ctx,
-        Settings::new(),
+        settings,
         State::new(),
         output,
         input,
-        InputSource::new()?,
+        InputSource::new(&key_bindings)?,
         interactive,
         client,
         || terminal::window_size().map(|s| s.columns.into()).ok(),
@@ -375,8 +485,13 @@ pub async fn chat(
         tool_permissions,
     )
     .await?;
+    if no_audit {
+        chat.audit_log = None;
+    }
 
+    file_backups::prune_stale_sessions(&chat.ctx, file_backups::STALE_SESSION_AGE).await;
     let result = chat.try_chat().await.map(|_| ExitCode::SUCCESS);
+    chat.file_backups.remove_session_backups(&chat.ctx).await;
     drop(chat); // Explicit drop for clarity
 
     result
@@ -423,6 +538,8 @@ pub struct ChatContext {
     initial_input: Option<String>,
     input_source: InputSource,
     interactive: bool,
+    /// Whether the spinner and other cursor movement can be drawn, see [should_animate].
+    animate: bool,
     /// The client to use to interact with the model.
     client: StreamingClient,
     /// Width of the terminal, required for [ParseState].
@@ -432,12 +549,32 @@ pub struct ChatContext {
     conversation_state: ConversationState,
     /// State to track tools that need confirmation.
     tool_permissions: ToolPermissions,
+    /// Input schemas of the available tools, used to check the model's arguments.
+    tool_schemas: HashMap<String, InputSchema>,
     /// Telemetry events to be sent as part of the conversation.
     tool_use_telemetry_events: HashMap<String, ToolUseEventBuilder>,
     /// State used to keep track of tool use relation
     tool_use_status: ToolUseStatus,
     /// Any failed requests that could be useful for error report/debugging
     failed_request_ids: Vec<String>,
+    /// Tool calls printed during the current turn, used to collapse long runs of read-only calls.
+    transcript: TurnTranscript,
+    /// When a tool was last cancelled with Ctrl+C.
+    tool_cancelled_at: Option<Instant>,
+    /// Progress reported to the watchdog, see [watchdog::spawn_watchdog].
+    heartbeats: Arc<Heartbeats>,
+    /// Tool invocations made this session, shown in /usage and on exit.
+    tool_usage: ToolUsageStats,
+    /// Where tool calls are recorded, if enabled.
+    audit_log: Option<AuditLog>,
+    /// Results of read-only tools that can be reused while the files they read are unchanged.
+    result_cache: ResultCache,
+    /// Previous contents of the files changed by fs_write, for /undo-file.
+    file_backups: FileBackups,
+    /// Whether fs_write calls are made dry runs, see /plan.
+    plan_mode: bool,
+    /// What the model last saw of each file, to catch writes to files changed outside of the chat.
+    file_versions: FileVersions,
 }
 
 impl ChatContext {
@@ -458,6 +595,23 @@ impl ChatContext {
     ) -> Result<Self> {
         let ctx_clone = Arc::clone(&ctx);
         let output_clone = output.clone();
+        let animate = interactive
+            && should_animate(
+                ctx.env(),
+                std::io::stdout().is_terminal() && std::io::stderr().is_terminal(),
+            );
+        let audit_log = AuditLog::from_settings(&ctx, &settings);
+        let result_cache = ResultCache::from_settings(&settings);
+        let file_backups = FileBackups::from_settings(&settings);
+        let tool_schemas = tool_config
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.input_schema.clone()))
+            .collect();
+        let transcript = if interactive {
+            TurnTranscript::from_settings(&settings)
+        } else {
+            TurnTranscript::default()
+        };
         Ok(Self {
             ctx,
             settings,
@@ -466,14 +620,25 @@ impl ChatContext {
             initial_input: input,
             input_source,
             interactive,
+            animate,
             client,
             terminal_width_provider,
             spinner: None,
             tool_permissions,
+            tool_schemas,
             conversation_state: ConversationState::new(ctx_clone, tool_config, profile, Some(output_clone)).await,
             tool_use_telemetry_events: HashMap::new(),
             tool_use_status: ToolUseStatus::Idle,
             failed_request_ids: Vec::new(),
+            transcript,
+            tool_cancelled_at: None,
+            heartbeats: Arc::new(Heartbeats::default()),
+            tool_usage: ToolUsageStats::default(),
+            audit_log,
+            result_cache,
+            file_backups,
+            plan_mode: false,
+            file_versions: FileVersions::default(),
         })
     }
 }
@@ -552,6 +717,21 @@ impl Default for ChatState {
     }
 }
 
+impl ChatState {
+    /// Name of the state, as reported to the watchdog.
+    fn checkpoint(&self) -> &'static str {
+        match self {
+            ChatState::PromptUser { .. } => "prompt user",
+            ChatState::HandleInput { .. } => "handle input",
+            ChatState::ValidateTools(_) => "validate tools",
+            ChatState::ExecuteTools(_) => "execute tools",
+            ChatState::HandleResponseStream(_) => "handle response stream",
+            ChatState::CompactHistory { .. } => "compact history",
+            ChatState::Exit => "exit",
+        }
+    }
+}
+
 impl ChatContext {
     /// Opens the user's preferred editor to compose a prompt
     fn open_editor(initial_text: Option<String>) -> Result<String, ChatError> {
@@ -682,6 +862,33 @@ impl ChatContext {
         Ok(())
     }
 
+    /// Prints the directories fs_read and fs_write are confined to, if [ALLOWED_PATHS_SETTING] is
+    /// set. With `always`, also says when they aren't restricted.
+    fn print_sandbox(&mut self, always: bool) -> Result<(), std::io::Error> {
+        let sandbox = Sandbox::load(&self.ctx);
+        if sandbox.is_enabled() {
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(format!(
+                    "\nfs_read and fs_write can only access: {} (set by {ALLOWED_PATHS_SETTING})\n",
+                    sandbox.describe()
+                )),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        } else if always {
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(
+                    "\nfs_read and fs_write can access any path. Set {ALLOWED_PATHS_SETTING} to restrict them.\n"
+                )),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        Ok(())
+    }
+
     async fn try_chat(&mut self) -> Result<()> {
         let is_small_screen = self.terminal_width() < GREETING_BREAK_POINT;
         if self.interactive && self.settings.get_bool_or("chat.greeting.enabled", true) {
@@ -740,10 +947,25 @@ impl ChatContext {
                 ))
             )?;
         }
+        if self.interactive {
+            self.print_sandbox(false)?;
+        }
         self.output.flush()?;
 
+        self.check_initial_context_size().await?;
+
         let mut ctrl_c_stream = signal(SignalKind::interrupt())?;
 
+        let _watchdog = match (watchdog::timeout_from_settings(&self.settings), directories::logs_dir()) {
+            (Some(timeout), Ok(dump_dir)) => Some(spawn_watchdog(
+                Arc::clone(&self.heartbeats),
+                timeout,
+                dump_dir,
+                TerminalWriter,
+            )),
+            _ => None,
+        };
+
         let mut next_state = Some(ChatState::PromptUser {
             tool_uses: None,
             pending_tool_index: None,
@@ -772,6 +994,9 @@ impl ChatContext {
             debug_assert!(next_state.is_some());
             let chat_state = next_state.take().unwrap_or_default();
             debug!(?chat_state, "changing to state");
+            self.heartbeats.beat(Component::ChatLoop, chat_state.checkpoint());
+            self.heartbeats
+                .set_in_flight(!matches!(chat_state, ChatState::PromptUser { .. } | ChatState::Exit));
 
             let result = match chat_state {
                 ChatState::PromptUser {
@@ -810,13 +1035,8 @@ impl ChatContext {
                         Some(_) = ctrl_c_stream.recv() => Err(ChatError::Interrupted { tool_uses: tool_uses_clone })
                     }
                 },
-                ChatState::ExecuteTools(tool_uses) => {
-                    let tool_uses_clone = tool_uses.clone();
-                    tokio::select! {
-                        res = self.tool_use_execute(tool_uses) => res,
-                        Some(_) = ctrl_c_stream.recv() => Err(ChatError::Interrupted { tool_uses: Some(tool_uses_clone) })
-                    }
-                },
+                // Ctrl+C is handled per tool so that a cancelled tool's result can still be sent.
+                ChatState::ExecuteTools(tool_uses) => self.tool_use_execute(tool_uses, &mut ctrl_c_stream).await,
                 ChatState::ValidateTools(tool_uses) => {
                     tokio::select! {
                         res = self.validate_tools(tool_uses) => res,
@@ -827,7 +1047,14 @@ impl ChatContext {
                     res = self.handle_response(response) => res,
                     Some(_) = ctrl_c_stream.recv() => Err(ChatError::Interrupted { tool_uses: None })
                 },
-                ChatState::Exit => return Ok(()),
+                ChatState::Exit => {
+                    if self.interactive && !self.tool_usage.is_empty() {
+                        queue!(self.output, style::Print("\n"))?;
+                        self.print_tool_usage()?;
+                        self.output.flush()?;
+                    }
+                    return Ok(());
+                },
             };
 
             next_state = Some(self.handle_state_execution_result(result).await?);
@@ -888,6 +1115,15 @@ impl ChatContext {
                 match e {
                     ChatError::Interrupted { tool_uses: inter } => {
                         execute!(self.output, style::Print("\n\n"))?;
+                        // A second Ctrl+C shortly after cancelling a tool exits, as it would have
+                        // before tools could be cancelled on their own.
+                        if self
+                            .tool_cancelled_at
+                            .is_some_and(|cancelled_at| cancelled_at.elapsed() < TOOL_CANCEL_EXIT_WINDOW)
+                        {
+                            return Ok(ChatState::Exit);
+                        }
+                        self.transcript.end_turn();
                         // If there was an interrupt during tool execution, then we add fake
                         // messages to "reset" the chat state.
                         match inter {
@@ -895,6 +1131,7 @@ impl ChatContext {
                                 self.conversation_state.abandon_tool_use(
                                     tool_uses,
                                     "The user interrupted the tool execution.".to_string(),
+                                    ResultFraming::from_settings(&self.settings),
                                 );
                                 let _ = self.conversation_state.as_sendable_conversation_state(false).await;
                                 self.conversation_state
@@ -1011,7 +1248,7 @@ impl ChatContext {
             .conversation_state
             .create_summary_request(custom_prompt.as_ref())
             .await;
-        if self.interactive {
+        if self.animate {
             execute!(self.output, cursor::Hide, style::Print("\n"))?;
             self.spinner = Some(Spinner::new(Spinners::Dots, "Creating summary...".to_string()));
         }
@@ -1187,42 +1424,78 @@ impl ChatContext {
         }
 
         let show_tool_use_confirmation_dialog = !skip_printing_tools && pending_tool_index.is_some();
-        if show_tool_use_confirmation_dialog {
+        let destructive = pending_tool_index.and_then(|i| destructive_command(&tool_uses, i));
+        if let (true, Some((_, reason))) = (show_tool_use_confirmation_dialog, &destructive) {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Red),
+                style::SetAttribute(Attribute::Bold),
+                style::Print(format!("\n⚠ This command {reason}.")),
+                style::SetAttribute(Attribute::Reset),
+                style::SetForegroundColor(Color::Red),
+                style::Print(" To run it anyway, type the command back exactly. Anything else rejects it.\n\n"),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        } else if show_tool_use_confirmation_dialog {
+            let bindings = KeyBindings::load(&self.settings);
+            let [approve, reject, trust] = [Action::ApproveTool, Action::RejectTool, Action::TrustTool]
+                .map(|action| bindings.key(action).to_string());
             execute!(
                 self.output,
                 style::SetForegroundColor(Color::DarkGrey),
                 style::Print("\nAllow this action? Use '"),
                 style::SetForegroundColor(Color::Green),
-                style::Print("t"),
+                style::Print(&trust),
                 style::SetForegroundColor(Color::DarkGrey),
                 style::Print("' to trust (always allow) this tool for the session. ["),
                 style::SetForegroundColor(Color::Green),
-                style::Print("y"),
+                style::Print(approve),
                 style::SetForegroundColor(Color::DarkGrey),
                 style::Print("/"),
                 style::SetForegroundColor(Color::Green),
-                style::Print("n"),
+                style::Print(reject),
                 style::SetForegroundColor(Color::DarkGrey),
                 style::Print("/"),
                 style::SetForegroundColor(Color::Green),
-                style::Print("t"),
+                style::Print(trust),
                 style::SetForegroundColor(Color::DarkGrey),
                 style::Print("]:\n\n"),
                 style::SetForegroundColor(Color::Reset),
             )?;
+
+            if let Some(Tool::FsWrite(fs_write)) = pending_tool_index.and_then(|i| tool_uses.get(i)).map(|t| &t.tool) {
+                if fs_write.diff_is_summarized(&self.ctx) {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("Use '"),
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(bindings.key(Action::ShowFullDiff).to_string()),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("' to show the full diff.\n\n"),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                }
+            }
         }
 
         // Do this here so that the skim integration sees an updated view of the context *during the current
         // q session*. (e.g., if I add files to context, that won't show up for skim for the current
         // q session unless we do this in prompt_user... unless you can find a better way)
         if let Some(ref context_manager) = self.conversation_state.context_manager {
+            let key = KeyBindings::load(&self.settings).key(Action::FuzzySearch);
             self.input_source
-                .put_skim_command_selector(Arc::new(context_manager.clone()));
+                .put_skim_command_selector(Arc::new(context_manager.clone()), key);
         }
 
-        let user_input = match self.read_user_input(&self.generate_tool_trust_prompt(), false) {
-            Some(input) => input,
-            None => return Ok(ChatState::Exit),
+        // Cancelling the approval prompt rejects the tool use rather than exiting.
+        let user_input = match (
+            self.read_user_input(&self.generate_tool_trust_prompt(), pending_tool_index.is_some()),
+            pending_tool_index,
+        ) {
+            (Some(input), _) => input,
+            (None, Some(_)) => KeyBindings::load(&self.settings).key(Action::RejectTool).to_string(),
+            (None, None) => return Ok(ChatState::Exit),
         };
 
         self.conversation_state.append_user_transcript(&user_input);
@@ -1235,11 +1508,121 @@ impl ChatContext {
 
     async fn handle_input(
         &mut self,
-        user_input: String,
+        mut user_input: String,
         tool_uses: Option<Vec<QueuedTool>>,
         pending_tool_index: Option<usize>,
     ) -> Result<ChatState, ChatError> {
-        let command_result = Command::parse(&user_input, &mut self.output);
+        // Check for a pending tool approval before parsing, since answers like '?' would otherwise be
+        // treated as commands.
+        if let Some(index) = pending_tool_index {
+            let bindings = KeyBindings::load(&self.settings);
+            let destructive = tool_uses.as_ref().and_then(|t| destructive_command(t, index));
+            if let (Some((command, _)), Some(tool_uses)) = (&destructive, &tool_uses) {
+                if is_typed_back(command, &user_input) {
+                    let mut tool_uses = tool_uses.clone();
+                    tool_uses[index].accepted = true;
+                    return Ok(ChatState::ExecuteTools(tool_uses));
+                }
+            }
+            let answer = bindings
+                .answer_action(BindingContext::ToolApproval, &user_input)
+                .filter(|action| APPROVAL_ANSWERS.contains(action));
+            match answer {
+                // Destructive commands can't be approved, or trusted, with a single key.
+                Some(Action::ApproveTool | Action::TrustTool) if destructive.is_some() => {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Red),
+                        style::Print("\nType the command back exactly to run it, or anything else to reject it.\n\n"),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses,
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                },
+                Some(action @ (Action::ApproveTool | Action::TrustTool)) => {
+                    let mut tool_uses = tool_uses.unwrap_or_default();
+                    let tool_use = &mut tool_uses[index];
+                    if action == Action::TrustTool {
+                        self.tool_permissions.trust_tool(&tool_use.name);
+                    }
+                    tool_use.accepted = true;
+
+                    return Ok(ChatState::ExecuteTools(tool_uses));
+                },
+                Some(Action::ShowFullDiff) => {
+                    if let Some(Tool::FsWrite(fs_write)) =
+                        tool_uses.as_ref().and_then(|t| t.get(index)).map(|t| &t.tool)
+                    {
+                        execute!(self.output, style::Print("\n"))?;
+                        fs_write
+                            .queue_full_diff(&self.ctx, &mut self.output)
+                            .map_err(|e| ChatError::Custom(format!("failed to show the diff: {e}").into()))?;
+                        self.output.flush()?;
+                    }
+                    return Ok(ChatState::PromptUser {
+                        tool_uses,
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                },
+                Some(Action::ShowShortcuts) => {
+                    execute!(
+                        self.output,
+                        style::Print("\n"),
+                        style::Print(bindings.help_text(BindingContext::ToolApproval)),
+                        style::Print("\n")
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses,
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                },
+                _ => (),
+            }
+        } else {
+            let bindings = KeyBindings::load(&self.settings);
+            let answer = bindings
+                .answer_action(BindingContext::Prompt, &user_input)
+                .filter(|action| PROMPT_ANSWERS.contains(action));
+            if answer == Some(Action::ShowShortcuts) {
+                execute!(
+                    self.output,
+                    style::Print("\n"),
+                    style::Print(bindings.help_text(BindingContext::Prompt)),
+                    style::Print("\n")
+                )?;
+                return Ok(ChatState::PromptUser {
+                    tool_uses,
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                });
+            }
+        }
+
+        // Multi-line pastes (e.g. a stack trace) are sent as a message even if a line starts with '/' or
+        // '!', once the user has confirmed them.
+        let is_paste = self.input_source.take_pasted() && user_input.contains('\n');
+        let command_result = if is_paste {
+            match self.confirm_paste(&user_input)? {
+                Some(prompt) => {
+                    user_input = prompt.clone();
+                    Ok(Command::Ask { prompt })
+                },
+                None => {
+                    return Ok(ChatState::PromptUser {
+                        tool_uses,
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                },
+            }
+        } else {
+            Command::parse(&user_input, &mut self.output)
+        };
 
         if let Err(error_message) = &command_result {
             // Display error message for command parsing errors
@@ -1258,30 +1641,22 @@ impl ChatContext {
         }
 
         let command = command_result.unwrap();
-        let mut tool_uses: Vec<QueuedTool> = tool_uses.unwrap_or_default();
+        let tool_uses: Vec<QueuedTool> = tool_uses.unwrap_or_default();
 
         Ok(match command {
-            Command::Ask { prompt } => {
-                // Check for a pending tool approval
-                if let Some(index) = pending_tool_index {
-                    let tool_use = &mut tool_uses[index];
-
-                    let is_trust = ["t", "T"].contains(&prompt.as_str());
-                    if ["y", "Y"].contains(&prompt.as_str()) || is_trust {
-                        if is_trust {
-                            self.tool_permissions.trust_tool(&tool_use.name);
-                        }
-                        tool_use.accepted = true;
-
-                        return Ok(ChatState::ExecuteTools(tool_uses));
-                    }
-                }
-
-                // Otherwise continue with normal chat on 'n' or other responses
+            Command::Ask { .. } => {
+                // Continue with normal chat on 'n' or other responses
                 self.tool_use_status = ToolUseStatus::Idle;
 
+                if let Some(denied) = pending_tool_index.and_then(|i| tool_uses.get(i)) {
+                    self.audit_tool_call(denied, Approval::Denied, AuditStatus::NotRun, None, None);
+                }
                 if pending_tool_index.is_some() {
-                    self.conversation_state.abandon_tool_use(tool_uses, user_input);
+                    self.conversation_state.abandon_tool_use(
+                        tool_uses,
+                        user_input,
+                        ResultFraming::from_settings(&self.settings),
+                    );
                 } else {
                     self.conversation_state.set_next_user_message(user_input).await;
                 }
@@ -1289,10 +1664,10 @@ impl ChatContext {
                 let conv_state = self.conversation_state.as_sendable_conversation_state(true).await;
 
                 if self.interactive {
-                    queue!(self.output, style::SetForegroundColor(Color::Magenta))?;
-                    queue!(self.output, style::SetForegroundColor(Color::Reset))?;
-                    queue!(self.output, cursor::Hide)?;
                     execute!(self.output, style::Print("\n"))?;
+                }
+                if self.animate {
+                    execute!(self.output, cursor::Hide)?;
                     self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_owned()));
                 }
 
@@ -1365,7 +1740,12 @@ impl ChatContext {
                     .await?
             },
             Command::Help => {
-                execute!(self.output, style::Print(HELP_TEXT))?;
+                execute!(
+                    self.output,
+                    style::Print(HELP_TEXT),
+                    style::Print(KeyBindings::load(&self.settings).help_text(BindingContext::Prompt)),
+                    style::Print(KEYBINDINGS_HELP_FOOTER)
+                )?;
                 ChatState::PromptUser {
                     tool_uses: Some(tool_uses),
                     pending_tool_index,
@@ -2249,6 +2629,10 @@ impl ChatContext {
                             self.output,
                             style::Print("\nTrusted tools can be run without confirmation\n"),
                             style::Print(format!("\n{}\n", tool_permissions.join("\n"))),
+                        )?;
+                        self.print_sandbox(true)?;
+                        queue!(
+                            self.output,
                             style::SetForegroundColor(Color::DarkGrey),
                             style::Print(format!("\n{}\n", "* Default settings")),
                             style::Print("\n💡 Use "),
@@ -2279,8 +2663,8 @@ impl ChatContext {
                 let context_token_count: TokenCount = data.context_messages.into();
                 let assistant_token_count: TokenCount = data.assistant_messages.into();
                 let user_token_count: TokenCount = data.user_messages.into();
-                let total_token_used: TokenCount =
-                    (data.context_messages + data.user_messages + data.assistant_messages).into();
+                let tool_spec_token_count: TokenCount = data.tool_specs.into();
+                let total_token_used: TokenCount = data.char_count().into();
 
                 let window_width = self.terminal_width();
                 // set a max width for the progress bar for better aesthetic
@@ -2292,9 +2676,14 @@ impl ChatContext {
                     * progress_bar_width as f64) as usize;
                 let user_width = ((user_token_count.value() as f64 / CONTEXT_WINDOW_SIZE as f64)
                     * progress_bar_width as f64) as usize;
+                let tool_spec_width = ((tool_spec_token_count.value() as f64 / CONTEXT_WINDOW_SIZE as f64)
+                    * progress_bar_width as f64) as usize;
 
                 let left_over_width = progress_bar_width
-                    - std::cmp::min(context_width + assistant_width + user_width, progress_bar_width);
+                    - std::cmp::min(
+                        tool_spec_width + context_width + assistant_width + user_width,
+                        progress_bar_width,
+                    );
 
                 queue!(
                     self.output,
@@ -2303,6 +2692,13 @@ impl ChatContext {
                         total_token_used,
                         CONTEXT_WINDOW_SIZE / 1000
                     )),
+                    style::SetForegroundColor(Color::DarkYellow),
+                    style::Print("|".repeat(if tool_spec_width == 0 && *tool_spec_token_count > 0 {
+                        1
+                    } else {
+                        0
+                    })),
+                    style::Print("█".repeat(tool_spec_width)),
                     style::SetForegroundColor(Color::DarkCyan),
                     // add a nice visual to mimic "tiny" progress, so the overral progress bar doesn't look too
                     // empty
@@ -2337,6 +2733,14 @@ impl ChatContext {
 
                 queue!(
                     self.output,
+                    style::SetForegroundColor(Color::DarkYellow),
+                    style::Print("█ Tool specs: "),
+                    style::SetForegroundColor(Color::Reset),
+                    style::Print(format!(
+                        "   ~{} tokens ({:.2}%)\n",
+                        tool_spec_token_count,
+                        (tool_spec_token_count.value() as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
+                    )),
                     style::SetForegroundColor(Color::DarkCyan),
                     style::Print("█ Context files: "),
                     style::SetForegroundColor(Color::Reset),
@@ -2363,6 +2767,10 @@ impl ChatContext {
                     )),
                 )?;
 
+                if !self.tool_usage.is_empty() {
+                    self.print_tool_usage()?;
+                }
+
                 queue!(
                     self.output,
                     style::SetAttribute(Attribute::Bold),
@@ -2393,90 +2801,480 @@ impl ChatContext {
                     skip_printing_tools: true,
                 }
             },
-        })
-    }
-
-    async fn tool_use_execute(&mut self, mut tool_uses: Vec<QueuedTool>) -> Result<ChatState, ChatError> {
-        // Verify tools have permissions.
-        for (index, tool) in tool_uses.iter_mut().enumerate() {
-            // Manually accepted by the user or otherwise verified already.
-            if tool.accepted {
-                continue;
-            }
-
-            // If there is an override, we will use it. Otherwise fall back to Tool's default.
-            let allowed = if self.tool_permissions.has(&tool.name) {
-                self.tool_permissions.is_trusted(&tool.name)
-            } else {
-                !tool.tool.requires_acceptance(&self.ctx)
-            };
-
-            if self.settings.get_bool_or("chat.enableNotifications", false) {
-                play_notification_bell(!allowed);
-            }
-
-            self.print_tool_descriptions(tool, allowed).await?;
-
-            if allowed {
-                tool.accepted = true;
-                continue;
-            }
-
-            let pending_tool_index = Some(index);
-            if !self.interactive {
-                // Cannot request in non-interactive, so fail.
-                return Err(ChatError::NonInteractiveToolApproval);
-            }
-
-            return Ok(ChatState::PromptUser {
-                tool_uses: Some(tool_uses),
-                pending_tool_index,
-                skip_printing_tools: false,
-            });
-        }
-
-        // Execute the requested tools.
-        let mut tool_results = vec![];
-
-        for tool in tool_uses {
-            let mut tool_telemetry = self.tool_use_telemetry_events.entry(tool.id.clone());
-            tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_accepted = true);
-
-            let tool_start = std::time::Instant::now();
-            let invoke_result = tool.tool.invoke(&self.ctx, &mut self.output).await;
-
-            if self.interactive && self.spinner.is_some() {
-                queue!(
+            Command::Cache { clear } => {
+                let (entries, bytes) = self.result_cache.usage();
+                let message = if !self.result_cache.enabled() {
+                    "\nThe tool result cache is disabled. Set chat.toolResultCache.enabled to true to enable it.\n\n"
+                        .to_string()
+                } else if clear {
+                    self.result_cache.clear();
+                    format!("\nCleared {entries} cached tool results.\n\n")
+                } else {
+                    format!("\n{entries} cached tool results ({bytes} bytes). Run /cache clear to drop them.\n\n")
+                };
+                execute!(
                     self.output,
-                    terminal::Clear(terminal::ClearType::CurrentLine),
-                    cursor::MoveToColumn(0),
-                    cursor::Show
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(message),
+                    style::SetForegroundColor(Color::Reset),
                 )?;
-            }
-            execute!(self.output, style::Print("\n"))?;
 
-            let tool_time = std::time::Instant::now().duration_since(tool_start);
-            let tool_time = format!("{}.{}", tool_time.as_secs(), tool_time.subsec_millis());
-            const CONTINUATION_LINE: &str = " ⋮ ";
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Undo { path } => {
+                match self.file_backups.undo(&self.ctx, path.as_deref()).await {
+                    Ok((path, message)) => {
+                        self.result_cache.clear();
+                        self.conversation_state.add_note(format!(
+                            "The user ran /undo, which reverted the last change to {path}: {message}"
+                        ));
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n{message}\n\n")),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                    Err(err) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Red),
+                            style::Print(format!("\nError: {err}\n\n")),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                }
 
-            match invoke_result {
-                Ok(result) => {
-                    debug!("tool result output: {:#?}", result);
-                    execute!(
-                        self.output,
-                        style::Print(CONTINUATION_LINE),
-                        style::Print("\n"),
-                        style::SetForegroundColor(Color::Green),
-                        style::SetAttribute(Attribute::Bold),
-                        style::Print(format!(" ● Completed in {}s", tool_time)),
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::UndoFile { path } => {
+                match self.file_backups.restore(&self.ctx, &path).await {
+                    Ok(message) => {
+                        // Cached reads of the file are now stale.
+                        self.result_cache.clear();
+                        self.conversation_state
+                            .add_note(format!("The user ran /undo-file {path}: {message}"));
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n{message}\n\n")),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                    Err(err) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Red),
+                            style::Print(format!("\nError: {err}\n\n")),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                }
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Plan { enabled } => {
+                self.plan_mode = enabled.unwrap_or(!self.plan_mode);
+                let message = if self.plan_mode {
+                    "\nPlan mode is on. File changes will be returned as diffs without being written, run /plan off to write them again.\n\n"
+                } else {
+                    "\nPlan mode is off. File changes will be written again.\n\n"
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(message),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Expand => {
+                if self.transcript.last_collapsed().is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nNo tool calls have been collapsed yet.\n\n"),
                         style::SetForegroundColor(Color::Reset),
-                        style::Print("\n"),
                     )?;
+                } else {
+                    let hidden = self.transcript.last_collapsed().to_vec();
+                    queue!(self.output, style::Print("\n"))?;
+                    self.output.write_all(&hidden)?;
+                    execute!(self.output, style::Print("\n"))?;
+                }
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+        })
+    }
+
+    /// Whether `tool` can run without asking the user first.
+    fn runs_without_approval(&self, tool: &QueuedTool) -> bool {
+        if tool.tool.requires_explicit_acceptance(&self.ctx) {
+            return false;
+        }
+        // If there is an override, we will use it. Otherwise fall back to Tool's default.
+        if self.tool_permissions.has(&tool.name) {
+            self.tool_permissions.is_trusted(&tool.name)
+        } else {
+            !tool.tool.requires_acceptance(&self.ctx)
+        }
+    }
+
+    fn audit_tool_call(
+        &self,
+        tool: &QueuedTool,
+        approval: Approval,
+        status: AuditStatus,
+        duration: Option<Duration>,
+        result: Option<serde_json::Value>,
+    ) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let mut entry = AuditEntry::new(
+            self.conversation_state.conversation_id(),
+            &tool.id,
+            &tool.name,
+            tool.args.clone(),
+            approval,
+            status,
+        );
+        entry.duration_ms = duration.map(|d| d.as_millis() as u64);
+        entry.result = result;
+        audit_log.record(entry);
+    }
+
+    fn print_tool_usage(&mut self) -> Result<(), std::io::Error> {
+        queue!(
+            self.output,
+            style::SetAttribute(Attribute::Bold),
+            style::Print("Tool usage this session\n"),
+            style::SetAttribute(Attribute::Reset),
+            style::Print(self.tool_usage.format_table()),
+            style::Print("\n"),
+        )
+    }
+
+    async fn tool_use_execute(
+        &mut self,
+        mut tool_uses: Vec<QueuedTool>,
+        ctrl_c_stream: &mut Signal,
+    ) -> Result<ChatState, ChatError> {
+        // Verify tools have permissions.
+        for (index, tool) in tool_uses.iter_mut().enumerate() {
+            // Manually accepted by the user or otherwise verified already.
+            if tool.accepted {
+                continue;
+            }
+
+            let allowed = self.runs_without_approval(tool);
+
+            if self.settings.get_bool_or("chat.enableNotifications", false) {
+                play_notification_bell(!allowed);
+            }
+
+            // Described when it runs instead, so that it can be collapsed.
+            if self.defers_description(tool) {
+                tool.accepted = true;
+                continue;
+            }
+
+            self.print_collapsed_summary()?;
+            self.print_tool_descriptions(tool, allowed).await?;
+
+            if allowed {
+                tool.accepted = true;
+                continue;
+            }
+
+            let pending_tool_index = Some(index);
+            if !self.interactive {
+                // Cannot request in non-interactive, so fail.
+                return Err(ChatError::NonInteractiveToolApproval);
+            }
+
+            return Ok(ChatState::PromptUser {
+                tool_uses: Some(tool_uses),
+                pending_tool_index,
+                skip_printing_tools: false,
+            });
+        }
+
+        // Execute the requested tools.
+        let mut tool_results = vec![];
+        let framing = ResultFraming::from_settings(&self.settings);
+
+        let mut remaining_tools = tool_uses.into_iter();
+        let mut cancelled = false;
+        let concurrency = ToolConcurrency::from_settings(&self.settings);
+        // Results of tools that already ran as part of a concurrent batch.
+        let mut prefetched: HashMap<String, ConcurrentResult> = HashMap::new();
+        let mut batch_cancelled = false;
+        // Where each write's file was backed up, keyed by tool use id.
+        let mut backup_notes: HashMap<String, String> = HashMap::new();
+
+        while let Some(tool) = remaining_tools.next() {
+            if !prefetched.contains_key(&tool.id) && concurrency.allows(&tool) {
+                let batch = std::iter::once(&tool)
+                    .chain(
+                        remaining_tools
+                            .as_slice()
+                            .iter()
+                            .take_while(|next| concurrency.allows(next)),
+                    )
+                    .filter(|t| !self.result_cache.contains(t))
+                    .collect::<Vec<_>>();
+                if batch.len() > 1 {
+                    for tool in &batch {
+                        let session_id = self.conversation_state.conversation_id().to_owned();
+                        if let Some(note) = self.file_backups.save(&self.ctx, &session_id, tool).await {
+                            backup_notes.insert(tool.id.clone(), note);
+                        }
+                    }
+                    self.heartbeats.set_in_flight_ids(
+                        std::iter::once(self.conversation_state.conversation_id().to_string())
+                            .chain(batch.iter().map(|t| t.id.clone()))
+                            .collect(),
+                    );
+                    self.heartbeats.beat(Component::ToolExecution, "concurrent tools start");
+                    let _paused = self.heartbeats.pause();
+                    tokio::select! {
+                        results = invoke_concurrently(&self.ctx, &batch, concurrency.limit()) => {
+                            prefetched.extend(batch.iter().map(|t| t.id.clone()).zip(results));
+                        },
+                        Some(_) = ctrl_c_stream.recv() => batch_cancelled = true,
+                    }
+                }
+            }
+
+            let key_args = tool.tool.key_args();
+            let target = key_args
+                .iter()
+                .find(|(key, _)| *key == "path")
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default();
+            let deferred = self.defers_description(&tool);
+            let collapsed = deferred && self.transcript.would_collapse();
+            let mut hidden_output = Vec::new();
+            if collapsed {
+                Self::queue_tool_description(&self.ctx, &mut hidden_output, &tool, Some("(trusted)")).await?;
+            } else if deferred {
+                self.print_tool_descriptions(&tool, true).await?;
+            }
+
+            self.heartbeats.set_in_flight_ids(vec![
+                self.conversation_state.conversation_id().to_string(),
+                tool.id.clone(),
+            ]);
+            self.heartbeats.beat(Component::ToolExecution, "tool invoke start");
+            let approval = if self.runs_without_approval(&tool) {
+                Approval::Trusted
+            } else {
+                Approval::UserApproved
+            };
+            let tool_start = std::time::Instant::now();
+            let mut tool_elapsed = None;
+            let mut cached = false;
+            let mut stale = None;
+            if !batch_cancelled && !prefetched.contains_key(&tool.id) {
+                // Writes to files that changed since they were read are refused, and not backed up.
+                stale = self.file_versions.check(&self.ctx, &tool).await.err();
+                let session_id = self.conversation_state.conversation_id().to_owned();
+                if stale.is_none() {
+                    if let Some(note) = self.file_backups.save(&self.ctx, &session_id, &tool).await {
+                        backup_notes.insert(tool.id.clone(), note);
+                    }
+                }
+            }
+            // Command output is shown as it arrives, which a spinner would draw over.
+            if matches!(tool.tool, Tool::ExecuteBash(_)) && self.spinner.is_some() {
+                drop(self.spinner.take());
+                queue!(
+                    self.output,
+                    terminal::Clear(terminal::ClearType::CurrentLine),
+                    cursor::MoveToColumn(0),
+                    cursor::Show
+                )?;
+            }
+            // Racing the invocation against Ctrl+C drops the tool's future on cancellation, which
+            // also kills any command it was running.
+            let mut invoke_result = if batch_cancelled {
+                None
+            } else if let Some((result, output, elapsed)) = prefetched.remove(&tool.id) {
+                tool_elapsed = Some(elapsed);
+                if collapsed {
+                    hidden_output.extend_from_slice(&output);
+                } else {
+                    self.output.write_all(&output)?;
+                }
+                Some(result)
+            } else if let Some(err) = stale {
+                Some(Err(err))
+            } else if let Some(output) = self.result_cache.get(&tool) {
+                cached = true;
+                Some(Ok(output))
+            } else if collapsed {
+                let _paused = self.heartbeats.pause();
+                tokio::select! {
+                    res = tool.tool.invoke(&self.ctx, &mut hidden_output) => Some(res),
+                    Some(_) = ctrl_c_stream.recv() => None,
+                }
+            } else {
+                let _paused = self.heartbeats.pause();
+                tokio::select! {
+                    res = tool.tool.invoke(&self.ctx, &mut self.output) => Some(res),
+                    Some(_) = ctrl_c_stream.recv() => None,
+                }
+            };
+
+            self.heartbeats.beat(Component::ToolExecution, "tool invoke end");
+            let tool_elapsed = tool_elapsed.unwrap_or_else(|| tool_start.elapsed());
+            // Runs even if the tool failed or was cancelled, since it may have changed something.
+            self.result_cache.invalidate_for(&self.ctx, &tool);
+            self.file_backups.record(&self.ctx, &tool).await;
+            if let (Some(Ok(output)), false) = (&invoke_result, cached) {
+                self.result_cache.insert(&self.ctx, &tool, output);
+                self.file_versions.record(&self.ctx, &tool).await;
+            }
+            if let (
+                Some(Ok(InvokeOutput {
+                    output: OutputKind::Text(text),
+                })),
+                Some(note),
+            ) = (&mut invoke_result, backup_notes.remove(&tool.id))
+            {
+                text.push_str(&note);
+                text.push('\n');
+            }
+
+            if self.interactive && self.spinner.is_some() {
+                queue!(
+                    self.output,
+                    terminal::Clear(terminal::ClearType::CurrentLine),
+                    cursor::MoveToColumn(0),
+                    cursor::Show
+                )?;
+            }
+
+            let Some(invoke_result) = invoke_result else {
+                if collapsed {
+                    self.print_collapsed_summary()?;
+                    self.output.write_all(&hidden_output)?;
+                }
+                execute!(
+                    self.output,
+                    style::Print("\n\n"),
+                    style::SetForegroundColor(Color::Yellow),
+                    style::SetAttribute(Attribute::Bold),
+                    style::Print(" ● Cancelled by user"),
+                    style::SetAttribute(Attribute::Reset),
+                    style::SetForegroundColor(Color::Reset),
+                    style::Print("\n\n"),
+                )?;
+                self.transcript.push_shown(ToolCallEvent {
+                    target,
+                    read_only: deferred,
+                    succeeded: false,
+                });
+                self.tool_use_telemetry_events
+                    .entry(tool.id.clone())
+                    .and_modify(|ev| ev.is_success = Some(false));
+                tool_results.push(cancelled_tool_use_result(framing, &tool));
+                self.tool_usage.record(&tool.name, false, tool_elapsed);
+                self.audit_tool_call(&tool, approval, AuditStatus::Cancelled, Some(tool_elapsed), None);
+                self.tool_cancelled_at = Some(Instant::now());
+                cancelled = true;
+                break;
+            };
+            if collapsed && invoke_result.is_err() {
+                // Errors are never collapsed, so show the call that was hidden.
+                self.print_collapsed_summary()?;
+                self.output.write_all(&hidden_output)?;
+            }
+            if !collapsed || invoke_result.is_err() {
+                execute!(self.output, style::Print("\n"))?;
+            }
+            self.tool_usage.record(&tool.name, invoke_result.is_ok(), tool_elapsed);
+            if self.audit_log.is_some() {
+                let (status, result) = match &invoke_result {
+                    Ok(result) => (AuditStatus::Success, match &result.output {
+                        OutputKind::Text(text) => serde_json::Value::String(text.clone()),
+                        OutputKind::Json(json) => json.clone(),
+                    }),
+                    Err(err) => (AuditStatus::Error, serde_json::Value::String(err.to_string())),
+                };
+                self.audit_tool_call(&tool, approval, status, Some(tool_elapsed), Some(result));
+            }
+
+            let mut tool_telemetry = self.tool_use_telemetry_events.entry(tool.id.clone());
+            tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_accepted = true);
+
+            let tool_time = format!("{}.{}", tool_elapsed.as_secs(), tool_elapsed.subsec_millis());
+            const CONTINUATION_LINE: &str = " ⋮ ";
+
+            match invoke_result {
+                Ok(result) => {
+                    debug!("tool result output: {:#?}", result);
+                    let event = ToolCallEvent {
+                        target,
+                        read_only: deferred,
+                        succeeded: true,
+                    };
+                    if collapsed {
+                        self.transcript.push_collapsed(event, &hidden_output);
+                    } else {
+                        execute!(
+                            self.output,
+                            style::Print(CONTINUATION_LINE),
+                            style::Print("\n"),
+                            style::SetForegroundColor(Color::Green),
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print(if cached {
+                                " ● Completed (cached)".to_string()
+                            } else {
+                                format!(" ● Completed in {}s", tool_time)
+                            }),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print("\n"),
+                        )?;
+                        self.transcript.push_shown(event);
+                    }
 
                     tool_telemetry.and_modify(|ev| ev.is_success = Some(true));
                     tool_results.push(ToolUseResult {
                         tool_use_id: tool.id,
-                        content: vec![result.into()],
+                        content: frame_result(
+                            framing,
+                            ResultEnvelope {
+                                tool_name: &tool.name,
+                                key_args,
+                                status: EnvelopeStatus::Success,
+                                content: vec![result.into()],
+                            },
+                            MAX_TOOL_RESPONSE_SIZE,
+                        ),
                         status: ToolResultStatus::Success,
                     });
                 },
@@ -2497,12 +3295,26 @@ impl ChatContext {
                     )?;
 
                     tool_telemetry.and_modify(|ev| ev.is_success = Some(false));
+                    self.transcript.push_shown(ToolCallEvent {
+                        target,
+                        read_only: deferred,
+                        succeeded: false,
+                    });
                     tool_results.push(ToolUseResult {
                         tool_use_id: tool.id,
-                        content: vec![ToolUseResultBlock::Text(format!(
-                            "An error occurred processing the tool: \n{}",
-                            &err
-                        ))],
+                        content: frame_result(
+                            framing,
+                            ResultEnvelope {
+                                tool_name: &tool.name,
+                                key_args,
+                                status: EnvelopeStatus::Error,
+                                content: vec![ToolUseResultBlock::Text(format!(
+                                    "An error occurred processing the tool: \n{}",
+                                    &err
+                                ))],
+                            },
+                            MAX_TOOL_RESPONSE_SIZE,
+                        ),
                         status: ToolResultStatus::Error,
                     });
                     if let ToolUseStatus::Idle = self.tool_use_status {
@@ -2516,14 +3328,19 @@ impl ChatContext {
             }
         }
 
+        // Tools queued after a cancelled one are never run.
+        if cancelled {
+            tool_results.extend(remaining_tools.map(|tool| cancelled_tool_use_result(framing, &tool)));
+        }
+
         self.conversation_state.add_tool_results(tool_results);
 
         self.send_tool_use_telemetry().await;
-        return Ok(ChatState::HandleResponseStream(
-            self.client
-                .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                .await?,
-        ));
+        let conversation_state = self.conversation_state.as_sendable_conversation_state(false).await;
+        tokio::select! {
+            res = self.client.send_message(conversation_state) => Ok(ChatState::HandleResponseStream(res?)),
+            Some(_) = ctrl_c_stream.recv() => Err(ChatError::Interrupted { tool_uses: None }),
+        }
     }
 
     async fn handle_response(&mut self, response: SendMessageOutput) -> Result<ChatState, ChatError> {
@@ -2536,19 +3353,31 @@ impl ChatContext {
 
         let mut tool_uses = Vec::new();
         let mut tool_name_being_recvd: Option<String> = None;
+        // Set while receiving a tool use that will be collapsed, so nothing is printed for it.
+        let mut quiet = false;
 
+        self.heartbeats
+            .set_in_flight_ids(vec![self.conversation_state.conversation_id().to_string()]);
         loop {
-            match parser.recv().await {
+            let event = parser.recv().await;
+            self.heartbeats.beat(Component::Renderer, "response event");
+            match event {
                 Ok(msg_event) => {
                     trace!("Consumed: {:?}", msg_event);
                     match msg_event {
                         parser::ResponseEvent::ToolUseStart { name } => {
+                            quiet = buf[offset..].trim().is_empty()
+                                && self.may_defer_description(&name)
+                                && self.transcript.would_collapse();
                             // We need to flush the buffer here, otherwise text will not be
                             // printed while we are receiving tool use events.
-                            buf.push('\n');
+                            if !quiet {
+                                buf.push('\n');
+                            }
                             tool_name_being_recvd = Some(name);
                         },
                         parser::ResponseEvent::AssistantText(text) => {
+                            quiet &= text.trim().is_empty();
                             buf.push_str(&text);
                         },
                         parser::ResponseEvent::ToolUse(tool_use) => {
@@ -2588,7 +3417,7 @@ impl ChatContext {
                                 "Encountered a stream timeout after waiting for {}s",
                                 duration.as_secs()
                             );
-                            if self.interactive {
+                            if self.animate {
                                 execute!(self.output, cursor::Hide)?;
                                 self.spinner =
                                     Some(Spinner::new(Spinners::Dots, "Dividing up the work...".to_string()));
@@ -2643,10 +3472,12 @@ impl ChatContext {
                                     )?;
                                 }
                                 execute!(self.output, style::Print("\n\n"), style::SetAttribute(Attribute::Reset))?;
-                                self.spinner = Some(Spinner::new(
-                                    Spinners::Dots,
-                                    "Trying to divide up the work...".to_string(),
-                                ));
+                                if self.animate {
+                                    self.spinner = Some(Spinner::new(
+                                        Spinners::Dots,
+                                        "Trying to divide up the work...".to_string(),
+                                    ));
+                                }
                             }
 
                             self.conversation_state.push_assistant_message(*message);
@@ -2673,7 +3504,7 @@ impl ChatContext {
             // Fix for the markdown parser copied over from q chat:
             // this is a hack since otherwise the parser might report Incomplete with useful data
             // still left in the buffer. I'm not sure how this is intended to be handled.
-            if ended {
+            if ended && !quiet {
                 buf.push('\n');
             }
 
@@ -2687,6 +3518,10 @@ impl ChatContext {
                 )?;
             }
 
+            if !buf[offset..].trim().is_empty() {
+                self.print_collapsed_summary()?;
+            }
+
             // Print the response for normal cases
             loop {
                 let input = Partial::new(&buf[offset..]);
@@ -2710,14 +3545,18 @@ impl ChatContext {
 
             // Set spinner after showing all of the assistant text content so far.
             if let (Some(name), true) = (&tool_name_being_recvd, self.interactive) {
-                queue!(
-                    self.output,
-                    style::SetForegroundColor(Color::Blue),
-                    style::Print(format!("\n{name}: ")),
-                    style::SetForegroundColor(Color::Reset),
-                    cursor::Hide,
-                )?;
-                self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
+                if !quiet {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::Blue),
+                        style::Print(format!("\n{name}: ")),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                }
+                if self.animate {
+                    execute!(self.output, cursor::Hide)?;
+                    self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
+                }
             }
 
             if ended {
@@ -2737,7 +3576,9 @@ impl ChatContext {
 
                 if self.interactive {
                     queue!(self.output, style::ResetColor, style::SetAttribute(Attribute::Reset))?;
-                    execute!(self.output, style::Print("\n"))?;
+                    if !quiet {
+                        execute!(self.output, style::Print("\n"))?;
+                    }
 
                     for (i, citation) in &state.citations {
                         queue!(
@@ -2759,6 +3600,8 @@ impl ChatContext {
         if !tool_uses.is_empty() {
             Ok(ChatState::ValidateTools(tool_uses))
         } else {
+            self.print_collapsed_summary()?;
+            self.transcript.end_turn();
             Ok(ChatState::PromptUser {
                 tool_uses: None,
                 pending_tool_index: None,
@@ -2780,12 +3623,37 @@ impl ChatContext {
                 .set_tool_use_id(tool_use_id.clone())
                 .set_tool_name(tool_use.name.clone())
                 .utterance_id(self.conversation_state.message_id().map(|s| s.to_string()));
-            match Tool::try_from(tool_use) {
+            let schema_errors = self
+                .tool_schemas
+                .get(&tool_use.name)
+                .map(|schema| schema.validate_args(&tool_use.args))
+                .unwrap_or_default();
+            let tool_use_args = tool_use.args.clone();
+            let tool = if schema_errors.is_empty() {
+                Tool::from_tool_use(tool_use, &unavailable_tools(&self.ctx, &self.settings))
+            } else {
+                Err(ToolUseResult {
+                    tool_use_id: tool_use_id.clone(),
+                    content: vec![ToolUseResultBlock::Text(format!(
+                        "Failed to validate tool parameters for {tool_use_name} against its input schema:\n- {}",
+                        schema_errors.join("\n- ")
+                    ))],
+                    status: ToolResultStatus::Error,
+                })
+            };
+            match tool {
                 Ok(mut tool) => {
                     // Apply non-Q-generated context to tools
                     self.contextualize_tool(&mut tool);
 
-                    match tool.validate(&self.ctx).await {
+                    let validated = match Sandbox::load(&self.ctx).check(&self.ctx, &tool) {
+                        Ok(()) => tool
+                            .validate(&self.ctx)
+                            .await
+                            .map_err(|err| eyre!("Failed to validate tool parameters: {err}")),
+                        Err(err) => Err(err),
+                    };
+                    match validated {
                         Ok(()) => {
                             tool_telemetry.is_valid = Some(true);
                             queued_tools.push(QueuedTool {
@@ -2793,15 +3661,14 @@ impl ChatContext {
                                 name: tool_use_name,
                                 tool,
                                 accepted: false,
+                                args: tool_use_args,
                             });
                         },
                         Err(err) => {
                             tool_telemetry.is_valid = Some(false);
                             tool_results.push(ToolUseResult {
                                 tool_use_id: tool_use_id.clone(),
-                                content: vec![ToolUseResultBlock::Text(format!(
-                                    "Failed to validate tool parameters: {err}"
-                                ))],
+                                content: vec![ToolUseResultBlock::Text(err.to_string())],
                                 status: ToolResultStatus::Error,
                             });
                         },
@@ -2869,7 +3736,6 @@ impl ChatContext {
     // output from Amazon Q.
     // TODO: Is there a better way?
     fn contextualize_tool(&self, tool: &mut Tool) {
-        #[allow(clippy::single_match)]
         match tool {
             Tool::GhIssue(gh_issue) => {
                 gh_issue.set_context(GhIssueContext {
@@ -2883,39 +3749,192 @@ impl ChatContext {
                     interactive: self.interactive,
                 });
             },
+            Tool::FsWrite(fs_write) if self.plan_mode => fs_write.set_dry_run(),
+            Tool::ExecuteBash(execute_bash) => execute_bash.set_terminal(self.output.is_terminal()),
             _ => (),
         };
     }
 
     async fn print_tool_descriptions(&mut self, tool_use: &QueuedTool, trusted: bool) -> Result<(), ChatError> {
+        let label = trusted.then(|| self.approval_label(tool_use));
+        Self::queue_tool_description(&self.ctx, &mut self.output, tool_use, label).await?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    async fn queue_tool_description(
+        ctx: &Context,
+        output: &mut impl Write,
+        tool_use: &QueuedTool,
+        label: Option<&str>,
+    ) -> Result<(), ChatError> {
         const TOOL_BULLET: &str = " ● ";
         const CONTINUATION_LINE: &str = " ⋮ ";
 
         queue!(
-            self.output,
+            output,
             style::SetForegroundColor(Color::Magenta),
             style::Print(format!(
                 "🛠️  Using tool: {} {}\n",
                 tool_use.tool.display_name(),
-                if trusted { "(trusted)".dark_green() } else { "".reset() }
+                label.unwrap_or_default().dark_green()
             )),
             style::SetForegroundColor(Color::Reset)
         )?;
-        queue!(self.output, style::Print(CONTINUATION_LINE))?;
-        queue!(self.output, style::Print("\n"))?;
-        queue!(self.output, style::Print(TOOL_BULLET))?;
+        queue!(output, style::Print(CONTINUATION_LINE))?;
+        queue!(output, style::Print("\n"))?;
+        queue!(output, style::Print(TOOL_BULLET))?;
 
-        self.output.flush()?;
+        output.flush()?;
 
         tool_use
             .tool
-            .queue_description(&self.ctx, &mut self.output)
+            .queue_description(ctx, output)
             .await
             .map_err(|e| ChatError::Custom(format!("failed to print tool, `{}`: {}", tool_use.name, e).into()))?;
 
         Ok(())
     }
 
+    /// Why `tool_use` runs without approval, for showing next to its name.
+    fn approval_label(&self, tool_use: &QueuedTool) -> &'static str {
+        match &tool_use.tool {
+            Tool::ExecuteBash(_) if !self.tool_permissions.has(&tool_use.name) => {
+                "(auto-approved: allowlisted command)"
+            },
+            _ => "(trusted)",
+        }
+    }
+
+    /// Whether `tool` is described when it runs rather than up front, so that long runs of such
+    /// calls can be collapsed in the transcript. Calls that must be approved are never deferred.
+    fn defers_description(&self, tool: &QueuedTool) -> bool {
+        self.may_defer_description(&tool.name) && !tool.tool.requires_explicit_acceptance(&self.ctx)
+    }
+
+    /// Whether calls to `tool_name` are deferred by [Self::defers_description], unless the call
+    /// itself must be approved.
+    fn may_defer_description(&self, tool_name: &str) -> bool {
+        self.transcript.enabled()
+            && tool_name == "fs_read"
+            && (!self.tool_permissions.has(tool_name) || self.tool_permissions.is_trusted(tool_name))
+    }
+
+    /// Prints the summary line for the current run of collapsed tool calls, if there is one.
+    fn print_collapsed_summary(&mut self) -> Result<(), std::io::Error> {
+        if let Some(summary) = self.transcript.take_summary() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(" ⋮ {summary}, run /expand to show\n\n")),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Shows a bounded preview of a multi-line paste and asks whether to send, edit or discard it.
+    /// Returns the text to send, or [None] if it was discarded.
+    fn confirm_paste(&mut self, pasted: &str) -> Result<Option<String>, std::io::Error> {
+        let cancel = KeyBindings::load(&self.settings).key(Action::Cancel);
+        let mut text = sanitize_paste(pasted);
+        self.print_paste_preview(&text)?;
+        loop {
+            let too_large = text.len() > MAX_USER_MESSAGE_SIZE;
+            let hint = if too_large {
+                format!("Press e to edit, {cancel} to discard: ")
+            } else {
+                format!("Press Enter to send, e to edit, {cancel} to discard: ")
+            };
+            match self.input_source.read_line(Some(&hint)).unwrap_or_default() {
+                Some(answer) if answer.trim().is_empty() && !too_large => return Ok(Some(text)),
+                Some(answer) if answer.trim().eq_ignore_ascii_case("e") => {
+                    match Self::open_editor(Some(text.clone())) {
+                        Ok(content) if content.trim().is_empty() => break,
+                        Ok(content) => {
+                            text = content;
+                            if text.len() <= MAX_USER_MESSAGE_SIZE {
+                                return Ok(Some(text));
+                            }
+                            self.print_paste_preview(&text)?;
+                        },
+                        Err(e) => {
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(Color::Red),
+                                style::Print(format!("\nError opening editor: {}\n\n", e)),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
+                        },
+                    }
+                },
+                Some(_) => (),
+                None => break,
+            }
+        }
+
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("\nDiscarded the pasted text.\n\n"),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        Ok(None)
+    }
+
+    /// Prints the first few lines of pasted text, masking anything that looks like a secret, along
+    /// with its size and whether it is too large to send.
+    fn print_paste_preview(&mut self, text: &str) -> Result<(), std::io::Error> {
+        let width = self.terminal_width().saturating_sub(2);
+        let line_count = text.lines().count();
+        execute!(self.output, style::SetForegroundColor(Color::DarkGrey))?;
+        for line in text.lines().take(PASTE_PREVIEW_LINES) {
+            let line = redact_secrets(line);
+            execute!(
+                self.output,
+                style::Print(format!("│ {}\n", truncate_safe(&line, width)))
+            )?;
+        }
+        if line_count > PASTE_PREVIEW_LINES {
+            execute!(
+                self.output,
+                style::Print(format!("│ … {} more lines\n", line_count - PASTE_PREVIEW_LINES))
+            )?;
+        }
+        execute!(
+            self.output,
+            style::Print(format!(
+                "Pasted {line_count} lines ({:.1} KB)\n",
+                text.len() as f64 / 1024.0
+            )),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+
+        if contains_secret(text) {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(
+                    "The pasted text looks like it contains a secret. It is masked above but would be sent as is.\n"
+                ),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+        }
+        if text.len() > MAX_USER_MESSAGE_SIZE {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Red),
+                style::Print(format!(
+                    "Pasted text is too large ({} KB), the limit is {} KB.\n",
+                    text.len() / 1024,
+                    MAX_USER_MESSAGE_SIZE / 1024
+                )),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+        }
+        Ok(())
+    }
+
     /// Helper function to read user input with a prompt and Ctrl+C handling
     fn read_user_input(&mut self, prompt: &str, exit_on_single_ctrl_c: bool) -> Option<String> {
         let mut ctrl_c = false;
@@ -2975,6 +3994,55 @@ impl ChatContext {
         })
     }
 
+    /// Warns if the tool specs and context files take up most of the context window before anything
+    /// has been sent, since the first request would otherwise fail with a confusing backend error.
+    async fn check_initial_context_size(&mut self) -> Result<(), ChatError> {
+        let data = self
+            .conversation_state
+            .backend_conversation_state(false, true)
+            .await
+            .calculate_conversation_size();
+        let total: TokenCount = data.char_count().into();
+        let usage = total.value() as f64 / CONTEXT_WINDOW_SIZE as f64;
+        if usage < INITIAL_CONTEXT_WARN_THRESHOLD {
+            return Ok(());
+        }
+
+        let tool_spec_tokens: TokenCount = data.tool_specs.into();
+        let context_tokens: TokenCount = data.context_messages.into();
+        warn!(%total, %tool_spec_tokens, %context_tokens, "Initial context uses {:.0}% of the context window", usage * 100.0);
+        if !self.interactive {
+            return Ok(());
+        }
+
+        let exceeds_window = usage >= 1.0;
+        execute!(
+            self.output,
+            style::SetForegroundColor(if exceeds_window { Color::Red } else { Color::Yellow }),
+            style::SetAttribute(Attribute::Bold),
+            style::Print(format!(
+                "\n⚠️ Tool specs and context files use ~{} of {}k tokens ({:.0}%) before the conversation has started.\n",
+                total,
+                CONTEXT_WINDOW_SIZE / 1000,
+                usage * 100.0
+            )),
+            style::SetAttribute(Attribute::Reset),
+            style::Print(format!(
+                "  Tool specs:    ~{} tokens\n  Context files: ~{} tokens\n",
+                tool_spec_tokens, context_tokens
+            )),
+            style::Print(if exceeds_window {
+                "Requests will fail until the context is reduced. "
+            } else {
+                ""
+            }),
+            style::Print("Use /context show to see tokens per context file and /context rm to remove large ones.\n\n"),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+
+        Ok(())
+    }
+
     /// Display character limit warnings based on current conversation size
     async fn display_char_warnings(&mut self) -> Result<(), std::io::Error> {
         let warning_level = self.conversation_state.get_token_warning_level().await;
@@ -3060,6 +4128,23 @@ impl From<ToolUseEventBuilder> for fig_telemetry::EventType {
     }
 }
 
+/// The command and why it is destructive, if the tool at `index` runs a destructive command.
+fn destructive_command(tool_uses: &[QueuedTool], index: usize) -> Option<(String, String)> {
+    match &tool_uses.get(index)?.tool {
+        Tool::ExecuteBash(execute_bash) => Some((execute_bash.command.clone(), execute_bash.destructive_reason()?)),
+        _ => None,
+    }
+}
+
+/// Normalizes line endings and drops control characters, e.g. an embedded ^D, from pasted text.
+fn sanitize_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect()
+}
+
 /// Testing helper
 fn split_tool_use_event(value: &Map<String, serde_json::Value>) -> Vec<ChatResponseStream> {
     let tool_use_id = value.get("tool_use_id").unwrap().as_str().unwrap().to_string();
@@ -3119,11 +4204,20 @@ fn create_stream(model_responses: serde_json::Value) -> StreamingClient {
 
 /// Returns all tools supported by Q chat.
 pub fn load_tools() -> Result<HashMap<String, ToolSpec>> {
-    Ok(serde_json::from_str(include_str!("tools/tool_index.json"))?)
+    let mut tools = serde_json::from_str(include_str!("tools/tool_index.json"))?;
+    apply_unavailable_tools(&mut tools, &unavailable_tools(&Context::new(), &Settings::new()));
+    tools::execute_bash::describe_default_shell(&mut tools);
+    Ok(tools)
 }
 
 #[cfg(test)]
 mod tests {
+    use input_source::{
+        PASTE_END,
+        PASTE_START,
+    };
+    use keybindings::BINDINGS;
+
     use super::*;
 
     #[tokio::test]
@@ -3175,6 +4269,206 @@ mod tests {
         assert_eq!(ctx.fs().read_to_string("/file.txt").await.unwrap(), "Hello, world!\n");
     }
 
+    /// Output written to a [SharedWriter], for asserting on what the chat printed.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    async fn chat_with_input(ctx: &Arc<Context>, lines: Vec<String>, responses: serde_json::Value) -> ChatContext {
+        chat_with_output(ctx, SharedWriter::stdout(), lines, responses).await
+    }
+
+    async fn chat_with_output(
+        ctx: &Arc<Context>,
+        output: SharedWriter,
+        lines: Vec<String>,
+        responses: serde_json::Value,
+    ) -> ChatContext {
+        let mut chat = ChatContext::new(
+            Arc::clone(ctx),
+            Settings::new_fake(),
+            State::new_fake(),
+            output,
+            None,
+            InputSource::new_mock(lines),
+            true,
+            create_stream(responses),
+            || Some(80),
+            None,
+            load_tools().expect("Tools failed to load."),
+            ToolPermissions::new(0),
+        )
+        .await
+        .unwrap();
+        chat.try_chat().await.unwrap();
+        chat
+    }
+
+    fn sent_prompts(chat: &ChatContext) -> Vec<String> {
+        chat.conversation_state
+            .history()
+            .iter()
+            .filter_map(|(user, _)| user.prompt().map(str::to_string))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_every_prompt_registers_its_actions() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let bindings = KeyBindings::default();
+        let mut input = InputSource::new(&bindings).unwrap();
+        input.put_skim_command_selector(
+            Arc::new(ContextManager::new(Arc::clone(&ctx)).await.unwrap()),
+            bindings.key(Action::FuzzySearch),
+        );
+
+        // Both prompts read from the same line editor, so its keys are active in either.
+        let registered = |context| match context {
+            BindingContext::Prompt => [input.bound_actions(), PROMPT_ANSWERS].concat(),
+            BindingContext::ToolApproval => [input.bound_actions(), APPROVAL_ANSWERS].concat(),
+            BindingContext::All => unreachable!(),
+        };
+        for context in [BindingContext::Prompt, BindingContext::ToolApproval] {
+            let registered = registered(context);
+            let help = bindings.help_text(context);
+            for binding in BINDINGS.iter().filter(|b| b.context.overlaps(context)) {
+                assert!(
+                    registered.contains(&binding.action),
+                    "{:?} isn't registered in {context:?}",
+                    binding.action
+                );
+                assert!(help.contains(&bindings.key(binding.action).to_string()), "{help}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_show_shortcuts_at_chat_prompt() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let output = Captured::default();
+        let chat = chat_with_output(
+            &ctx,
+            SharedWriter::new(output.clone()),
+            vec!["?".to_string(), "exit".to_string()],
+            serde_json::json!([]),
+        )
+        .await;
+        assert!(sent_prompts(&chat).is_empty());
+        let output = output.text();
+        assert!(output.contains("Show these shortcuts"), "{output}");
+        assert!(output.contains("Fuzzy search commands"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_paste_of_commands_is_sent_as_message() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let pasted = "/quit\n/clear\n!rm -rf /";
+        let chat = chat_with_input(
+            &ctx,
+            vec![
+                format!("{PASTE_START}{pasted}{PASTE_END}"),
+                String::new(),
+                "what was that?".to_string(),
+                "exit".to_string(),
+            ],
+            serde_json::json!([["Those are commands."], ["A paste."]]),
+        )
+        .await;
+
+        let prompts = sent_prompts(&chat);
+        assert_eq!(prompts.len(), 2, "{prompts:?}");
+        assert!(prompts[0].contains(pasted), "{prompts:?}");
+        assert!(prompts[1].contains("what was that?"), "{prompts:?}");
+    }
+
+    #[tokio::test]
+    async fn test_paste_with_eof_is_sent_without_it() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let chat = chat_with_input(
+            &ctx,
+            vec![
+                format!("{PASTE_START}first\x04\r\nsecond{PASTE_END}"),
+                String::new(),
+                "still here".to_string(),
+                "exit".to_string(),
+            ],
+            serde_json::json!([["Got it."], ["Yes."]]),
+        )
+        .await;
+
+        let prompts = sent_prompts(&chat);
+        assert_eq!(prompts.len(), 2, "{prompts:?}");
+        assert!(prompts[0].contains("first\nsecond"), "{prompts:?}");
+        assert!(!prompts[0].contains('\x04'), "{prompts:?}");
+    }
+
+    #[tokio::test]
+    async fn test_discarded_paste_is_not_sent() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let chat = chat_with_input(
+            &ctx,
+            vec![
+                format!("{PASTE_START}a\nb{PASTE_END}"),
+                // Anything but Enter or e asks again, and running out of input discards like Esc.
+                "x".to_string(),
+            ],
+            serde_json::json!([]),
+        )
+        .await;
+
+        assert!(sent_prompts(&chat).is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_paste() {
+        assert_eq!(sanitize_paste("a\r\nb\rc\x04\td\x1b"), "a\nb\nc\td");
+    }
+
+    #[tokio::test]
+    async fn test_escaping_reads_are_not_deferred() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), ctx.fs().chroot_path("/outside")).unwrap();
+        ctx.fs().write("/inside.txt", "inside").await.unwrap();
+
+        let chat = ChatContext::new(
+            Arc::clone(&ctx),
+            Settings::from_slice(&[(transcript::COLLAPSE_THRESHOLD_SETTING, 2.into())]),
+            State::new_fake(),
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![]),
+            true,
+            create_stream(serde_json::json!([])),
+            || Some(80),
+            None,
+            load_tools().expect("Tools failed to load."),
+            ToolPermissions::new(0),
+        )
+        .await
+        .unwrap();
+        let read = |path: &str| tools::queued("1", "fs_read", serde_json::json!({ "mode": "Line", "path": path }));
+        assert!(chat.defers_description(&read("/inside.txt")));
+        assert!(!chat.defers_description(&read("/outside/secret.txt")));
+    }
+
     #[tokio::test]
     async fn test_flow_tool_permissions() {
         let _ = tracing_subscriber::fmt::try_init();
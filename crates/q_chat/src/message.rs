@@ -27,6 +27,9 @@ use super::tools::{
 };
 use super::util::truncate_safe;
 
+/// Result content sent for each tool use the user declined or interrupted.
+pub const TOOL_USE_CANCELLED_MESSAGE: &str = "Tool use was cancelled by the user";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserMessage {
     pub additional_context: String,
@@ -62,20 +65,25 @@ impl UserMessage {
     }
 
     pub fn new_cancelled_tool_uses<'a>(prompt: Option<String>, tool_use_ids: impl Iterator<Item = &'a str>) -> Self {
+        Self::new_cancelled_tool_use_results(
+            prompt,
+            tool_use_ids
+                .map(|id| ToolUseResult {
+                    tool_use_id: id.to_string(),
+                    content: vec![ToolUseResultBlock::Text(TOOL_USE_CANCELLED_MESSAGE.to_string())],
+                    status: ToolResultStatus::Error,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn new_cancelled_tool_use_results(prompt: Option<String>, tool_use_results: Vec<ToolUseResult>) -> Self {
         Self {
             additional_context: String::new(),
             env_context: UserEnvContext::generate_new(),
             content: UserMessageContent::CancelledToolUses {
                 prompt,
-                tool_use_results: tool_use_ids
-                    .map(|id| ToolUseResult {
-                        tool_use_id: id.to_string(),
-                        content: vec![ToolUseResultBlock::Text(
-                            "Tool use was cancelled by the user".to_string(),
-                        )],
-                        status: ToolResultStatus::Error,
-                    })
-                    .collect(),
+                tool_use_results,
             },
         }
     }
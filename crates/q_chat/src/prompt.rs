@@ -1,4 +1,16 @@
 use std::borrow::Cow;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
 
 use crossterm::style::Stylize;
 use eyre::Result;
@@ -22,19 +34,28 @@ use rustyline::{
     Cmd,
     Completer,
     CompletionType,
+    ConditionalEventHandler,
     Config,
     Context,
     EditMode,
     Editor,
+    Event,
+    EventContext,
     EventHandler,
     Helper,
     Hinter,
     KeyCode,
     KeyEvent,
     Modifiers,
+    RepeatCount,
 };
 use winnow::stream::AsChar;
 
+use super::keybindings::{
+    Action,
+    KeyBindings,
+};
+
 pub const COMMANDS: &[&str] = &[
     "/clear",
     "/help",
@@ -68,6 +89,14 @@ pub const COMMANDS: &[&str] = &[
     "/compact help",
     "/compact --summary",
     "/usage",
+    "/expand",
+    "/cache",
+    "/cache clear",
+    "/undo",
+    "/undo-file",
+    "/plan",
+    "/plan on",
+    "/plan off",
 ];
 
 pub fn generate_prompt(current_profile: Option<&str>, warning: bool) -> String {
@@ -196,6 +225,62 @@ pub struct ChatHelper {
     #[rustyline(Hinter)]
     hinter: (),
     validator: MultiLineValidator,
+    /// Set by [PasteDetector] when the line being edited received a bracketed paste.
+    pasted: Arc<AtomicBool>,
+}
+
+impl ChatHelper {
+    /// Returns whether text was pasted since the last call, resetting the flag.
+    pub fn take_pasted(&self) -> bool {
+        self.pasted.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Records that the terminal started a bracketed paste. Returning [None] lets rustyline insert the
+/// pasted text as usual, newlines included, instead of submitting it line by line.
+struct PasteDetector(Arc<AtomicBool>);
+
+impl ConditionalEventHandler for PasteDetector {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, _ctx: &EventContext<'_>) -> Option<Cmd> {
+        self.0.store(true, Ordering::Relaxed);
+        None
+    }
+}
+
+/// Keys arriving closer together than this came from a paste rather than from typing.
+const RAPID_KEY_GAP: Duration = Duration::from_millis(8);
+
+/// Detects pastes on terminals without bracketed paste, where pasted text arrives as a burst of
+/// keystrokes. An Enter arriving right after another key inserts a newline instead of submitting,
+/// so the pasted lines end up in a single message.
+struct RapidInputDetector {
+    last_key: Mutex<Option<Instant>>,
+    pasted: Arc<AtomicBool>,
+}
+
+impl RapidInputDetector {
+    /// Records a key arriving at `now`, returning whether it is an Enter that belongs to a paste.
+    fn is_pasted_enter(&self, is_enter: bool, line_is_empty: bool, now: Instant) -> bool {
+        let Ok(mut last_key) = self.last_key.lock() else {
+            return false;
+        };
+        let rapid = last_key
+            .replace(now)
+            .is_some_and(|last| now.duration_since(last) < RAPID_KEY_GAP);
+        if is_enter && rapid && !line_is_empty {
+            self.pasted.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+}
+
+impl ConditionalEventHandler for RapidInputDetector {
+    fn handle(&self, evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        let is_enter = evt.get(0) == Some(&KeyEvent(KeyCode::Enter, Modifiers::NONE));
+        self.is_pasted_enter(is_enter, ctx.line().is_empty(), Instant::now())
+            .then(|| Cmd::Insert(1, "\n".to_string()))
+    }
 }
 
 impl Validator for ChatHelper {
@@ -218,37 +303,58 @@ impl Highlighter for ChatHelper {
     }
 }
 
-pub fn rl() -> Result<Editor<ChatHelper, DefaultHistory>> {
+/// Creates the line editor for the chat, along with the actions whose keys it binds.
+pub fn rl(bindings: &KeyBindings) -> Result<(Editor<ChatHelper, DefaultHistory>, Vec<Action>)> {
     let edit_mode = match fig_settings::settings::get_string_opt("chat.editMode").as_deref() {
         Some("vi" | "vim") => EditMode::Vi,
         _ => EditMode::Emacs,
     };
     let config = Config::builder()
+        .bracketed_paste(true)
         .history_ignore_space(true)
         .completion_type(CompletionType::List)
         .edit_mode(edit_mode)
         .build();
+    let pasted = Arc::new(AtomicBool::new(false));
     let h = ChatHelper {
         completer: ChatCompleter::new(),
         hinter: (),
         validator: MultiLineValidator,
+        pasted: Arc::clone(&pasted),
     };
     let mut rl = Editor::with_config(config)?;
     rl.set_helper(Some(h));
 
-    // Add custom keybinding for Alt+Enter to insert a newline
     rl.bind_sequence(
-        KeyEvent(KeyCode::Enter, Modifiers::ALT),
-        EventHandler::Simple(Cmd::Insert(1, "\n".to_string())),
+        KeyEvent(KeyCode::BracketedPasteStart, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(PasteDetector(Arc::clone(&pasted)))),
     );
-
-    // Add custom keybinding for Ctrl+J to insert a newline
     rl.bind_sequence(
-        KeyEvent(KeyCode::Char('j'), Modifiers::CTRL),
-        EventHandler::Simple(Cmd::Insert(1, "\n".to_string())),
+        Event::Any,
+        EventHandler::Conditional(Box::new(RapidInputDetector {
+            last_key: Mutex::new(None),
+            pasted,
+        })),
     );
 
-    Ok(rl)
+    // Add custom keybindings to insert a newline, Ctrl+J and Alt+Enter by default
+    let mut bound = Vec::new();
+    for action in [Action::InsertNewline, Action::InsertNewlineAlt] {
+        rl.bind_sequence(
+            bindings.key(action).to_key_event(),
+            EventHandler::Simple(Cmd::Insert(1, "\n".to_string())),
+        );
+        bound.push(action);
+    }
+
+    // Cancelling is handled like Ctrl+C. Esc is left alone in vi mode, where it leaves insert mode.
+    let cancel = bindings.key(Action::Cancel).to_key_event();
+    if !(edit_mode == EditMode::Vi && cancel == KeyEvent(KeyCode::Esc, Modifiers::NONE)) {
+        rl.bind_sequence(cancel, EventHandler::Simple(Cmd::Interrupt));
+        bound.push(Action::Cancel);
+    }
+
+    Ok((rl, bound))
 }
 
 #[cfg(test)]
@@ -275,6 +381,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rapid_input_detector() {
+        let pasted = Arc::new(AtomicBool::new(false));
+        let detector = RapidInputDetector {
+            last_key: Mutex::new(None),
+            pasted: Arc::clone(&pasted),
+        };
+        let start = Instant::now();
+        let typed = |ms| start + Duration::from_millis(ms);
+
+        // Typing, then pressing Enter, submits.
+        assert!(!detector.is_pasted_enter(false, true, typed(0)));
+        assert!(!detector.is_pasted_enter(true, false, typed(200)));
+        assert!(!pasted.load(Ordering::Relaxed));
+
+        // An Enter arriving in the same burst as the preceding text is part of a paste.
+        assert!(!detector.is_pasted_enter(false, true, typed(1000)));
+        assert!(detector.is_pasted_enter(true, false, typed(1001)));
+        assert!(pasted.load(Ordering::Relaxed));
+
+        // Enter on an empty line always submits.
+        assert!(!detector.is_pasted_enter(true, true, typed(1002)));
+    }
+
     #[test]
     fn test_chat_completer_command_completion() {
         let completer = ChatCompleter::new();
@@ -0,0 +1,327 @@
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+use std::path::PathBuf;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use fig_os_shim::Context;
+use fig_settings::Settings;
+
+use super::tools::fs_read::has_glob_chars;
+use super::tools::{
+    InvokeOutput,
+    OutputKind,
+    QueuedTool,
+    Tool,
+    sanitize_path_tool_arg,
+};
+
+/// Setting enabling the session cache of read-only tool results. Off by default.
+pub const RESULT_CACHE_ENABLED_SETTING: &str = "chat.toolResultCache.enabled";
+/// Setting holding how many seconds a cached result stays valid.
+pub const RESULT_CACHE_TTL_SETTING: &str = "chat.toolResultCache.ttlSeconds";
+/// Setting holding the total size in bytes of cached results.
+pub const RESULT_CACHE_MAX_BYTES_SETTING: &str = "chat.toolResultCache.maxBytes";
+
+const DEFAULT_TTL_SECS: i64 = 300;
+const DEFAULT_MAX_BYTES: i64 = 5 * 1024 * 1024;
+
+#[derive(Debug)]
+struct CacheEntry {
+    output: OutputKind,
+    /// Path the tool read, used to invalidate the entry when it is written to.
+    path: PathBuf,
+    inserted: Instant,
+    size: usize,
+}
+
+/// Caches the results of idempotent read tools for the rest of the session, so that re-reading an
+/// unchanged file doesn't invoke the tool again.
+#[derive(Debug)]
+pub struct ResultCache {
+    enabled: bool,
+    ttl: Duration,
+    max_bytes: usize,
+    entries: HashMap<String, CacheEntry>,
+    /// Keys from oldest to newest, for eviction.
+    order: VecDeque<String>,
+    total_bytes: usize,
+}
+
+impl Default for ResultCache {
+    fn default() -> Self {
+        Self::new(
+            false,
+            Duration::from_secs(DEFAULT_TTL_SECS as u64),
+            DEFAULT_MAX_BYTES as usize,
+        )
+    }
+}
+
+impl ResultCache {
+    pub fn new(enabled: bool, ttl: Duration, max_bytes: usize) -> Self {
+        Self {
+            enabled,
+            ttl,
+            max_bytes,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            total_bytes: 0,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::new(
+            settings.get_bool_or(RESULT_CACHE_ENABLED_SETTING, false),
+            Duration::from_secs(settings.get_int_or(RESULT_CACHE_TTL_SETTING, DEFAULT_TTL_SECS).max(0) as u64),
+            settings
+                .get_int_or(RESULT_CACHE_MAX_BYTES_SETTING, DEFAULT_MAX_BYTES)
+                .max(0) as usize,
+        )
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Number of cached results and their total size in bytes.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.entries.len(), self.total_bytes)
+    }
+
+    /// Whether a fresh result is cached for `tool`.
+    pub fn contains(&self, tool: &QueuedTool) -> bool {
+        cache_key(tool)
+            .and_then(|key| self.entries.get(&key))
+            .is_some_and(|entry| entry.inserted.elapsed() < self.ttl)
+    }
+
+    /// Returns a copy of the cached result for `tool`, if there is a fresh one.
+    pub fn get(&mut self, tool: &QueuedTool) -> Option<InvokeOutput> {
+        if !self.enabled {
+            return None;
+        }
+        let key = cache_key(tool)?;
+        let entry = self.entries.get(&key)?;
+        if entry.inserted.elapsed() >= self.ttl {
+            self.remove(&key);
+            return None;
+        }
+        Some(InvokeOutput {
+            output: clone_output(&entry.output),
+        })
+    }
+
+    /// Caches the result of `tool`, if it is a cacheable read tool.
+    pub fn insert(&mut self, ctx: &Context, tool: &QueuedTool, output: &InvokeOutput) {
+        if !self.enabled {
+            return;
+        }
+        let (Some(key), Some(path)) = (cache_key(tool), tool_path(ctx, tool)) else {
+            return;
+        };
+        let size = output_size(&output.output);
+        if size > self.max_bytes {
+            return;
+        }
+
+        self.remove(&key);
+        while self.total_bytes + size > self.max_bytes {
+            match self.order.pop_front() {
+                Some(oldest) => self.remove(&oldest),
+                None => break,
+            }
+        }
+        self.entries.insert(key.clone(), CacheEntry {
+            output: clone_output(&output.output),
+            path,
+            inserted: Instant::now(),
+            size,
+        });
+        self.order.push_back(key);
+        self.total_bytes += size;
+    }
+
+    /// Drops any results that `tool` may have made stale. A write invalidates reads of the written
+    /// path and of any directory containing it, and a shell command invalidates everything.
+    pub fn invalidate_for(&mut self, ctx: &Context, tool: &QueuedTool) {
+        match &tool.tool {
+            Tool::FsWrite(fs_write) if !fs_write.dry_run() => {
+                let Some(written) = tool_path(ctx, tool) else {
+                    return self.clear();
+                };
+                let stale = self
+                    .entries
+                    .iter()
+                    .filter(|(_, entry)| written.starts_with(&entry.path) || entry.path.starts_with(&written))
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>();
+                for key in stale {
+                    self.remove(&key);
+                }
+            },
+            Tool::ExecuteBash(_) => self.clear(),
+            _ => (),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.total_bytes = 0;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_bytes -= entry.size;
+            self.order.retain(|k| k != key);
+        }
+    }
+}
+
+/// Key identifying a call by tool name and canonicalized arguments, for tools whose results can be
+/// cached.
+fn cache_key(tool: &QueuedTool) -> Option<String> {
+    match tool.tool {
+        // Batch and glob reads aren't cached, since a write to any one of the files would make them
+        // stale.
+        Tool::FsRead(ref fs_read) if matches!(fs_read.paths()[..], [path] if !has_glob_chars(path)) => {
+            Some(format!("{}:{}", tool.name, canonicalize(&tool.args)))
+        },
+        _ => None,
+    }
+}
+
+fn tool_path(ctx: &Context, tool: &QueuedTool) -> Option<PathBuf> {
+    tool.tool
+        .key_args()
+        .into_iter()
+        .find(|(key, _)| *key == "path")
+        .map(|(_, path)| sanitize_path_tool_arg(ctx, path))
+}
+
+/// Serializes `value` with object keys sorted, so that argument order doesn't affect the key.
+fn canonicalize(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut fields = map.iter().collect::<Vec<_>>();
+            fields.sort_by_key(|(key, _)| *key);
+            let fields = fields
+                .into_iter()
+                .map(|(key, value)| format!("{}:{}", serde_json::Value::String(key.clone()), canonicalize(value)))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(","))
+        },
+        serde_json::Value::Array(values) => {
+            format!("[{}]", values.iter().map(canonicalize).collect::<Vec<_>>().join(","))
+        },
+        other => other.to_string(),
+    }
+}
+
+fn clone_output(output: &OutputKind) -> OutputKind {
+    match output {
+        OutputKind::Text(text) => OutputKind::Text(text.clone()),
+        OutputKind::Json(json) => OutputKind::Json(json.clone()),
+    }
+}
+
+fn output_size(output: &OutputKind) -> usize {
+    match output {
+        OutputKind::Text(text) => text.len(),
+        OutputKind::Json(json) => json.to_string().len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::queued;
+
+    fn read(path: &str) -> QueuedTool {
+        queued("1", "fs_read", serde_json::json!({ "path": path, "mode": "Line" }))
+    }
+
+    fn text(s: &str) -> InvokeOutput {
+        InvokeOutput {
+            output: OutputKind::Text(s.to_string()),
+        }
+    }
+
+    fn cached_text(cache: &mut ResultCache, tool: &QueuedTool) -> Option<String> {
+        cache.get(tool).map(|output| match output.output {
+            OutputKind::Text(text) => text,
+            OutputKind::Json(json) => json.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_cache_hit_ignores_argument_order() {
+        let ctx = Context::new_fake();
+        let mut cache = ResultCache::new(true, Duration::from_secs(60), 1024);
+        cache.insert(&ctx, &read("/a.txt"), &text("a"));
+
+        let reordered = queued("1", "fs_read", serde_json::json!({ "mode": "Line", "path": "/a.txt" }));
+        assert_eq!(cached_text(&mut cache, &reordered).as_deref(), Some("a"));
+        assert!(cache.get(&read("/b.txt")).is_none());
+    }
+
+    #[test]
+    fn test_cache_disabled() {
+        let ctx = Context::new_fake();
+        let mut cache = ResultCache::from_settings(&Settings::new_fake());
+        cache.insert(&ctx, &read("/a.txt"), &text("a"));
+        assert!(cache.get(&read("/a.txt")).is_none());
+    }
+
+    #[test]
+    fn test_cache_invalidation() {
+        let ctx = Context::new_fake();
+        let mut cache = ResultCache::new(true, Duration::from_secs(60), 1024);
+        cache.insert(&ctx, &read("/dir/a.txt"), &text("a"));
+        cache.insert(&ctx, &read("/dir"), &text("listing"));
+        cache.insert(&ctx, &read("/other.txt"), &text("other"));
+
+        let write = queued(
+            "1",
+            "fs_write",
+            serde_json::json!({ "command": "create", "path": "/dir/a.txt", "file_text": "new" }),
+        );
+        cache.invalidate_for(&ctx, &write);
+        assert!(cache.get(&read("/dir/a.txt")).is_none());
+        assert!(cache.get(&read("/dir")).is_none());
+        assert!(cache.get(&read("/other.txt")).is_some());
+
+        cache.invalidate_for(
+            &ctx,
+            &queued("1", "execute_bash", serde_json::json!({ "command": "ls" })),
+        );
+        assert_eq!(cache.usage(), (0, 0));
+    }
+
+    #[test]
+    fn test_cache_ttl_and_budget() {
+        let ctx = Context::new_fake();
+        let mut cache = ResultCache::new(true, Duration::ZERO, 1024);
+        cache.insert(&ctx, &read("/a.txt"), &text("a"));
+        assert!(cache.get(&read("/a.txt")).is_none());
+        assert_eq!(cache.usage(), (0, 0));
+
+        let mut cache = ResultCache::new(true, Duration::from_secs(60), 10);
+        cache.insert(&ctx, &read("/a.txt"), &text("aaaaaa"));
+        cache.insert(&ctx, &read("/b.txt"), &text("bbbbbb"));
+        assert!(cache.get(&read("/a.txt")).is_none(), "oldest entry is evicted");
+        assert!(cache.get(&read("/b.txt")).is_some());
+
+        cache.insert(&ctx, &read("/c.txt"), &text("c".repeat(11).as_str()));
+        assert!(
+            cache.get(&read("/c.txt")).is_none(),
+            "entries over budget aren't cached"
+        );
+        assert_eq!(cache.usage(), (1, 6));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+
+use fig_api_client::model::ToolResultStatus;
+use fig_settings::Settings;
+use tracing::warn;
+
+use super::consts::MAX_TOOL_RESPONSE_SIZE;
+use super::message::{
+    TOOL_USE_CANCELLED_MESSAGE,
+    ToolUseResult,
+    ToolUseResultBlock,
+};
+use super::tools::QueuedTool;
+use super::util::truncate_safe;
+
+/// Setting used to select how tool results are framed before being sent back to the model.
+pub const RESULT_FRAMING_SETTING: &str = "chat.toolResultFraming";
+
+/// Max length of a single argument value rendered in a structured header.
+const MAX_HEADER_ARG_LEN: usize = 80;
+
+const TRUNCATED_SUFFIX: &str = " ... truncated";
+
+/// How a tool result is presented to the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultFraming {
+    /// The tool output as-is.
+    #[default]
+    Plain,
+    /// The tool output preceded by a header block naming the tool, its key arguments, and the
+    /// result status.
+    Structured,
+}
+
+impl ResultFraming {
+    /// Reads the framing from [RESULT_FRAMING_SETTING], falling back to [ResultFraming::Plain].
+    pub fn from_settings(settings: &Settings) -> Self {
+        match settings.get_string_opt(RESULT_FRAMING_SETTING) {
+            Some(value) => Self::parse(&value).unwrap_or_else(|| {
+                warn!(?value, "unknown {RESULT_FRAMING_SETTING}, using plain framing");
+                Self::default()
+            }),
+            None => Self::default(),
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "plain" => Some(Self::Plain),
+            "structured" => Some(Self::Structured),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeStatus {
+    Success,
+    Error,
+    Cancelled,
+}
+
+impl fmt::Display for EnvelopeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EnvelopeStatus::Success => "success",
+            EnvelopeStatus::Error => "error",
+            EnvelopeStatus::Cancelled => "cancelled",
+        })
+    }
+}
+
+/// Everything known about a tool result at the point it is handed back to the model.
+#[derive(Debug, Clone)]
+pub struct ResultEnvelope<'a> {
+    pub tool_name: &'a str,
+    pub key_args: Vec<(&'static str, String)>,
+    pub status: EnvelopeStatus,
+    pub content: Vec<ToolUseResultBlock>,
+}
+
+/// Frames the result in `envelope`, truncating text content so that the framed result, including
+/// any header, fits within `budget` bytes.
+pub fn frame_result(framing: ResultFraming, envelope: ResultEnvelope<'_>, budget: usize) -> Vec<ToolUseResultBlock> {
+    let header = match framing {
+        ResultFraming::Plain => None,
+        ResultFraming::Structured => Some(structured_header(&envelope)),
+    };
+
+    let mut remaining = budget.saturating_sub(header.as_ref().map_or(0, |h| h.len()));
+    let mut blocks = Vec::with_capacity(envelope.content.len() + 1);
+    if let Some(header) = header {
+        blocks.push(ToolUseResultBlock::Text(header));
+    }
+
+    for block in envelope.content {
+        match block {
+            ToolUseResultBlock::Text(text) if text.len() > remaining => {
+                let kept = truncate_safe(&text, remaining.saturating_sub(TRUNCATED_SUFFIX.len()));
+                blocks.push(ToolUseResultBlock::Text(format!("{kept}{TRUNCATED_SUFFIX}")));
+                remaining = 0;
+            },
+            ToolUseResultBlock::Text(text) => {
+                remaining -= text.len();
+                blocks.push(ToolUseResultBlock::Text(text));
+            },
+            ToolUseResultBlock::Json(value) => {
+                remaining = remaining.saturating_sub(value.to_string().len());
+                blocks.push(ToolUseResultBlock::Json(value));
+            },
+        }
+    }
+
+    blocks
+}
+
+/// Builds the result reported to the model for a tool use that the user cancelled.
+pub fn cancelled_tool_use_result(framing: ResultFraming, tool: &QueuedTool) -> ToolUseResult {
+    ToolUseResult {
+        tool_use_id: tool.id.clone(),
+        content: frame_result(
+            framing,
+            ResultEnvelope {
+                tool_name: &tool.name,
+                key_args: tool.tool.key_args(),
+                status: EnvelopeStatus::Cancelled,
+                content: vec![ToolUseResultBlock::Text(TOOL_USE_CANCELLED_MESSAGE.to_string())],
+            },
+            MAX_TOOL_RESPONSE_SIZE,
+        ),
+        status: ToolResultStatus::Error,
+    }
+}
+
+fn structured_header(envelope: &ResultEnvelope<'_>) -> String {
+    let mut header = format!("[tool result]\ntool: {}\n", envelope.tool_name);
+    if !envelope.key_args.is_empty() {
+        let args = envelope
+            .key_args
+            .iter()
+            .map(|(key, value)| {
+                let value = value.replace('\n', " ");
+                if value.len() > MAX_HEADER_ARG_LEN {
+                    format!("{key}={}...", truncate_safe(&value, MAX_HEADER_ARG_LEN))
+                } else {
+                    format!("{key}={value}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        header.push_str(&format!("args: {args}\n"));
+    }
+    header.push_str(&format!("status: {}\n[end tool result header]\n", envelope.status));
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(blocks: &[ToolUseResultBlock]) -> String {
+        blocks
+            .iter()
+            .map(|b| match b {
+                ToolUseResultBlock::Text(t) => t.clone(),
+                ToolUseResultBlock::Json(v) => v.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n---\n")
+    }
+
+    fn envelope(status: EnvelopeStatus, content: &str) -> ResultEnvelope<'static> {
+        ResultEnvelope {
+            tool_name: "fs_read",
+            key_args: vec![("mode", "Line".to_string()), ("path", "src/main.rs".to_string())],
+            status,
+            content: vec![ToolUseResultBlock::Text(content.to_string())],
+        }
+    }
+
+    #[test]
+    fn test_plain_framing() {
+        for status in [
+            EnvelopeStatus::Success,
+            EnvelopeStatus::Error,
+            EnvelopeStatus::Cancelled,
+        ] {
+            let framed = frame_result(ResultFraming::Plain, envelope(status, "fn main() {}"), 1000);
+            assert_eq!(render(&framed), "fn main() {}");
+        }
+    }
+
+    #[test]
+    fn test_structured_framing_success() {
+        let framed = frame_result(
+            ResultFraming::Structured,
+            envelope(EnvelopeStatus::Success, "fn main() {}"),
+            1000,
+        );
+        assert_eq!(
+            render(&framed),
+            "[tool result]\ntool: fs_read\nargs: mode=Line, path=src/main.rs\nstatus: success\n[end tool result \
+             header]\n\n---\nfn main() {}"
+        );
+    }
+
+    #[test]
+    fn test_structured_framing_error() {
+        let framed = frame_result(
+            ResultFraming::Structured,
+            envelope(
+                EnvelopeStatus::Error,
+                "An error occurred processing the tool: \nnot found",
+            ),
+            1000,
+        );
+        assert_eq!(
+            render(&framed),
+            "[tool result]\ntool: fs_read\nargs: mode=Line, path=src/main.rs\nstatus: error\n[end tool result \
+             header]\n\n---\nAn error occurred processing the tool: \nnot found"
+        );
+    }
+
+    #[test]
+    fn test_structured_framing_cancelled() {
+        let framed = frame_result(
+            ResultFraming::Structured,
+            ResultEnvelope {
+                tool_name: "execute_bash",
+                key_args: vec![("command", "echo one\necho two".to_string())],
+                status: EnvelopeStatus::Cancelled,
+                content: vec![ToolUseResultBlock::Text(
+                    "Tool use was cancelled by the user".to_string(),
+                )],
+            },
+            1000,
+        );
+        assert_eq!(
+            render(&framed),
+            "[tool result]\ntool: execute_bash\nargs: command=echo one echo two\nstatus: cancelled\n[end tool result \
+             header]\n\n---\nTool use was cancelled by the user"
+        );
+    }
+
+    #[test]
+    fn test_budget_includes_header() {
+        let content = "a".repeat(500);
+        let plain = frame_result(ResultFraming::Plain, envelope(EnvelopeStatus::Success, &content), 500);
+        assert_eq!(render(&plain), content);
+
+        let structured = frame_result(
+            ResultFraming::Structured,
+            envelope(EnvelopeStatus::Success, &content),
+            500,
+        );
+        let total: usize = structured
+            .iter()
+            .map(|b| match b {
+                ToolUseResultBlock::Text(t) => t.len(),
+                ToolUseResultBlock::Json(v) => v.to_string().len(),
+            })
+            .sum();
+        assert!(total <= 500);
+        assert!(render(&structured).ends_with(TRUNCATED_SUFFIX));
+    }
+
+    #[test]
+    fn test_framing_from_settings() {
+        assert_eq!(
+            ResultFraming::from_settings(&Settings::new_fake()),
+            ResultFraming::Plain
+        );
+        let settings = Settings::from_slice(&[(RESULT_FRAMING_SETTING, "Structured".into())]);
+        assert_eq!(ResultFraming::from_settings(&settings), ResultFraming::Structured);
+        let settings = Settings::from_slice(&[(RESULT_FRAMING_SETTING, "xml".into())]);
+        assert_eq!(ResultFraming::from_settings(&settings), ResultFraming::Plain);
+    }
+}
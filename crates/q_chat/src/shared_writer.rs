@@ -1,5 +1,6 @@
 use std::io::{
     self,
+    IsTerminal,
     Write,
 };
 use std::sync::{
@@ -11,6 +12,7 @@ use std::sync::{
 #[derive(Clone)]
 pub struct SharedWriter {
     inner: Arc<Mutex<Box<dyn Write + Send + 'static>>>,
+    terminal: bool,
 }
 
 impl SharedWriter {
@@ -20,20 +22,32 @@ impl SharedWriter {
     {
         Self {
             inner: Arc::new(Mutex::new(Box::new(writer))),
+            terminal: false,
         }
     }
 
     pub fn stdout() -> Self {
-        Self::new(io::stdout())
+        Self {
+            terminal: io::stdout().is_terminal(),
+            ..Self::new(io::stdout())
+        }
     }
 
     pub fn stderr() -> Self {
-        Self::new(io::stderr())
+        Self {
+            terminal: io::stderr().is_terminal(),
+            ..Self::new(io::stderr())
+        }
     }
 
     pub fn null() -> Self {
         Self::new(NullWriter {})
     }
+
+    /// Whether what is written is shown on a terminal.
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
 }
 
 impl std::fmt::Debug for SharedWriter {
@@ -1,5 +1,8 @@
 use std::ops::Deref;
 
+use aws_smithy_types::Document;
+use fig_api_client::model::Tool;
+
 use super::conversation_state::{
     BackendConversationState,
     ConversationSize,
@@ -111,7 +114,22 @@ impl CharCounter for BackendConversationState<'_> {
 
 impl CharCounter for ConversationSize {
     fn char_count(&self) -> CharCount {
-        self.user_messages + self.assistant_messages + self.context_messages
+        self.user_messages + self.assistant_messages + self.context_messages + self.tool_specs
+    }
+}
+
+impl CharCounter for &[Tool] {
+    fn char_count(&self) -> CharCount {
+        self.iter()
+            .map(|tool| match tool {
+                Tool::ToolSpecification(spec) => {
+                    spec.name.len()
+                        + spec.description.len()
+                        + spec.input_schema.json.as_ref().map_or(0, calculate_document_char_count)
+                },
+            })
+            .sum::<usize>()
+            .into()
     }
 }
 
@@ -178,6 +196,20 @@ fn calculate_value_char_count(document: &serde_json::Value) -> usize {
     }
 }
 
+/// Unlike [calculate_value_char_count], object keys are counted as well since the property names of
+/// a schema are sent to the model.
+fn calculate_document_char_count(document: &Document) -> usize {
+    match document {
+        Document::Null | Document::Bool(_) | Document::Number(_) => 1,
+        Document::String(s) => s.len(),
+        Document::Array(vec) => vec.iter().map(calculate_document_char_count).sum(),
+        Document::Object(map) => map
+            .iter()
+            .map(|(k, v)| k.len() + calculate_document_char_count(v))
+            .sum(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -248,4 +280,25 @@ mod tests {
             0
         );
     }
+
+    #[test]
+    fn test_tool_spec_char_count() {
+        use fig_api_client::model::{
+            ToolInputSchema,
+            ToolSpecification,
+        };
+
+        let tools = [Tool::ToolSpecification(ToolSpecification {
+            name: "fs_read".to_string(),
+            description: "Read".to_string(),
+            input_schema: ToolInputSchema {
+                json: Some(super::super::tools::serde_value_to_document(serde_json::json!({
+                    "type": "object",
+                    "required": ["path"],
+                }))),
+            },
+        })];
+        // name (7) + description (4) + "type" (4) + "object" (6) + "required" (8) + "path" (4)
+        assert_eq!(*tools.as_slice().char_count(), 33);
+    }
 }
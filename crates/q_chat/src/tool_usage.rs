@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Invocation counts for a single tool.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolUsage {
+    pub invocations: usize,
+    pub successes: usize,
+    pub failures: usize,
+    pub total_time: Duration,
+}
+
+/// Per-tool invocation counts for the current session.
+#[derive(Debug, Default)]
+pub struct ToolUsageStats {
+    by_tool: HashMap<String, ToolUsage>,
+}
+
+impl ToolUsageStats {
+    /// Records a single invocation of `tool_name`. Cancelled invocations count as failures.
+    pub fn record(&mut self, tool_name: &str, succeeded: bool, duration: Duration) {
+        let usage = self.by_tool.entry(tool_name.to_string()).or_default();
+        usage.invocations += 1;
+        if succeeded {
+            usage.successes += 1;
+        } else {
+            usage.failures += 1;
+        }
+        usage.total_time += duration;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_tool.is_empty()
+    }
+
+    /// Usage per tool, most invoked first.
+    pub fn sorted(&self) -> Vec<(&str, &ToolUsage)> {
+        let mut rows = self
+            .by_tool
+            .iter()
+            .map(|(name, usage)| (name.as_str(), usage))
+            .collect::<Vec<_>>();
+        rows.sort_by(|(a_name, a), (b_name, b)| b.invocations.cmp(&a.invocations).then(a_name.cmp(b_name)));
+        rows
+    }
+
+    /// Formats the usage as a table with a trailing total row.
+    pub fn format_table(&self) -> String {
+        let rows = self.sorted();
+        let name_width = rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(std::iter::once("Total".len()))
+            .max()
+            .unwrap_or_default();
+
+        let mut table = format!(
+            "{:<name_width$}  {:>5}  {:>7}  {:>6}  {:>8}\n",
+            "Tool", "Calls", "Success", "Failed", "Time"
+        );
+        let mut total = ToolUsage::default();
+        for (name, usage) in rows {
+            table.push_str(&format_row(name, usage, name_width));
+            total.invocations += usage.invocations;
+            total.successes += usage.successes;
+            total.failures += usage.failures;
+            total.total_time += usage.total_time;
+        }
+        table.push_str(&format_row("Total", &total, name_width));
+        table
+    }
+}
+
+fn format_row(name: &str, usage: &ToolUsage, name_width: usize) -> String {
+    format!(
+        "{:<name_width$}  {:>5}  {:>7}  {:>6}  {:>7.1}s\n",
+        name,
+        usage.invocations,
+        usage.successes,
+        usage.failures,
+        usage.total_time.as_secs_f64()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_usage_table() {
+        let mut stats = ToolUsageStats::default();
+        assert!(stats.is_empty());
+        stats.record("fs_read", true, Duration::from_millis(200));
+        stats.record("execute_bash", true, Duration::from_millis(1500));
+        stats.record("fs_read", false, Duration::from_millis(100));
+        stats.record("fs_read", true, Duration::from_millis(200));
+
+        assert_eq!(stats.sorted().iter().map(|(name, _)| *name).collect::<Vec<_>>(), vec![
+            "fs_read",
+            "execute_bash"
+        ]);
+        assert_eq!(
+            stats.format_table(),
+            "Tool          Calls  Success  Failed      Time\n\
+             fs_read           3        2       1      0.5s\n\
+             execute_bash      1        1       0      1.5s\n\
+             Total             4        3       1      2.0s\n"
+        );
+    }
+}
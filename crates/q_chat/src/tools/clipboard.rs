@@ -0,0 +1,215 @@
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Color,
+};
+use eyre::{
+    Result,
+    bail,
+    eyre,
+};
+use fig_os_shim::{
+    Context,
+    Os,
+};
+use serde::Deserialize;
+
+use super::super::audit_log::{
+    env_secrets,
+    redact,
+};
+use super::super::util::truncate_safe;
+use super::{
+    InvokeOutput,
+    OutputKind,
+};
+
+/// Max size in bytes of content that can be written to or read from the clipboard.
+const MAX_CLIPBOARD_SIZE: usize = 100_000;
+
+/// How long to wait on the system clipboard before giving up. Some clipboard providers block
+/// indefinitely when no clipboard owner is running.
+const CLIPBOARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command")]
+pub enum Clipboard {
+    #[serde(rename = "set")]
+    Set { content: String },
+    #[serde(rename = "get")]
+    Get,
+}
+
+impl Clipboard {
+    pub async fn invoke(&self, _updates: impl Write) -> Result<InvokeOutput> {
+        match self {
+            Clipboard::Set { content } => {
+                let content = content.clone();
+                let len = content.len();
+                run_with_timeout(move |clipboard| clipboard.set_text(content)).await?;
+                Ok(InvokeOutput {
+                    output: OutputKind::Text(format!("Copied {len} bytes to the clipboard")),
+                })
+            },
+            Clipboard::Get => {
+                let text = run_with_timeout(|clipboard| clipboard.get_text()).await?;
+                let output = if text.len() > MAX_CLIPBOARD_SIZE {
+                    format!("{} ... truncated", truncate_safe(&text, MAX_CLIPBOARD_SIZE))
+                } else {
+                    text
+                };
+                Ok(InvokeOutput {
+                    output: OutputKind::Text(output),
+                })
+            },
+        }
+    }
+
+    pub fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
+        match self {
+            Clipboard::Set { content } => {
+                // Secrets are masked so that approving the copy doesn't put them on screen.
+                let masked = redact(content, &env_secrets(ctx));
+                if masked != *content {
+                    queue!(
+                        updates,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("⚠ The content looks like it contains a secret, which is masked below.\n"),
+                        style::ResetColor,
+                    )?;
+                }
+                queue!(
+                    updates,
+                    style::Print(format!("Copying {} bytes to the clipboard:\n\n", content.len())),
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(masked),
+                    style::ResetColor,
+                    style::Print("\n"),
+                )?;
+            },
+            Clipboard::Get => {
+                queue!(updates, style::Print("Reading the contents of the clipboard\n"))?;
+            },
+        }
+        Ok(())
+    }
+
+    pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        if let Clipboard::Set { content } = self {
+            if content.len() > MAX_CLIPBOARD_SIZE {
+                bail!(
+                    "Content is {} bytes, which exceeds the clipboard limit of {MAX_CLIPBOARD_SIZE} bytes",
+                    content.len()
+                );
+            }
+        }
+
+        if !has_clipboard(ctx) {
+            bail!("No clipboard available: neither DISPLAY nor WAYLAND_DISPLAY is set");
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a system clipboard can be used, which on Linux requires an X11 or Wayland display.
+pub fn has_clipboard(ctx: &Context) -> bool {
+    ctx.platform().os() != Os::Linux || ctx.env().get("DISPLAY").is_ok() || ctx.env().get("WAYLAND_DISPLAY").is_ok()
+}
+
+/// Runs `f` against the system clipboard on a blocking thread, failing if the clipboard can't be
+/// opened or doesn't respond within [CLIPBOARD_TIMEOUT].
+async fn run_with_timeout<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error> + Send + 'static,
+{
+    let task = tokio::task::spawn_blocking(move || {
+        let mut clipboard = arboard::Clipboard::new()?;
+        f(&mut clipboard)
+    });
+
+    match tokio::time::timeout(CLIPBOARD_TIMEOUT, task).await {
+        Ok(Ok(Ok(value))) => Ok(value),
+        Ok(Ok(Err(err))) => Err(eyre!("No clipboard available: {err}")),
+        Ok(Err(err)) => Err(eyre!("Clipboard task failed: {err}")),
+        Err(_) => Err(eyre!(
+            "No clipboard available: timed out waiting for the system clipboard"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_deser() {
+        let clipboard = serde_json::from_value::<Clipboard>(serde_json::json!({
+            "command": "set",
+            "content": "hello",
+        }))
+        .unwrap();
+        assert!(matches!(clipboard, Clipboard::Set { content } if content == "hello"));
+
+        let clipboard = serde_json::from_value::<Clipboard>(serde_json::json!({ "command": "get" })).unwrap();
+        assert!(matches!(clipboard, Clipboard::Get));
+    }
+
+    #[tokio::test]
+    async fn test_clipboard_size_cap() {
+        let ctx = Context::builder().with_env_var("DISPLAY", ":0").build_fake();
+        let mut clipboard = Clipboard::Set {
+            content: "a".repeat(MAX_CLIPBOARD_SIZE),
+        };
+        assert!(clipboard.validate(&ctx).await.is_ok());
+
+        let mut clipboard = Clipboard::Set {
+            content: "a".repeat(MAX_CLIPBOARD_SIZE + 1),
+        };
+        let err = clipboard.validate(&ctx).await.unwrap_err();
+        assert!(err.to_string().contains("exceeds the clipboard limit"));
+    }
+
+    #[test]
+    fn test_clipboard_description_masks_secrets() {
+        let ctx = Context::builder()
+            .with_env_var("MY_API_TOKEN", "s3cr3t-value")
+            .build_fake();
+        let describe = |content: &str| {
+            let mut out = Vec::new();
+            Clipboard::Set {
+                content: content.to_string(),
+            }
+            .queue_description(&ctx, &mut out)
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let description = describe("export GITHUB_TOKEN=abc123\ncurl -H s3cr3t-value example.com");
+        assert!(description.contains("looks like it contains a secret"), "{description}");
+        assert!(description.contains("GITHUB_TOKEN=<redacted>"), "{description}");
+        assert!(description.contains("<redacted:MY_API_TOKEN>"), "{description}");
+        assert!(!description.contains("abc123") && !description.contains("s3cr3t-value"));
+
+        let description = describe("hello world");
+        assert!(!description.contains("secret"), "{description}");
+        assert!(description.contains("hello world"));
+    }
+
+    #[tokio::test]
+    async fn test_clipboard_headless() {
+        let ctx = Context::builder().with_os(Os::Linux).build_fake();
+        let err = Clipboard::Get.validate(&ctx).await.unwrap_err();
+        assert!(err.to_string().starts_with("No clipboard available"));
+
+        let ctx = Context::builder()
+            .with_os(Os::Linux)
+            .with_env_var("WAYLAND_DISPLAY", "wayland-0")
+            .build_fake();
+        assert!(Clipboard::Get.validate(&ctx).await.is_ok());
+    }
+}
@@ -0,0 +1,714 @@
+//! Rendering diffs in the terminal, for the changes shown by fs_write.
+
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Attribute,
+    Color,
+};
+use eyre::Result;
+use fig_os_shim::Context;
+use similar::{
+    ChangeTag,
+    DiffTag,
+    TextDiff,
+};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{
+    Theme,
+    ThemeSet,
+};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use tracing::error;
+use unicode_width::UnicodeWidthChar;
+
+use super::supports_truecolor;
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME_SET: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Columns between tab stops.
+const TAB_WIDTH: usize = 4;
+/// Lines longer than this aren't syntax highlighted or diffed by word, which would be slow for
+/// files such as minified JavaScript.
+const MAX_DETAILED_LINE_BYTES: usize = 4096;
+/// Number of terminal rows a line may wrap onto before the rest of it is cut.
+const MAX_ROWS_PER_LINE: usize = 8;
+/// Columns shown of each line when not wrapping.
+const MAX_UNWRAPPED_COLUMNS: usize = 500;
+/// Pairs of changed lines less similar than this are highlighted as whole lines, not by word.
+const MIN_WORD_DIFF_RATIO: f32 = 0.5;
+
+const DELETE_GUTTER_BG: Color = Color::Rgb { r: 79, g: 40, b: 40 };
+const DELETE_LINE_BG: Color = Color::Rgb { r: 36, g: 25, b: 28 };
+const DELETE_WORD_BG: Color = Color::Rgb { r: 99, g: 44, b: 46 };
+const INSERT_GUTTER_BG: Color = Color::Rgb { r: 40, g: 67, b: 43 };
+const INSERT_LINE_BG: Color = Color::Rgb { r: 24, g: 38, b: 30 };
+const INSERT_WORD_BG: Color = Color::Rgb { r: 44, g: 92, b: 54 };
+const TRAILING_WHITESPACE_BG: Color = Color::Rgb { r: 140, g: 40, b: 40 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// No escape codes at all, e.g. with `NO_COLOR` set.
+    Plain,
+    /// The basic terminal colors.
+    Basic,
+    /// 24 bit color, with syntax highlighting.
+    TrueColor,
+}
+
+impl ColorMode {
+    pub fn from_ctx(ctx: &Context) -> Self {
+        let env = ctx.env();
+        if env.get("NO_COLOR").is_ok_and(|v| !v.is_empty())
+            || env.get("TERM").is_ok_and(|term| term == "dumb")
+            || style::Colored::ansi_color_disabled_memoized()
+        {
+            ColorMode::Plain
+        } else if supports_truecolor(ctx) {
+            ColorMode::TrueColor
+        } else {
+            ColorMode::Basic
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOptions {
+    pub color: ColorMode,
+    /// Terminal width to wrap lines at, if any.
+    pub width: Option<usize>,
+    /// Number of unchanged lines shown around each change. Longer runs of unchanged lines are
+    /// collapsed, unless this is `None`.
+    pub context_lines: Option<usize>,
+    /// Number of lines to print before summarizing the rest of the diff, if any.
+    pub max_lines: Option<usize>,
+}
+
+impl DiffOptions {
+    /// Options for printing to the current terminal.
+    pub fn new(ctx: &Context, context_lines: Option<usize>, max_lines: Option<usize>) -> Self {
+        Self {
+            color: ColorMode::from_ctx(ctx),
+            width: crossterm::terminal::size()
+                .ok()
+                .map(|(columns, _)| columns.into())
+                .filter(|columns| *columns > 0),
+            context_lines,
+            max_lines,
+        }
+    }
+}
+
+/// A line of the diff.
+#[derive(Debug)]
+struct Row<'a> {
+    tag: ChangeTag,
+    /// 0-indexed line in the old and new text.
+    old_index: Option<usize>,
+    new_index: Option<usize>,
+    /// The line without its line ending.
+    text: &'a str,
+    /// Byte ranges of `text` that changed, when only part of the line did.
+    changed_words: Vec<Range<usize>>,
+}
+
+#[derive(Debug)]
+enum Item<'a> {
+    Row(Row<'a>),
+    /// A run of unchanged lines that isn't shown.
+    Collapsed(usize),
+}
+
+/// Byte ranges of a line, each with its syntax highlighting color.
+type LineColors = Vec<(Range<usize>, Color)>;
+
+/// Prints a git-diff style comparison of `old` and `new`, with the changed words of each changed
+/// line highlighted.
+/// - `path` - used to pick the syntax highlighting.
+/// - `start_line` - 1-indexed line number that `old` and `new` start at.
+pub fn print_diff(
+    updates: &mut impl Write,
+    path: &str,
+    old: &str,
+    new: &str,
+    start_line: usize,
+    options: &DiffOptions,
+) -> Result<()> {
+    let diff = TextDiff::from_lines(old, new);
+    let items = diff_items(&diff, options.context_lines);
+    let (old_colors, new_colors) = match options.color {
+        ColorMode::TrueColor => (highlight(path, old), highlight(path, new)),
+        _ => (None, None),
+    };
+
+    let (mut max_old, mut max_new) = (1, 1);
+    for item in &items {
+        if let Item::Row(row) = item {
+            max_old = row.old_index.map_or(max_old, |i| i + start_line);
+            max_new = row.new_index.map_or(max_new, |i| i + start_line);
+        }
+    }
+    let renderer = Renderer {
+        options,
+        theme: &THEME_SET.themes["base16-ocean.dark"],
+        start_line,
+        old_width: terminal_width_required_for_line_count(max_old),
+        new_width: terminal_width_required_for_line_count(max_new),
+    };
+
+    let total_rows = items.iter().filter(|item| matches!(item, Item::Row(_))).count();
+    let max_rows = options.max_lines.unwrap_or(usize::MAX);
+    let mut shown_rows = 0;
+    for item in &items {
+        match item {
+            Item::Row(_) if shown_rows == max_rows => break,
+            Item::Row(row) => {
+                let colors = match row.tag {
+                    ChangeTag::Delete => line_colors(&old_colors, row.old_index),
+                    _ => line_colors(&new_colors, row.new_index),
+                };
+                renderer.print_row(updates, row, colors)?;
+                shown_rows += 1;
+            },
+            Item::Collapsed(lines) => renderer.print_collapsed(updates, *lines)?,
+        }
+    }
+    queue!(updates, style::Print("\n"))?;
+
+    if shown_rows < total_rows {
+        let (added, removed) = change_counts(&diff);
+        let summary = [
+            (
+                Color::DarkGrey,
+                format!("… {} more lines not shown (", total_rows - shown_rows),
+            ),
+            (Color::Green, format!("+{added}")),
+            (Color::DarkGrey, " ".to_string()),
+            (Color::Red, format!("-{removed}")),
+            (Color::DarkGrey, " lines in total)\n".to_string()),
+        ];
+        for (color, text) in summary {
+            renderer.print_colored(updates, color, &text)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether [print_diff] leaves out any of the diff, by collapsing unchanged lines or by
+/// summarizing the lines after [DiffOptions::max_lines].
+pub fn is_summarized(old: &str, new: &str, options: &DiffOptions) -> bool {
+    let diff = TextDiff::from_lines(old, new);
+    let items = diff_items(&diff, options.context_lines);
+    let rows = items.iter().filter(|item| matches!(item, Item::Row(_))).count();
+    rows > options.max_lines.unwrap_or(usize::MAX) || items.iter().any(|item| matches!(item, Item::Collapsed(_)))
+}
+
+/// Returns the number of `(added, removed)` lines in `diff`.
+pub fn change_counts<'a>(diff: &TextDiff<'a, 'a, 'a, str>) -> (usize, usize) {
+    diff.iter_all_changes()
+        .fold((0, 0), |(added, removed), change| match change.tag() {
+            ChangeTag::Insert => (added + 1, removed),
+            ChangeTag::Delete => (added, removed + 1),
+            ChangeTag::Equal => (added, removed),
+        })
+}
+
+/// Returns the number of terminal cells required for displaying line numbers. This is used to
+/// determine how many characters the gutter should allocate when displaying line numbers for a
+/// text file.
+///
+/// For example, `10` and `99` both take 2 cells, whereas `100` and `999` take 3.
+fn terminal_width_required_for_line_count(line_count: usize) -> usize {
+    line_count.to_string().chars().count()
+}
+
+/// The lines of `diff` to print, with runs of unchanged lines further than `context_lines` from
+/// a change collapsed.
+fn diff_items<'a>(diff: &TextDiff<'a, 'a, 'a, str>, context_lines: Option<usize>) -> Vec<Item<'a>> {
+    let groups = match context_lines {
+        Some(context_lines) => diff.grouped_ops(context_lines),
+        None => vec![diff.ops().to_vec()],
+    };
+    let old_len = diff.old_slices().len();
+    let mut items = Vec::new();
+    let mut next_old = 0;
+    for group in groups.iter().filter(|group| !group.is_empty()) {
+        let start = group[0].old_range().start;
+        if start > next_old {
+            items.push(Item::Collapsed(start - next_old));
+        }
+        let mut rows = group
+            .iter()
+            .flat_map(|op| diff.iter_changes(op))
+            .map(|change| Row {
+                tag: change.tag(),
+                old_index: change.old_index(),
+                new_index: change.new_index(),
+                text: change.value().trim_end_matches('\n').trim_end_matches('\r'),
+                changed_words: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        mark_changed_words(&mut rows);
+        items.extend(rows.into_iter().map(Item::Row));
+        next_old = group[group.len() - 1].old_range().end;
+    }
+    if old_len > next_old {
+        items.push(Item::Collapsed(old_len - next_old));
+    }
+    items
+}
+
+/// Byte ranges of the words that changed within a line.
+type WordRanges = Vec<Range<usize>>;
+
+/// Pairs up each run of deleted lines with the inserted lines that follow it, and marks the words
+/// that changed between each pair.
+fn mark_changed_words(rows: &mut [Row<'_>]) {
+    let mut i = 0;
+    while i < rows.len() {
+        let run_len = |from: usize, tag: ChangeTag| rows[from..].iter().take_while(|row| row.tag == tag).count();
+        let deleted = run_len(i, ChangeTag::Delete);
+        if deleted == 0 {
+            i += 1;
+            continue;
+        }
+        let inserted = run_len(i + deleted, ChangeTag::Insert);
+        for pair in 0..deleted.min(inserted) {
+            let (old, new) = (i + pair, i + deleted + pair);
+            if let Some((old_words, new_words)) = changed_words(rows[old].text, rows[new].text) {
+                rows[old].changed_words = old_words;
+                rows[new].changed_words = new_words;
+            }
+        }
+        i += deleted + inserted;
+    }
+}
+
+/// Byte ranges of the words that differ between `old` and `new`, or `None` if the lines are too
+/// different, or too long, for that to be useful.
+fn changed_words(old: &str, new: &str) -> Option<(WordRanges, WordRanges)> {
+    if old.len() > MAX_DETAILED_LINE_BYTES || new.len() > MAX_DETAILED_LINE_BYTES {
+        return None;
+    }
+    let diff = TextDiff::from_words(old, new);
+    if diff.ratio() < MIN_WORD_DIFF_RATIO {
+        return None;
+    }
+    let offsets = |slices: &[&str]| {
+        std::iter::once(0)
+            .chain(slices.iter().scan(0, |end, slice| {
+                *end += slice.len();
+                Some(*end)
+            }))
+            .collect::<Vec<_>>()
+    };
+    let (old_offsets, new_offsets) = (offsets(diff.old_slices()), offsets(diff.new_slices()));
+    let (mut old_words, mut new_words) = (Vec::new(), Vec::new());
+    for (tag, old_range, new_range) in diff.ops().iter().map(|op| op.as_tag_tuple()) {
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        if !old_range.is_empty() {
+            old_words.push(old_offsets[old_range.start]..old_offsets[old_range.end]);
+        }
+        if !new_range.is_empty() {
+            new_words.push(new_offsets[new_range.start]..new_offsets[new_range.end]);
+        }
+    }
+    Some((old_words, new_words))
+}
+
+/// Syntax highlighting colors for each line of `text`, if the file type is known and its lines
+/// are short enough to highlight.
+fn highlight(path: &str, text: &str) -> Option<Vec<LineColors>> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    let syntax = SYNTAX_SET.find_syntax_by_extension(extension)?;
+    if text.lines().any(|line| line.len() > MAX_DETAILED_LINE_BYTES) {
+        return None;
+    }
+    let mut highlighter = HighlightLines::new(syntax, &THEME_SET.themes["base16-ocean.dark"]);
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(text) {
+        let ranges = match highlighter.highlight_line(line, &SYNTAX_SET) {
+            Ok(ranges) => ranges,
+            Err(err) => {
+                error!(?err, "unable to syntax highlight the diff");
+                return None;
+            },
+        };
+        let mut start = 0;
+        lines.push(
+            ranges
+                .into_iter()
+                .map(|(style, piece)| {
+                    start += piece.len();
+                    (start - piece.len()..start, syntect_to_crossterm_color(style.foreground))
+                })
+                .collect(),
+        );
+    }
+    Some(lines)
+}
+
+fn line_colors(colors: &Option<Vec<LineColors>>, index: Option<usize>) -> &[(Range<usize>, Color)] {
+    colors
+        .as_ref()
+        .zip(index)
+        .and_then(|(colors, i)| colors.get(i))
+        .map_or(&[], Vec::as_slice)
+}
+
+fn syntect_to_crossterm_color(syntect: syntect::highlighting::Color) -> Color {
+    Color::Rgb {
+        r: syntect.r,
+        g: syntect.g,
+        b: syntect.b,
+    }
+}
+
+/// How a character of a line is styled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CellStyle {
+    /// Syntax highlighting color.
+    color: Option<Color>,
+    changed: bool,
+    /// Trailing whitespace on a changed line, shown with visible markers.
+    trailing_whitespace: bool,
+}
+
+/// A character as displayed.
+#[derive(Debug)]
+struct Cell {
+    text: String,
+    width: usize,
+    style: CellStyle,
+}
+
+struct Renderer<'a> {
+    options: &'a DiffOptions,
+    theme: &'a Theme,
+    start_line: usize,
+    old_width: usize,
+    new_width: usize,
+}
+
+impl Renderer<'_> {
+    /// Width of the gutter, e.g. `+ 12  13:`.
+    fn gutter_width(&self) -> usize {
+        self.old_width + self.new_width + 5
+    }
+
+    fn print_row(&self, updates: &mut impl Write, row: &Row<'_>, colors: &[(Range<usize>, Color)]) -> Result<()> {
+        let content_width = self
+            .options
+            .width
+            .map(|width| width.saturating_sub(self.gutter_width() + 1).max(10));
+        let max_columns = content_width.map_or(MAX_UNWRAPPED_COLUMNS, |width| width * MAX_ROWS_PER_LINE);
+        let (cells, cut_chars) = cells(row, colors, max_columns);
+
+        let mut lines = Vec::new();
+        let mut line: Vec<&Cell> = Vec::new();
+        let mut line_width = 0;
+        for cell in &cells {
+            if content_width.is_some_and(|width| line_width + cell.width > width) && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+            line_width += cell.width;
+            line.push(cell);
+        }
+        lines.push(line);
+
+        let (sign, separator) = match row.tag {
+            ChangeTag::Equal => (" ", ", "),
+            ChangeTag::Delete => ("-", "  "),
+            ChangeTag::Insert => ("+", "  "),
+        };
+        let line_number = |i: Option<usize>| i.map(|i| (i + self.start_line).to_string()).unwrap_or_default();
+        let gutter = format!(
+            "{sign} {:>old_width$}{separator}{:>new_width$}:",
+            line_number(row.old_index),
+            line_number(row.new_index),
+            old_width = self.old_width,
+            new_width = self.new_width,
+        );
+        let last = lines.len() - 1;
+        for (i, line) in lines.into_iter().enumerate() {
+            let gutter = if i == 0 {
+                gutter.clone()
+            } else {
+                " ".repeat(self.gutter_width())
+            };
+            self.print_line(updates, row.tag, &gutter, &line)?;
+            if i == last && cut_chars > 0 {
+                self.print_colored(updates, Color::DarkGrey, &format!(" … {cut_chars} more characters"))?;
+            }
+            self.end_line(updates)?;
+        }
+        Ok(())
+    }
+
+    fn print_line(&self, updates: &mut impl Write, tag: ChangeTag, gutter: &str, cells: &[&Cell]) -> Result<()> {
+        let text = |cells: &[&Cell]| cells.iter().map(|cell| cell.text.as_str()).collect::<String>();
+        let (gutter_bg, line_bg, word_bg, fg) = match (self.options.color, tag) {
+            (ColorMode::Plain, _) => {
+                queue!(
+                    updates,
+                    style::Print(gutter),
+                    style::Print(" "),
+                    style::Print(text(cells))
+                )?;
+                return Ok(());
+            },
+            (ColorMode::Basic, ChangeTag::Equal) => (Color::Reset, Color::Reset, Color::Reset, Color::Reset),
+            (ColorMode::Basic, ChangeTag::Delete) => (Color::Reset, Color::Reset, Color::Reset, Color::Red),
+            (ColorMode::Basic, ChangeTag::Insert) => (Color::Reset, Color::Reset, Color::Reset, Color::Green),
+            (ColorMode::TrueColor, ChangeTag::Equal) => {
+                let (gutter_bg, line_bg) = theme_colors(self.theme);
+                (gutter_bg, line_bg, line_bg, Color::Reset)
+            },
+            (ColorMode::TrueColor, ChangeTag::Delete) => {
+                (DELETE_GUTTER_BG, DELETE_LINE_BG, DELETE_WORD_BG, Color::Reset)
+            },
+            (ColorMode::TrueColor, ChangeTag::Insert) => {
+                (INSERT_GUTTER_BG, INSERT_LINE_BG, INSERT_WORD_BG, Color::Reset)
+            },
+        };
+
+        queue!(
+            updates,
+            style::SetBackgroundColor(gutter_bg),
+            style::SetForegroundColor(fg),
+            style::Print(gutter),
+            style::SetBackgroundColor(line_bg),
+            style::Print(" "),
+        )?;
+        for run in cells.chunk_by(|a, b| a.style == b.style) {
+            let style = run[0].style;
+            // Without 24 bit color, changed words and whitespace are shown in reverse video.
+            let reverse = self.options.color == ColorMode::Basic && (style.changed || style.trailing_whitespace);
+            let bg = match (style.trailing_whitespace, style.changed) {
+                _ if self.options.color == ColorMode::Basic => Color::Reset,
+                (true, _) => TRAILING_WHITESPACE_BG,
+                (false, true) => word_bg,
+                (false, false) => line_bg,
+            };
+            queue!(
+                updates,
+                style::SetForegroundColor(style.color.unwrap_or(fg)),
+                style::SetBackgroundColor(bg),
+            )?;
+            if reverse {
+                queue!(updates, style::SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(updates, style::Print(text(run)))?;
+            if reverse {
+                queue!(updates, style::SetAttribute(Attribute::NoReverse))?;
+            }
+        }
+        queue!(updates, style::SetBackgroundColor(line_bg))?;
+        Ok(())
+    }
+
+    /// Ends a line, filling the rest of it with the line's background color.
+    fn end_line(&self, updates: &mut impl Write) -> Result<()> {
+        if self.options.color != ColorMode::Plain {
+            queue!(
+                updates,
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine),
+                style::ResetColor,
+            )?;
+        }
+        queue!(updates, style::Print("\n"))?;
+        Ok(())
+    }
+
+    fn print_collapsed(&self, updates: &mut impl Write, lines: usize) -> Result<()> {
+        let lines = match lines {
+            1 => "1 unchanged line".to_string(),
+            n => format!("{n} unchanged lines"),
+        };
+        let text = format!("{:width$}… {lines} …\n", "", width = self.gutter_width() + 1);
+        self.print_colored(updates, Color::DarkGrey, &text)
+    }
+
+    fn print_colored(&self, updates: &mut impl Write, color: Color, text: &str) -> Result<()> {
+        if self.options.color == ColorMode::Plain {
+            queue!(updates, style::Print(text))?;
+        } else {
+            queue!(
+                updates,
+                style::SetForegroundColor(color),
+                style::Print(text),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn theme_colors(theme: &Theme) -> (Color, Color) {
+    match (theme.settings.gutter, theme.settings.background) {
+        (Some(gutter), Some(background)) => (
+            syntect_to_crossterm_color(gutter),
+            syntect_to_crossterm_color(background),
+        ),
+        (None, Some(background)) => (
+            syntect_to_crossterm_color(background),
+            syntect_to_crossterm_color(background),
+        ),
+        _ => (Color::Reset, Color::Reset),
+    }
+}
+
+/// Splits the text of `row` into the cells to display, up to `max_columns` columns. Tabs are
+/// expanded, control characters replaced, and trailing whitespace on changed lines made visible.
+/// Returns the cells and the number of characters left out.
+fn cells(row: &Row<'_>, colors: &[(Range<usize>, Color)], max_columns: usize) -> (Vec<Cell>, usize) {
+    let trailing_start = match row.tag {
+        ChangeTag::Equal => row.text.len(),
+        _ => row.text.trim_end_matches([' ', '\t']).len(),
+    };
+    let in_ranges = |ranges: &mut std::iter::Peekable<std::slice::Iter<'_, Range<usize>>>, i: usize| {
+        while ranges.next_if(|range| range.end <= i).is_some() {}
+        ranges.peek().is_some_and(|range| range.contains(&i))
+    };
+    let mut changed = row.changed_words.iter().peekable();
+    let mut colors = colors.iter().peekable();
+
+    let mut cells = Vec::new();
+    let mut column = 0;
+    for (i, c) in row.text.char_indices() {
+        while colors.next_if(|(range, _)| range.end <= i).is_some() {}
+        let style = CellStyle {
+            color: colors
+                .peek()
+                .filter(|(range, _)| range.contains(&i))
+                .map(|(_, color)| *color),
+            changed: in_ranges(&mut changed, i),
+            trailing_whitespace: i >= trailing_start,
+        };
+        let (text, width) = match c {
+            '\t' => {
+                let width = TAB_WIDTH - column % TAB_WIDTH;
+                if style.trailing_whitespace {
+                    (format!("→{}", " ".repeat(width - 1)), width)
+                } else {
+                    (" ".repeat(width), width)
+                }
+            },
+            ' ' if style.trailing_whitespace => ("·".to_string(), 1),
+            c if c.is_control() => ("�".to_string(), 1),
+            c => (c.to_string(), c.width().unwrap_or(0)),
+        };
+        if column + width > max_columns {
+            return (cells, row.text[i..].chars().count());
+        }
+        column += width;
+        cells.push(Cell { text, width, style });
+    }
+    (cells, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(old: &str, new: &str, options: DiffOptions) -> String {
+        let mut out = Vec::new();
+        print_diff(&mut out, "test.txt", old, new, 1, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn plain(width: Option<usize>, context_lines: Option<usize>) -> DiffOptions {
+        DiffOptions {
+            color: ColorMode::Plain,
+            width,
+            context_lines,
+            max_lines: None,
+        }
+    }
+
+    #[test]
+    fn test_gutter_width() {
+        assert_eq!(terminal_width_required_for_line_count(1), 1);
+        assert_eq!(terminal_width_required_for_line_count(9), 1);
+        assert_eq!(terminal_width_required_for_line_count(10), 2);
+        assert_eq!(terminal_width_required_for_line_count(99), 2);
+        assert_eq!(terminal_width_required_for_line_count(100), 3);
+        assert_eq!(terminal_width_required_for_line_count(999), 3);
+    }
+
+    #[test]
+    fn test_changed_words() {
+        let (old, new) = changed_words("let x = foo(1);", "let x = bar(1);").unwrap();
+        assert_eq!(&"let x = foo(1);"[old[0].clone()], "foo(1);");
+        assert_eq!(&"let x = bar(1);"[new[0].clone()], "bar(1);");
+        assert_eq!(changed_words("completely", "different words here"), None);
+    }
+
+    #[test]
+    fn test_print_diff_plain() {
+        let out = render("a\nb\n", "a\nc\n", plain(None, Some(3)));
+        assert_eq!(out, "  1, 1: a\n- 2   : b\n+    2: c\n\n");
+
+        // Trailing whitespace and tabs on changed lines are visible.
+        let out = render("", "\tx = 1;  \t\n", plain(None, Some(3)));
+        assert_eq!(out, "+    1:     x = 1;··→   \n\n");
+    }
+
+    #[test]
+    fn test_print_diff_collapses_unchanged_lines() {
+        let old = (1..=100).map(|i| format!("{i}\n")).collect::<String>();
+        let new = old.replace("50\n", "fifty\n");
+        let out = render(&old, &new, plain(None, Some(2)));
+        assert!(out.starts_with(&format!("{:10}… 47 unchanged lines …\n", "")), "{out}");
+        assert!(out.contains("\n  48, 48: 48\n"), "{out}");
+        assert!(out.contains("- 50    : 50\n+     50: fifty\n"), "{out}");
+        assert!(out.ends_with(&format!("{:10}… 48 unchanged lines …\n\n", "")), "{out}");
+        assert!(is_summarized(&old, &new, &plain(None, Some(2))));
+
+        let out = render(&old, &new, plain(None, None));
+        assert!(!out.contains("unchanged"), "{out}");
+        assert!(!is_summarized(&old, &new, &plain(None, None)));
+    }
+
+    #[test]
+    fn test_print_diff_wraps_long_lines() {
+        let out = render("", &format!("{}\n", "x".repeat(25)), plain(Some(20), None));
+        let (row, continued) = ("x".repeat(12), format!("{:8}{}", "", "x".repeat(12)));
+        assert_eq!(out, format!("+    1: {row}\n{continued}\n{:8}x\n\n", ""));
+
+        // Very long lines, e.g. of minified files, are cut after a few rows.
+        let out = render("", &"x".repeat(1_000_000), plain(Some(20), None));
+        assert_eq!(out.trim_end().lines().count(), MAX_ROWS_PER_LINE);
+        assert!(
+            out.contains(&format!(" … {} more characters\n", 1_000_000 - 12 * MAX_ROWS_PER_LINE)),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_print_diff_colors() {
+        let options = DiffOptions {
+            color: ColorMode::Basic,
+            ..plain(None, None)
+        };
+        let out = render("let x = foo(1);\n", "let x = bar(1);\n", options);
+        let reversed = |text: &str| format!("\x1b[7m{text}\x1b[27m");
+        assert!(out.contains(&reversed("foo(1);")), "{out:?}");
+        assert!(out.contains(&reversed("bar(1);")), "{out:?}");
+        assert_eq!(
+            String::from_utf8(strip_ansi_escapes::strip(&out)).unwrap(),
+            "- 1   : let x = foo(1);\n+    1: let x = bar(1);\n\n"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use encoding_rs::{
+    BIG5,
+    EUC_JP,
+    EUC_KR,
+    Encoding,
+    GBK,
+    SHIFT_JIS,
+    UTF_8,
+    UTF_16BE,
+    UTF_16LE,
+    WINDOWS_1252,
+};
+use eyre::{
+    Result,
+    bail,
+    eyre,
+};
+
+/// Number of leading bytes checked for a null byte when deciding whether a file is binary.
+const BINARY_SNIFF_BYTES: usize = 8192;
+
+/// Multi-byte legacy encodings tried, in order, for files that aren't valid UTF-8.
+const CJK_ENCODINGS: &[&Encoding] = &[SHIFT_JIS, EUC_JP, EUC_KR, GBK, BIG5];
+
+/// How the bytes of a text file are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEncoding {
+    encoding: &'static Encoding,
+    /// Whether the file starts with a byte order mark.
+    bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+impl TextEncoding {
+    /// The encoding named by `label`, e.g. `latin1`, `shift_jis` or `utf-16le`.
+    pub fn from_label(label: &str) -> Result<Self> {
+        let label = label.trim();
+        let encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| eyre!("Unknown encoding: {label}. Use a name such as utf-8, latin1 or shift_jis"))?;
+        Ok(Self { encoding, bom: false })
+    }
+
+    /// Detects the encoding of `bytes` from a byte order mark, or failing that by finding an
+    /// encoding the file is valid in and looks like text. Returns `None` for binary content.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+            let text = &bytes[bom_len..];
+            return decode_strict(encoding, text)
+                .is_some()
+                .then_some(Self { encoding, bom: true });
+        }
+        if bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+            return None;
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            return Some(Self::default());
+        }
+
+        let detected = |encoding| Some(Self { encoding, bom: false });
+        if let Some(&encoding) = CJK_ENCODINGS
+            .iter()
+            .filter(|_| !mostly_isolated_high_bytes(bytes))
+            .find(|encoding| decode_strict(encoding, bytes).is_some_and(|text| looks_like_cjk(&text)))
+        {
+            return detected(encoding);
+        }
+        // Files in single byte encodings such as Latin-1 are mostly ASCII, unlike binary data.
+        let ascii = bytes.iter().filter(|b| b.is_ascii()).count();
+        let text = decode_strict(WINDOWS_1252, bytes)?;
+        if ascii * 10 >= bytes.len() * 7 && !text.chars().any(is_unexpected_control) {
+            return detected(WINDOWS_1252);
+        }
+        None
+    }
+
+    /// Whether this is plain UTF-8, which needs no mention to the model.
+    pub fn is_utf8(&self) -> bool {
+        self.encoding == UTF_8 && !self.bom
+    }
+
+    pub fn name(&self) -> String {
+        let name = match self.encoding.name() {
+            "windows-1252" => "windows-1252 (Latin-1)",
+            name => name,
+        };
+        if self.bom {
+            format!("{name} with BOM")
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Decodes `bytes`, which must be valid in this encoding. A byte order mark is skipped.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String> {
+        let bytes = match Encoding::for_bom(bytes) {
+            Some((encoding, bom_len)) if encoding == self.encoding => &bytes[bom_len..],
+            _ => bytes,
+        };
+        decode_strict(self.encoding, bytes).ok_or_else(|| eyre!("The file isn't valid {}", self.name()))
+    }
+
+    /// Encodes `text` for writing back to the file, failing if it contains characters that this
+    /// encoding can't represent.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(text.len());
+        if self.bom {
+            bytes.extend_from_slice(match self.encoding.name() {
+                "UTF-16LE" => b"\xff\xfe",
+                "UTF-16BE" => b"\xfe\xff",
+                _ => b"\xef\xbb\xbf",
+            });
+        }
+        // encoding_rs only decodes UTF-16, so it is encoded here.
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            for unit in text.encode_utf16() {
+                bytes.extend_from_slice(&if self.encoding == UTF_16LE {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                });
+            }
+            return Ok(bytes);
+        }
+
+        let (encoded, _, had_errors) = self.encoding.encode(text);
+        if had_errors {
+            let (line, c) = text
+                .lines()
+                .enumerate()
+                .find_map(|(i, line)| {
+                    line.chars()
+                        .find(|c| self.encoding.encode(c.encode_utf8(&mut [0; 4])).2)
+                        .map(|c| (i + 1, c))
+                })
+                .unwrap_or_default();
+            bail!(
+                "The file is encoded in {}, which can't represent the character '{c}' on line {line} of the edited file, so the file wasn't changed. Use other characters, or if the user wants the file converted to UTF-8, rewrite all of it with the `create` command and `encoding` set to \"utf-8\"",
+                self.name()
+            );
+        }
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+}
+
+/// Decodes `bytes` as text, in the encoding named by `label` if given and otherwise the detected
+/// one. Returns `None` if no encoding was given and the content looks binary.
+pub fn decode(bytes: &[u8], label: Option<&str>) -> Result<Option<(String, TextEncoding)>> {
+    let encoding = match label {
+        Some(label) => TextEncoding::from_label(label)?,
+        None => match TextEncoding::detect(bytes) {
+            Some(encoding) => encoding,
+            None => return Ok(None),
+        },
+    };
+    let encoding = TextEncoding {
+        bom: Encoding::for_bom(bytes).is_some_and(|(bom_encoding, _)| bom_encoding == encoding.encoding),
+        ..encoding
+    };
+    Ok(Some((encoding.decode(bytes)?, encoding)))
+}
+
+fn decode_strict(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|text| text.into_owned())
+}
+
+/// Whether the non-ASCII characters of `text` are almost all CJK characters, as expected when it
+/// was decoded in the right CJK encoding.
+fn looks_like_cjk(text: &str) -> bool {
+    let non_ascii = text.chars().filter(|c| !c.is_ascii()).count();
+    let cjk = text
+        .chars()
+        .filter(|c| {
+            matches!(*c as u32,
+                0x3000..=0x30ff | 0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xac00..=0xd7af | 0xf900..=0xfaff | 0xff00..=0xffef)
+        })
+        .count();
+    non_ascii > 0 && cjk * 10 >= non_ascii * 9 && !text.chars().any(is_unexpected_control)
+}
+
+/// Whether most non-ASCII bytes are between ASCII ones, as with accented letters in single byte
+/// encodings. CJK characters take two non-ASCII bytes in each of the legacy CJK encodings.
+fn mostly_isolated_high_bytes(bytes: &[u8]) -> bool {
+    let high = |i: usize| bytes.get(i).is_some_and(|b| !b.is_ascii());
+    let (mut total, mut isolated) = (0, 0);
+    for i in (0..bytes.len()).filter(|&i| high(i)) {
+        total += 1;
+        if (i == 0 || !high(i - 1)) && !high(i + 1) {
+            isolated += 1;
+        }
+    }
+    isolated * 2 > total
+}
+
+fn is_unexpected_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let detect = |bytes: &[u8]| TextEncoding::detect(bytes).map(|encoding| encoding.name());
+        assert_eq!(detect(b"plain text\n").as_deref(), Some("UTF-8"));
+        assert_eq!(detect("caf\u{e9}\n".as_bytes()).as_deref(), Some("UTF-8"));
+        assert_eq!(detect(b"\xef\xbb\xbfbom\n").as_deref(), Some("UTF-8 with BOM"));
+        assert_eq!(detect(b"\xff\xfeh\0i\0").as_deref(), Some("UTF-16LE with BOM"));
+        assert_eq!(
+            detect(b"// caf\xe9 cr\xe8me br\xfbl\xe9e\nfn main() {}\n").as_deref(),
+            Some("windows-1252 (Latin-1)")
+        );
+        assert_eq!(
+            detect(b"cr\xe8me br\xfbl\xe9e\nna\xefve\n").as_deref(),
+            Some("windows-1252 (Latin-1)")
+        );
+        let (shift_jis, _, _) = SHIFT_JIS.encode("// こんにちは世界\nfn main() {}\n");
+        assert_eq!(detect(&shift_jis).as_deref(), Some("Shift_JIS"));
+
+        assert_eq!(detect(b"hello\0world"), None);
+        assert_eq!(detect(b"\xff\xfe\xfd"), None);
+        assert_eq!(detect(b"\x89\xab\xcd\xef\x90\x91\x92"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for bytes in [
+            &b"caf\xe9\n"[..],
+            b"\xef\xbb\xbfbom\n",
+            b"\xff\xfeh\0i\0",
+            b"\xfe\xff\0h\0i",
+            &SHIFT_JIS.encode("こんにちは\n").0,
+        ] {
+            let (text, encoding) = decode(bytes, None).unwrap().unwrap();
+            assert!(!text.starts_with('\u{feff}'));
+            assert_eq!(encoding.encode(&text).unwrap(), bytes, "{}", encoding.name());
+        }
+    }
+
+    #[test]
+    fn test_explicit_and_unrepresentable() {
+        let (text, encoding) = decode(b"\x82\xa0", Some("shift_jis")).unwrap().unwrap();
+        assert_eq!(text, "あ");
+        assert!(decode(b"\x82\xa0", Some("not-an-encoding")).is_err());
+
+        let latin1 = TextEncoding::from_label("latin1").unwrap();
+        let err = latin1.encode("ok\n→ arrow\n").unwrap_err().to_string();
+        assert!(err.contains("can't represent the character '→' on line 2"), "{err}");
+        assert!(encoding.encode("日本").is_ok());
+    }
+}
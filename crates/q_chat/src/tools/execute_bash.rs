@@ -1,10 +1,22 @@
-use std::collections::VecDeque;
-use std::io::Write;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    VecDeque,
+};
+use std::io::{
+    IsTerminal,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
 use std::process::{
     ExitStatus,
     Stdio,
 };
-use std::str::from_utf8;
+use std::sync::LazyLock;
+use std::time::Duration;
 
 use crossterm::queue;
 use crossterm::style::{
@@ -14,99 +26,208 @@ use crossterm::style::{
 use eyre::{
     Context as EyreContext,
     Result,
+    bail,
 };
 use fig_os_shim::Context;
+use regex::Regex;
 use serde::Deserialize;
-use tokio::io::AsyncBufReadExt;
+use tokio::io::{
+    AsyncBufReadExt,
+    AsyncRead,
+    AsyncReadExt,
+};
 use tokio::select;
-use tracing::error;
+use tracing::{
+    error,
+    warn,
+};
 
-use super::super::util::truncate_safe;
+use super::super::audit_log::{
+    REDACTED,
+    is_secret_name,
+};
+use super::fs_read::format_size;
 use super::{
     InvokeOutput,
     MAX_TOOL_RESPONSE_SIZE,
     OutputKind,
+    ToolSpec,
+    format_path,
+    sanitize_path_tool_arg,
 };
 
-const READONLY_COMMANDS: &[&str] = &["ls", "cat", "echo", "pwd", "which", "head", "tail", "find", "grep"];
+#[cfg(unix)]
+mod pty;
+mod shell;
+
+pub use shell::Shell;
+
+/// Setting holding how many seconds a command may run for when the model doesn't give a timeout.
+/// Zero means no limit.
+pub const EXECUTE_BASH_TIMEOUT_SETTING: &str = "chat.executeBash.timeoutSeconds";
+
+const DEFAULT_TIMEOUT_SECS: i64 = 300;
+
+/// How often a running command is checked for having stopped to wait for terminal input.
+const STOPPED_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Setting holding the most bytes kept of each of a command's stdout and stderr. Longer output
+/// keeps its start and end.
+pub const EXECUTE_BASH_MAX_OUTPUT_BYTES_SETTING: &str = "chat.executeBash.maxOutputBytes";
+
+const DEFAULT_MAX_OUTPUT_BYTES: i64 = 64 * 1024;
+
+/// Setting holding extra regular expressions for commands that are destructive enough to need
+/// typing back before they run, in addition to [DEFAULT_DESTRUCTIVE_PATTERNS].
+pub const DESTRUCTIVE_PATTERNS_SETTING: &str = "chat.executeBash.destructivePatterns";
+/// Setting that makes commands matching a destructive pattern be refused outright.
+pub const REFUSE_DESTRUCTIVE_SETTING: &str = "chat.executeBash.refuseDestructive";
+
+/// Branches that force pushes are treated as destructive for.
+const PROTECTED_BRANCHES: &str = r"(main|master|develop|trunk|prod|production|release\S*)";
+
+/// Commands that can do damage that is hard to undo, as `(description, pattern)`.
+static DEFAULT_DESTRUCTIVE_PATTERNS: LazyLock<Vec<(&str, Regex)>> = LazyLock::new(|| {
+    [
+        (
+            "recursively deletes the root or home directory",
+            r"\brm\s+(?:-\S+\s+)*(?:/|/\*|~|~/|~/\*|\$HOME|\$HOME/|\$HOME/\*)(?:\s|$|[;&|)])".to_string(),
+        ),
+        ("formats a filesystem", r"\bmkfs(?:\.\w+)?\b".to_string()),
+        ("writes directly to a device", r"\bdd\b[^;&|]*\bof=/dev/".to_string()),
+        ("overwrites a disk device", r">\s*/dev/(?:sd|hd|nvme|disk|mmcblk)".to_string()),
+        ("is a fork bomb", r":\(\)\s*\{\s*:\s*\|\s*:\s*&\s*\}\s*;\s*:".to_string()),
+        (
+            "force pushes to a protected branch",
+            format!(
+                r"\bgit\s+push\b[^;&|]*(?:\s(?:--force\S*|-f)\b[^;&|]*\s{PROTECTED_BRANCHES}\b|\s{PROTECTED_BRANCHES}\b[^;&|]*\s(?:--force\S*|-f)\b|\s\+{PROTECTED_BRANCHES}\b)"
+            ),
+        ),
+        (
+            "recursively changes the permissions of the root directory",
+            r"\bch(?:mod|own)\s+(?:\S+\s+)*?-R\b[^;&|]*\s/(?:\s|$)".to_string(),
+        ),
+    ]
+    .into_iter()
+    .map(|(description, pattern)| (description, Regex::new(&pattern).expect("valid regex")))
+    .collect()
+});
+
+/// Setting holding the command prefixes that run without approval, such as `git status`, in place
+/// of [DEFAULT_ALLOWED_COMMANDS].
+pub const ALLOWED_COMMANDS_SETTING: &str = "chat.executeBash.allowedCommands";
+
+/// Read-only commands that run without approval.
+const DEFAULT_ALLOWED_COMMANDS: &[&str] = &[
+    "ls",
+    "cat",
+    "echo",
+    "pwd",
+    "which",
+    "head",
+    "tail",
+    "find",
+    "grep",
+    "git status",
+    "git diff",
+    "git log",
+    "git show",
+];
+
+/// Environment variables that allowlisted commands may be given without needing approval, since
+/// they only change formatting.
+const SAFE_ENV_VARS: &[&str] = &[
+    "COLUMNS", "LINES", "NO_COLOR", "CLICOLOR", "TERM", "LANG", "LC_ALL", "TZ",
+];
+
+/// Shell operators that an allowlisted command may be joined to others with.
+const COMMAND_SEPARATORS: &[&str] = &["|", "||", "&&", ";"];
+
+/// Arguments that make otherwise read-only commands write, delete or run other commands, such as
+/// `find -exec` or `git diff --output`.
+const UNSAFE_ARG_PREFIXES: &[&str] = &["-exec", "-ok", "-delete", "-fprint", "-fls", "--output"];
+
+/// Commands that access the system clipboard. These are rejected in favor of the clipboard tool,
+/// which shows the user what is being copied and fails cleanly on headless machines.
+const CLIPBOARD_COMMANDS: &[&str] = &["pbcopy", "pbpaste", "xclip", "xsel", "wl-copy", "wl-paste"];
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExecuteBash {
     pub command: String,
+    /// Seconds after which the command is killed, where zero means no limit. Defaults to
+    /// [EXECUTE_BASH_TIMEOUT_SETTING].
+    pub timeout_seconds: Option<u64>,
+    /// Directory to run the command in, relative to the current directory unless absolute.
+    pub cwd: Option<String>,
+    /// Environment variables set for the command, overriding inherited ones. An empty value
+    /// unsets the variable.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Whether to run the command in a pseudo-terminal connected to the user's terminal, so that
+    /// they can respond to its prompts.
+    #[serde(default)]
+    pub interactive: bool,
+    /// Shell to run the command in, defaulting to [Shell::platform_default].
+    pub shell: Option<Shell>,
+
+    /// Whether the updates passed to [Self::invoke] are shown on the user's terminal.
+    #[serde(skip_deserializing)]
+    pub terminal: bool,
 }
 
 impl ExecuteBash {
-    pub fn requires_acceptance(&self) -> bool {
-        let Some(args) = shlex::split(&self.command) else {
-            return true;
-        };
-
-        const DANGEROUS_PATTERNS: &[&str] = &["<(", "$(", "`", ">", "&&", "||"];
-        if args
-            .iter()
-            .any(|arg| DANGEROUS_PATTERNS.iter().any(|p| arg.contains(p)))
-        {
-            return true;
-        }
-
-        // Split commands by pipe and check each one
-        let mut current_cmd = Vec::new();
-        let mut all_commands = Vec::new();
-
-        for arg in args {
-            if arg == "|" {
-                if !current_cmd.is_empty() {
-                    all_commands.push(current_cmd);
-                }
-                current_cmd = Vec::new();
-            } else if arg.contains("|") {
-                // if pipe appears without spacing e.g. `echo myimportantfile|args rm` it won't get
-                // parsed out, in this case - we want to verify before running
-                return true;
-            } else {
-                current_cmd.push(arg);
-            }
-        }
-        if !current_cmd.is_empty() {
-            all_commands.push(current_cmd);
-        }
-
-        // Check if each command in the pipe chain starts with a safe command
-        for cmd_args in all_commands {
-            match cmd_args.first() {
-                // Special casing for `find` so that we support most cases while safeguarding
-                // against unwanted mutations
-                Some(cmd)
-                    if cmd == "find"
-                        && cmd_args
-                            .iter()
-                            .any(|arg| arg.contains("-exec") || arg.contains("-delete")) =>
-                {
-                    return true;
-                },
-                Some(cmd) if !READONLY_COMMANDS.contains(&cmd.as_str()) => return true,
-                None => return true,
-                _ => (),
-            }
-        }
+    pub fn set_terminal(&mut self, terminal: bool) {
+        self.terminal = terminal;
+    }
 
-        false
+    pub fn requires_acceptance(&self) -> bool {
+        // Variables such as `PATH`, `BASH_ENV` or `GIT_CONFIG_*` can make any command run others.
+        let env_is_safe = self.env.keys().all(|name| SAFE_ENV_VARS.contains(&name.as_str()));
+        !env_is_safe || !is_allowlisted(&self.command, &allowed_commands())
     }
 
-    pub async fn invoke(&self, updates: impl Write) -> Result<InvokeOutput> {
-        let output = run_command(&self.command, MAX_TOOL_RESPONSE_SIZE / 3, Some(updates)).await?;
-        let result = serde_json::json!({
-            "exit_status": output.exit_status.unwrap_or(0).to_string(),
-            "stdout": output.stdout,
-            "stderr": output.stderr,
-        });
+    pub async fn invoke(&self, ctx: &Context, updates: impl Write) -> Result<InvokeOutput> {
+        let cwd = self.working_dir(ctx)?;
+        if self.interactive {
+            #[cfg(unix)]
+            let output = pty::run_interactive(
+                &self.command,
+                self.shell(),
+                cwd.as_deref(),
+                &self.env,
+                max_output_bytes(),
+                self.timeout(),
+                updates,
+            )
+            .await?;
+            #[cfg(not(unix))]
+            bail!("Interactive commands are only supported on macOS and Linux");
+            #[cfg(unix)]
+            return Ok(InvokeOutput {
+                output: OutputKind::Json(output.to_json()),
+            });
+        }
+        // Output is only shown as it arrives on a terminal, but it's read the same way regardless so
+        // that the result doesn't depend on where the chat is running.
+        let (mut updates, mut sink) = (updates, std::io::sink());
+        let updates: &mut dyn Write = if self.terminal { &mut updates } else { &mut sink };
+        let output = run_command(
+            &self.command,
+            self.shell(),
+            cwd.as_deref(),
+            &self.env,
+            max_output_bytes(),
+            self.timeout(),
+            Some(updates),
+        )
+        .await?;
 
         Ok(InvokeOutput {
-            output: OutputKind::Json(result),
+            output: OutputKind::Json(output.to_json()),
         })
     }
 
-    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+    pub fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
         queue!(updates, style::Print("I will run the following shell command: "),)?;
 
         // TODO: Could use graphemes for a better heuristic
@@ -114,53 +235,411 @@ impl ExecuteBash {
             queue!(updates, style::Print("\n"),)?;
         }
 
-        Ok(queue!(
+        queue!(
             updates,
             style::SetForegroundColor(Color::Green),
             style::Print(&self.command),
-            style::Print("\n\n"),
-            style::ResetColor
-        )?)
+            style::ResetColor,
+            style::Print("\n"),
+        )?;
+        if !self.env.is_empty() {
+            queue!(
+                updates,
+                style::Print("with environment: "),
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(self.env_description()),
+                style::ResetColor,
+                style::Print("\n"),
+            )?;
+        }
+        if let Some(dir) = self.working_dir(ctx).ok().flatten() {
+            let cwd = ctx.fs().chroot_path(ctx.env().current_dir()?);
+            let dir = match format_path(&cwd, &dir) {
+                dir if Path::new(&dir).is_absolute() || dir.starts_with("..") => dir,
+                dir if dir.is_empty() => ".".to_string(),
+                dir => format!("./{dir}"),
+            };
+            queue!(
+                updates,
+                style::Print("run in: "),
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(dir),
+                style::ResetColor,
+                style::Print("\n"),
+            )?;
+        }
+        if let Some(shell) = self.shell.filter(|shell| *shell != Shell::platform_default()) {
+            queue!(
+                updates,
+                style::Print("in shell: "),
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(shell.name()),
+                style::ResetColor,
+                style::Print("\n"),
+            )?;
+        }
+        if self.interactive {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print("interactive: "),
+                style::ResetColor,
+                style::Print("your keyboard is connected to the command until it exits\n"),
+            )?;
+        }
+        Ok(queue!(updates, style::Print("\n"))?)
     }
 
-    pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+    /// Why the command is destructive enough to need typing back before it runs, if it is.
+    pub fn destructive_reason(&self) -> Option<String> {
+        let extra = fig_settings::settings::get::<Vec<String>>(DESTRUCTIVE_PATTERNS_SETTING)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        find_destructive(&self.expanded_command(), &extra)
+    }
+
+    /// The command as the shell will receive it, with the variables from [Self::env] substituted,
+    /// so that a pattern can't be hidden behind one.
+    fn expanded_command(&self) -> String {
+        let mut command = self.command.clone();
+        for (name, value) in &self.env {
+            command = command
+                .replace(&format!("${{{name}}}"), value)
+                .replace(&format!("${name}"), value);
+        }
+        command
+    }
+
+    /// The variables in [Self::env], with the values of secret-looking ones masked.
+    fn env_description(&self) -> String {
+        self.env
+            .iter()
+            .map(|(name, value)| match value.as_str() {
+                "" => format!("{name} (unset)"),
+                _ if is_secret_name(name) => format!("{name}={REDACTED}"),
+                value => format!("{name}={}", shlex::try_quote(value).unwrap_or_else(|_| value.into())),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The directory given by [Self::cwd], which must exist.
+    fn working_dir(&self, ctx: &Context) -> Result<Option<PathBuf>> {
+        let Some(cwd) = &self.cwd else {
+            return Ok(None);
+        };
+        let mut dir = sanitize_path_tool_arg(ctx, cwd);
+        if dir.is_relative() {
+            dir = ctx.fs().chroot_path(ctx.env().current_dir()?).join(dir);
+        }
+        if !dir.is_dir() {
+            bail!(
+                "The working directory '{}' {}, so the command wasn't run",
+                dir.display(),
+                if dir.exists() {
+                    "isn't a directory"
+                } else {
+                    "doesn't exist"
+                }
+            );
+        }
+        Ok(Some(dir))
+    }
+
+    pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        self.working_dir(ctx)?;
+        self.shell().command(&self.command, self.interactive)?;
+        if self.interactive && !(std::io::stdin().is_terminal() && self.terminal) {
+            bail!(
+                "`interactive` needs the user's terminal, which this chat isn't running in. Run the command without it, passing any input it needs with flags or a pipe."
+            );
+        }
+        if let Some(name) = self
+            .env
+            .keys()
+            .find(|name| name.is_empty() || name.contains(['=', '\0']))
+        {
+            bail!("'{name}' isn't a valid environment variable name");
+        }
+        if let Some(reason) = self.destructive_reason() {
+            if fig_settings::settings::get_bool_or(REFUSE_DESTRUCTIVE_SETTING, false) {
+                bail!(
+                    "This command {reason}, and the user has set {REFUSE_DESTRUCTIVE_SETTING} so that such commands are never run. Don't retry it in another form; explain to the user what you wanted to do instead."
+                );
+            }
+        }
         // TODO: probably some small amount of PATH checking
+        if let Some(command) = self.clipboard_command() {
+            bail!(
+                "`{command}` accesses the system clipboard and cannot be run with execute_bash. Use the clipboard tool instead."
+            );
+        }
         Ok(())
     }
+
+    fn shell(&self) -> Shell {
+        self.shell.unwrap_or_else(Shell::platform_default)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        // The user is at the terminal of an interactive command, so it only has an explicit limit.
+        if self.interactive && self.timeout_seconds.is_none() {
+            return None;
+        }
+        let secs = self.timeout_seconds.unwrap_or_else(|| {
+            fig_settings::settings::get_int_or(EXECUTE_BASH_TIMEOUT_SETTING, DEFAULT_TIMEOUT_SECS).max(0) as u64
+        });
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Returns the first clipboard command invoked by [Self::command], if any.
+    fn clipboard_command(&self) -> Option<&'static str> {
+        self.command
+            .split(|c: char| c.is_whitespace() || matches!(c, '|' | ';' | '&' | '(' | ')' | '`'))
+            .filter_map(|word| word.rsplit('/').next())
+            .find_map(|word| CLIPBOARD_COMMANDS.iter().find(|c| **c == word).copied())
+    }
+}
+
+/// Returns the description of the first destructive pattern matching `command`, trying the built in
+/// patterns and then the regular expressions in `extra`. The command is also matched with its
+/// quotes removed and whitespace collapsed, so that e.g. `rm "-rf"  /` is caught.
+fn find_destructive(command: &str, extra: &[String]) -> Option<String> {
+    let normalized = shlex::split(command).map(|words| words.join(" "));
+    let candidates = std::iter::once(command)
+        .chain(normalized.as_deref())
+        .collect::<Vec<_>>();
+    let matches = |regex: &Regex| candidates.iter().any(|c| regex.is_match(c));
+    if let Some((description, _)) = DEFAULT_DESTRUCTIVE_PATTERNS.iter().find(|(_, regex)| matches(regex)) {
+        return Some(description.to_string());
+    }
+    extra.iter().find_map(|pattern| match Regex::new(pattern) {
+        Ok(regex) => {
+            matches(&regex).then(|| format!("matches the pattern `{pattern}` in {DESTRUCTIVE_PATTERNS_SETTING}"))
+        },
+        Err(err) => {
+            warn!(%err, pattern, "Ignoring invalid pattern in {DESTRUCTIVE_PATTERNS_SETTING}");
+            None
+        },
+    })
 }
 
+/// Whether `input` is `command` typed back, ignoring differences in whitespace.
+pub fn is_typed_back(command: &str, input: &str) -> bool {
+    command.split_whitespace().eq(input.split_whitespace())
+}
+
+/// Kills the process group led by the given pid when dropped, unless disarmed. This is how a
+/// running command gets stopped when [run_command] is cancelled.
+struct ProcessGroupGuard(Option<u32>);
+
+impl ProcessGroupGuard {
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            use nix::sys::signal::{
+                Signal,
+                killpg,
+            };
+            use nix::unistd::Pid;
+
+            if let Err(err) = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL) {
+                tracing::debug!(?err, pid, "failed to kill cancelled command");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct CommandResult {
     pub exit_status: Option<i32>,
     /// Truncated stdout
     pub stdout: String,
     /// Truncated stderr
     pub stderr: String,
+    /// Size of the whole of stdout, if it was truncated.
+    pub stdout_bytes: Option<usize>,
+    /// Size of the whole of stderr, if it was truncated.
+    pub stderr_bytes: Option<usize>,
+}
+
+impl CommandResult {
+    fn to_json(&self) -> serde_json::Value {
+        let mut result = serde_json::json!({
+            "exit_status": self.exit_status.unwrap_or(0).to_string(),
+            "stdout": self.stdout,
+            "stderr": self.stderr,
+        });
+        // The full sizes of truncated output, so that the model knows how much it didn't see.
+        if let Some(bytes) = self.stdout_bytes {
+            result["stdout_bytes"] = bytes.into();
+        }
+        if let Some(bytes) = self.stderr_bytes {
+            result["stderr_bytes"] = bytes.into();
+        }
+        result
+    }
+}
+
+/// Keeps the start and end of an output stream within a byte budget, along with its total size,
+/// so that commands with huge output don't use unbounded memory.
+#[derive(Debug)]
+struct OutputBuffer {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    head_limit: usize,
+    tail_limit: usize,
+    total_bytes: usize,
 }
 
-/// Run a bash command.
+impl OutputBuffer {
+    fn new(max_bytes: usize) -> Self {
+        let head_limit = max_bytes / 2;
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            head_limit,
+            tail_limit: max_bytes - head_limit,
+            total_bytes: 0,
+        }
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        self.total_bytes += bytes.len();
+        let head_len = bytes.len().min(self.head_limit - self.head.len());
+        self.head.extend_from_slice(&bytes[..head_len]);
+        bytes = &bytes[head_len..];
+        if bytes.len() > self.tail_limit {
+            bytes = &bytes[bytes.len() - self.tail_limit..];
+        }
+        let overflow = (self.tail.len() + bytes.len()).saturating_sub(self.tail_limit);
+        self.tail.drain(..overflow);
+        self.tail.extend(bytes);
+    }
+
+    /// Pushes a line read without its line ending, separating it from the previous line.
+    fn push_line(&mut self, line: &str, first: bool) {
+        if !first {
+            self.push(b"\n");
+        }
+        self.push(line.as_bytes());
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.total_bytes > self.head.len() + self.tail.len()
+    }
+
+    /// The output as text, with a marker saying how much was left out of the middle. Characters
+    /// split by the truncation are dropped rather than replaced.
+    fn into_string(self) -> String {
+        let OutputBuffer {
+            mut head,
+            tail,
+            total_bytes,
+            ..
+        } = self;
+        let mut tail = Vec::from(tail);
+        if total_bytes == head.len() + tail.len() {
+            head.append(&mut tail);
+            return String::from_utf8_lossy(&head).into_owned();
+        }
+
+        if let Err(err) = std::str::from_utf8(&head) {
+            if err.error_len().is_none() {
+                head.truncate(err.valid_up_to());
+            }
+        }
+        let partial = tail.iter().take(3).take_while(|b| (**b & 0xc0) == 0x80).count();
+        let tail = &tail[partial..];
+        format!(
+            "{}\n[… {} truncated …]\n{}",
+            String::from_utf8_lossy(&head),
+            format_size(total_bytes - head.len() - tail.len()),
+            String::from_utf8_lossy(tail)
+        )
+    }
+}
+
+/// Reads all of `reader` into `buffer`.
+async fn read_into(mut reader: impl AsyncRead + Unpin, buffer: &mut OutputBuffer) -> std::io::Result<()> {
+    let mut chunk = [0; 8192];
+    loop {
+        match reader.read(&mut chunk).await? {
+            0 => return Ok(()),
+            n => buffer.push(&chunk[..n]),
+        }
+    }
+}
+
+/// Reads [EXECUTE_BASH_MAX_OUTPUT_BYTES_SETTING], capped to what a tool response can hold.
+fn max_output_bytes() -> usize {
+    (fig_settings::settings::get_int_or(EXECUTE_BASH_MAX_OUTPUT_BYTES_SETTING, DEFAULT_MAX_OUTPUT_BYTES).max(0)
+        as usize)
+        .min(MAX_TOOL_RESPONSE_SIZE / 3)
+}
+
+/// Run a shell command.
 /// # Arguments
-/// * `max_result_size` - max size of output streams, truncating if required
+/// * `shell` - the shell that runs the command
+/// * `cwd` - directory to run the command in, instead of the current one
+/// * `env` - environment variables to set, where an empty value unsets the variable
+/// * `max_result_size` - max size of each output stream, keeping its start and end if longer
+/// * `timeout` - how long the command may run before it, and every process it started, is killed
 /// * `updates` - output stream to push informational messages about the progress
 /// # Returns
-/// A [`CommandResult`]
+/// A [`CommandResult`], or an error with the output captured so far if the command timed out
 pub async fn run_command<W: Write>(
     command: &str,
+    shell: Shell,
+    cwd: Option<&Path>,
+    env: &BTreeMap<String, String>,
     max_result_size: usize,
+    timeout: Option<Duration>,
     mut updates: Option<W>,
 ) -> Result<CommandResult> {
+    let mut cmd = shell.command(command, false)?;
+    // Run in a separate process group so that the command, and anything it spawns, can be killed
+    // as a unit if the tool is cancelled. Since the group is never in the foreground, reading an
+    // inherited terminal would stop the command, so stdin is empty instead, on every platform so
+    // that commands behave the same. The tool description tells the model to pipe input in, or to
+    // use `interactive`.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    cmd.stdin(Stdio::null());
+    configure_command(&mut cmd, cwd, env);
+
     // We need to maintain a handle on stderr and stdout, but pipe it to the terminal as well
-    let mut child = tokio::process::Command::new("bash")
-        .arg("-c")
-        .arg(command)
-        .stdin(Stdio::inherit())
+    let mut child = cmd
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
         .wrap_err_with(|| format!("Unable to spawn command '{}'", command))?;
+    let mut guard = ProcessGroupGuard(child.id());
+    let pgid = child.id();
+    let interrupted = async {
+        let deadline = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        select! {
+            () = deadline => Interruption::TimedOut,
+            () = stopped_for_terminal_input(pgid) => Interruption::WaitingForInput,
+        }
+    };
+    tokio::pin!(interrupted);
 
-    let stdout_final: String;
-    let stderr_final: String;
-    let exit_status: ExitStatus;
+    let mut stdout_buf = OutputBuffer::new(max_result_size);
+    let mut stderr_buf = OutputBuffer::new(max_result_size);
+    let exit_status: Result<ExitStatus, Interruption>;
 
     // Buffered output vs all-at-once
     if let Some(u) = updates.as_mut() {
@@ -172,89 +651,260 @@ pub async fn run_command<W: Write>(
         let stderr = tokio::io::BufReader::new(stderr);
         let mut stderr = stderr.lines();
 
-        const LINE_COUNT: usize = 1024;
-        let mut stdout_buf = VecDeque::with_capacity(LINE_COUNT);
-        let mut stderr_buf = VecDeque::with_capacity(LINE_COUNT);
-
         let mut stdout_done = false;
         let mut stderr_done = false;
+        let (mut stdout_lines, mut stderr_lines) = (0, 0);
         exit_status = loop {
             select! {
                 biased;
                 line = stdout.next_line(), if !stdout_done => match line {
                     Ok(Some(line)) => {
-                        writeln!(u, "{line}")?;
-                        if stdout_buf.len() >= LINE_COUNT {
-                            stdout_buf.pop_front();
-                        }
-                        stdout_buf.push_back(line);
+                        queue_live_line(u, &line)?;
+                        stdout_buf.push_line(&line, stdout_lines == 0);
+                        stdout_lines += 1;
                     },
                     Ok(None) => stdout_done = true,
                     Err(err) => error!(%err, "Failed to read stdout of child process"),
                 },
                 line = stderr.next_line(), if !stderr_done => match line {
                     Ok(Some(line)) => {
-                        writeln!(u, "{line}")?;
-                        if stderr_buf.len() >= LINE_COUNT {
-                            stderr_buf.pop_front();
-                        }
-                        stderr_buf.push_back(line);
+                        queue_live_line(u, &line)?;
+                        stderr_buf.push_line(&line, stderr_lines == 0);
+                        stderr_lines += 1;
                     },
                     Ok(None) => stderr_done = true,
                     Err(err) => error!(%err, "Failed to read stderr of child process"),
                 },
                 exit_status = child.wait() => {
-                    break exit_status;
+                    break Ok(exit_status.wrap_err_with(|| format!("No exit status for '{}'", command))?);
                 },
+                interruption = &mut interrupted => break Err(interruption),
             };
-        }
-        .wrap_err_with(|| format!("No exit status for '{}'", command))?;
+        };
 
         u.flush()?;
-
-        stdout_final = stdout_buf.into_iter().collect::<Vec<_>>().join("\n");
-        stderr_final = stderr_buf.into_iter().collect::<Vec<_>>().join("\n");
     } else {
         // Take output all at once since we are not reporting anything in real time
         //
         // NOTE: If we don't split this logic, then any writes to stdout while calling
         // this function concurrently may cause the piped child output to be ignored
 
-        let output = child
-            .wait_with_output()
-            .await
-            .wrap_err_with(|| format!("No exit status for '{}'", command))?;
-
-        exit_status = output.status;
-        stdout_final = from_utf8(&output.stdout).unwrap_or_default().to_string();
-        stderr_final = from_utf8(&output.stderr).unwrap_or_default().to_string();
+        // The output is read into the buffers as it arrives, so what was read is kept on a timeout.
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        exit_status = select! {
+            (status, _, _) = async {
+                tokio::join!(
+                    child.wait(),
+                    read_into(stdout, &mut stdout_buf),
+                    read_into(stderr, &mut stderr_buf)
+                )
+            } => Ok(status.wrap_err_with(|| format!("No exit status for '{}'", command))?),
+            interruption = &mut interrupted => Err(interruption),
+        };
     }
 
+    let exit_status = match exit_status {
+        Ok(exit_status) => exit_status,
+        Err(interruption) => {
+            // Dropping the guard kills the command's process group.
+            drop(guard);
+            let (stdout, stderr) = (stdout_buf.into_string(), stderr_buf.into_string());
+            match interruption {
+                Interruption::TimedOut => bail!(
+                    "The command timed out after {:?}, so it was killed along with every process it started. Output before the timeout:\nstdout:\n{stdout}\nstderr:\n{stderr}",
+                    timeout.unwrap_or_default(),
+                ),
+                Interruption::WaitingForInput => bail!(
+                    "The command stopped to wait for input from the terminal, which it can't get unless `interactive` is true, so it was killed along with every process it started. Run it again with `interactive` set to true so that the user can respond, or pass the input it needs with flags or a pipe. Output before it stopped:\nstdout:\n{stdout}\nstderr:\n{stderr}"
+                ),
+            }
+        },
+    };
+
+    // The command finished on its own, so leave anything it intentionally backgrounded running.
+    guard.disarm();
+
     Ok(CommandResult {
         exit_status: exit_status.code(),
-        stdout: format!(
-            "{}{}",
-            truncate_safe(&stdout_final, max_result_size),
-            if stdout_final.len() > max_result_size {
-                " ... truncated"
-            } else {
-                ""
-            }
-        ),
-        stderr: format!(
-            "{}{}",
-            truncate_safe(&stderr_final, max_result_size),
-            if stderr_final.len() > max_result_size {
-                " ... truncated"
-            } else {
-                ""
+        stdout_bytes: stdout_buf.is_truncated().then_some(stdout_buf.total_bytes),
+        stderr_bytes: stderr_buf.is_truncated().then_some(stderr_buf.total_bytes),
+        stdout: stdout_buf.into_string(),
+        stderr: stderr_buf.into_string(),
+    })
+}
+
+/// Tells the model which shell commands run in by default, since it differs between platforms.
+pub fn describe_default_shell(tools: &mut HashMap<String, ToolSpec>) {
+    if let Some(spec) = tools.get_mut("execute_bash") {
+        spec.description.push_str(&format!(
+            " Commands run in {} unless `shell` is given, so write them for that shell.",
+            Shell::platform_default().name()
+        ));
+    }
+}
+
+/// Why a command was killed before it exited.
+enum Interruption {
+    TimedOut,
+    /// The command tried to read from or configure the terminal, which stops processes outside of
+    /// its foreground process group.
+    WaitingForInput,
+}
+
+/// Sets the directory and environment that a command runs with.
+fn configure_command(cmd: &mut tokio::process::Command, cwd: Option<&Path>, env: &BTreeMap<String, String>) {
+    if let Some(cwd) = cwd {
+        cmd.current_dir(cwd);
+    }
+    for (name, value) in env {
+        if value.is_empty() {
+            cmd.env_remove(name);
+        } else {
+            cmd.env(name, value);
+        }
+    }
+}
+
+/// Completes once a process in the process group `pgid` is stopped, as happens when a command run
+/// in the background reads from the terminal. Never completes where this can't be checked.
+async fn stopped_for_terminal_input(pgid: Option<u32>) {
+    let Some(pgid) = pgid.filter(|_| cfg!(unix)) else {
+        return std::future::pending().await;
+    };
+    loop {
+        tokio::time::sleep(STOPPED_POLL_INTERVAL).await;
+        if is_group_stopped(pgid).await {
+            return;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn is_group_stopped(pgid: u32) -> bool {
+    let Ok(mut entries) = tokio::fs::read_dir("/proc").await else {
+        return false;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry.file_name().to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(stat) = tokio::fs::read_to_string(entry.path().join("stat")).await else {
+            continue;
+        };
+        // The fields after the parenthesized command name are the state, parent pid and group.
+        let fields = stat
+            .rsplit_once(')')
+            .map(|(_, rest)| rest.split_whitespace().collect::<Vec<_>>())
+            .unwrap_or_default();
+        if let [state, _, group, ..] = fields.as_slice() {
+            if *state == "T" && group.parse() == Ok(pgid) {
+                return true;
             }
-        ),
+        }
+    }
+    false
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn is_group_stopped(pgid: u32) -> bool {
+    let Ok(output) = tokio::process::Command::new("ps")
+        .args(["-A", "-o", "pgid=,stat="])
+        .output()
+        .await
+    else {
+        return false;
+    };
+    String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        fields.next().and_then(|group| group.parse().ok()) == Some(pgid)
+            && fields.next().is_some_and(|stat| stat.starts_with('T'))
+    })
+}
+
+/// Queues a line of a command's output to be shown as it runs, dimmed so that it stands apart from
+/// the model's responses.
+fn queue_live_line(updates: &mut impl Write, line: &str) -> Result<()> {
+    queue!(
+        updates,
+        style::SetForegroundColor(Color::DarkGrey),
+        style::Print(displayable_line(line)),
+        style::ResetColor,
+        style::Print("\n"),
+    )?;
+    Ok(())
+}
+
+/// `line` without the escape sequences and control characters that would move the cursor or
+/// change colors, keeping only what was written after the last carriage return as a terminal
+/// would for progress bars.
+fn displayable_line(line: &str) -> String {
+    let line = line.trim_end_matches('\r');
+    let line = line.rsplit('\r').next().unwrap_or_default();
+    // Tabs would otherwise be stripped along with the other control characters.
+    strip_ansi_escapes::strip_str(line.replace('\t', "    "))
+}
+
+/// Reads [ALLOWED_COMMANDS_SETTING], falling back to [DEFAULT_ALLOWED_COMMANDS].
+fn allowed_commands() -> Vec<String> {
+    fig_settings::settings::get::<Vec<String>>(ALLOWED_COMMANDS_SETTING)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| DEFAULT_ALLOWED_COMMANDS.iter().map(|c| c.to_string()).collect())
+}
+
+/// Whether every command in `command` starts with the words of an entry in `allowed`, so that it
+/// can run without approval. Commands may only be joined by [COMMAND_SEPARATORS], and anything else
+/// the shell interprets, such as redirection or command substitution, needs approval.
+fn is_allowlisted(command: &str, allowed: &[String]) -> bool {
+    // shlex treats newlines as spaces, but bash runs each line as a separate command.
+    if command.contains('\n') {
+        return false;
+    }
+    let Some(args) = shlex::split(command) else {
+        return false;
+    };
+
+    const DANGEROUS_PATTERNS: &[&str] = &["<(", "$(", "`", ">", "<"];
+    if args
+        .iter()
+        .any(|arg| DANGEROUS_PATTERNS.iter().any(|p| arg.contains(p)))
+    {
+        return false;
+    }
+
+    let mut commands = vec![Vec::new()];
+    for arg in args {
+        if COMMAND_SEPARATORS.contains(&arg.as_str()) {
+            commands.push(Vec::new());
+        } else if arg.contains([';', '|', '&']) {
+            // Operators without spacing, e.g. `ls;rm myimportantfile` or `ls &`, aren't split out
+            // by shlex, so they are verified before running.
+            return false;
+        } else if let Some(current) = commands.last_mut() {
+            current.push(arg);
+        }
+    }
+
+    let allowed = allowed
+        .iter()
+        .filter_map(|entry| shlex::split(entry))
+        .collect::<Vec<_>>();
+    commands.iter().all(|cmd_args| {
+        !cmd_args.is_empty()
+            && allowed
+                .iter()
+                .any(|entry| !entry.is_empty() && cmd_args.starts_with(entry))
+            && !cmd_args
+                .iter()
+                .any(|arg| UNSAFE_ARG_PREFIXES.iter().any(|prefix| arg.starts_with(prefix)))
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
 
     #[ignore = "todo: fix failing on musl for some reason"]
@@ -268,7 +918,7 @@ mod tests {
         });
         let out = serde_json::from_value::<ExecuteBash>(v)
             .unwrap()
-            .invoke(&mut stdout)
+            .invoke(&Context::new_fake(), &mut stdout)
             .await
             .unwrap();
 
@@ -286,7 +936,7 @@ mod tests {
         });
         let out = serde_json::from_value::<ExecuteBash>(v)
             .unwrap()
-            .invoke(&mut stdout)
+            .invoke(&Context::new_fake(), &mut stdout)
             .await
             .unwrap();
 
@@ -305,7 +955,7 @@ mod tests {
         });
         let out = serde_json::from_value::<ExecuteBash>(v)
             .unwrap()
-            .invoke(&mut stdout)
+            .invoke(&Context::new_fake(), &mut stdout)
             .await
             .unwrap();
         if let OutputKind::Json(json) = out.output {
@@ -355,6 +1005,23 @@ mod tests {
             ("find . -name '*.c' -execdir gcc -o '{}.out' '{}' \\;", true),
             ("find important-dir/ -delete", true),
             ("find important-dir/ -name '*.txt'", false),
+            // Allowlisted prefixes
+            ("git status", false),
+            ("git diff HEAD~1 -- src/", false),
+            ("git log --oneline | head -n 5", false),
+            ("git status && git diff", false),
+            ("ls ; pwd", false),
+            ("git push", true),
+            ("git statusx", true),
+            ("git diff --output=patch.diff", true),
+            // Lists that aren't entirely allowlisted
+            ("ls; rm -rf ~", true),
+            ("ls ;rm -rf ~", true),
+            ("ls\nrm -rf ~", true),
+            ("ls &", true),
+            ("ls & rm -rf ~", true),
+            ("git status;", true),
+            ("cat < myimportantfile", true),
         ];
         for (cmd, expected) in cmds {
             let tool = serde_json::from_value::<ExecuteBash>(serde_json::json!({
@@ -370,4 +1037,447 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_displayable_line() {
+        assert_eq!(displayable_line("plain\toutput"), "plain    output");
+        assert_eq!(
+            displayable_line("\x1b[1;32mCompiling\x1b[0m q_chat"),
+            "Compiling q_chat"
+        );
+        assert_eq!(displayable_line("10%\r50%\r100%\r"), "100%");
+        assert_eq!(displayable_line("\x1b[2K\x1b[1Gdone\x07"), "done");
+        assert_eq!(displayable_line("\x1b]0;title\x07text"), "text");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_live_output() {
+        let mut updates = Vec::new();
+        let output = run_command(
+            "printf 'one\\ntwo\\n'; printf 'oops\\n' >&2",
+            Shell::Bash,
+            None,
+            &BTreeMap::new(),
+            1024,
+            None,
+            Some(&mut updates),
+        )
+        .await
+        .unwrap();
+        let live = String::from_utf8(updates).unwrap();
+        for line in ["one", "two", "oops"] {
+            assert!(live.contains(&format!("{line}\x1b[0m\n")), "{live:?}");
+        }
+        assert_eq!(output.stdout, "one\ntwo");
+        assert_eq!(output.stderr, "oops");
+
+        // Output is only streamed when the updates are shown on a terminal.
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut tool = serde_json::from_value::<ExecuteBash>(serde_json::json!({ "command": "echo live" })).unwrap();
+        let mut updates = Vec::new();
+        tool.invoke(&ctx, &mut updates).await.unwrap();
+        assert!(updates.is_empty());
+        tool.set_terminal(true);
+        tool.invoke(&ctx, &mut updates).await.unwrap();
+        assert!(String::from_utf8(updates).unwrap().contains("live"));
+    }
+
+    #[test]
+    fn test_describe_default_shell() {
+        let mut tools: HashMap<String, ToolSpec> =
+            serde_json::from_str(include_str!("tool_index.json")).expect("tool index is valid");
+        describe_default_shell(&mut tools);
+        let description = &tools["execute_bash"].description;
+        assert!(
+            description.ends_with(&format!(
+                "Commands run in {} unless `shell` is given, so write them for that shell.",
+                Shell::platform_default().name()
+            )),
+            "{description}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shell() {
+        let ctx = Context::new_fake();
+        let mut tool = serde_json::from_value::<ExecuteBash>(serde_json::json!({
+            "command": "echo $0",
+            "shell": "sh",
+        }))
+        .unwrap();
+        tool.validate(&ctx).await.unwrap();
+        let mut description = Vec::new();
+        tool.queue_description(&ctx, &mut description).unwrap();
+        let description = String::from_utf8(strip_ansi_escapes::strip(description)).unwrap();
+        assert!(description.contains("in shell: sh"), "{description}");
+
+        let OutputKind::Json(output) = tool.invoke(&ctx, std::io::sink()).await.unwrap().output else {
+            panic!("Expected JSON output");
+        };
+        assert!(output["stdout"].as_str().unwrap().ends_with("sh"), "{output}");
+
+        tool.shell = Some(Shell::Cmd);
+        if Shell::Cmd.program().is_none() {
+            assert!(tool.validate(&ctx).await.is_err());
+        }
+        assert!(
+            serde_json::from_value::<ExecuteBash>(serde_json::json!({ "command": "ls", "shell": "fish" })).is_err()
+        );
+    }
+
+    #[test]
+    fn test_requires_acceptance_with_env() {
+        let tool = |env: serde_json::Value| {
+            serde_json::from_value::<ExecuteBash>(serde_json::json!({ "command": "git status", "env": env })).unwrap()
+        };
+        assert!(!tool(serde_json::json!({})).requires_acceptance());
+        assert!(!tool(serde_json::json!({ "NO_COLOR": "1", "COLUMNS": "120" })).requires_acceptance());
+        for env in [
+            serde_json::json!({
+                "GIT_CONFIG_COUNT": "1",
+                "GIT_CONFIG_KEY_0": "core.fsmonitor",
+                "GIT_CONFIG_VALUE_0": "touch pwned",
+            }),
+            serde_json::json!({ "BASH_ENV": "/tmp/script.sh" }),
+            serde_json::json!({ "PATH": "/tmp/evil:/usr/bin" }),
+            serde_json::json!({ "GIT_EXTERNAL_DIFF": "touch pwned" }),
+            serde_json::json!({ "LD_PRELOAD": "/tmp/evil.so" }),
+            serde_json::json!({ "NO_COLOR": "1", "GIT_PAGER": "touch pwned" }),
+        ] {
+            assert!(tool(env.clone()).requires_acceptance(), "{env}");
+        }
+    }
+
+    #[test]
+    fn test_allowed_commands_setting() {
+        let allowed = ["cargo check".to_string(), "git status".to_string()];
+        assert!(is_allowlisted("cargo check -p q_chat", &allowed));
+        assert!(is_allowlisted("git status | cargo check", &allowed));
+        assert!(!is_allowlisted("cargo build", &allowed));
+        assert!(!is_allowlisted("ls", &allowed));
+        assert!(!is_allowlisted("ls", &[]));
+        assert!(!is_allowlisted("ls", &[String::new()]));
+    }
+
+    #[test]
+    fn test_destructive_patterns() {
+        for (command, destructive) in [
+            ("rm -rf /", true),
+            ("rm -rf / --no-preserve-root", true),
+            ("sudo rm -r -f ~", true),
+            ("rm \"-rf\"  '/'", true),
+            ("cd /tmp && rm -rf $HOME/*", true),
+            ("mkfs.ext4 /dev/sdb1", true),
+            ("dd if=image.iso of=/dev/disk2 bs=4m", true),
+            ("cat zeros > /dev/sda", true),
+            (":(){ :|:& };:", true),
+            ("git push --force origin main", true),
+            ("git push origin master -f", true),
+            ("git push origin +release/1.2", true),
+            ("chmod -R 777 /", true),
+            ("rm -rf ./build", false),
+            ("rm -rf /tmp/build", false),
+            ("dd if=/dev/zero of=disk.img", false),
+            ("git push --force origin my-feature", false),
+            ("git push origin main", false),
+            ("echo mkfsx", false),
+        ] {
+            assert_eq!(find_destructive(command, &[]).is_some(), destructive, "{command}");
+        }
+
+        let extra = vec![r"\bterraform\s+destroy\b".to_string(), "(invalid".to_string()];
+        let reason = find_destructive("terraform destroy -auto-approve", &extra).unwrap();
+        assert!(reason.contains(DESTRUCTIVE_PATTERNS_SETTING), "{reason}");
+        assert!(find_destructive("terraform plan", &extra).is_none());
+
+        // Variables from `env` are expanded before matching.
+        let tool = serde_json::from_value::<ExecuteBash>(serde_json::json!({
+            "command": "rm -rf ${TARGET}",
+            "env": { "TARGET": "/" },
+        }))
+        .unwrap();
+        assert_eq!(tool.expanded_command(), "rm -rf /");
+
+        assert!(is_typed_back("rm -rf /", "  rm  -rf / "));
+        assert!(!is_typed_back("rm -rf /", "y"));
+    }
+
+    #[tokio::test]
+    async fn test_clipboard_commands_are_redirected() {
+        let ctx = Context::new_fake();
+        for command in [
+            "echo secret | pbcopy",
+            "cat file.txt|xclip -selection clipboard",
+            "/usr/bin/wl-copy < out.txt",
+            "pbpaste > notes.txt",
+        ] {
+            let mut tool = serde_json::from_value::<ExecuteBash>(serde_json::json!({ "command": command })).unwrap();
+            let err = tool.validate(&ctx).await.unwrap_err();
+            assert!(err.to_string().contains("Use the clipboard tool instead"), "{command}");
+        }
+
+        let mut tool =
+            serde_json::from_value::<ExecuteBash>(serde_json::json!({ "command": "echo xclipboard" })).unwrap();
+        assert!(tool.validate(&ctx).await.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancelled_command_is_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let command = format!("(sleep 1; touch {}) & wait", marker.display());
+
+        let res = tokio::time::timeout(
+            Duration::from_millis(200),
+            run_command(
+                &command,
+                Shell::Bash,
+                None,
+                &BTreeMap::new(),
+                1024,
+                None,
+                Some(std::io::sink()),
+            ),
+        )
+        .await;
+        assert!(res.is_err(), "command should still have been running");
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists(), "the command's process group should have been killed");
+    }
+
+    #[test]
+    fn test_output_buffer() {
+        let mut buffer = OutputBuffer::new(10);
+        buffer.push(b"short");
+        assert!(!buffer.is_truncated());
+        assert_eq!(buffer.into_string(), "short");
+
+        // Output arriving in many small pieces keeps the same start and end as one big piece.
+        let output = "0123456789".repeat(100);
+        let mut whole = OutputBuffer::new(10);
+        whole.push(output.as_bytes());
+        let mut pieces = OutputBuffer::new(10);
+        for piece in output.as_bytes().chunks(3) {
+            pieces.push(piece);
+        }
+        assert!(whole.is_truncated());
+        assert_eq!(whole.total_bytes, 1000);
+        assert_eq!(whole.into_string(), "01234\n[… 990 bytes truncated …]\n56789");
+        assert_eq!(pieces.into_string(), "01234\n[… 990 bytes truncated …]\n56789");
+
+        let mut lines = OutputBuffer::new(100);
+        lines.push_line("one", true);
+        lines.push_line("two", false);
+        assert_eq!(lines.into_string(), "one\ntwo");
+    }
+
+    #[test]
+    fn test_output_buffer_utf8_boundaries() {
+        // Each "é" is two bytes, so both ends of the budget split one.
+        let output = "é".repeat(100);
+        let mut buffer = OutputBuffer::new(10);
+        buffer.push(output.as_bytes());
+        assert_eq!(buffer.into_string(), "éé\n[… 192 bytes truncated …]\néé");
+
+        // Multi-byte characters split across pushes.
+        let mut buffer = OutputBuffer::new(1024);
+        let bytes = "日本語".as_bytes();
+        buffer.push(&bytes[..4]);
+        buffer.push(&bytes[4..]);
+        assert_eq!(buffer.into_string(), "日本語");
+
+        let mut buffer = OutputBuffer::new(1024 * 1024);
+        buffer.push(&vec![b'x'; 3 * 1024 * 1024]);
+        assert!(buffer.into_string().contains("[… 2MB truncated …]"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_interleaved_output_is_truncated_per_stream() {
+        let command = "for i in $(seq 1 2000); do echo \"out $i\"; echo \"err $i\" >&2; done";
+        for updates in [Some(std::io::sink()), None] {
+            let is_streamed = updates.is_some();
+            let output = run_command(command, Shell::Bash, None, &BTreeMap::new(), 1024, None, updates)
+                .await
+                .unwrap();
+            for (stream, bytes, prefix) in [
+                (&output.stdout, output.stdout_bytes, "out"),
+                (&output.stderr, output.stderr_bytes, "err"),
+            ] {
+                assert!(stream.starts_with(&format!("{prefix} 1\n{prefix} 2\n")), "{stream}");
+                assert!(stream.ends_with(&format!("{prefix} 2000{}", if is_streamed { "" } else { "\n" })));
+                assert!(stream.contains("truncated …]"), "{stream}");
+                assert!(!stream.contains(if prefix == "out" { "err" } else { "out" }));
+                // Streamed output is read as lines, which loses the final line ending.
+                let total = (1..=2000).map(|i| format!("{prefix} {i}\n").len()).sum::<usize>();
+                assert_eq!(bytes, Some(total - usize::from(is_streamed)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_working_dir() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().create_dir_all("/services/api").await.unwrap();
+        let tool = |cwd: &str| {
+            serde_json::from_value::<ExecuteBash>(serde_json::json!({ "command": "pwd", "cwd": cwd })).unwrap()
+        };
+
+        let mut api = tool("services/api");
+        api.validate(&ctx).await.unwrap();
+        assert_eq!(
+            api.working_dir(&ctx).unwrap(),
+            Some(ctx.fs().chroot_path("/services/api"))
+        );
+        let mut description = Vec::new();
+        api.queue_description(&ctx, &mut description).unwrap();
+        let description = String::from_utf8(strip_ansi_escapes::strip(description)).unwrap();
+        assert!(description.contains("run in: ./services/api"), "{description}");
+
+        let mut absolute = tool("/services");
+        absolute.validate(&ctx).await.unwrap();
+
+        let err = tool("services/web").validate(&ctx).await.unwrap_err().to_string();
+        assert!(err.contains("services/web' doesn't exist"), "{err}");
+        let err = tool("services/web")
+            .invoke(&ctx, std::io::sink())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("doesn't exist"), "{err}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_env() {
+        let ctx = Context::new_fake();
+        // HOME is inherited from the test process, so unsetting it must override that.
+        assert!(std::env::var_os("HOME").is_some());
+        let mut tool = serde_json::from_value::<ExecuteBash>(serde_json::json!({
+            "command": "echo \"$Q_TEST_GREETING ${HOME-unset}\"",
+            "env": { "Q_TEST_GREETING": "hello world", "HOME": "" },
+        }))
+        .unwrap();
+        tool.validate(&ctx).await.unwrap();
+        let output = run_command(
+            &tool.command,
+            Shell::Bash,
+            None,
+            &tool.env,
+            1024,
+            None,
+            None::<std::io::Sink>,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.stdout, "hello world unset\n");
+
+        tool.env.insert("HOME".to_string(), "explicit".to_string());
+        let output = run_command(
+            &tool.command,
+            Shell::Bash,
+            None,
+            &tool.env,
+            1024,
+            None,
+            None::<std::io::Sink>,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.stdout, "hello world explicit\n");
+
+        tool.env
+            .insert("GITHUB_TOKEN".to_string(), "ghp_1234567890".to_string());
+        let mut description = Vec::new();
+        tool.queue_description(&ctx, &mut description).unwrap();
+        let description = String::from_utf8(strip_ansi_escapes::strip(description)).unwrap();
+        assert!(
+            description
+                .contains("with environment: GITHUB_TOKEN=<redacted> HOME=explicit Q_TEST_GREETING='hello world'"),
+            "{description}"
+        );
+
+        tool.env.insert("BAD=NAME".to_string(), "x".to_string());
+        assert!(tool.validate(&ctx).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let command = format!("echo started; (sleep 1; touch {}) & wait", marker.display());
+
+        for updates in [Some(std::io::sink()), None] {
+            let err = run_command(
+                &command,
+                Shell::Bash,
+                None,
+                &BTreeMap::new(),
+                1024,
+                Some(Duration::from_millis(200)),
+                updates,
+            )
+            .await
+            .expect_err("command should have timed out")
+            .to_string();
+            assert!(err.contains("timed out after 200ms"), "{err}");
+            assert!(err.contains("stdout:\nstarted"), "{err}");
+        }
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists(), "the command's process group should have been killed");
+
+        let output = run_command(
+            "echo done",
+            Shell::Bash,
+            None,
+            &BTreeMap::new(),
+            1024,
+            Some(Duration::from_secs(10)),
+            Some(std::io::sink()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.stdout, "done");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_stdin_is_empty() {
+        let output = run_command(
+            "cat; read -r line || echo eof",
+            Shell::Bash,
+            None,
+            &BTreeMap::new(),
+            1024,
+            Some(Duration::from_secs(10)),
+            Some(std::io::sink()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.stdout, "eof");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_waiting_for_terminal_input() {
+        // Stopped the way that reading from the terminal outside of the foreground group would.
+        let err = run_command(
+            "echo started; kill -STOP $$",
+            Shell::Bash,
+            None,
+            &BTreeMap::new(),
+            1024,
+            None,
+            Some(std::io::sink()),
+        )
+        .await
+        .expect_err("command should have been stopped")
+        .to_string();
+        assert!(err.contains("`interactive` set to true"), "{err}");
+        assert!(err.contains("stdout:\nstarted"), "{err}");
+    }
 }
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{
+    self,
+    IsTerminal,
+    Write,
+};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::time::Duration;
+
+use crossterm::terminal;
+use eyre::{
+    Context as EyreContext,
+    Result,
+    bail,
+};
+use nix::pty::{
+    Winsize,
+    openpty,
+};
+use nix::sys::signal::{
+    SigHandler,
+    Signal,
+    signal,
+};
+use tokio::io::AsyncReadExt;
+use tokio::select;
+
+use super::{
+    CommandResult,
+    OutputBuffer,
+    ProcessGroupGuard,
+    Shell,
+    configure_command,
+    displayable_line,
+};
+
+/// How long the user's keyboard is polled for before checking whether the command has exited, so
+/// that a keystroke meant for the chat isn't read after the command is done.
+const INPUT_POLL_MILLIS: i32 = 50;
+
+/// How long output written by the command just before it exited is waited for.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Runs `command` in a pseudo-terminal that is connected to the user's terminal, so that they can
+/// respond to any prompts. What the terminal showed, including echoed input, is returned as stdout.
+pub async fn run_interactive<W: Write>(
+    command: &str,
+    shell: Shell,
+    cwd: Option<&Path>,
+    env: &BTreeMap<String, String>,
+    max_result_size: usize,
+    timeout: Option<Duration>,
+    mut updates: W,
+) -> Result<CommandResult> {
+    let (cols, rows) = terminal::size().unwrap_or((80, 24));
+    let winsize = Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let pty = openpty(Some(&winsize), None).wrap_err("Unable to open a pseudo-terminal")?;
+
+    let mut cmd = shell.command(command, true)?;
+    configure_command(&mut cmd, cwd, env);
+    cmd.stdin(pty.slave.try_clone()?)
+        .stdout(pty.slave.try_clone()?)
+        .stderr(pty.slave.try_clone()?);
+    // The command gets its own session with the pseudo-terminal as its controlling terminal, so
+    // that it can prompt through /dev/tty and is sent signals for Ctrl+C. Its process group is then
+    // its own pid, as with other commands.
+    let pre_exec = || {
+        for signo in [
+            Signal::SIGCHLD,
+            Signal::SIGHUP,
+            Signal::SIGINT,
+            Signal::SIGQUIT,
+            Signal::SIGPIPE,
+        ] {
+            unsafe { signal(signo, SigHandler::SigDfl) }?;
+        }
+        nix::unistd::setsid()?;
+        // The type of TIOCSCTTY differs between platforms.
+        if unsafe { libc::ioctl(0, libc::TIOCSCTTY as _, 0) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    };
+    unsafe { cmd.pre_exec(pre_exec) };
+    let mut child = cmd
+        .spawn()
+        .wrap_err_with(|| format!("Unable to spawn command '{}'", command))?;
+    let mut guard = ProcessGroupGuard(child.id());
+    // Only the command holds the terminal open, so that reading from it ends when the command does.
+    drop(pty.slave);
+
+    let master = File::from(pty.master);
+    let input = InputForwarder::start(master.try_clone()?)?;
+    let mut output = tokio::fs::File::from_std(master);
+    let mut transcript = OutputBuffer::new(max_result_size);
+    let mut buf = [0; 4096];
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+
+    let mut output_done = false;
+    let exit_status = loop {
+        select! {
+            read = output.read(&mut buf), if !output_done => match read {
+                Ok(n) if n > 0 => {
+                    updates.write_all(&buf[..n])?;
+                    updates.flush()?;
+                    transcript.push(&buf[..n]);
+                },
+                // The terminal reports an error rather than the end of input on Linux once the
+                // command has closed it.
+                _ => output_done = true,
+            },
+            exit_status = child.wait() => {
+                break Some(exit_status.wrap_err_with(|| format!("No exit status for '{}'", command))?);
+            },
+            () = &mut deadline => break None,
+        }
+    };
+    drop(input);
+
+    while !output_done {
+        match tokio::time::timeout(DRAIN_TIMEOUT, output.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => {
+                updates.write_all(&buf[..n])?;
+                transcript.push(&buf[..n]);
+            },
+            _ => output_done = true,
+        }
+    }
+    updates.flush()?;
+
+    let transcript_bytes = transcript.is_truncated().then_some(transcript.total_bytes);
+    let transcript = transcript
+        .into_string()
+        .lines()
+        .map(displayable_line)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let Some(exit_status) = exit_status else {
+        drop(guard);
+        bail!(
+            "The command timed out after {:?}, so it was killed along with every process it started. The terminal showed:\n{transcript}",
+            timeout.unwrap_or_default(),
+        );
+    };
+    guard.disarm();
+
+    Ok(CommandResult {
+        exit_status: exit_status.code(),
+        stdout: transcript,
+        stderr: String::new(),
+        stdout_bytes: transcript_bytes,
+        stderr_bytes: None,
+    })
+}
+
+/// Puts the user's terminal in raw mode and copies what they type to the command until dropped.
+/// Nothing is forwarded when stdin isn't a terminal.
+struct InputForwarder {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl InputForwarder {
+    fn start(mut master: File) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        if !io::stdin().is_terminal() {
+            return Ok(Self { stop, thread: None });
+        }
+        terminal::enable_raw_mode().wrap_err("Unable to connect the command to the terminal")?;
+
+        let thread_stop = Arc::clone(&stop);
+        let thread = std::thread::spawn(move || {
+            let stdin = io::stdin();
+            let fd = stdin.as_raw_fd();
+            let mut buf = [0; 1024];
+            while !thread_stop.load(Ordering::Relaxed) {
+                let mut pollfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                match unsafe { libc::poll(&mut pollfd, 1, INPUT_POLL_MILLIS) } {
+                    0 => continue,
+                    n if n < 0 => break,
+                    _ => (),
+                }
+                match nix::unistd::read(fd, &mut buf) {
+                    Ok(n) if n > 0 => {
+                        if master.write_all(&buf[..n]).is_err() {
+                            break;
+                        }
+                    },
+                    _ => break,
+                }
+            }
+        });
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for InputForwarder {
+    fn drop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.stop.store(true, Ordering::Relaxed);
+        if thread.join().is_err() {
+            tracing::error!("Input forwarding thread panicked");
+        }
+        if let Err(err) = terminal::disable_raw_mode() {
+            tracing::error!(%err, "Failed to restore the terminal after an interactive command");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_interactive() {
+        let mut updates = Vec::new();
+        let output = run_interactive(
+            "test -t 0 && test -t 1 && echo \"on a terminal\"; printf 'a\\r\\x1b[1mb\\x1b[0m\\n'; exit 3",
+            Shell::Bash,
+            None,
+            &BTreeMap::new(),
+            1024,
+            None,
+            &mut updates,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.exit_status, Some(3));
+        assert_eq!(output.stdout, "on a terminal\nb");
+        assert!(String::from_utf8(updates).unwrap().contains("on a terminal"));
+
+        let err = run_interactive(
+            "echo started; sleep 10",
+            Shell::Bash,
+            None,
+            &BTreeMap::new(),
+            1024,
+            Some(Duration::from_millis(500)),
+            std::io::sink(),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("timed out"), "{err}");
+        assert!(err.contains("started"), "{err}");
+    }
+}
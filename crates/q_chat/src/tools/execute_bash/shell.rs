@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use base64::prelude::*;
+use eyre::{
+    Result,
+    bail,
+};
+use serde::Deserialize;
+
+/// A shell that commands can be run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Sh,
+    Zsh,
+    Powershell,
+    Cmd,
+}
+
+const ALL_SHELLS: &[Shell] = &[Shell::Bash, Shell::Sh, Shell::Zsh, Shell::Powershell, Shell::Cmd];
+
+impl Shell {
+    /// The shell that commands run in when none is given: bash, or on Windows PowerShell if it is
+    /// installed and cmd otherwise.
+    pub fn platform_default() -> Self {
+        if !cfg!(windows) {
+            Self::Bash
+        } else if Self::Powershell.program().is_some() {
+            Self::Powershell
+        } else {
+            Self::Cmd
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Sh => "sh",
+            Self::Zsh => "zsh",
+            Self::Powershell => "powershell",
+            Self::Cmd => "cmd",
+        }
+    }
+
+    /// The executable that runs this shell, or `None` if it isn't installed. PowerShell Core is
+    /// preferred over Windows PowerShell.
+    pub fn program(self) -> Option<PathBuf> {
+        match self {
+            Self::Powershell => find_on_path("pwsh").or_else(|| find_on_path("powershell")),
+            Self::Cmd if !cfg!(windows) => None,
+            shell => find_on_path(shell.name()),
+        }
+    }
+
+    /// A process that runs `command` in this shell. Interactive commands may prompt for input.
+    pub fn command(self, command: &str, interactive: bool) -> Result<tokio::process::Command> {
+        let Some(program) = self.program() else {
+            let installed = ALL_SHELLS
+                .iter()
+                .filter(|shell| shell.program().is_some())
+                .map(|shell| shell.name())
+                .collect::<Vec<_>>();
+            bail!(
+                "The {} shell isn't installed, or isn't on PATH, so the command wasn't run. Shells that are available: {}",
+                self.name(),
+                installed.join(", ")
+            );
+        };
+        let mut cmd = tokio::process::Command::new(program);
+        match self {
+            Self::Bash | Self::Sh | Self::Zsh => {
+                cmd.arg("-c").arg(command);
+            },
+            Self::Powershell => {
+                cmd.args(["-NoLogo", "-NoProfile"]);
+                if !interactive {
+                    cmd.arg("-NonInteractive");
+                }
+                // Encoding the command sidesteps the ways that PowerShell versions differ in how
+                // they parse quotes in their arguments.
+                cmd.arg("-EncodedCommand").arg(encode_powershell_command(command));
+            },
+            Self::Cmd => {
+                // cmd parses its command line itself rather than following the quoting rules that
+                // arguments are escaped with. `/S` strips only the outer quotes added here.
+                #[cfg(windows)]
+                cmd.raw_arg(format!("/D /S /C \"{command}\""));
+            },
+        }
+        Ok(cmd)
+    }
+}
+
+/// `command` as PowerShell's `-EncodedCommand` expects it, base64 of its UTF-16LE encoding.
+fn encode_powershell_command(command: &str) -> String {
+    let bytes = command.encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+    BASE64_STANDARD.encode(bytes)
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(format!("{name}{}", std::env::consts::EXE_SUFFIX)))
+        .find(|program| program.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Commands whose text must reach the shell unchanged.
+    const QUOTING_CASES: &[(&str, &str)] = &[
+        ("echo 'single quoted'", "single quoted"),
+        ("echo \"double \\\"quoted\\\"\"", "double \"quoted\""),
+        ("echo it\\'s", "it's"),
+        ("echo 'a  b' | tr -s ' '", "a b"),
+        ("printf '%s\\n' 'back\\slash'", "back\\slash"),
+        ("x=1; echo \"$x\" '$x'", "1 $x"),
+        ("echo 'ünïcödé ✓'", "ünïcödé ✓"),
+        ("echo one\necho two", "one\ntwo"),
+    ];
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_posix_quoting() {
+        for shell in [Shell::Bash, Shell::Sh, Shell::Zsh] {
+            if shell.program().is_none() {
+                continue;
+            }
+            for (command, expected) in QUOTING_CASES {
+                let output = shell.command(command, false).unwrap().output().await.unwrap();
+                assert_eq!(
+                    String::from_utf8(output.stdout).unwrap().trim_end(),
+                    *expected,
+                    "{} -c {command:?}",
+                    shell.name()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_powershell_quoting() {
+        assert_eq!(encode_powershell_command("echo 'hi'"), "ZQBjAGgAbwAgACcAaABpACcA");
+        let cmd = Shell::Powershell.command("Write-Output 'it''s \"quoted\"' $HOME", false);
+        let Ok(mut cmd) = cmd else {
+            return;
+        };
+        let args = cmd.as_std().get_args().collect::<Vec<_>>();
+        assert!(args.contains(&"-NonInteractive".as_ref()));
+        let output = cmd.output().await.unwrap();
+        assert!(
+            String::from_utf8_lossy(&output.stdout).starts_with("it's \"quoted\""),
+            "{output:?}"
+        );
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn test_cmd_quoting() {
+        let output = Shell::Cmd
+            .command("echo \"a  b\"&& echo c", false)
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "\"a  b\"\r\nc\r\n");
+    }
+
+    #[test]
+    fn test_program() {
+        #[cfg(unix)]
+        {
+            assert_eq!(Shell::platform_default(), Shell::Bash);
+            assert!(Shell::Bash.program().is_some());
+            assert!(Shell::Cmd.program().is_none());
+            let err = Shell::Cmd.command("dir", false).unwrap_err().to_string();
+            assert!(err.contains("The cmd shell isn't installed"), "{err}");
+            assert!(err.contains("bash"), "{err}");
+        }
+        assert_eq!(
+            serde_json::from_value::<Shell>(serde_json::json!("powershell")).unwrap(),
+            Shell::Powershell
+        );
+    }
+}
@@ -1,7 +1,15 @@
-use std::collections::VecDeque;
+use std::collections::{
+    HashSet,
+    VecDeque,
+};
 use std::fs::Metadata;
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::Arc;
 
 use crossterm::queue;
 use crossterm::style::{
@@ -13,6 +21,11 @@ use eyre::{
     bail,
 };
 use fig_os_shim::Context;
+use ignore::Match;
+use ignore::gitignore::{
+    Gitignore,
+    GitignoreBuilder,
+};
 use serde::{
     Deserialize,
     Serialize,
@@ -23,14 +36,27 @@ use tracing::{
     warn,
 };
 
+use super::encoding::{
+    self,
+    TextEncoding,
+};
 use super::{
     InvokeOutput,
     MAX_TOOL_RESPONSE_SIZE,
     OutputKind,
+    Sandbox,
     format_path,
+    is_within_roots,
+    resolve_symlinks,
     sanitize_path_tool_arg,
+    workspace_roots,
 };
 
+/// Setting holding the most bytes of file content returned by a single read.
+pub const FS_READ_MAX_BYTES_SETTING: &str = "chat.fsRead.maxBytes";
+
+const DEFAULT_MAX_READ_BYTES: i64 = 200 * 1024;
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "mode")]
 pub enum FsRead {
@@ -41,6 +67,17 @@ pub enum FsRead {
 
 impl FsRead {
     pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        if !self.follow_symlinks() {
+            for path in self.target_paths(ctx) {
+                if let Some(resolved) = resolve_symlinks(&path) {
+                    bail!(
+                        "'{}' is read through a symlink to '{}', and follow_symlinks is false",
+                        path.display(),
+                        resolved.display()
+                    );
+                }
+            }
+        }
         match self {
             FsRead::Line(fs_line) => fs_line.validate(ctx).await,
             FsRead::Directory(fs_directory) => fs_directory.validate(ctx).await,
@@ -50,10 +87,84 @@ impl FsRead {
 
     pub async fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
         match self {
-            FsRead::Line(fs_line) => fs_line.queue_description(ctx, updates).await,
-            FsRead::Directory(fs_directory) => fs_directory.queue_description(updates),
-            FsRead::Search(fs_search) => fs_search.queue_description(updates),
+            FsRead::Line(fs_line) => fs_line.queue_description(ctx, updates).await?,
+            FsRead::Directory(fs_directory) => fs_directory.queue_description(updates)?,
+            FsRead::Search(fs_search) => fs_search.queue_description(updates)?,
+        }
+
+        // Make it obvious what is really being read when a path goes through a symlink.
+        let roots = workspace_roots(ctx);
+        for path in self.target_paths(ctx) {
+            let Some(target) = resolve_symlinks(&path) else {
+                continue;
+            };
+            if !is_within_roots(&target, &roots) {
+                queue!(
+                    updates,
+                    style::SetForegroundColor(Color::Yellow),
+                    style::Print(format!(
+                        "\n⚠ This reads through a symlink OUTSIDE of your workspace: {}",
+                        target.display()
+                    )),
+                    style::ResetColor,
+                )?;
+            } else if path.is_symlink() {
+                queue!(
+                    updates,
+                    style::Print("\n  "),
+                    style::Print(path.display()),
+                    style::Print(" → "),
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(target.display()),
+                    style::ResetColor,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns where a path really resolves to, if it goes through a symlink to somewhere outside
+    /// of the workspace.
+    pub fn escaping_symlink_target(&self, ctx: &Context) -> Option<PathBuf> {
+        let roots = workspace_roots(ctx);
+        self.target_paths(ctx).into_iter().find_map(|path| {
+            let resolved = resolve_symlinks(&path)?;
+            (!is_within_roots(&resolved, &roots)).then_some(resolved)
+        })
+    }
+
+    /// The paths that are actually read, with globs expanded to the directory they search and
+    /// every path they match.
+    fn target_paths(&self, ctx: &Context) -> Vec<PathBuf> {
+        self.paths()
+            .into_iter()
+            .flat_map(|path| {
+                if is_glob(ctx, path) {
+                    glob_targets(ctx, path).unwrap_or_default()
+                } else {
+                    vec![sanitize_path_tool_arg(ctx, path)]
+                }
+            })
+            .collect()
+    }
+
+    /// The paths being read.
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            FsRead::Line(FsLine { paths: Some(paths), .. }) => paths.iter().map(String::as_str).collect(),
+            FsRead::Line(fs_line) => vec![&fs_line.path],
+            FsRead::Directory(fs_directory) => vec![&fs_directory.path],
+            FsRead::Search(fs_search) => vec![&fs_search.path],
+        }
+    }
+
+    fn follow_symlinks(&self) -> bool {
+        match self {
+            FsRead::Line(fs_line) => fs_line.follow_symlinks,
+            FsRead::Directory(fs_directory) => fs_directory.follow_symlinks,
+            FsRead::Search(fs_search) => fs_search.follow_symlinks,
         }
+        .unwrap_or(true)
     }
 
     pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
@@ -68,30 +179,88 @@ impl FsRead {
 /// Read lines from a file.
 #[derive(Debug, Clone, Deserialize)]
 pub struct FsLine {
+    /// The file to read, unless `paths` is given.
+    #[serde(default)]
     pub path: String,
+    /// Files to read in a single call, each returned in its own section.
+    pub paths: Option<Vec<String>>,
+    /// Read files excluded by `.gitignore` files when `path` is a glob.
+    pub include_ignored: Option<bool>,
     pub start_line: Option<i32>,
     pub end_line: Option<i32>,
+    /// Return a hex dump of the start of the file if it is binary.
+    pub force: Option<bool>,
+    /// Which end of the file to keep when it is too large to return in full.
+    pub from: Option<ReadFrom>,
+    pub follow_symlinks: Option<bool>,
+    /// Encoding to decode the file with, instead of detecting it.
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadFrom {
+    #[default]
+    Start,
+    End,
 }
 
 impl FsLine {
     const DEFAULT_END_LINE: i32 = -1;
     const DEFAULT_START_LINE: i32 = 1;
+    /// Most files read for a glob.
+    const MAX_GLOB_MATCHES: usize = 50;
 
     pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
-        let path = sanitize_path_tool_arg(ctx, &self.path);
-        if !path.exists() {
-            bail!("'{}' does not exist", self.path);
-        }
-        let is_file = ctx.fs().symlink_metadata(&path).await?.is_file();
-        if !is_file {
-            bail!("'{}' is not a file", self.path);
+        match &self.paths {
+            Some(paths) if paths.is_empty() => bail!("`paths` must list at least one file"),
+            // Each file is checked as it is read, so that one bad path doesn't fail the others.
+            Some(_) => Ok(()),
+            None if self.path.is_empty() => bail!("Either `path` or `paths` must be given"),
+            None if is_glob(ctx, &self.path) => Ok(()),
+            None => check_is_file(ctx, &self.path, self.follow_symlinks.unwrap_or(true)).await,
         }
-        Ok(())
     }
 
     pub async fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
+        if let Some(paths) = &self.paths {
+            queue!(updates, style::Print("Reading files: "))?;
+            for (i, path) in paths.iter().enumerate() {
+                if i > 0 {
+                    queue!(updates, style::Print(", "))?;
+                }
+                queue!(
+                    updates,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(path),
+                    style::ResetColor,
+                )?;
+            }
+            return Ok(());
+        }
+        if is_glob(ctx, &self.path) {
+            return Ok(queue!(
+                updates,
+                style::Print("Reading files matching: "),
+                style::SetForegroundColor(Color::Green),
+                style::Print(&self.path),
+                style::ResetColor,
+            )?);
+        }
+
         let path = sanitize_path_tool_arg(ctx, &self.path);
-        let line_count = ctx.fs().read_to_string(&path).await?.lines().count();
+        let bytes = ctx.fs().read(&path).await?;
+        let Some((file, _)) = encoding::decode(&bytes, self.encoding.as_deref()).ok().flatten() else {
+            return Ok(queue!(
+                updates,
+                style::Print("Reading binary file: "),
+                style::SetForegroundColor(Color::Green),
+                style::Print(&self.path),
+                style::ResetColor,
+                style::Print(format!(", {} bytes", bytes.len())),
+            )?);
+        };
+        let line_count = file.lines().count();
         queue!(
             updates,
             style::Print("Reading file: "),
@@ -101,8 +270,8 @@ impl FsLine {
             style::Print(", "),
         )?;
 
-        let start = convert_negative_index(line_count, self.start_line()) + 1;
-        let end = convert_negative_index(line_count, self.end_line()) + 1;
+        let (start, end) = self.line_range(line_count);
+        let (start, end) = (start + 1, end + 1);
         match (start, end) {
             _ if start == 1 && end == line_count => Ok(queue!(updates, style::Print("all lines".to_string()))?),
             _ if end == line_count => Ok(queue!(
@@ -128,36 +297,141 @@ impl FsLine {
     }
 
     pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
-        let path = sanitize_path_tool_arg(ctx, &self.path);
+        let max_bytes = max_read_bytes();
+        let from = self.from.unwrap_or_default();
+        if self.paths.is_none() && is_glob(ctx, &self.path) {
+            return self.invoke_glob(ctx, updates, max_bytes, from).await;
+        }
+        let Some(paths) = &self.paths else {
+            let output = match self.read_file(ctx, &self.path, updates).await? {
+                OutputKind::Text(text) => OutputKind::Text(truncate_content(text, max_bytes, from)),
+                output => output,
+            };
+            return Ok(InvokeOutput { output });
+        };
+
+        let mut files = Vec::new();
+        for path in paths {
+            let contents = match check_is_file(ctx, path, self.follow_symlinks.unwrap_or(true)).await {
+                Ok(()) => self.read_file(ctx, path, updates).await,
+                Err(err) => Err(err),
+            };
+            files.push((path.as_str(), contents.map(output_text)));
+        }
+        Ok(InvokeOutput {
+            output: OutputKind::Text(format_batch(files, max_bytes, from)),
+        })
+    }
+
+    /// Reads every file matching the glob in `path`, in the same format as `paths`.
+    async fn invoke_glob(
+        &self,
+        ctx: &Context,
+        updates: &mut impl Write,
+        max_bytes: usize,
+        from: ReadFrom,
+    ) -> Result<InvokeOutput> {
+        let cwd = ctx.fs().chroot_path(ctx.env().current_dir()?);
+        let pattern = absolute_path(ctx, &self.path)?;
+        let mut matches = Vec::new();
+        let mut gitignored_count = 0;
+        for path in glob::glob(&pattern.to_string_lossy())?.filter_map(|entry| entry.ok()) {
+            if !path.is_file() {
+                continue;
+            }
+            if !self.include_ignored.unwrap_or_default() && is_path_gitignored(ctx, &path).await {
+                gitignored_count += 1;
+                continue;
+            }
+            matches.push(path);
+        }
+
+        let gitignored_note = match gitignored_count {
+            0 => String::new(),
+            n => format!(
+                "\n\nNote: {n} matching files excluded by .gitignore were skipped. Set `include_ignored` to true to read them."
+            ),
+        };
+        if matches.is_empty() {
+            let mut message = format!("No files match '{}'.", self.path);
+            if let Some((dir, subdirs)) = nearby_directories(ctx, &pattern).await {
+                message.push_str(&format!(
+                    " Directories in {}: {}",
+                    format_path(&cwd, &dir),
+                    if subdirs.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        subdirs.join(", ")
+                    }
+                ));
+            }
+            message.push_str(&gitignored_note);
+            return Ok(InvokeOutput {
+                output: OutputKind::Text(message),
+            });
+        }
+
+        let unread_count = matches.len().saturating_sub(Self::MAX_GLOB_MATCHES);
+        matches.truncate(Self::MAX_GLOB_MATCHES);
+        let display_paths = matches.iter().map(|path| format_path(&cwd, path)).collect::<Vec<_>>();
+        let mut files = Vec::new();
+        for (path, display_path) in matches.iter().zip(&display_paths) {
+            let contents = self.read_file(ctx, &path.to_string_lossy(), updates).await;
+            files.push((display_path.as_str(), contents.map(output_text)));
+        }
+        let mut output = format_batch(files, max_bytes, from);
+        if unread_count > 0 {
+            output.push_str(&format!(
+                "\n\n[{unread_count} more matching files were not read. Use a more specific pattern to read them.]"
+            ));
+        }
+        output.push_str(&gitignored_note);
+        Ok(InvokeOutput {
+            output: OutputKind::Text(output),
+        })
+    }
+
+    /// Reads the lines of `path`, or describes it if it is binary. Text is returned in full, for
+    /// the caller to truncate.
+    async fn read_file(&self, ctx: &Context, path: &str, updates: &mut impl Write) -> Result<OutputKind> {
+        let path = sanitize_path_tool_arg(ctx, path);
         let relative_path = format_path(ctx.env().current_dir()?, &path);
         debug!(?path, "Reading");
-        let file = ctx.fs().read_to_string(&path).await?;
+        let bytes = ctx.fs().read(&path).await?;
+        let Some((file, encoding)) = encoding::decode(&bytes, self.encoding.as_deref())? else {
+            return Ok(if self.force.unwrap_or_default() {
+                OutputKind::Text(hex_dump(&bytes))
+            } else {
+                describe_binary(&relative_path, &bytes, true)
+            });
+        };
         let line_count = file.lines().count();
-        let (start, end) = (
-            convert_negative_index(line_count, self.start_line()),
-            convert_negative_index(line_count, self.end_line()),
-        );
-
-        // safety check to ensure end is always greater than start
-        let end = end.max(start);
+        let (start, end) = self.line_range(line_count);
 
-        if start >= line_count {
-            bail!(
-                "starting index: {} is outside of the allowed range: ({}, {})",
-                self.start_line(),
-                -(line_count as i64),
-                line_count
+        // The range should be inclusive on both ends.
+        let lines = file.lines().enumerate().skip(start).take(end + 1 - start);
+        let mut file_contents = if line_count == 0 || (start == 0 && end + 1 == line_count) {
+            lines.map(|(_, line)| line).collect::<Vec<_>>().join("\n")
+        } else {
+            // Number partial reads so the model can tell where in the file the slice came from.
+            let mut contents = format!(
+                "[showing lines {}–{} of {}]\n",
+                format_count(start + 1),
+                format_count(end + 1),
+                format_count(line_count)
+            );
+            contents.push_str(
+                &lines
+                    .map(|(i, line)| format!("{}: {}", i + 1, line))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
             );
+            contents
+        };
+        if !encoding.is_utf8() {
+            file_contents.insert_str(0, &encoding_note(&encoding));
         }
 
-        // The range should be inclusive on both ends.
-        let file_contents = file
-            .lines()
-            .skip(start)
-            .take(end - start + 1)
-            .collect::<Vec<_>>()
-            .join("\n");
-
         queue!(
             updates,
             style::Print("Reading: "),
@@ -167,23 +441,21 @@ impl FsLine {
             style::Print("\n"),
         )?;
 
-        let byte_count = file_contents.len();
-        if byte_count > MAX_TOOL_RESPONSE_SIZE {
-            bail!(
-                "This tool only supports reading {MAX_TOOL_RESPONSE_SIZE} bytes at a
-time. You tried to read {byte_count} bytes. Try executing with fewer lines specified."
-            );
-        }
-
-        Ok(InvokeOutput {
-            output: OutputKind::Text(file_contents),
-        })
+        Ok(OutputKind::Text(file_contents))
     }
 
     fn start_line(&self) -> i32 {
         self.start_line.unwrap_or(Self::DEFAULT_START_LINE)
     }
 
+    /// The 0-based, inclusive range of lines to read, clamped to the file.
+    fn line_range(&self, line_count: usize) -> (usize, usize) {
+        let last = line_count.saturating_sub(1);
+        let start = convert_negative_index(line_count, self.start_line()).min(last);
+        let end = convert_negative_index(line_count, self.end_line()).min(last).max(start);
+        (start, end)
+    }
+
     fn end_line(&self) -> i32 {
         self.end_line.unwrap_or(Self::DEFAULT_END_LINE)
     }
@@ -195,6 +467,9 @@ pub struct FsSearch {
     pub path: String,
     pub pattern: String,
     pub context_lines: Option<usize>,
+    pub follow_symlinks: Option<bool>,
+    /// Encoding to decode the file with, instead of detecting it.
+    pub encoding: Option<String>,
 }
 
 impl FsSearch {
@@ -208,7 +483,10 @@ impl FsSearch {
         if !path.exists() {
             bail!("File not found: {}", relative_path);
         }
-        if !ctx.fs().symlink_metadata(path).await?.is_file() {
+        if !metadata(ctx, &path, self.follow_symlinks.unwrap_or(true))
+            .await?
+            .is_file()
+        {
             bail!("Path is not a file: {}", relative_path);
         }
         if self.pattern.is_empty() {
@@ -237,7 +515,12 @@ impl FsSearch {
         let pattern = &self.pattern;
         let relative_path = format_path(ctx.env().current_dir()?, &file_path);
 
-        let file_content = ctx.fs().read_to_string(&file_path).await?;
+        let bytes = ctx.fs().read(&file_path).await?;
+        let Some((file_content, _)) = encoding::decode(&bytes, self.encoding.as_deref())? else {
+            return Ok(InvokeOutput {
+                output: describe_binary(&relative_path, &bytes, false),
+            });
+        };
         let lines: Vec<&str> = LinesWithEndings::from(&file_content).collect();
 
         let mut results = Vec::new();
@@ -295,10 +578,24 @@ impl FsSearch {
 pub struct FsDirectory {
     pub path: String,
     pub depth: Option<usize>,
+    /// Maximum number of entries to list before truncating.
+    pub max_entries: Option<usize>,
+    /// Names or glob patterns of entries to leave out, in addition to `.git`.
+    pub ignore: Option<Vec<String>>,
+    /// List entries excluded by `.gitignore` files.
+    pub include_ignored: Option<bool>,
+    /// Descend into symlinked directories. Symlinks leading outside of the workspace are never
+    /// followed.
+    pub follow_symlinks: Option<bool>,
 }
 
 impl FsDirectory {
+    /// Entries that are never listed.
+    const ALWAYS_IGNORED: &[&str] = &[".git"];
     const DEFAULT_DEPTH: usize = 0;
+    const DEFAULT_MAX_ENTRIES: usize = 1000;
+    /// Room kept under [MAX_TOOL_RESPONSE_SIZE] for the notes that follow the entries.
+    const NOTES_BYTES: usize = 1024;
 
     pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
         let path = sanitize_path_tool_arg(ctx, &self.path);
@@ -306,7 +603,10 @@ impl FsDirectory {
         if !path.exists() {
             bail!("Directory not found: {}", relative_path);
         }
-        if !ctx.fs().symlink_metadata(path).await?.is_dir() {
+        if !metadata(ctx, &path, self.follow_symlinks.unwrap_or(true))
+            .await?
+            .is_dir()
+        {
             bail!("Path is not a directory: {}", relative_path);
         }
         Ok(())
@@ -332,16 +632,44 @@ impl FsDirectory {
         let path = sanitize_path_tool_arg(ctx, &self.path);
         let cwd = ctx.env().current_dir()?;
         let max_depth = self.depth();
-        debug!(?path, max_depth, "Reading directory at path with depth");
+        let max_entries = self.max_entries.unwrap_or(Self::DEFAULT_MAX_ENTRIES);
+        let ignored = self.ignore_patterns();
+        let include_ignored = self.include_ignored.unwrap_or_default();
+        let follow_symlinks = self.follow_symlinks.unwrap_or(true);
+        let mut roots = workspace_roots(ctx);
+        let sandbox = Sandbox::load(ctx);
+        debug!(?path, max_depth, max_entries, "Reading directory at path with depth");
         let mut result = Vec::new();
+        let mut result_bytes = 0;
+        // Set once the listing reaches `max_entries` or the response size limit.
+        let mut full = false;
+        // Entries found once the listing is full, which are counted but not listed.
+        let mut truncated_count = 0;
+        // Entries left out because of a `.gitignore`.
+        let mut gitignored_count = 0;
+        // Canonical paths of the directories already queued, so that symlinks can't cause a loop.
+        let mut visited = HashSet::new();
+        if let Ok(canonical) = ctx.fs().canonicalize(&path).await {
+            // Links within the directory being listed are fine to follow, even outside of the workspace.
+            roots.push(canonical.clone());
+            visited.insert(canonical);
+        }
+        let gitignores = if include_ignored {
+            Vec::new()
+        } else {
+            parent_gitignores(ctx, &path).await
+        };
         let mut dir_queue = VecDeque::new();
-        dir_queue.push_back((path, 0));
-        while let Some((path, depth)) = dir_queue.pop_front() {
+        dir_queue.push_back((path, 0, gitignores));
+        while let Some((path, depth, mut gitignores)) = dir_queue.pop_front() {
             if depth > max_depth {
                 break;
             }
+            if !include_ignored {
+                gitignores.extend(load_gitignore(ctx, &path).await);
+            }
             let relative_path = format_path(&cwd, &path);
-            if !relative_path.is_empty() {
+            if !relative_path.is_empty() && !full {
                 queue!(
                     updates,
                     style::Print("Reading: "),
@@ -354,7 +682,41 @@ impl FsDirectory {
             let mut read_dir = ctx.fs().read_dir(path).await?;
             while let Some(ent) = read_dir.next_entry().await? {
                 use std::os::unix::fs::MetadataExt;
+                let name = ent.file_name().to_string_lossy().into_owned();
+                if ignored.iter().any(|pattern| pattern.matches(&name)) {
+                    continue;
+                }
                 let md = ent.metadata().await?;
+                let link_target = if md.is_symlink() {
+                    ctx.fs().canonicalize(ent.path()).await.ok()
+                } else {
+                    None
+                };
+                let is_dir = match &link_target {
+                    Some(target) => ctx.fs().symlink_metadata(target).await.is_ok_and(|md| md.is_dir()),
+                    None => md.is_dir(),
+                };
+                let descend = match &link_target {
+                    Some(target) => follow_symlinks && is_within_roots(target, &roots) && sandbox.allows(target),
+                    None => !md.is_symlink(),
+                };
+                if is_gitignored(&gitignores, &ent.path(), is_dir) {
+                    gitignored_count += 1;
+                    continue;
+                }
+                if is_dir && descend && depth < max_depth {
+                    if let Ok(canonical) = ctx.fs().canonicalize(ent.path()).await {
+                        if visited.insert(canonical) {
+                            dir_queue.push_back((ent.path(), depth + 1, gitignores.clone()));
+                        }
+                    }
+                }
+                full |= result.len() >= max_entries;
+                if full {
+                    truncated_count += 1;
+                    continue;
+                }
+
                 let formatted_mode = format_mode(md.permissions().mode()).into_iter().collect::<String>();
 
                 let modified_timestamp = md.modified()?.duration_since(std::time::UNIX_EPOCH)?.as_secs();
@@ -367,7 +729,7 @@ impl FsDirectory {
 
                 // Mostly copying "The Long Format" from `man ls`.
                 // TODO: query user/group database to convert uid/gid to names?
-                result.push(format!(
+                let entry = format!(
                     "{}{} {} {} {} {} {} {}",
                     format_ftype(&md),
                     formatted_mode,
@@ -377,30 +739,435 @@ impl FsDirectory {
                     md.size(),
                     formatted_date,
                     ent.path().to_string_lossy()
-                ));
-                if md.is_dir() {
-                    dir_queue.push_back((ent.path(), depth + 1));
+                );
+                if result_bytes + entry.len() + 1 > MAX_TOOL_RESPONSE_SIZE - Self::NOTES_BYTES {
+                    full = true;
+                    truncated_count += 1;
+                    continue;
                 }
+                result_bytes += entry.len() + 1;
+                result.push(entry);
             }
         }
 
-        let file_count = result.len();
-        let result = result.join("\n");
-        let byte_count = result.len();
-        if byte_count > MAX_TOOL_RESPONSE_SIZE {
-            bail!(
-                "This tool only supports reading up to {MAX_TOOL_RESPONSE_SIZE} bytes at a time. You tried to read {byte_count} bytes ({file_count} files). Try executing with fewer lines specified."
-            );
+        if truncated_count > 0 {
+            result.push(format!(
+                "…{truncated_count} more entries not listed. Use a smaller depth or a more specific path to see them."
+            ));
+        }
+        if gitignored_count > 0 {
+            result.push(format!(
+                "Note: {gitignored_count} entries excluded by .gitignore were hidden. Set `include_ignored` to true to list them."
+            ));
         }
-
         Ok(InvokeOutput {
-            output: OutputKind::Text(result),
+            output: OutputKind::Text(result.join("\n")),
         })
     }
 
     fn depth(&self) -> usize {
         self.depth.unwrap_or(Self::DEFAULT_DEPTH)
     }
+
+    fn ignore_patterns(&self) -> Vec<glob::Pattern> {
+        Self::ALWAYS_IGNORED
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.ignore.iter().flatten().cloned())
+            .filter_map(|pattern| match glob::Pattern::new(&pattern) {
+                Ok(pattern) => Some(pattern),
+                Err(err) => {
+                    warn!(?err, pattern, "ignoring invalid fs_read ignore pattern");
+                    None
+                },
+            })
+            .collect()
+    }
+}
+
+/// Loads the `.gitignore` in `dir`, if there is one.
+async fn load_gitignore(ctx: &Context, dir: &Path) -> Option<Arc<Gitignore>> {
+    let contents = ctx.fs().read_to_string(dir.join(".gitignore")).await.ok()?;
+    // Entries are matched by their real path, which differs from `dir` under a chroot.
+    let mut builder = GitignoreBuilder::new(ctx.fs().chroot_path(dir));
+    for line in contents.lines() {
+        if let Err(err) = builder.add_line(None, line) {
+            warn!(?err, ?dir, "ignoring invalid .gitignore line");
+        }
+    }
+    builder.build().ok().map(Arc::new)
+}
+
+/// Loads the `.gitignore` files of the directories above `dir`, up to the root of its git
+/// repository, ordered from the outermost.
+async fn parent_gitignores(ctx: &Context, dir: &Path) -> Vec<Arc<Gitignore>> {
+    if ctx.fs().exists(dir.join(".git")) {
+        return Vec::new();
+    }
+    let mut gitignores = Vec::new();
+    for parent in dir.ancestors().skip(1) {
+        gitignores.extend(load_gitignore(ctx, parent).await);
+        if ctx.fs().exists(parent.join(".git")) {
+            gitignores.reverse();
+            return gitignores;
+        }
+    }
+    // Outside of a repository, only the `.gitignore` files within `dir` apply.
+    Vec::new()
+}
+
+/// Whether `path` is ignored, with `gitignores` ordered from the outermost directory. As in git,
+/// the innermost `.gitignore` with a matching rule decides.
+fn is_gitignored(gitignores: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    gitignores
+        .iter()
+        .rev()
+        .find_map(|gitignore| match gitignore.matched(path, is_dir) {
+            Match::None => None,
+            matched => Some(matched.is_ignore()),
+        })
+        .unwrap_or(false)
+}
+
+/// Number of leading bytes included in the hex dump of a binary file.
+const HEX_DUMP_BYTES: usize = 4096;
+
+/// Line put before the content of files that aren't UTF-8, which is converted for the model.
+fn encoding_note(encoding: &TextEncoding) -> String {
+    format!(
+        "[this file is encoded in {}, and was converted to UTF-8. fs_write keeps the encoding when editing it]\n",
+        encoding.name()
+    )
+}
+
+/// Best effort detection of common binary formats from their magic bytes.
+fn detect_binary_type(bytes: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "PNG image"),
+        (b"\xff\xd8\xff", "JPEG image"),
+        (b"GIF87a", "GIF image"),
+        (b"GIF89a", "GIF image"),
+        (b"%PDF-", "PDF document"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"\x1f\x8b", "gzip archive"),
+        (b"\x7fELF", "ELF executable or shared library"),
+        (b"\xcf\xfa\xed\xfe", "Mach-O binary"),
+        (b"\xca\xfe\xba\xbe", "Mach-O universal binary or Java class"),
+        (b"MZ", "Windows executable"),
+        (b"\0asm", "WebAssembly module"),
+        (b"SQLite format 3\0", "SQLite database"),
+    ];
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return "WebP image";
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map_or("unknown binary data", |(_, kind)| kind)
+}
+
+/// Describes a binary file in place of its content.
+fn describe_binary(path: &str, bytes: &[u8], can_force: bool) -> OutputKind {
+    let image = image_info(bytes);
+    let mut note = match image {
+        // Tool results can only hold text and JSON, so there is no way to show the image itself.
+        Some(_) => "This is an image file. Images can't be returned by this tool, so only its metadata is included."
+            .to_string(),
+        None => "This is a binary file, so its content is not returned.".to_string(),
+    };
+    if can_force {
+        note.push_str(&format!(
+            " Read it again in Line mode with `force` set to true to get a hex dump of the first {HEX_DUMP_BYTES} bytes."
+        ));
+    }
+    let mut description = serde_json::json!({
+        "path": path,
+        "size_bytes": bytes.len(),
+        "detected_type": detect_binary_type(bytes),
+        "note": note,
+    });
+    if let Some((media_type, dimensions)) = image {
+        description["media_type"] = media_type.into();
+        if let Some((width, height)) = dimensions {
+            description["width"] = width.into();
+            description["height"] = height.into();
+        }
+    }
+    OutputKind::Json(description)
+}
+
+/// Returns the media type of an image, along with its `(width, height)` when it can be read from
+/// the header.
+fn image_info(bytes: &[u8]) -> Option<(&'static str, Option<(u32, u32)>)> {
+    let be16 = |i: usize| bytes.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32);
+    let be32 = |i: usize| {
+        bytes
+            .get(i..i + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let le16 = |i: usize| bytes.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32);
+
+    match detect_binary_type(bytes) {
+        // The IHDR chunk always comes first, holding the width and height.
+        "PNG image" => Some(("image/png", be32(16).zip(be32(20)))),
+        "GIF image" => Some(("image/gif", le16(6).zip(le16(8)))),
+        "WebP image" => Some(("image/webp", None)),
+        "JPEG image" => {
+            // Walk the segments up to the start of frame, which holds the dimensions.
+            let mut i = 2;
+            let mut dimensions = None;
+            while let (Some(&0xff), Some(&marker)) = (bytes.get(i), bytes.get(i + 1)) {
+                if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+                    dimensions = be16(i + 7).zip(be16(i + 5));
+                    break;
+                }
+                match be16(i + 2) {
+                    Some(len) => i += 2 + len as usize,
+                    None => break,
+                }
+            }
+            Some(("image/jpeg", dimensions))
+        },
+        _ => None,
+    }
+}
+
+/// Formats the start of `bytes` like `xxd`: an offset, 16 bytes in hex, then the printable
+/// characters.
+fn hex_dump(bytes: &[u8]) -> String {
+    let dumped = &bytes[..bytes.len().min(HEX_DUMP_BYTES)];
+    let mut output = format!(
+        "[hex dump of the first {} of {} bytes]\n",
+        format_count(dumped.len()),
+        format_count(bytes.len())
+    );
+    for (i, chunk) in dumped.chunks(16).enumerate() {
+        let hex = chunk
+            .chunks(2)
+            .map(|pair| pair.iter().map(|b| format!("{b:02x}")).collect::<String>())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        output.push_str(&format!("{:08x}: {hex:<39}  {ascii}\n", i * 16));
+    }
+    output
+}
+
+/// Whether `path` contains characters that have a meaning in globs.
+pub fn has_glob_chars(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Whether `path` is a glob pattern rather than a literal path. Existing paths are literal even if
+/// they contain glob characters, such as `app/[id]/page.tsx`.
+pub fn is_glob(ctx: &Context, path: &str) -> bool {
+    has_glob_chars(path) && !absolute_path(ctx, path).is_ok_and(|path| path.symlink_metadata().is_ok())
+}
+
+/// `path` as an absolute path, with relative paths taken from the current directory.
+pub fn absolute_path(ctx: &Context, path: &str) -> Result<PathBuf> {
+    let sanitized = sanitize_path_tool_arg(ctx, path);
+    if sanitized.is_absolute() {
+        return Ok(sanitized);
+    }
+    Ok(ctx.fs().chroot_path(ctx.env().current_dir()?).join(sanitized))
+}
+
+/// The literal directory that the glob `pattern` searches in.
+pub fn glob_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !has_glob_chars(&c.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+/// The directory that the glob `path` searches in, followed by every path that it matches.
+pub fn glob_targets(ctx: &Context, path: &str) -> Result<Vec<PathBuf>> {
+    let pattern = absolute_path(ctx, path)?;
+    let matches = glob::glob(&pattern.to_string_lossy())?.filter_map(|entry| entry.ok());
+    Ok(std::iter::once(glob_base(&pattern)).chain(matches).collect())
+}
+
+/// Whether the file at `path` is excluded by a `.gitignore` in or above its directory, within its
+/// git repository.
+async fn is_path_gitignored(ctx: &Context, path: &Path) -> bool {
+    let Some(dir) = path.parent() else {
+        return false;
+    };
+    let mut gitignores = parent_gitignores(ctx, dir).await;
+    gitignores.extend(load_gitignore(ctx, dir).await);
+    gitignores
+        .iter()
+        .rev()
+        .find_map(|gitignore| match gitignore.matched_path_or_any_parents(path, false) {
+            Match::None => None,
+            matched => Some(matched.is_ignore()),
+        })
+        .unwrap_or(false)
+}
+
+/// Finds the deepest existing directory in the literal part of the glob `pattern`, along with the
+/// names of its subdirectories, to point the model somewhere useful when nothing matched.
+async fn nearby_directories(ctx: &Context, pattern: &Path) -> Option<(PathBuf, Vec<String>)> {
+    let literal = glob_base(pattern);
+    let dir = literal.ancestors().find(|dir| dir.is_dir())?.to_path_buf();
+    let mut subdirs = Vec::new();
+    let mut read_dir = ctx.fs().read_dir(&dir).await.ok()?;
+    while let Ok(Some(ent)) = read_dir.next_entry().await {
+        if ent.path().is_dir() {
+            subdirs.push(format!("{}/", ent.file_name().to_string_lossy()));
+        }
+    }
+    subdirs.sort();
+    subdirs.truncate(20);
+    Some((dir, subdirs))
+}
+
+/// Metadata of `path`, or of what it links to if `follow_symlinks` is true.
+async fn metadata(ctx: &Context, path: &Path, follow_symlinks: bool) -> std::io::Result<Metadata> {
+    if follow_symlinks {
+        ctx.fs().metadata(path).await
+    } else {
+        ctx.fs().symlink_metadata(path).await
+    }
+}
+
+async fn check_is_file(ctx: &Context, path: &str, follow_symlinks: bool) -> Result<()> {
+    let sanitized = sanitize_path_tool_arg(ctx, path);
+    if !sanitized.exists() {
+        bail!("'{}' does not exist", path);
+    }
+    if !metadata(ctx, &sanitized, follow_symlinks).await?.is_file() {
+        bail!("'{}' is not a file", path);
+    }
+    Ok(())
+}
+
+fn output_text(output: OutputKind) -> String {
+    match output {
+        OutputKind::Text(text) => text,
+        OutputKind::Json(json) => json.to_string(),
+    }
+}
+
+/// Joins files read together into delimited sections, sharing `max_bytes` between them. The budget
+/// is handed out from the smallest file up, so that what small files don't need goes to the larger
+/// ones, which are truncated if they still don't fit.
+fn format_batch(files: Vec<(&str, Result<String>)>, max_bytes: usize, from: ReadFrom) -> String {
+    let len = |contents: &Result<String>| contents.as_ref().map_or(0, |c| c.len());
+    let mut order = (0..files.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| len(&files[i].1));
+    let mut budgets = vec![0; files.len()];
+    let mut remaining = max_bytes;
+    for (n, &i) in order.iter().enumerate() {
+        budgets[i] = len(&files[i].1).min(remaining / (order.len() - n));
+        remaining -= budgets[i];
+    }
+
+    let mut truncated = Vec::new();
+    let mut output = files
+        .into_iter()
+        .zip(budgets)
+        .map(|((path, contents), budget)| match contents {
+            Ok(contents) => {
+                if contents.len() > budget {
+                    truncated.push(path);
+                }
+                format!("==> {path} <==\n{}", truncate_content(contents, budget, from))
+            },
+            Err(err) => format!("==> {path} <==\nError: {err}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if !truncated.is_empty() {
+        output.push_str(&format!(
+            "\n\n[truncated to fit the size limit: {}]",
+            truncated.join(", ")
+        ));
+    }
+    output
+}
+
+/// Reads [FS_READ_MAX_BYTES_SETTING], capped to what a tool response can hold.
+fn max_read_bytes() -> usize {
+    (fig_settings::settings::get_int_or(FS_READ_MAX_BYTES_SETTING, DEFAULT_MAX_READ_BYTES).max(0) as usize)
+        .min(MAX_TOOL_RESPONSE_SIZE)
+}
+
+/// Cuts `content` down to at most about `max_bytes`, keeping whole lines where possible, and notes
+/// how much was left out.
+fn truncate_content(content: String, max_bytes: usize, from: ReadFrom) -> String {
+    if content.len() <= max_bytes {
+        return content;
+    }
+
+    let kept = match from {
+        ReadFrom::Start => {
+            let mut end = max_bytes;
+            while !content.is_char_boundary(end) {
+                end -= 1;
+            }
+            let head = &content[..end];
+            head.rfind('\n').map_or(head, |i| &head[..i])
+        },
+        ReadFrom::End => {
+            let mut start = content.len() - max_bytes;
+            while !content.is_char_boundary(start) {
+                start += 1;
+            }
+            let tail = &content[start..];
+            tail.find('\n').map_or(tail, |i| &tail[i + 1..])
+        },
+    };
+    let marker = format!(
+        "[truncated: returned {} of {}, use start_line/end_line to read more]",
+        format_size(kept.len()),
+        format_size(content.len())
+    );
+    match from {
+        ReadFrom::Start => format!("{kept}\n{marker}"),
+        ReadFrom::End => format!("{marker}\n{kept}"),
+    }
+}
+
+/// Formats a byte count for display, e.g. `200KB` or `40.3MB`.
+pub fn format_size(bytes: usize) -> String {
+    const KB: f64 = 1024.0;
+    const MB: f64 = KB * 1024.0;
+    let bytes_f = bytes as f64;
+    let (value, unit) = if bytes_f >= MB {
+        (bytes_f / MB, "MB")
+    } else if bytes_f >= KB {
+        (bytes_f / KB, "KB")
+    } else {
+        return format!("{bytes} bytes");
+    };
+    let formatted = format!("{value:.1}");
+    format!("{}{unit}", formatted.strip_suffix(".0").unwrap_or(&formatted))
+}
+
+/// Formats `n` with thousands separators, e.g. `5,432`.
+fn format_count(n: usize) -> String {
+    let digits = n.to_string();
+    let first_group = match digits.len() % 3 {
+        0 => 3,
+        len => len,
+    };
+    let mut formatted = digits[..first_group].to_string();
+    for group in digits.as_bytes()[first_group..].chunks(3) {
+        formatted.push(',');
+        formatted.push_str(std::str::from_utf8(group).unwrap_or_default());
+    }
+    formatted
 }
 
 /// Converts negative 1-based indices to positive 0-based indices.
@@ -543,24 +1310,36 @@ mod tests {
                     .unwrap();
 
                 if let OutputKind::Text(text) = output.output {
-                    assert_eq!(text, $expected.join("\n"), "actual(left) does not equal
+                    assert_eq!(text, $expected, "actual(left) does not equal
                                 expected(right) for (start_line, end_line): ({:?}, {:?})", $start_line, $end_line);
                 } else {
                     panic!("expected text output");
                 }
             }
         }
-        assert_lines!(None::<i32>, None::<i32>, lines[..]);
-        assert_lines!(1, 2, lines[..=1]);
-        assert_lines!(1, -1, lines[..]);
-        assert_lines!(2, 1, lines[1..=1]);
-        assert_lines!(-2, -1, lines[2..]);
-        assert_lines!(-2, None::<i32>, lines[2..]);
-        assert_lines!(2, None::<i32>, lines[1..]);
+        // Reading the whole file returns it as is.
+        assert_lines!(None::<i32>, None::<i32>, lines.join("\n"));
+        assert_lines!(1, -1, lines.join("\n"));
+        assert_lines!(-100, 100, lines.join("\n"));
+
+        // Partial reads are numbered.
+        assert_lines!(
+            1,
+            2,
+            "[showing lines 1–2 of 4]\n1: 1: Hello world!\n2: 2: This is line 2"
+        );
+        assert_lines!(2, 1, "[showing lines 2–2 of 4]\n2: 2: This is line 2");
+        assert_lines!(-2, -1, "[showing lines 3–4 of 4]\n3: 3: asdf\n4: 4: Hello world!");
+        assert_lines!(
+            -2,
+            None::<i32>,
+            "[showing lines 3–4 of 4]\n3: 3: asdf\n4: 4: Hello world!"
+        );
+        assert_lines!(2, 3, "[showing lines 2–3 of 4]\n2: 2: This is line 2\n3: 3: asdf");
     }
 
     #[tokio::test]
-    async fn test_fs_read_line_past_eof() {
+    async fn test_fs_read_line_out_of_range_clamps() {
         let ctx = setup_test_directory().await;
         let mut stdout = std::io::stdout();
         let v = serde_json::json!({
@@ -569,15 +1348,149 @@ mod tests {
             "start_line": 100,
             "end_line": None::<i32>,
         });
+        let output = serde_json::from_value::<FsRead>(v)
+            .unwrap()
+            .invoke(&ctx, &mut stdout)
+            .await
+            .unwrap();
         assert!(
-            serde_json::from_value::<FsRead>(v)
-                .unwrap()
-                .invoke(&ctx, &mut stdout)
-                .await
-                .is_err()
+            matches!(output.output, OutputKind::Text(text) if text == "[showing lines 4–4 of 4]\n4: 4: Hello world!")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_batch() {
+        let ctx = setup_test_directory().await;
+        ctx.fs().write("/other.txt", "other file").await.unwrap();
+        let v = serde_json::json!({
+            "mode": "Line",
+            "paths": [TEST_FILE_PATH, "/missing.txt", "/other.txt"],
+        });
+        let mut fs_read = serde_json::from_value::<FsRead>(v).unwrap();
+        fs_read.validate(&ctx).await.unwrap();
+        let output = fs_read.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert_eq!(
+            text,
+            format!(
+                "==> {TEST_FILE_PATH} <==\n{}\n\n==> /missing.txt <==\nError: '/missing.txt' does not exist\n\n==> /other.txt <==\nother file",
+                TEST_FILE_CONTENTS.trim_end()
+            )
+        );
+
+        let mut empty = serde_json::from_value::<FsRead>(serde_json::json!({ "mode": "Line", "paths": [] })).unwrap();
+        assert!(empty.validate(&ctx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_glob() {
+        let ctx = setup_test_directory().await;
+        let fs = ctx.fs();
+        fs.create_dir_all("/.git").await.unwrap();
+        fs.write("/.gitignore", "generated.tf\n").await.unwrap();
+        fs.create_dir_all("/infra/modules").await.unwrap();
+        fs.write("/infra/main.tf", "main").await.unwrap();
+        fs.write("/infra/vars.tf", "vars").await.unwrap();
+        fs.write("/infra/generated.tf", "generated").await.unwrap();
+        fs.write("/infra/README.md", "readme").await.unwrap();
+        let read = |path: &str| {
+            let ctx = Arc::clone(&ctx);
+            let v = serde_json::json!({ "mode": "Line", "path": path });
+            async move {
+                let mut fs_read = serde_json::from_value::<FsRead>(v).unwrap();
+                fs_read.validate(&ctx).await.unwrap();
+                match fs_read.invoke(&ctx, &mut std::io::sink()).await.unwrap().output {
+                    OutputKind::Text(text) => text,
+                    OutputKind::Json(_) => panic!("expected text output"),
+                }
+            }
+        };
+
+        assert!(is_glob(&ctx, "infra/*.tf") && is_glob(&ctx, "file?.txt") && is_glob(&ctx, "[ab].rs"));
+        assert!(!is_glob(&ctx, "/infra/main.tf"));
+
+        // Paths that exist are read literally, even with glob characters in them.
+        fs.create_dir_all("/app/[id]").await.unwrap();
+        fs.write("/app/[id]/page.tsx", "page").await.unwrap();
+        assert!(!is_glob(&ctx, "/app/[id]/page.tsx"));
+        assert_eq!(read("/app/[id]/page.tsx").await, "page");
+
+        assert_eq!(
+            read("infra/*.tf").await,
+            "==> infra/main.tf <==\nmain\n\n==> infra/vars.tf <==\nvars\n\n\
+             Note: 1 matching files excluded by .gitignore were skipped. Set `include_ignored` to true to read them."
+        );
+        assert_eq!(
+            read("/infra/nope/*.tf").await,
+            "No files match '/infra/nope/*.tf'. Directories in infra: modules/"
         );
     }
 
+    #[test]
+    fn test_format_batch_budget() {
+        let files = vec![
+            ("small", Ok("a".repeat(10))),
+            ("large", Ok("b".repeat(100))),
+            ("missing", Err(eyre::eyre!("'missing' does not exist"))),
+        ];
+        let output = format_batch(files, 50, ReadFrom::Start);
+        let sections = output.split("\n\n").collect::<Vec<_>>();
+        assert_eq!(sections[0], format!("==> small <==\n{}", "a".repeat(10)));
+        // The small file only needs 10 bytes, leaving the rest of the budget to the large one.
+        assert!(sections[1].starts_with(&format!(
+            "==> large <==\n{}\n[truncated: returned 40 bytes",
+            "b".repeat(40)
+        )));
+        assert_eq!(sections[2], "==> missing <==\nError: 'missing' does not exist");
+        assert_eq!(sections[3], "[truncated to fit the size limit: large]");
+    }
+
+    #[test]
+    fn test_truncate_content() {
+        let content = "short".to_string();
+        assert_eq!(truncate_content(content.clone(), 5, ReadFrom::Start), content);
+
+        // Whole lines are kept where possible.
+        let content = "line one\nline two\nline three".to_string();
+        assert_eq!(
+            truncate_content(content.clone(), 12, ReadFrom::Start),
+            "line one\n[truncated: returned 8 bytes of 28 bytes, use start_line/end_line to read more]"
+        );
+        assert_eq!(
+            truncate_content(content, 12, ReadFrom::End),
+            "[truncated: returned 10 bytes of 28 bytes, use start_line/end_line to read more]\nline three"
+        );
+
+        // Never splits a multi-byte character, even without a line break to cut at.
+        let content = "€".repeat(10);
+        for max_bytes in [3, 4, 5] {
+            let head = truncate_content(content.clone(), max_bytes, ReadFrom::Start);
+            assert!(
+                head.starts_with("€\n[truncated: returned 3 bytes of 30 bytes"),
+                "{max_bytes}: {head}"
+            );
+            let tail = truncate_content(content.clone(), max_bytes, ReadFrom::End);
+            assert!(tail.ends_with("]\n€"), "{max_bytes}: {tail}");
+        }
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(200 * 1024), "200KB");
+        assert_eq!(format_size(40 * 1024 * 1024 + 300 * 1024), "40.3MB");
+    }
+
+    #[test]
+    fn test_format_count() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(180), "180");
+        assert_eq!(format_count(5432), "5,432");
+        assert_eq!(format_count(1234567), "1,234,567");
+    }
+
     #[test]
     fn test_format_mode() {
         macro_rules! assert_mode {
@@ -591,6 +1504,317 @@ mod tests {
         assert_mode!(0o641, "rw-r----x");
     }
 
+    #[tokio::test]
+    async fn test_fs_read_binary_file() {
+        let ctx = setup_test_directory().await;
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".repeat(500);
+        ctx.fs().write("/image.png", &png).await.unwrap();
+        let read = |v: serde_json::Value| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                serde_json::from_value::<FsRead>(v)
+                    .unwrap()
+                    .invoke(&ctx, &mut std::io::sink())
+                    .await
+                    .unwrap()
+                    .output
+            }
+        };
+
+        let OutputKind::Json(description) = read(serde_json::json!({ "mode": "Line", "path": "/image.png" })).await
+        else {
+            panic!("expected a description of the binary file");
+        };
+        assert_eq!(description["size_bytes"], png.len());
+        assert_eq!(description["detected_type"], "PNG image");
+        assert_eq!(description["media_type"], "image/png");
+
+        let search = serde_json::json!({ "mode": "Search", "path": "/image.png", "pattern": "IHDR" });
+        assert!(matches!(read(search).await, OutputKind::Json(_)));
+
+        let force = serde_json::json!({ "mode": "Line", "path": "/image.png", "force": true });
+        let OutputKind::Text(dump) = read(force).await else {
+            panic!("expected a hex dump");
+        };
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "[hex dump of the first 4,096 of 8,000 bytes]");
+        assert_eq!(
+            lines[1],
+            "00000000: 8950 4e47 0d0a 1a0a 0000 000d 4948 4452  .PNG........IHDR"
+        );
+        assert_eq!(lines.len(), 1 + HEX_DUMP_BYTES / 16);
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_encodings() {
+        let ctx = setup_test_directory().await;
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("// こんにちは\nfn main() {}\n");
+        ctx.fs().write("/sjis.rs", &shift_jis).await.unwrap();
+        let read = |v: serde_json::Value| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                serde_json::from_value::<FsRead>(v)
+                    .unwrap()
+                    .invoke(&ctx, &mut std::io::sink())
+                    .await
+                    .unwrap()
+                    .output
+            }
+        };
+
+        let OutputKind::Text(text) = read(serde_json::json!({ "mode": "Line", "path": "/sjis.rs" })).await else {
+            panic!("expected text output");
+        };
+        assert_eq!(
+            text,
+            "[this file is encoded in Shift_JIS, and was converted to UTF-8. fs_write keeps the encoding when editing it]\n// こんにちは\nfn main() {}"
+        );
+
+        // An explicit encoding overrides detection.
+        ctx.fs().write("/latin1.txt", b"caf\xe9\n").await.unwrap();
+        let explicit = serde_json::json!({ "mode": "Line", "path": "/latin1.txt", "encoding": "iso-8859-15" });
+        let OutputKind::Text(text) = read(explicit).await else {
+            panic!("expected text output");
+        };
+        assert!(text.ends_with("]\ncafé"), "{text}");
+        assert!(text.contains("ISO-8859-15"), "{text}");
+
+        let search = serde_json::json!({ "mode": "Search", "path": "/sjis.rs", "pattern": "こんにちは" });
+        let OutputKind::Text(matches) = read(search).await else {
+            panic!("expected search results");
+        };
+        assert!(matches.contains("→ 1: // こんにちは"), "{matches}");
+    }
+
+    #[test]
+    fn test_image_info() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend(640_u32.to_be_bytes());
+        png.extend(480_u32.to_be_bytes());
+        assert_eq!(image_info(&png), Some(("image/png", Some((640, 480)))));
+
+        let gif = b"GIF89a\x20\x00\x10\x00";
+        assert_eq!(image_info(gif), Some(("image/gif", Some((32, 16)))));
+
+        // SOI, an APP0 segment, then a baseline start of frame.
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04\0\0\xff\xc0\x00\x11\x08\x00\x64\x00\xc8";
+        assert_eq!(image_info(jpeg), Some(("image/jpeg", Some((200, 100)))));
+
+        assert_eq!(image_info(b"\x7fELF"), None);
+    }
+
+    #[test]
+    fn test_is_binary() {
+        let is_binary = |bytes: &[u8]| TextEncoding::detect(bytes).is_none();
+        assert!(!is_binary(b""));
+        assert!(!is_binary("hello → world\n".as_bytes()));
+        assert!(is_binary(b"hello\0world"));
+        assert!(is_binary(b"\xff\xfe\xfd"));
+        assert_eq!(
+            detect_binary_type(b"\x7fELF\x02\x01"),
+            "ELF executable or shared library"
+        );
+        assert_eq!(detect_binary_type(b"\0\x01\x02"), "unknown binary data");
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_directory_limits() {
+        let ctx = setup_test_directory().await;
+        ctx.fs().create_dir_all("/.git/objects").await.unwrap();
+        ctx.fs().symlink("/", "/aaaa2/root_link").await.unwrap();
+        let list = |v: serde_json::Value| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                match serde_json::from_value::<FsRead>(v)
+                    .unwrap()
+                    .invoke(&ctx, &mut std::io::sink())
+                    .await
+                    .unwrap()
+                    .output
+                {
+                    OutputKind::Text(text) => text,
+                    OutputKind::Json(_) => panic!("expected text output"),
+                }
+            }
+        };
+
+        // The symlink back to the root is listed but not followed a second time.
+        let text = list(serde_json::json!({ "mode": "Directory", "path": "/", "depth": 10 })).await;
+        assert!(!text.contains(".git"), ".git should never be listed: {text}");
+        assert_eq!(text.lines().filter(|l| l.ends_with("root_link")).count(), 1);
+        assert_eq!(text.lines().count(), 9);
+
+        let text = list(serde_json::json!({ "mode": "Directory", "path": "/", "depth": 10, "max_entries": 3 })).await;
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(
+            lines[3].starts_with("…6 more entries"),
+            "unexpected truncation marker: {}",
+            lines[3]
+        );
+
+        let text =
+            list(serde_json::json!({ "mode": "Directory", "path": "/", "depth": 10, "ignore": ["aaaa*"] })).await;
+        assert!(!text.contains("aaaa"));
+        assert_eq!(text.lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_directory_truncated_at_size_limit() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().create_dir_all("/big").await.unwrap();
+        let total = 4000;
+        for i in 0..total {
+            ctx.fs()
+                .write(format!("/big/{i:04}{}", "x".repeat(200)), "")
+                .await
+                .unwrap();
+        }
+
+        let output = serde_json::from_value::<FsRead>(
+            serde_json::json!({ "mode": "Directory", "path": "/big", "max_entries": 100_000 }),
+        )
+        .unwrap()
+        .invoke(&ctx, &mut std::io::sink())
+        .await
+        .unwrap()
+        .output;
+        let text = output_text(output);
+        assert!(text.len() <= MAX_TOOL_RESPONSE_SIZE);
+        let lines = text.lines().collect::<Vec<_>>();
+        let listed = lines.len() - 1;
+        assert!(listed > 0 && listed < total);
+        assert!(
+            lines[listed].starts_with(&format!("…{} more entries", total - listed)),
+            "unexpected truncation marker: {}",
+            lines[listed]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_directory_gitignore() {
+        let ctx = setup_test_directory().await;
+        let fs = ctx.fs();
+        fs.create_dir_all("/.git").await.unwrap();
+        fs.write("/.gitignore", "target/\n*.log\n!keep.log\n").await.unwrap();
+        fs.create_dir_all("/target/debug").await.unwrap();
+        fs.write("/debug.log", "").await.unwrap();
+        fs.write("/keep.log", "").await.unwrap();
+        fs.write("/aaaa1/.gitignore", "bbbb1\n").await.unwrap();
+        let list = |v: serde_json::Value| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                match serde_json::from_value::<FsRead>(v)
+                    .unwrap()
+                    .invoke(&ctx, &mut std::io::sink())
+                    .await
+                    .unwrap()
+                    .output
+                {
+                    OutputKind::Text(text) => text,
+                    OutputKind::Json(_) => panic!("expected text output"),
+                }
+            }
+        };
+
+        let text = list(serde_json::json!({ "mode": "Directory", "path": "/", "depth": 10 })).await;
+        assert!(text.contains("keep.log"));
+        for hidden in ["target", "debug.log", "bbbb1"] {
+            assert!(!text.contains(hidden), "{hidden} should be hidden: {text}");
+        }
+        assert!(text.ends_with(
+            "Note: 3 entries excluded by .gitignore were hidden. Set `include_ignored` to true to list them."
+        ));
+
+        // The parent .gitignore still applies when listing a subdirectory.
+        let text = list(serde_json::json!({ "mode": "Directory", "path": "/aaaa1" })).await;
+        assert!(!text.contains("bbbb1"));
+
+        let text =
+            list(serde_json::json!({ "mode": "Directory", "path": "/", "depth": 10, "include_ignored": true })).await;
+        for shown in ["target", "debug", "debug.log", "bbbb1", "cccc1"] {
+            assert!(text.contains(shown), "{shown} should be listed: {text}");
+        }
+        assert!(!text.contains("Note:"));
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_follows_symlinks_in_workspace() {
+        let ctx = setup_test_directory().await;
+        ctx.fs().symlink(TEST_FILE_PATH, "/file_link.txt").await.unwrap();
+        ctx.fs().symlink("/aaaa1", "/dir_link").await.unwrap();
+        let run = |v: serde_json::Value| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                let mut fs_read = serde_json::from_value::<FsRead>(v).unwrap();
+                fs_read.validate(&ctx).await.unwrap();
+                output_text(fs_read.invoke(&ctx, &mut std::io::sink()).await.unwrap().output)
+            }
+        };
+
+        let text = run(serde_json::json!({ "mode": "Line", "path": "/file_link.txt" })).await;
+        assert_eq!(text, TEST_FILE_CONTENTS.trim_end());
+        let text = run(serde_json::json!({ "mode": "Line", "paths": ["/file_link.txt"] })).await;
+        assert!(text.contains(TEST_FILE_CONTENTS.lines().next().unwrap()), "{text}");
+        let text = run(serde_json::json!({ "mode": "Search", "path": "/file_link.txt", "pattern": "hello" })).await;
+        assert!(text.contains("Hello world!"), "{text}");
+        let text = run(serde_json::json!({ "mode": "Directory", "path": "/dir_link" })).await;
+        assert!(text.contains("bbbb1"), "{text}");
+
+        // Without following symlinks, the link itself is rejected.
+        let mut strict = serde_json::from_value::<FsRead>(
+            serde_json::json!({ "mode": "Line", "path": "/file_link.txt", "follow_symlinks": false }),
+        )
+        .unwrap();
+        assert!(strict.validate(&ctx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_symlink_escape() {
+        let ctx = setup_test_directory().await;
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), ctx.fs().chroot_path("/aaaa1/outside")).unwrap();
+        ctx.fs().symlink(TEST_FILE_PATH, "/inside_link.txt").await.unwrap();
+        let read = |v: serde_json::Value| serde_json::from_value::<FsRead>(v).unwrap();
+
+        let escaping = read(serde_json::json!({ "mode": "Line", "path": "/aaaa1/outside/secret.txt" }));
+        assert_eq!(
+            escaping.escaping_symlink_target(&ctx),
+            Some(outside.path().canonicalize().unwrap().join("secret.txt"))
+        );
+        assert!(crate::tools::Tool::FsRead(escaping).requires_explicit_acceptance(&ctx));
+        let inside = read(serde_json::json!({ "mode": "Line", "path": "/inside_link.txt" }));
+        assert!(inside.escaping_symlink_target(&ctx).is_none());
+
+        let mut strict =
+            read(serde_json::json!({ "mode": "Line", "path": "/inside_link.txt", "follow_symlinks": false }));
+        assert!(strict.validate(&ctx).await.is_err());
+        let mut strict = read(serde_json::json!({ "mode": "Line", "path": TEST_FILE_PATH, "follow_symlinks": false }));
+        assert!(strict.validate(&ctx).await.is_ok());
+
+        // Globs are checked through the directory they search and each of their matches.
+        for glob in ["/aaaa1/outside/*", "/aaaa1/*/secret.txt", "/aaaa1/**/*.txt"] {
+            let escaping = read(serde_json::json!({ "mode": "Line", "path": glob }));
+            assert!(escaping.escaping_symlink_target(&ctx).is_some(), "{glob}");
+            let mut strict = read(serde_json::json!({ "mode": "Line", "path": glob, "follow_symlinks": false }));
+            assert!(strict.validate(&ctx).await.is_err(), "{glob}");
+        }
+        let inside = read(serde_json::json!({ "mode": "Line", "path": "/*.txt" }));
+        assert!(inside.escaping_symlink_target(&ctx).is_none());
+
+        // Directory walks list the escaping link without following it.
+        let listing = read(serde_json::json!({ "mode": "Directory", "path": "/", "depth": 10 }))
+            .invoke(&ctx, &mut std::io::sink())
+            .await
+            .unwrap();
+        let OutputKind::Text(text) = listing.output else {
+            panic!("expected text output");
+        };
+        assert!(text.contains("outside"));
+        assert!(!text.contains("secret.txt"));
+    }
+
     #[tokio::test]
     async fn test_fs_read_directory_invoke() {
         let ctx = setup_test_directory().await;
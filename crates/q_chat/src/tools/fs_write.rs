@@ -1,6 +1,17 @@
-use std::io::Write;
-use std::path::Path;
-use std::sync::LazyLock;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{
+    self,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
 
 use crossterm::queue;
 use crossterm::style::{
@@ -8,35 +19,41 @@ use crossterm::style::{
     Color,
 };
 use eyre::{
-    ContextCompat as _,
     Result,
     bail,
     eyre,
 };
-use fig_os_shim::Context;
+use fig_os_shim::{
+    Context,
+    Shim as _,
+};
 use serde::Deserialize;
-use similar::DiffableStr;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::ThemeSet;
-use syntect::parsing::SyntaxSet;
-use syntect::util::{
-    LinesWithEndings,
-    as_24_bit_terminal_escaped,
+use syntect::util::LinesWithEndings;
+use tracing::warn;
+
+use super::diff::{
+    self,
+    DiffOptions,
 };
-use tracing::{
-    error,
-    warn,
+use super::encoding::{
+    self,
+    TextEncoding,
 };
-
 use super::{
     InvokeOutput,
+    OutputKind,
+    canonicalize_lenient,
     format_path,
+    is_within_roots,
+    patch,
     sanitize_path_tool_arg,
-    supports_truecolor,
+    workspace_roots,
 };
 
-static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
-static THEME_SET: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+/// Number of diff lines shown in the approval prompt before the rest of the diff is summarized.
+pub const DIFF_PREVIEW_MAX_LINES: usize = 200;
+/// Number of unchanged lines shown around each change.
+const DIFF_CONTEXT_LINES: usize = 3;
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "command")]
@@ -48,198 +65,514 @@ pub enum FsWrite {
         path: String,
         file_text: Option<String>,
         new_str: Option<String>,
+        /// Whether to create any missing parent directories. Defaults to true.
+        create_dirs: Option<bool>,
+        #[serde(default)]
+        dry_run: bool,
+        /// Write even if the file changed since it was last read.
+        #[serde(default)]
+        force: bool,
+        /// Encoding of the file, instead of the detected one.
+        encoding: Option<String>,
     },
     #[serde(rename = "str_replace")]
     StrReplace {
         path: String,
         old_str: String,
         new_str: String,
+        /// Replace every occurrence of `old_str` instead of requiring it to be unique.
+        #[serde(default)]
+        replace_all: bool,
+        #[serde(default)]
+        dry_run: bool,
+        /// Write even if the file changed since it was last read.
+        #[serde(default)]
+        force: bool,
+        /// Encoding of the file, instead of the detected one.
+        encoding: Option<String>,
     },
     #[serde(rename = "insert")]
     Insert {
         path: String,
-        insert_line: usize,
+        /// Line to insert after, with 0 meaning the start of the file. Inserts at the end of the
+        /// file if omitted.
+        #[serde(alias = "line")]
+        insert_line: Option<usize>,
+        #[serde(alias = "content")]
         new_str: String,
+        #[serde(default)]
+        dry_run: bool,
+        /// Write even if the file changed since it was last read.
+        #[serde(default)]
+        force: bool,
+        /// Encoding of the file, instead of the detected one.
+        encoding: Option<String>,
     },
     #[serde(rename = "append")]
-    Append { path: String, new_str: String },
+    Append {
+        path: String,
+        new_str: String,
+        #[serde(default)]
+        dry_run: bool,
+        /// Write even if the file changed since it was last read.
+        #[serde(default)]
+        force: bool,
+        /// Encoding of the file, instead of the detected one.
+        encoding: Option<String>,
+    },
+    /// Applies a unified diff of a single file.
+    #[serde(rename = "patch")]
+    Patch {
+        path: String,
+        diff: String,
+        #[serde(default)]
+        dry_run: bool,
+        /// Write even if the file changed since it was last read.
+        #[serde(default)]
+        force: bool,
+        /// Encoding of the file, instead of the detected one.
+        encoding: Option<String>,
+    },
 }
 
 impl FsWrite {
     pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
         let fs = ctx.fs();
         let cwd = ctx.env().current_dir()?;
+        let path = sanitize_path_tool_arg(ctx, self.path());
+        let relative_path = format_path(&cwd, &path);
+        let (old, new, encoding, summary) = self.apply(ctx, &path).await?;
+        let bytes = encoding.encode(&new)?;
+
+        let invoke_description = match self {
+            _ if self.dry_run() => "Dry run, not writing: ",
+            FsWrite::Create { .. } if fs.exists(&path) => "Replacing: ",
+            FsWrite::Create { .. } => "Creating: ",
+            FsWrite::StrReplace { .. } | FsWrite::Insert { .. } => "Updating: ",
+            FsWrite::Append { .. } => "Appending to: ",
+            FsWrite::Patch { .. } => "Patching: ",
+        };
+        queue!(
+            updates,
+            style::Print(invoke_description),
+            style::SetForegroundColor(Color::Green),
+            style::Print(&relative_path),
+            style::ResetColor,
+            style::Print("\n"),
+        )?;
+        if self.dry_run() {
+            return Ok(dry_run_output(&relative_path, &old, &new, summary));
+        }
+
+        let created_dirs = self.dirs_to_create(ctx);
+        if let (FsWrite::Create { .. }, Some(parent)) = (self, path.parent()) {
+            fs.create_dir_all(parent).await?;
+        }
+        write_file(ctx, &path, &bytes).await?;
+
+        let mut output = diff_output(&relative_path, &old, &new);
+        if let OutputKind::Text(text) = &mut output.output {
+            if let Some(summary) = summary {
+                text.insert_str(0, &format!("{summary}\n"));
+            }
+            if !created_dirs.is_empty() {
+                let created_dirs = created_dirs
+                    .iter()
+                    .map(|dir| format_path(&cwd, dir))
+                    .collect::<Vec<_>>();
+                text.insert_str(0, &format!("Created directories: {}\n", created_dirs.join(", ")));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Computes the change to the file at `path` without writing it. Returns the current and new
+    /// contents of the file and the encoding to write it in, along with a summary of the change for
+    /// the tool result.
+    async fn apply(&self, ctx: &Context, path: &Path) -> Result<(String, String, TextEncoding, Option<String>)> {
+        let (old, new, encoding, summary) = self.apply_text(ctx, path).await?;
+        let summary = if encoding.is_utf8() {
+            summary
+        } else {
+            let note = format!("Kept the file's {} encoding", encoding.name());
+            join_notes(summary.into_iter().chain([note]).collect())
+        };
+        Ok((old, new, encoding, summary))
+    }
+
+    async fn apply_text(&self, ctx: &Context, path: &Path) -> Result<(String, String, TextEncoding, Option<String>)> {
+        let fs = ctx.fs();
         match self {
-            FsWrite::Create { path, .. } => {
-                let file_text = self.canonical_create_command_text();
-                let path = sanitize_path_tool_arg(ctx, path);
-                if let Some(parent) = path.parent() {
-                    fs.create_dir_all(parent).await?;
+            FsWrite::Create { .. } => {
+                let exists = fs.exists(path);
+                // The file is replaced anyway, so `encoding` only picks the encoding to write.
+                let (old, detected) = if exists {
+                    read_text(ctx, path, None).await.unwrap_or_default()
+                } else {
+                    Default::default()
+                };
+                let encoding = match self.encoding() {
+                    Some(label) => TextEncoding::from_label(label)?,
+                    None => detected,
+                };
+                let content = self.canonical_create_command_text();
+                let line_ending = if exists {
+                    line_ending(&old)
+                } else {
+                    new_file_line_ending()
+                };
+                let mut new = convert_line_endings(&content, line_ending).into_owned();
+                let mut notes = conversion_note(&content, line_ending, exists)
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                if !match_trailing_newline(&old, &mut new, line_ending) {
+                    notes.push(TRAILING_NEWLINE_NOTE.to_string());
                 }
-
-                let invoke_description = if fs.exists(&path) { "Replacing: " } else { "Creating: " };
-                queue!(
-                    updates,
-                    style::Print(invoke_description),
-                    style::SetForegroundColor(Color::Green),
-                    style::Print(format_path(cwd, &path)),
-                    style::ResetColor,
-                    style::Print("\n"),
-                )?;
-
-                write_to_file(ctx, path, file_text).await?;
-                Ok(Default::default())
+                Ok((old, new, encoding, join_notes(notes)))
             },
-            FsWrite::StrReplace { path, old_str, new_str } => {
-                let path = sanitize_path_tool_arg(ctx, path);
-                let file = fs.read_to_string(&path).await?;
-                let matches = file.match_indices(old_str).collect::<Vec<_>>();
-                queue!(
-                    updates,
-                    style::Print("Updating: "),
-                    style::SetForegroundColor(Color::Green),
-                    style::Print(format_path(cwd, &path)),
-                    style::ResetColor,
-                    style::Print("\n"),
-                )?;
-                match matches.len() {
-                    0 => Err(eyre!("no occurrences of \"{old_str}\" were found")),
-                    1 => {
-                        let file = file.replacen(old_str, new_str, 1);
-                        fs.write(path, file).await?;
-                        Ok(Default::default())
-                    },
-                    x => Err(eyre!("{x} occurrences of old_str were found when only 1 is expected")),
+            FsWrite::StrReplace {
+                old_str,
+                new_str,
+                replace_all,
+                ..
+            } => {
+                let (old, encoding) = read_text(ctx, path, self.encoding()).await?;
+                let (new, replaced) = replace_occurrences(&old, old_str, new_str, *replace_all)?;
+                let mut notes = Vec::new();
+                if replaced > 1 {
+                    notes.push(format!("Replaced {replaced} occurrences of old_str"));
+                }
+                let (_, converted) = match_line_endings(&old, old_str, new_str);
+                if converted != new_str.as_str() {
+                    notes.extend(conversion_note(new_str, line_ending(&converted), true));
                 }
+                Ok((old, new, encoding, join_notes(notes)))
             },
             FsWrite::Insert {
-                path,
-                insert_line,
-                new_str,
+                insert_line, new_str, ..
             } => {
-                let path = sanitize_path_tool_arg(ctx, path);
-                let mut file = fs.read_to_string(&path).await?;
-                queue!(
-                    updates,
-                    style::Print("Updating: "),
-                    style::SetForegroundColor(Color::Green),
-                    style::Print(format_path(cwd, &path)),
-                    style::ResetColor,
-                    style::Print("\n"),
-                )?;
-
-                // Get the index of the start of the line to insert at.
-                let num_lines = file.lines().enumerate().map(|(i, _)| i + 1).last().unwrap_or(1);
-                let insert_line = insert_line.clamp(&0, &num_lines);
-                let mut i = 0;
-                for _ in 0..*insert_line {
-                    let line_len = &file[i..].find("\n").map_or(file[i..].len(), |i| i + 1);
-                    i += line_len;
-                }
-                file.insert_str(i, new_str);
-                write_to_file(ctx, &path, file).await?;
-                Ok(Default::default())
+                let (old, encoding) = read_text(ctx, path, self.encoding()).await?;
+                let (new, first, last) = insert_lines(&old, *insert_line, new_str)?;
+                let lines = if first == last {
+                    format!("line {first}")
+                } else {
+                    format!("lines {first}-{last}")
+                };
+                let mut notes = vec![format!("Inserted {lines}")];
+                notes.extend(conversion_note(new_str, line_ending(&old), true));
+                Ok((old, new, encoding, join_notes(notes)))
             },
-            FsWrite::Append { path, new_str } => {
-                let path = sanitize_path_tool_arg(ctx, path);
-
-                queue!(
-                    updates,
-                    style::Print("Appending to: "),
-                    style::SetForegroundColor(Color::Green),
-                    style::Print(format_path(cwd, &path)),
-                    style::ResetColor,
-                    style::Print("\n"),
-                )?;
-
-                let mut file = fs.read_to_string(&path).await?;
-                if !file.ends_with_newline() {
-                    file.push('\n');
+            FsWrite::Append { new_str, .. } => {
+                let (old, encoding) = read_text(ctx, path, self.encoding()).await?;
+                let line_ending = line_ending(&old);
+                let mut new = old.clone();
+                if !new.is_empty() && !new.ends_with('\n') {
+                    new.push_str(line_ending);
                 }
-                file.push_str(new_str);
-                write_to_file(ctx, path, file).await?;
-                Ok(Default::default())
+                new.push_str(&convert_line_endings(new_str, line_ending));
+                let mut notes = conversion_note(new_str, line_ending, true)
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                if !match_trailing_newline(&old, &mut new, line_ending) {
+                    notes.push(TRAILING_NEWLINE_NOTE.to_string());
+                }
+                Ok((old, new, encoding, join_notes(notes)))
+            },
+            FsWrite::Patch { diff, .. } => {
+                let (old, encoding) = read_text(ctx, path, self.encoding()).await?;
+                let applied = patch::apply(&old, &patch::parse(diff)?)?;
+                let summary = applied.summary();
+                Ok((old, applied.content, encoding, Some(summary)))
             },
         }
     }
 
     pub fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
-        let cwd = ctx.env().current_dir()?;
         self.print_relative_path(ctx, updates)?;
+        self.print_created_dirs(ctx, updates)?;
+        self.queue_diff(ctx, updates, true)
+    }
+
+    /// Whether to only compute the change and return its diff, without writing anything.
+    pub fn dry_run(&self) -> bool {
+        match self {
+            FsWrite::Create { dry_run, .. }
+            | FsWrite::StrReplace { dry_run, .. }
+            | FsWrite::Insert { dry_run, .. }
+            | FsWrite::Append { dry_run, .. }
+            | FsWrite::Patch { dry_run, .. } => *dry_run,
+        }
+    }
+
+    /// Whether to overwrite changes made to the file since the model last read it.
+    pub fn force(&self) -> bool {
+        match self {
+            FsWrite::Create { force, .. }
+            | FsWrite::StrReplace { force, .. }
+            | FsWrite::Insert { force, .. }
+            | FsWrite::Append { force, .. }
+            | FsWrite::Patch { force, .. } => *force,
+        }
+    }
+
+    /// Makes this a dry run, e.g. while the user only wants changes proposed.
+    pub fn set_dry_run(&mut self) {
+        match self {
+            FsWrite::Create { dry_run, .. }
+            | FsWrite::StrReplace { dry_run, .. }
+            | FsWrite::Insert { dry_run, .. }
+            | FsWrite::Append { dry_run, .. }
+            | FsWrite::Patch { dry_run, .. } => *dry_run = true,
+        }
+    }
+
+    /// The encoding given for the file, if it shouldn't be detected.
+    fn encoding(&self) -> Option<&str> {
+        match self {
+            FsWrite::Create { encoding, .. }
+            | FsWrite::StrReplace { encoding, .. }
+            | FsWrite::Insert { encoding, .. }
+            | FsWrite::Append { encoding, .. }
+            | FsWrite::Patch { encoding, .. } => encoding.as_deref(),
+        }
+    }
+
+    /// The path being written to.
+    pub fn path(&self) -> &str {
+        match self {
+            FsWrite::Create { path, .. }
+            | FsWrite::StrReplace { path, .. }
+            | FsWrite::Insert { path, .. }
+            | FsWrite::Append { path, .. }
+            | FsWrite::Patch { path, .. } => path,
+        }
+    }
+
+    /// Returns where the written file really is, following any symlinks, if that is outside of the
+    /// workspace.
+    pub fn outside_workspace_target(&self, ctx: &Context) -> Option<PathBuf> {
+        let path = sanitize_path_tool_arg(ctx, self.path());
+        match canonicalize_lenient(&path) {
+            Some(target) => (!is_within_roots(&target, &workspace_roots(ctx))).then_some(target),
+            None => Some(path),
+        }
+    }
+
+    /// The missing parent directories that [FsWrite::Create] will create, outermost first.
+    pub fn dirs_to_create(&self, ctx: &Context) -> Vec<PathBuf> {
+        let FsWrite::Create { path, .. } = self else {
+            return Vec::new();
+        };
+        let path = sanitize_path_tool_arg(ctx, path);
+        let mut dirs = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| !dir.as_os_str().is_empty() && !ctx.fs().exists(dir))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        dirs.reverse();
+        dirs
+    }
+
+    /// Whether any of the directories this will create are outside of the workspace.
+    pub fn creates_dirs_outside_workspace(&self, ctx: &Context) -> bool {
+        self.dirs_to_create(ctx).first().is_some_and(|dir| {
+            canonicalize_lenient(dir).is_none_or(|dir| !is_within_roots(&dir, &workspace_roots(ctx)))
+        })
+    }
+
+    fn print_created_dirs(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
+        let dirs = self.dirs_to_create(ctx);
+        if dirs.is_empty() {
+            return Ok(());
+        }
+        let cwd = ctx.env().current_dir()?;
+        let dirs = dirs
+            .iter()
+            .map(|dir| format_path(&cwd, dir))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if self.creates_dirs_outside_workspace(ctx) {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(format!(
+                    "⚠ This creates directories OUTSIDE of your workspace: {dirs}\n\n"
+                )),
+                style::ResetColor,
+            )?;
+        } else {
+            queue!(
+                updates,
+                style::Print("Creates directories: "),
+                style::SetForegroundColor(Color::Green),
+                style::Print(dirs),
+                style::ResetColor,
+                style::Print("\n\n"),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Prints the whole diff of the change, for when [Self::queue_description] only showed part
+    /// of it.
+    pub fn queue_full_diff(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
+        self.queue_diff(ctx, updates, false)
+    }
+
+    /// Whether the diff shown by [Self::queue_description] leaves out part of the change.
+    pub fn diff_is_summarized(&self, ctx: &Context) -> bool {
+        self.preview(ctx)
+            .is_ok_and(|preview| diff::is_summarized(&preview.old, &preview.new, &diff_options(ctx, true)))
+    }
+
+    fn queue_diff(&self, ctx: &Context, updates: &mut impl Write, summarize: bool) -> Result<()> {
+        let preview = self.preview(ctx)?;
+        diff::print_diff(
+            updates,
+            &preview.path,
+            &preview.old,
+            &preview.new,
+            preview.start_line,
+            &diff_options(ctx, summarize),
+        )
+    }
+
+    /// Returns the part of the file affected by this change, before and after the change.
+    fn preview(&self, ctx: &Context) -> Result<DiffPreview> {
+        let cwd = ctx.env().current_dir()?;
         match self {
             FsWrite::Create { path, .. } => {
                 let file_text = self.canonical_create_command_text();
-                let relative_path = format_path(cwd, path);
-                let prev = if ctx.fs().exists(path) {
-                    let file = ctx.fs().read_to_string_sync(path)?;
-                    stylize_output_if_able(ctx, path, &file)
+                let old = if ctx.fs().exists(path) {
+                    read_text_sync(ctx, path, None)
+                        .map(|(text, _)| text)
+                        .unwrap_or_default()
                 } else {
                     Default::default()
                 };
-                let new = stylize_output_if_able(ctx, &relative_path, &file_text);
-                print_diff(updates, &prev, &new, 1)?;
-                Ok(())
+                Ok(DiffPreview {
+                    path: format_path(cwd, path),
+                    old,
+                    new: file_text,
+                    start_line: 1,
+                })
             },
             FsWrite::Insert {
                 path,
                 insert_line,
                 new_str,
+                ..
             } => {
                 let relative_path = format_path(cwd, path);
-                let file = ctx.fs().read_to_string_sync(&relative_path)?;
-
-                // Diff the old with the new by adding extra context around the line being inserted
-                // at.
-                let (prefix, start_line, suffix, _) = get_lines_with_context(&file, *insert_line, *insert_line, 3);
-                let insert_line_content = LinesWithEndings::from(&file)
-                    // don't include any content if insert_line is 0
-                    .nth(insert_line.checked_sub(1).unwrap_or(usize::MAX))
-                    .unwrap_or_default();
-                let old = [prefix, insert_line_content, suffix].join("");
-                let new = [prefix, insert_line_content, new_str, suffix].join("");
-
-                let old = stylize_output_if_able(ctx, &relative_path, &old);
-                let new = stylize_output_if_able(ctx, &relative_path, &new);
-                print_diff(updates, &old, &new, start_line)?;
-                Ok(())
+                let (file, _) = read_text_sync(ctx, &relative_path, self.encoding())?;
+                let (new_file, first, last) = insert_lines(&file, *insert_line, new_str)?;
+
+                // Diff the old with the new by adding extra context around the inserted lines.
+                let context_start = (first - 1).saturating_sub(DIFF_CONTEXT_LINES);
+                let old = line_window(&file, context_start, first - 1 + DIFF_CONTEXT_LINES);
+                let new = line_window(&new_file, context_start, last + DIFF_CONTEXT_LINES);
+                Ok(DiffPreview {
+                    path: relative_path,
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    start_line: context_start + 1,
+                })
             },
-            FsWrite::StrReplace { path, old_str, new_str } => {
+            FsWrite::StrReplace {
+                path,
+                old_str,
+                new_str,
+                replace_all,
+                ..
+            } => {
                 let relative_path = format_path(cwd, path);
-                let file = ctx.fs().read_to_string_sync(&relative_path)?;
-                let (start_line, _) = match line_number_at(&file, old_str) {
-                    Some((start_line, end_line)) => (start_line, end_line),
-                    _ => (0, 0),
+                let (file, _) = read_text_sync(ctx, &relative_path, self.encoding())?;
+                let (old, new, start_line) = match replacement_hunk(&file, old_str, new_str, *replace_all) {
+                    Some(hunk) => hunk,
+                    None => (old_str.clone(), new_str.clone(), 0),
                 };
-                let old_str = stylize_output_if_able(ctx, &relative_path, old_str);
-                let new_str = stylize_output_if_able(ctx, &relative_path, new_str);
-                print_diff(updates, &old_str, &new_str, start_line)?;
-
-                Ok(())
+                Ok(DiffPreview {
+                    path: relative_path,
+                    old,
+                    new,
+                    start_line,
+                })
             },
-            FsWrite::Append { path, new_str } => {
+            FsWrite::Append { path, new_str, .. } => {
                 let relative_path = format_path(cwd, path);
-                let start_line = ctx.fs().read_to_string_sync(&relative_path)?.lines().count() + 1;
-                let file = stylize_output_if_able(ctx, &relative_path, new_str);
-                print_diff(updates, &Default::default(), &file, start_line)?;
-                Ok(())
+                let (file, _) = read_text_sync(ctx, &relative_path, self.encoding())?;
+                let start_line = file.lines().count() + 1;
+                Ok(DiffPreview {
+                    path: relative_path,
+                    old: Default::default(),
+                    new: new_str.clone(),
+                    start_line,
+                })
+            },
+            FsWrite::Patch { path, diff, .. } => {
+                let relative_path = format_path(cwd, path);
+                let (file, _) = read_text_sync(ctx, &relative_path, self.encoding())?;
+                let applied = patch::apply(&file, &patch::parse(diff)?)?;
+                let (old, new, start_line) = changed_region(&file, &applied.content);
+                Ok(DiffPreview {
+                    path: relative_path,
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    start_line,
+                })
             },
         }
     }
 
     pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        check_writable(&sanitize_path_tool_arg(ctx, self.path()))?;
+        let encoding = self.encoding().map(str::to_string);
+
+        if matches!(self, FsWrite::Create {
+            create_dirs: Some(false),
+            ..
+        }) {
+            if let Some(dir) = self.dirs_to_create(ctx).first() {
+                bail!(
+                    "The directory {} doesn't exist. Set `create_dirs` to true to create it",
+                    dir.display()
+                )
+            }
+        }
+
         match self {
             FsWrite::Create { path, .. } => {
                 if path.is_empty() {
                     bail!("Path must not be empty")
                 };
             },
-            FsWrite::StrReplace { path, .. } | FsWrite::Insert { path, .. } => {
+            FsWrite::StrReplace { path, old_str, .. } => {
+                let path = sanitize_path_tool_arg(ctx, path);
+                if !path.exists() {
+                    bail!("The provided path must exist in order to replace or insert contents into it")
+                }
+                if old_str.is_empty() {
+                    bail!("old_str must not be empty")
+                }
+            },
+            FsWrite::Insert {
+                path,
+                insert_line,
+                new_str,
+                ..
+            } => {
                 let path = sanitize_path_tool_arg(ctx, path);
                 if !path.exists() {
                     bail!("The provided path must exist in order to replace or insert contents into it")
                 }
+                if new_str.is_empty() {
+                    bail!("Content to insert must not be empty")
+                }
+                let (file, _) = read_text(ctx, &path, encoding.as_deref()).await?;
+                insert_lines(&file, *insert_line, new_str)?;
             },
-            FsWrite::Append { path, new_str } => {
+            FsWrite::Append { path, new_str, .. } => {
                 if path.is_empty() {
                     bail!("Path must not be empty")
                 };
@@ -247,6 +580,15 @@ impl FsWrite {
                     bail!("Content to append must not be empty")
                 };
             },
+            FsWrite::Patch { path, diff, .. } => {
+                let path = sanitize_path_tool_arg(ctx, path);
+                if !path.exists() {
+                    bail!("The provided path must exist in order to patch it")
+                }
+                // Check that the patch applies before asking the user to approve it.
+                let (file, _) = read_text(ctx, &path, encoding.as_deref()).await?;
+                patch::apply(&file, &patch::parse(diff)?)?;
+            },
         }
 
         Ok(())
@@ -254,13 +596,21 @@ impl FsWrite {
 
     fn print_relative_path(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
         let cwd = ctx.env().current_dir()?;
-        let path = match self {
-            FsWrite::Create { path, .. } => path,
-            FsWrite::StrReplace { path, .. } => path,
-            FsWrite::Insert { path, .. } => path,
-            FsWrite::Append { path, .. } => path,
-        };
-        let relative_path = format_path(cwd, path);
+        if let Some(target) = self.outside_workspace_target(ctx) {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::Red),
+                style::SetAttribute(style::Attribute::Bold),
+                style::Print(format!(
+                    "⚠ This writes OUTSIDE of your workspace: {}\n",
+                    target.display()
+                )),
+                style::SetAttribute(style::Attribute::Reset),
+                style::ResetColor,
+                style::Print("\n"),
+            )?;
+        }
+        let relative_path = format_path(cwd, self.path());
         queue!(
             updates,
             style::Print("Path: "),
@@ -292,15 +642,546 @@ impl FsWrite {
     }
 }
 
-/// Writes `content` to `path`, adding a newline if necessary.
-async fn write_to_file(ctx: &Context, path: impl AsRef<Path>, mut content: String) -> Result<()> {
-    if !content.ends_with_newline() {
-        content.push('\n');
+/// Reads the file at `path` as text, decoded from the encoding named by `label` or otherwise the
+/// detected one.
+async fn read_text(ctx: &Context, path: impl AsRef<Path>, label: Option<&str>) -> Result<(String, TextEncoding)> {
+    let path = path.as_ref();
+    decode_file(path, &ctx.fs().read(path).await?, label)
+}
+
+fn read_text_sync(ctx: &Context, path: impl AsRef<Path>, label: Option<&str>) -> Result<(String, TextEncoding)> {
+    let path = path.as_ref();
+    decode_file(path, &ctx.fs().read_sync(path)?, label)
+}
+
+fn decode_file(path: &Path, bytes: &[u8], label: Option<&str>) -> Result<(String, TextEncoding)> {
+    match encoding::decode(bytes, label)? {
+        Some(decoded) => Ok(decoded),
+        None => bail!(
+            "{} looks like a binary file, so it can't be edited as text. If it is text in an encoding that wasn't detected, set `encoding` to that encoding",
+            path.display()
+        ),
+    }
+}
+
+/// Replaces the contents of `path` with `content` atomically, so that an interrupted write leaves
+/// either the old or the new file and never a partial one. Falls back to writing the file in place
+/// where it can't be replaced, e.g. on network mounts that don't support renaming over a file.
+async fn write_file(ctx: &Context, path: impl AsRef<Path>, content: &[u8]) -> Result<()> {
+    let fs = ctx.fs();
+    let path = path.as_ref();
+    if !fs.is_real() && !fs.is_chroot() {
+        return Ok(fs.write(path, content).await?);
+    }
+
+    let target = fs.chroot_path(path);
+    check_writable(&target)?;
+    let bytes = content.to_vec();
+    match tokio::task::spawn_blocking(move || replace_file(&target, |file| file.write_all(&bytes))).await? {
+        Ok(()) => Ok(()),
+        Err(ReplaceError::Unsupported(err)) => {
+            warn!(
+                ?err,
+                ?path,
+                "failed to replace the file atomically, writing it in place instead"
+            );
+            Ok(fs.write(path, content).await?)
+        },
+        Err(ReplaceError::Failed(err)) => Err(err.into()),
     }
-    ctx.fs().write(path.as_ref(), content).await?;
+}
+
+/// Returns an error explaining why `path` can't be written to, if it is an existing file that
+/// isn't writable. Read only files are never written to, even when running as root, since renaming
+/// over them would otherwise succeed.
+fn check_writable(path: &Path) -> Result<()> {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return Ok(());
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{
+            MetadataExt,
+            PermissionsExt,
+        };
+
+        use nix::unistd::{
+            AccessFlags,
+            Uid,
+            User,
+            access,
+        };
+
+        let mode = metadata.permissions().mode();
+        if mode & 0o222 == 0 {
+            bail!(
+                "{} is read-only (mode {:o}), so it wasn't changed. Ask the user to make it writable, e.g. with `chmod u+w {}`, and then try again",
+                path.display(),
+                mode & 0o7777,
+                path.display()
+            );
+        }
+        if access(path, AccessFlags::W_OK).is_err() {
+            let owner = metadata.uid();
+            if owner != Uid::effective().as_raw() {
+                let owner = User::from_uid(owner.into())
+                    .ok()
+                    .flatten()
+                    .map_or_else(|| owner.to_string(), |user| user.name);
+                bail!(
+                    "{} is owned by {owner} and isn't writable by the current user, so it wasn't changed. Ask the user to change its owner or permissions and then try again",
+                    path.display()
+                );
+            }
+            bail!(
+                "{} isn't writable by the current user (mode {:o}), so it wasn't changed. Ask the user to change its permissions and then try again",
+                path.display(),
+                mode & 0o7777
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    if metadata.permissions().readonly() {
+        bail!(
+            "{} has the read-only attribute set, so it wasn't changed. Ask the user to clear it, e.g. with `attrib -r {}`, and then try again",
+            path.display(),
+            path.display()
+        );
+    }
+
     Ok(())
 }
 
+#[derive(Debug)]
+enum ReplaceError {
+    /// The file can't be replaced atomically, but may still be writable in place.
+    Unsupported(io::Error),
+    /// Writing the new contents failed. The original file is unchanged.
+    Failed(io::Error),
+}
+
+/// Writes a temporary file next to `path` with `write`, syncs it to disk, and renames it over
+/// `path`. The original file's permissions and (where allowed) ownership are kept, and symlinks are
+/// written through rather than replaced.
+fn replace_file(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> Result<(), ReplaceError> {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let metadata = std::fs::metadata(&path).ok();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut temp = tempfile::Builder::new()
+        .prefix(&format!(".{file_name}."))
+        .suffix(".tmp")
+        .tempfile_in(dir)
+        .map_err(ReplaceError::Unsupported)?;
+    if let Some(metadata) = &metadata {
+        if let Err(err) = temp.as_file().set_permissions(metadata.permissions()) {
+            warn!(?err, ?path, "failed to copy the file's permissions");
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Only succeeds for the file's owner when the group is unchanged, or as root.
+            let _ = std::os::unix::fs::fchown(temp.as_file(), Some(metadata.uid()), Some(metadata.gid()));
+        }
+    }
+
+    write(temp.as_file_mut())
+        .and_then(|()| temp.as_file().sync_all())
+        .map_err(ReplaceError::Failed)?;
+    temp.persist(&path)
+        .map_err(|err| ReplaceError::Unsupported(err.error))?;
+    // Sync the directory too so that the rename itself is durable.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Inserts `content` as whole lines after line `insert_line` of `file`, or at the end of the file
+/// if `insert_line` is `None`. The content is converted to the file's line endings.
+///
+/// Returns the new file along with the 1-indexed first and last lines of the inserted content.
+fn insert_lines(file: &str, insert_line: Option<usize>, content: &str) -> Result<(String, usize, usize)> {
+    let line_count = file.lines().count();
+    let insert_line = insert_line.unwrap_or(line_count);
+    if insert_line > line_count {
+        bail!(
+            "insert_line {insert_line} is past the end of the file, which has {line_count} lines. Use 0 to insert at the start of the file, or leave insert_line out to insert at the end"
+        );
+    }
+
+    let line_ending = line_ending(file);
+    let mut content = convert_line_endings(content, line_ending).into_owned();
+    if !content.ends_with('\n') {
+        content.push_str(line_ending);
+    }
+
+    let i = line_window(file, 0, insert_line).len();
+    let mut new = file[..i].to_string();
+    // The last line may not have a line ending yet.
+    if !new.is_empty() && !new.ends_with('\n') {
+        new.push_str(line_ending);
+    }
+    new.push_str(&content);
+    new.push_str(&file[i..]);
+    match_trailing_newline(file, &mut new, line_ending);
+
+    let first = insert_line + 1;
+    Ok((new, first, insert_line + content.lines().count()))
+}
+
+/// The dominant line ending of `file`, `\n` unless most of its lines end with `\r\n`.
+fn line_ending(file: &str) -> &'static str {
+    let crlf = file.matches("\r\n").count();
+    if crlf > 0 && crlf * 2 >= file.matches('\n').count() {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// Setting holding the line ending of new files, `lf` (the default) or `crlf`.
+pub const NEW_FILE_LINE_ENDING_SETTING: &str = "chat.fsWrite.newFileLineEnding";
+
+const TRAILING_NEWLINE_NOTE: &str = "Left out the trailing newline, since the file didn't end with one";
+
+fn new_file_line_ending() -> &'static str {
+    match fig_settings::settings::get_string(NEW_FILE_LINE_ENDING_SETTING)
+        .ok()
+        .flatten()
+    {
+        Some(line_ending) if line_ending.eq_ignore_ascii_case("crlf") => "\r\n",
+        _ => "\n",
+    }
+}
+
+/// Converts every line ending in `content` to `line_ending`.
+fn convert_line_endings<'a>(content: &'a str, line_ending: &str) -> Cow<'a, str> {
+    let crlf = content.matches("\r\n").count();
+    match line_ending {
+        "\r\n" if crlf < content.matches('\n').count() => {
+            Cow::Owned(content.replace("\r\n", "\n").replace('\n', "\r\n"))
+        },
+        "\n" if crlf > 0 => Cow::Owned(content.replace("\r\n", "\n")),
+        _ => Cow::Borrowed(content),
+    }
+}
+
+/// Note for the tool result saying that `content` was converted to `line_ending`, if it was.
+/// `existing` is whether the line ending was taken from the file rather than the default for new
+/// files.
+fn conversion_note(content: &str, line_ending: &str, existing: bool) -> Option<String> {
+    if !matches!(convert_line_endings(content, line_ending), Cow::Owned(_)) {
+        return None;
+    }
+    let name = if line_ending == "\r\n" { "CRLF" } else { "LF" };
+    Some(if existing {
+        format!("Converted the line endings of the new content to {name} to match the file")
+    } else {
+        format!("Converted the line endings to {name}, which new files use")
+    })
+}
+
+/// Gives `new` a trailing line ending, unless it replaces a non-empty `old` file that doesn't have
+/// one, in which case any trailing line ending is removed instead. Returns false if one was
+/// removed.
+fn match_trailing_newline(old: &str, new: &mut String, line_ending: &str) -> bool {
+    if old.is_empty() || old.ends_with('\n') {
+        if !new.ends_with('\n') {
+            new.push_str(line_ending);
+        }
+        return true;
+    }
+    let trimmed = new
+        .strip_suffix('\n')
+        .map(|s| s.strip_suffix('\r').unwrap_or(s))
+        .map(str::len);
+    match trimmed {
+        Some(len) => {
+            new.truncate(len);
+            false
+        },
+        None => true,
+    }
+}
+
+fn join_notes(notes: Vec<String>) -> Option<String> {
+    (!notes.is_empty()).then(|| notes.join("\n"))
+}
+
+/// Returns the 0-indexed lines `[start, end)` of `content`, including their line endings.
+fn line_window(content: &str, start: usize, end: usize) -> &str {
+    let offset = |n: usize| LinesWithEndings::from(content).take(n).map(str::len).sum::<usize>();
+    let start = offset(start);
+    &content[start..offset(end).max(start)]
+}
+
+/// Returns the lines of `old` and `new` that differ, with [DIFF_CONTEXT_LINES] lines of context on
+/// either side, along with the 1-indexed line number they start at.
+fn changed_region<'a>(old: &'a str, new: &'a str) -> (&'a str, &'a str, usize) {
+    let old_lines = LinesWithEndings::from(old).collect::<Vec<_>>();
+    let new_lines = LinesWithEndings::from(new).collect::<Vec<_>>();
+    let prefix = old_lines.iter().zip(&new_lines).take_while(|(a, b)| a == b).count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let start = prefix.saturating_sub(DIFF_CONTEXT_LINES);
+    let old_end = (old_lines.len() - suffix + DIFF_CONTEXT_LINES).min(old_lines.len());
+    let new_end = (new_lines.len() - suffix + DIFF_CONTEXT_LINES).min(new_lines.len());
+    (
+        line_window(old, start, old_end),
+        line_window(new, start, new_end),
+        start + 1,
+    )
+}
+
+/// Tool result describing a write as a plain unified diff, so that the change can be reviewed from
+/// the conversation afterwards. Long diffs are cut after [DIFF_PREVIEW_MAX_LINES] lines.
+fn diff_output(path: &str, old: &str, new: &str) -> InvokeOutput {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let (added, removed) = diff::change_counts(&diff);
+    let unified = unified_diff(&diff, path);
+
+    let total_lines = unified.lines().count();
+    let mut text = unified
+        .lines()
+        .take(DIFF_PREVIEW_MAX_LINES)
+        .fold(String::new(), |mut acc, line| {
+            acc.push_str(line);
+            acc.push('\n');
+            acc
+        });
+    if total_lines > DIFF_PREVIEW_MAX_LINES {
+        text.push_str(&format!(
+            "[diff truncated: {} more lines not shown, +{added} -{removed} lines in total]\n",
+            total_lines - DIFF_PREVIEW_MAX_LINES
+        ));
+    }
+    if text.is_empty() {
+        text = format!("No changes were made to {path}\n");
+    }
+
+    InvokeOutput {
+        output: OutputKind::Text(text),
+    }
+}
+
+/// Tool result for a dry run. The whole diff is returned, in a form that the `patch` command can
+/// apply as is.
+fn dry_run_output(path: &str, old: &str, new: &str, summary: Option<String>) -> InvokeOutput {
+    let unified = unified_diff(&similar::TextDiff::from_lines(old, new), path);
+    let text = if unified.is_empty() {
+        format!("Dry run, no changes were written. {path} would not be changed\n")
+    } else {
+        let summary = summary.map(|summary| format!("{summary}\n")).unwrap_or_default();
+        format!(
+            "Dry run, no changes were written. {summary}Apply the diff below to {path} with the `patch` command to make these changes:\n{unified}"
+        )
+    };
+    InvokeOutput {
+        output: OutputKind::Text(text),
+    }
+}
+
+fn unified_diff<'a>(diff: &'a similar::TextDiff<'a, 'a, 'a, str>, path: &str) -> String {
+    diff.unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .header(&format!("a/{path}"), &format!("b/{path}"))
+        .to_string()
+}
+
+/// How the change is shown to the user. Summarized diffs collapse unchanged lines beyond
+/// [DIFF_CONTEXT_LINES] and stop after [DIFF_PREVIEW_MAX_LINES] lines.
+fn diff_options(ctx: &Context, summarize: bool) -> DiffOptions {
+    if summarize {
+        DiffOptions::new(ctx, Some(DIFF_CONTEXT_LINES), Some(DIFF_PREVIEW_MAX_LINES))
+    } else {
+        DiffOptions::new(ctx, None, None)
+    }
+}
+
+/// The lines of a file affected by a [FsWrite], before and after the write.
+#[derive(Debug)]
+struct DiffPreview {
+    /// Path to display, also used to pick the syntax highlighting.
+    path: String,
+    old: String,
+    new: String,
+    /// 1-indexed line number that `old` and `new` start at.
+    start_line: usize,
+}
+
+/// Returns the lines around the occurrence of `old_str` in `file` before and after it is replaced
+/// with `new_str`, along with the 1-indexed line number they start at. With `replace_all`, this
+/// covers every occurrence. The lines containing the occurrences are kept whole, with
+/// [DIFF_CONTEXT_LINES] lines of context on either side.
+///
+/// Returns `(old, new, start_line)`
+fn replacement_hunk(file: &str, old_str: &str, new_str: &str, replace_all: bool) -> Option<(String, String, usize)> {
+    if file.is_empty() || old_str.is_empty() {
+        return None;
+    }
+    let (old_str, new_str) = match_line_endings(file, old_str, new_str);
+    let starts = occurrences(file, &old_str);
+    let match_start = *starts.first()?;
+    let match_end = if replace_all { *starts.last()? } else { match_start } + old_str.len();
+
+    // Widen the match to whole lines.
+    let line_start = file[..match_start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = if match_end == file.len() || file[..match_end].ends_with('\n') && match_end > line_start {
+        match_end
+    } else {
+        file[match_end..].find('\n').map_or(file.len(), |i| match_end + i + 1)
+    };
+    let start_line = file[..line_start].matches('\n').count() + 1;
+    let end_line = start_line + file[line_start..line_end].lines().count().max(1) - 1;
+
+    let (prefix, new_start_line, suffix, _) = get_lines_with_context(file, start_line, end_line, DIFF_CONTEXT_LINES);
+    let matched = &file[match_start..match_end];
+    let replaced = if replace_all {
+        matched.replace(old_str.as_ref(), &new_str)
+    } else {
+        matched.replacen(old_str.as_ref(), &new_str, 1)
+    };
+    let old = [prefix, &file[line_start..line_end], suffix].join("");
+    let new = [
+        prefix,
+        &file[line_start..match_start],
+        &replaced,
+        &file[match_end..line_end],
+        suffix,
+    ]
+    .join("");
+    Some((old, new, new_start_line))
+}
+
+/// Replaces `old_str` in `file` with `new_str`, returning the new content and the number of
+/// occurrences replaced.
+///
+/// `old_str` must occur exactly once unless `replace_all` is set, in which case its occurrences
+/// must not overlap. The errors are worded for the model, so that it can fix its next attempt.
+fn replace_occurrences(file: &str, old_str: &str, new_str: &str, replace_all: bool) -> Result<(String, usize)> {
+    let (old_str, new_str) = match_line_endings(file, old_str, new_str);
+    let starts = occurrences(file, &old_str);
+    let line_numbers = || {
+        let mut lines = starts
+            .iter()
+            .map(|i| (file[..*i].matches('\n').count() + 1).to_string())
+            .collect::<Vec<_>>();
+        lines.dedup();
+        lines.join(", ")
+    };
+
+    match starts.len() {
+        0 => {
+            let mut message = format!(
+                "no occurrences of \"{old_str}\" were found. old_str must match the file exactly, including whitespace and indentation."
+            );
+            if let Some((line, text)) = closest_match(file, &old_str) {
+                message.push_str(&format!(
+                    " The closest match starts at line {line}, re-read the file if it may have changed:\n{text}"
+                ));
+            }
+            Err(eyre!(message))
+        },
+        1 => Ok((file.replacen(old_str.as_ref(), &new_str, 1), 1)),
+        n if !replace_all => Err(eyre!(
+            "old_str was found {n} times, on lines {}. Include more of the surrounding lines in old_str to make it unique, or set `replace_all` to true to replace every occurrence.",
+            line_numbers()
+        )),
+        n => {
+            if starts.windows(2).any(|w| w[1] < w[0] + old_str.len()) {
+                bail!(
+                    "old_str was found {n} times, on lines {}, and some of the occurrences overlap so `replace_all` can't be used. Include more of the surrounding lines in old_str to pick which one to replace.",
+                    line_numbers()
+                );
+            }
+            Ok((file.replace(old_str.as_ref(), &new_str), n))
+        },
+    }
+}
+
+/// Byte offsets of every occurrence of `needle` in `haystack`, including overlapping ones.
+fn occurrences(haystack: &str, needle: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    if needle.is_empty() {
+        return starts;
+    }
+    let mut from = 0;
+    while let Some(i) = haystack[from..].find(needle) {
+        let start = from + i;
+        starts.push(start);
+        from = start + haystack[start..].chars().next().map_or(1, char::len_utf8);
+    }
+    starts
+}
+
+/// Models write `\n` line endings regardless of the file, so when `file` uses `\r\n` and `old_str`
+/// doesn't, both strings are converted to `\r\n`. Otherwise `new_str` is given the line endings of
+/// `old_str`, or of the file if `old_str` is a single line, so that the rest of a file with mixed
+/// line endings is left as it is.
+fn match_line_endings<'a>(file: &str, old_str: &'a str, new_str: &'a str) -> (Cow<'a, str>, Cow<'a, str>) {
+    if file.contains("\r\n") && old_str.contains('\n') && !old_str.contains('\r') && !file.contains(old_str) {
+        (
+            convert_line_endings(old_str, "\r\n"),
+            convert_line_endings(new_str, "\r\n"),
+        )
+    } else {
+        let line_ending = if old_str.contains('\n') {
+            line_ending(old_str)
+        } else {
+            line_ending(file)
+        };
+        (Cow::Borrowed(old_str), convert_line_endings(new_str, line_ending))
+    }
+}
+
+/// Returns the 1-indexed line number and text of the lines in `file` most similar to `needle`, if
+/// any are similar enough to be worth suggesting.
+fn closest_match<'a>(file: &'a str, needle: &str) -> Option<(usize, &'a str)> {
+    const MIN_RATIO: f32 = 0.6;
+    const SEARCH_TIME: Duration = Duration::from_millis(200);
+
+    let needle = needle.trim();
+    let window = needle.lines().count().max(1);
+    let mut offsets = vec![0];
+    offsets.extend(LinesWithEndings::from(file).scan(0, |end, line| {
+        *end += line.len();
+        Some(*end)
+    }));
+    if offsets.len() <= window {
+        return None;
+    }
+
+    let deadline = Instant::now() + SEARCH_TIME;
+    let mut best: Option<(f32, usize)> = None;
+    for start in 0..offsets.len() - window {
+        if Instant::now() > deadline {
+            break;
+        }
+        let candidate = file[offsets[start]..offsets[start + window]].trim();
+        let ratio = similar::TextDiff::configure()
+            .deadline(deadline)
+            .diff_chars(candidate, needle)
+            .ratio();
+        if best.is_none_or(|(best_ratio, _)| ratio > best_ratio) {
+            best = Some((ratio, start));
+        }
+    }
+
+    best.filter(|(ratio, _)| *ratio >= MIN_RATIO)
+        .map(|(_, start)| (start + 1, file[offsets[start]..offsets[start + window]].trim_end()))
+}
+
 /// Returns a prefix/suffix pair before and after the content dictated by `[start_line, end_line]`
 /// within `content`. The updated start and end lines containing the original context along with
 /// the suffix and prefix are returned.
@@ -337,250 +1218,25 @@ fn get_lines_with_context(
     }
     let mut prefix_end = prefix_start;
     for line in LinesWithEndings::from(&content[prefix_start..]).take(start_line - new_start_line) {
-        prefix_end += line.len();
-    }
-
-    // Build suffix
-    let mut suffix_start = 0;
-    for line in LinesWithEndings::from(content).take(end_line + zero_check_inc) {
-        suffix_start += line.len();
-    }
-    let mut suffix_end = suffix_start;
-    for line in LinesWithEndings::from(&content[suffix_start..]).take(new_end_line - end_line) {
-        suffix_end += line.len();
-    }
-
-    (
-        &content[prefix_start..prefix_end],
-        new_start_line + 1,
-        &content[suffix_start..suffix_end],
-        new_end_line + zero_check_inc,
-    )
-}
-
-/// Prints a git-diff style comparison between `old_str` and `new_str`.
-/// - `start_line` - 1-indexed line number that `old_str` and `new_str` start at.
-fn print_diff(
-    updates: &mut impl Write,
-    old_str: &StylizedFile,
-    new_str: &StylizedFile,
-    start_line: usize,
-) -> Result<()> {
-    let diff = similar::TextDiff::from_lines(&old_str.content, &new_str.content);
-
-    // First, get the gutter width required for both the old and new lines.
-    let (mut max_old_i, mut max_new_i) = (1, 1);
-    for change in diff.iter_all_changes() {
-        if let Some(i) = change.old_index() {
-            max_old_i = i + start_line;
-        }
-        if let Some(i) = change.new_index() {
-            max_new_i = i + start_line;
-        }
-    }
-    let old_line_num_width = terminal_width_required_for_line_count(max_old_i);
-    let new_line_num_width = terminal_width_required_for_line_count(max_new_i);
-
-    // Now, print
-    fn fmt_index(i: Option<usize>, start_line: usize) -> String {
-        match i {
-            Some(i) => (i + start_line).to_string(),
-            _ => " ".to_string(),
-        }
-    }
-    for change in diff.iter_all_changes() {
-        // Define the colors per line.
-        let (text_color, gutter_bg_color, line_bg_color) = match (change.tag(), new_str.truecolor) {
-            (similar::ChangeTag::Equal, true) => (style::Color::Reset, new_str.gutter_bg, new_str.line_bg),
-            (similar::ChangeTag::Delete, true) => (
-                style::Color::Reset,
-                style::Color::Rgb { r: 79, g: 40, b: 40 },
-                style::Color::Rgb { r: 36, g: 25, b: 28 },
-            ),
-            (similar::ChangeTag::Insert, true) => (
-                style::Color::Reset,
-                style::Color::Rgb { r: 40, g: 67, b: 43 },
-                style::Color::Rgb { r: 24, g: 38, b: 30 },
-            ),
-            (similar::ChangeTag::Equal, false) => (style::Color::Reset, new_str.gutter_bg, new_str.line_bg),
-            (similar::ChangeTag::Delete, false) => (style::Color::Red, new_str.gutter_bg, new_str.line_bg),
-            (similar::ChangeTag::Insert, false) => (style::Color::Green, new_str.gutter_bg, new_str.line_bg),
-        };
-        // Define the change tag character to print, if any.
-        let sign = match change.tag() {
-            similar::ChangeTag::Equal => " ",
-            similar::ChangeTag::Delete => "-",
-            similar::ChangeTag::Insert => "+",
-        };
-
-        let old_i_str = fmt_index(change.old_index(), start_line);
-        let new_i_str = fmt_index(change.new_index(), start_line);
-
-        // Print the gutter and line numbers.
-        queue!(updates, style::SetBackgroundColor(gutter_bg_color))?;
-        queue!(
-            updates,
-            style::SetForegroundColor(text_color),
-            style::Print(sign),
-            style::Print(" ")
-        )?;
-        queue!(
-            updates,
-            style::Print(format!(
-                "{:>old_line_num_width$}",
-                old_i_str,
-                old_line_num_width = old_line_num_width
-            ))
-        )?;
-        if sign == " " {
-            queue!(updates, style::Print(", "))?;
-        } else {
-            queue!(updates, style::Print("  "))?;
-        }
-        queue!(
-            updates,
-            style::Print(format!(
-                "{:>new_line_num_width$}",
-                new_i_str,
-                new_line_num_width = new_line_num_width
-            ))
-        )?;
-        // Print the line.
-        queue!(
-            updates,
-            style::SetForegroundColor(style::Color::Reset),
-            style::Print(":"),
-            style::SetForegroundColor(text_color),
-            style::SetBackgroundColor(line_bg_color),
-            style::Print(" "),
-            style::Print(change),
-            style::ResetColor,
-        )?;
-    }
-    queue!(
-        updates,
-        crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine),
-        style::Print("\n"),
-    )?;
-
-    Ok(())
-}
-
-/// Returns a 1-indexed line number range of the start and end of `needle` inside `file`.
-fn line_number_at(file: impl AsRef<str>, needle: impl AsRef<str>) -> Option<(usize, usize)> {
-    let file = file.as_ref();
-    let needle = needle.as_ref();
-    if let Some((i, _)) = file.match_indices(needle).next() {
-        let start = file[..i].matches("\n").count();
-        let end = needle.matches("\n").count();
-        Some((start + 1, start + end + 1))
-    } else {
-        None
-    }
-}
-
-/// Returns the number of terminal cells required for displaying line numbers. This is used to
-/// determine how many characters the gutter should allocate when displaying line numbers for a
-/// text file.
-///
-/// For example, `10` and `99` both take 2 cells, whereas `100` and `999` take 3.
-fn terminal_width_required_for_line_count(line_count: usize) -> usize {
-    line_count.to_string().chars().count()
-}
-
-fn stylize_output_if_able(ctx: &Context, path: impl AsRef<Path>, file_text: &str) -> StylizedFile {
-    if supports_truecolor(ctx) {
-        match stylized_file(path, file_text) {
-            Ok(s) => return s,
-            Err(err) => {
-                error!(?err, "unable to syntax highlight the output");
-            },
-        }
-    }
-    StylizedFile {
-        truecolor: false,
-        content: file_text.to_string(),
-        gutter_bg: style::Color::Reset,
-        line_bg: style::Color::Reset,
+        prefix_end += line.len();
     }
-}
-
-/// Represents a [String] that is potentially stylized with truecolor escape codes.
-#[derive(Debug)]
-struct StylizedFile {
-    /// Whether or not the file is stylized with 24bit color.
-    truecolor: bool,
-    /// File content. If [Self::truecolor] is true, then it has escape codes for styling with 24bit
-    /// color.
-    content: String,
-    /// Background color for the gutter.
-    gutter_bg: style::Color,
-    /// Background color for the line content.
-    line_bg: style::Color,
-}
 
-impl Default for StylizedFile {
-    fn default() -> Self {
-        Self {
-            truecolor: false,
-            content: Default::default(),
-            gutter_bg: style::Color::Reset,
-            line_bg: style::Color::Reset,
-        }
+    // Build suffix
+    let mut suffix_start = 0;
+    for line in LinesWithEndings::from(content).take(end_line + zero_check_inc) {
+        suffix_start += line.len();
     }
-}
-
-/// Returns a 24bit terminal escaped syntax-highlighted [String] of the file pointed to by `path`,
-/// if able.
-fn stylized_file(path: impl AsRef<Path>, file_text: impl AsRef<str>) -> Result<StylizedFile> {
-    let ps = &*SYNTAX_SET;
-    let ts = &*THEME_SET;
-
-    let extension = path
-        .as_ref()
-        .extension()
-        .wrap_err("missing extension")?
-        .to_str()
-        .wrap_err("not utf8")?;
-
-    let syntax = ps
-        .find_syntax_by_extension(extension)
-        .wrap_err_with(|| format!("missing extension: {}", extension))?;
-
-    let theme = &ts.themes["base16-ocean.dark"];
-    let mut highlighter = HighlightLines::new(syntax, theme);
-    let file_text = file_text.as_ref().lines();
-    let mut file = String::new();
-    for line in file_text {
-        let mut ranges = Vec::new();
-        ranges.append(&mut highlighter.highlight_line(line, ps)?);
-        let mut escaped_line = as_24_bit_terminal_escaped(&ranges[..], false);
-        escaped_line.push_str(&format!(
-            "{}\n",
-            crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine),
-        ));
-        file.push_str(&escaped_line);
+    let mut suffix_end = suffix_start;
+    for line in LinesWithEndings::from(&content[suffix_start..]).take(new_end_line - end_line) {
+        suffix_end += line.len();
     }
 
-    let (line_bg, gutter_bg) = match (theme.settings.background, theme.settings.gutter) {
-        (Some(line_bg), Some(gutter_bg)) => (line_bg, gutter_bg),
-        (Some(line_bg), None) => (line_bg, line_bg),
-        _ => bail!("missing theme"),
-    };
-    Ok(StylizedFile {
-        truecolor: true,
-        content: file,
-        gutter_bg: syntect_to_crossterm_color(gutter_bg),
-        line_bg: syntect_to_crossterm_color(line_bg),
-    })
-}
-
-fn syntect_to_crossterm_color(syntect: syntect::highlighting::Color) -> style::Color {
-    style::Color::Rgb {
-        r: syntect.r,
-        g: syntect.g,
-        b: syntect.b,
-    }
+    (
+        &content[prefix_start..prefix_end],
+        new_start_line + 1,
+        &content[suffix_start..suffix_end],
+        new_end_line + zero_check_inc,
+    )
 }
 
 #[cfg(test)]
@@ -721,6 +1377,110 @@ mod tests {
         );
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_fs_write_outside_workspace() {
+        let ctx = setup_test_directory().await;
+        let write = |path: &str| {
+            crate::tools::Tool::FsWrite(
+                serde_json::from_value::<FsWrite>(serde_json::json!({
+                    "path": path,
+                    "command": "append",
+                    "new_str": "export PATH=/tmp:$PATH",
+                }))
+                .unwrap(),
+            )
+        };
+        let description = |tool: &crate::tools::Tool| {
+            let crate::tools::Tool::FsWrite(fw) = tool else {
+                unreachable!()
+            };
+            let mut out = Vec::new();
+            fw.queue_description(&ctx, &mut out).unwrap();
+            String::from_utf8(strip_ansi_escapes::strip(out)).unwrap()
+        };
+
+        let inside = write(TEST_FILE_PATH);
+        assert!(!inside.requires_explicit_acceptance(&ctx));
+        assert!(!description(&inside).contains("OUTSIDE"));
+
+        // Writing through a symlink that leads outside of the workspace.
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join(".bashrc"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path().join(".bashrc"), ctx.fs().chroot_path("/bashrc")).unwrap();
+        let escaping = write("/bashrc");
+        assert!(escaping.requires_explicit_acceptance(&ctx));
+        let crate::tools::Tool::FsWrite(mut dry_run) = escaping.clone() else {
+            unreachable!()
+        };
+        dry_run.set_dry_run();
+        assert!(crate::tools::Tool::FsWrite(dry_run).requires_explicit_acceptance(&ctx));
+        let target = outside.path().canonicalize().unwrap().join(".bashrc");
+        assert!(
+            description(&escaping).starts_with(&format!(
+                "⚠ This writes OUTSIDE of your workspace: {}\n",
+                target.display()
+            )),
+            "{}",
+            description(&escaping)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_create_dirs() {
+        let ctx = setup_test_directory().await;
+        let write = |v: serde_json::Value| serde_json::from_value::<FsWrite>(v).unwrap();
+
+        let mut fw = write(serde_json::json!({
+            "path": "/aaaa1/new/nested/file.txt",
+            "command": "create",
+            "file_text": "hello",
+        }));
+        assert_eq!(fw.dirs_to_create(&ctx), vec![
+            ctx.fs().chroot_path("/aaaa1/new"),
+            ctx.fs().chroot_path("/aaaa1/new/nested")
+        ]);
+        assert!(!fw.creates_dirs_outside_workspace(&ctx));
+        fw.validate(&ctx).await.unwrap();
+        let mut out = Vec::new();
+        fw.queue_description(&ctx, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("Creates directories: "));
+
+        let output = fw.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert!(text.starts_with("Created directories: "), "{text}");
+        assert!(text.contains("/aaaa1/new/nested\n"), "{text}");
+        assert_eq!(
+            ctx.fs().read_to_string("/aaaa1/new/nested/file.txt").await.unwrap(),
+            "hello\n"
+        );
+
+        // Opting out of creating directories.
+        let mut fw = write(serde_json::json!({
+            "path": "/missing/file.txt",
+            "command": "create",
+            "file_text": "hello",
+            "create_dirs": false,
+        }));
+        assert!(fw.validate(&ctx).await.is_err());
+
+        // Creating directories outside of the workspace needs to be confirmed.
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), ctx.fs().chroot_path("/outside")).unwrap();
+        let fw = write(serde_json::json!({
+            "path": "/outside/new/file.txt",
+            "command": "create",
+            "file_text": "hello",
+        }));
+        assert!(fw.creates_dirs_outside_workspace(&ctx));
+        assert!(crate::tools::Tool::FsWrite(fw.clone()).requires_explicit_acceptance(&ctx));
+        let mut out = Vec::new();
+        fw.queue_description(&ctx, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("OUTSIDE of your workspace"));
+    }
+
     #[tokio::test]
     async fn test_fs_write_tool_str_replace() {
         let ctx = setup_test_directory().await;
@@ -867,8 +1627,9 @@ mod tests {
             .invoke(&ctx, &mut stdout)
             .await
             .unwrap();
+        // The file is still missing a trailing newline.
         let actual = ctx.fs().read_to_string(test_file_path).await.unwrap();
-        assert_eq!(actual, format!("{}{}\n", test_file_contents, new_str));
+        assert_eq!(actual, format!("{}\n{}", test_file_contents, new_str));
 
         // Then, test prepending
         let v = serde_json::json!({
@@ -883,7 +1644,7 @@ mod tests {
             .await
             .unwrap();
         let actual = ctx.fs().read_to_string(test_file_path).await.unwrap();
-        assert_eq!(actual, format!("{}{}{}\n", new_str, test_file_contents, new_str));
+        assert_eq!(actual, format!("{}\n{}\n{}", new_str, test_file_contents, new_str));
     }
 
     #[tokio::test]
@@ -929,6 +1690,379 @@ mod tests {
         assert!(result.is_err(), "Appending to non-existent file should fail");
     }
 
+    #[test]
+    fn test_insert_lines() {
+        let file = "a\nb\nc\n";
+        assert_eq!(
+            insert_lines(file, Some(0), "x").unwrap(),
+            ("x\na\nb\nc\n".to_string(), 1, 1)
+        );
+        assert_eq!(
+            insert_lines(file, Some(2), "x\ny\n").unwrap(),
+            ("a\nb\nx\ny\nc\n".to_string(), 3, 4)
+        );
+        // Omitting the line appends.
+        assert_eq!(
+            insert_lines(file, None, "x").unwrap(),
+            ("a\nb\nc\nx\n".to_string(), 4, 4)
+        );
+        assert_eq!(
+            insert_lines(file, Some(3), "x").unwrap(),
+            ("a\nb\nc\nx\n".to_string(), 4, 4)
+        );
+        let err = insert_lines(file, Some(4), "x").unwrap_err();
+        assert!(
+            err.to_string().contains("past the end of the file, which has 3 lines"),
+            "{err}"
+        );
+
+        // Line endings follow the file.
+        assert_eq!(
+            insert_lines("a\r\nb\r\n", Some(1), "x\ny").unwrap(),
+            ("a\r\nx\r\ny\r\nb\r\n".to_string(), 2, 3)
+        );
+        assert_eq!(insert_lines("", None, "x").unwrap(), ("x\n".to_string(), 1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_insert_result_and_preview() {
+        let ctx = setup_test_directory().await;
+        let v = serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "insert",
+            "line": 2,
+            "content": "new line\nanother line",
+        });
+        let mut fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        fw.validate(&ctx).await.unwrap();
+        let mut out = Vec::new();
+        fw.queue_description(&ctx, &mut out).unwrap();
+        let out = String::from_utf8(strip_ansi_escapes::strip(out)).unwrap();
+        assert!(out.contains("+    3: new line"), "{out}");
+        assert!(out.contains("  3, 5: 3: asdf"), "{out}");
+
+        let output = fw.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert!(text.starts_with("Inserted lines 3-4\n"), "{text}");
+
+        let v = serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "insert",
+            "insert_line": 100,
+            "new_str": "x",
+        });
+        assert!(
+            serde_json::from_value::<FsWrite>(v)
+                .unwrap()
+                .validate(&ctx)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_replace_file_interrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        std::fs::write(&path, "original\n").unwrap();
+
+        let result = replace_file(&path, |file| {
+            file.write_all(b"half of the")?;
+            Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"))
+        });
+        assert!(matches!(result, Err(ReplaceError::Failed(_))), "{result:?}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original\n");
+        // The temporary file is cleaned up.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_file_keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ctx = setup_test_directory().await;
+        let path = ctx.fs().chroot_path("/script.sh");
+        std::fs::write(&path, "echo 1\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o751)).unwrap();
+        ctx.fs().symlink("/script.sh", "/link.sh").await.unwrap();
+
+        write_file(&ctx, "/link.sh", b"echo 2\n").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "echo 2\n");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o751);
+        assert!(
+            ctx.fs()
+                .symlink_metadata("/link.sh")
+                .await
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_fs_write_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ctx = setup_test_directory().await;
+        let path = ctx.fs().chroot_path(TEST_FILE_PATH);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+
+        let mut fw = serde_json::from_value::<FsWrite>(serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "append",
+            "new_str": "more",
+        }))
+        .unwrap();
+        let err = fw.validate(&ctx).await.unwrap_err().to_string();
+        assert!(err.contains("is read-only (mode 444)"), "{err}");
+        assert!(err.contains("chmod u+w"), "{err}");
+        assert!(write_file(&ctx, TEST_FILE_PATH, b"new\n").await.is_err());
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            TEST_FILE_CONTENTS
+        );
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        fw.validate(&ctx).await.unwrap();
+    }
+
+    #[test]
+    fn test_changed_region() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n10\n";
+        assert_eq!(
+            changed_region(old, new),
+            ("3\n4\n5\n6\n7\n8\n9\n", "3\n4\n5\nsix\n7\n8\n9\n", 3)
+        );
+        assert_eq!(changed_region("a\n", "a\nb\n"), ("a\n", "a\nb\n", 1));
+        assert_eq!(changed_region("", "a\n"), ("", "a\n", 1));
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_line_endings() {
+        let ctx = setup_test_directory().await;
+        let run = |args: serde_json::Value| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                let output = serde_json::from_value::<FsWrite>(args)
+                    .unwrap()
+                    .invoke(&ctx, &mut std::io::sink())
+                    .await
+                    .unwrap();
+                let OutputKind::Text(text) = output.output else {
+                    panic!("expected text output");
+                };
+                text
+            }
+        };
+
+        // Replacing a CRLF file without a trailing newline.
+        ctx.fs().write("/crlf.txt", "a\r\nb").await.unwrap();
+        let text = run(serde_json::json!({ "command": "create", "path": "/crlf.txt", "file_text": "a\nb\nc\n" })).await;
+        assert_eq!(ctx.fs().read_to_string("/crlf.txt").await.unwrap(), "a\r\nb\r\nc");
+        assert!(text.contains("to CRLF to match the file"), "{text}");
+        assert!(text.contains(TRAILING_NEWLINE_NOTE), "{text}");
+
+        run(serde_json::json!({ "command": "append", "path": "/crlf.txt", "new_str": "d\ne" })).await;
+        assert_eq!(
+            ctx.fs().read_to_string("/crlf.txt").await.unwrap(),
+            "a\r\nb\r\nc\r\nd\r\ne"
+        );
+
+        // Only the replaced lines of a file with mixed line endings are touched.
+        ctx.fs().write("/mixed.txt", "a\r\nb\nc\r\n").await.unwrap();
+        let text = run(serde_json::json!({
+            "command": "str_replace",
+            "path": "/mixed.txt",
+            "old_str": "b",
+            "new_str": "b1\nb2",
+        }))
+        .await;
+        assert_eq!(
+            ctx.fs().read_to_string("/mixed.txt").await.unwrap(),
+            "a\r\nb1\r\nb2\nc\r\n"
+        );
+        assert!(text.contains("to CRLF to match the file"), "{text}");
+
+        // New files use LF by default.
+        let text = run(serde_json::json!({ "command": "create", "path": "/new.txt", "file_text": "a\r\nb" })).await;
+        assert_eq!(ctx.fs().read_to_string("/new.txt").await.unwrap(), "a\nb\n");
+        assert!(
+            text.contains("Converted the line endings to LF, which new files use"),
+            "{text}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_encodings() {
+        let ctx = setup_test_directory().await;
+        let replace = |path: &str, old_str: &str, new_str: &str| {
+            serde_json::from_value::<FsWrite>(serde_json::json!({
+                "command": "str_replace",
+                "path": path,
+                "old_str": old_str,
+                "new_str": new_str,
+            }))
+            .unwrap()
+        };
+
+        // Edits to a Latin-1 file are written back in Latin-1.
+        ctx.fs().write("/latin1.txt", b"caf\xe9\nna\xefve\n").await.unwrap();
+        let output = replace("/latin1.txt", "café", "crème brûlée")
+            .invoke(&ctx, &mut std::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(
+            ctx.fs().read("/latin1.txt").await.unwrap(),
+            b"cr\xe8me br\xfbl\xe9e\nna\xefve\n"
+        );
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert!(
+            text.contains("Kept the file's windows-1252 (Latin-1) encoding"),
+            "{text}"
+        );
+
+        // Characters Latin-1 can't represent are refused without changing the file.
+        let err = replace("/latin1.txt", "naïve", "naïve → 日本")
+            .invoke(&ctx, &mut std::io::sink())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("can't represent the character '→' on line 2"), "{err}");
+        assert_eq!(
+            ctx.fs().read("/latin1.txt").await.unwrap(),
+            b"cr\xe8me br\xfbl\xe9e\nna\xefve\n"
+        );
+
+        // The file can be rewritten in another encoding when asked to.
+        serde_json::from_value::<FsWrite>(serde_json::json!({
+            "command": "create",
+            "path": "/latin1.txt",
+            "file_text": "naïve → 日本\n",
+            "encoding": "utf-8",
+        }))
+        .unwrap()
+        .invoke(&ctx, &mut std::io::sink())
+        .await
+        .unwrap();
+        assert_eq!(ctx.fs().read_to_string("/latin1.txt").await.unwrap(), "naïve → 日本\n");
+
+        // Binary files can't be edited as text.
+        ctx.fs().write("/data.bin", b"\0\x01\x02one").await.unwrap();
+        let mut fw = replace("/data.bin", "one", "two");
+        assert!(fw.validate(&ctx).await.is_ok());
+        let err = fw.invoke(&ctx, &mut std::io::sink()).await.unwrap_err().to_string();
+        assert!(err.contains("looks like a binary file"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_dry_run() {
+        let ctx = setup_test_directory().await;
+        let fw = serde_json::from_value::<FsWrite>(serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "str_replace",
+            "old_str": "Hello world!",
+            "new_str": "Goodbye world!",
+            "replace_all": true,
+            "dry_run": true,
+        }))
+        .unwrap();
+        assert!(!crate::tools::Tool::FsWrite(fw.clone()).requires_acceptance(&ctx));
+
+        let output = fw.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert!(text.starts_with("Dry run, no changes were written."), "{text}");
+        assert!(text.contains("Replaced 2 occurrences of old_str\n"), "{text}");
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            TEST_FILE_CONTENTS
+        );
+
+        // The diff can be applied as is.
+        let diff = &text[text.find("--- ").unwrap()..];
+        let mut patch = serde_json::from_value::<FsWrite>(serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "patch",
+            "diff": diff,
+        }))
+        .unwrap();
+        patch.validate(&ctx).await.unwrap();
+        patch.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            TEST_FILE_CONTENTS.replace("Hello", "Goodbye")
+        );
+
+        // Dry runs don't create anything either.
+        let mut fw = serde_json::from_value::<FsWrite>(serde_json::json!({
+            "path": "/new/file.txt",
+            "command": "create",
+            "file_text": "hello",
+        }))
+        .unwrap();
+        fw.set_dry_run();
+        fw.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        assert!(!ctx.fs().exists("/new"));
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_patch() {
+        let ctx = setup_test_directory().await;
+        let diff = "\
+--- a/test_file.txt
++++ b/test_file.txt
+@@ -2,2 +2,2 @@
+ 2: This is line 2
+-3: asdf
++3: qwerty
+";
+        let mut fw = serde_json::from_value::<FsWrite>(serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "patch",
+            "diff": diff,
+        }))
+        .unwrap();
+        fw.validate(&ctx).await.unwrap();
+        let mut out = Vec::new();
+        fw.queue_description(&ctx, &mut out).unwrap();
+        let out = String::from_utf8(strip_ansi_escapes::strip(out)).unwrap();
+        assert!(out.contains("- 3   : 3: asdf"), "{out}");
+        assert!(out.contains("+    3: 3: qwerty"), "{out}");
+        // The file isn't changed until the patch is invoked.
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            TEST_FILE_CONTENTS
+        );
+
+        let output = fw.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert!(text.starts_with("Applied 1 hunk\n"), "{text}");
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            TEST_FILE_CONTENTS.replace("asdf", "qwerty")
+        );
+
+        // Patches that don't apply are rejected before being approved.
+        let mut fw = serde_json::from_value::<FsWrite>(serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "patch",
+            "diff": diff,
+        }))
+        .unwrap();
+        let err = fw.validate(&ctx).await.unwrap_err();
+        assert!(err.to_string().starts_with("hunk 1 failed at line 2"), "{err}");
+    }
+
     #[test]
     fn test_lines_with_context() {
         let content = "Hello\nWorld!\nhow\nare\nyou\ntoday?";
@@ -942,12 +2076,176 @@ mod tests {
     }
 
     #[test]
-    fn test_gutter_width() {
-        assert_eq!(terminal_width_required_for_line_count(1), 1);
-        assert_eq!(terminal_width_required_for_line_count(9), 1);
-        assert_eq!(terminal_width_required_for_line_count(10), 2);
-        assert_eq!(terminal_width_required_for_line_count(99), 2);
-        assert_eq!(terminal_width_required_for_line_count(100), 3);
-        assert_eq!(terminal_width_required_for_line_count(999), 3);
+    fn test_replacement_hunk() {
+        let file = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        // Whole lines are shown, with context on either side.
+        assert_eq!(
+            replacement_hunk(file, "e", "E", false),
+            Some((
+                "b\nc\nd\ne\nf\ng\nh\n".to_string(),
+                "b\nc\nd\nE\nf\ng\nh\n".to_string(),
+                2
+            ))
+        );
+        // Context is clamped to the start and end of the file.
+        assert_eq!(
+            replacement_hunk(file, "a\nb\n", "z\n", false),
+            Some(("a\nb\nc\nd\ne\n".to_string(), "z\nc\nd\ne\n".to_string(), 1))
+        );
+        assert_eq!(
+            replacement_hunk("one two\nthree", "two\nthr", "2\n3", false),
+            Some(("one two\nthree".to_string(), "one 2\n3ee".to_string(), 1))
+        );
+        assert_eq!(replacement_hunk(file, "x", "y", false), None);
+
+        // Every occurrence is covered with `replace_all`.
+        let file = "x\na\na\na\na\na\na\nx\n";
+        assert_eq!(
+            replacement_hunk(file, "x", "y", true),
+            Some((file.to_string(), file.replace('x', "y"), 1))
+        );
+    }
+
+    #[test]
+    fn test_replace_occurrences() {
+        let file = "fn a() {}\nfn b() {}\nfn a() {}\n";
+        let err = replace_occurrences(file, "fn a() {}", "fn c() {}", false).unwrap_err();
+        assert!(err.to_string().contains("found 2 times, on lines 1, 3"), "{err}");
+        assert_eq!(
+            replace_occurrences(file, "fn a() {}", "fn c() {}", true).unwrap(),
+            ("fn c() {}\nfn b() {}\nfn c() {}\n".to_string(), 2)
+        );
+        assert_eq!(
+            replace_occurrences(file, "fn b", "fn d", false).unwrap(),
+            ("fn a() {}\nfn d() {}\nfn a() {}\n".to_string(), 1)
+        );
+
+        // Not found, with a hint pointing at the closest lines.
+        let err = replace_occurrences(file, "fn  b() { }", "", false).unwrap_err();
+        assert!(err.to_string().contains("no occurrences"), "{err}");
+        assert!(err.to_string().contains("closest match starts at line 2"), "{err}");
+        let err = replace_occurrences(file, "struct Unrelated;", "", false).unwrap_err();
+        assert!(!err.to_string().contains("closest match"), "{err}");
+    }
+
+    #[test]
+    fn test_replace_occurrences_overlapping() {
+        // "aa" occurs at both offset 0 and 1.
+        let err = replace_occurrences("aaa\n", "aa", "b", false).unwrap_err();
+        assert!(err.to_string().contains("found 2 times, on lines 1."), "{err}");
+        let err = replace_occurrences("aaa\n", "aa", "b", true).unwrap_err();
+        assert!(err.to_string().contains("overlap"), "{err}");
+        assert_eq!(occurrences("abababa", "aba"), vec![0, 2, 4]);
+        assert_eq!(occurrences("ééé", "éé"), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_replace_occurrences_crlf() {
+        let file = "one\r\ntwo\r\nthree\r\n";
+        // The model's `\n` line endings are matched against the file's `\r\n`, and the replacement
+        // keeps them.
+        assert_eq!(
+            replace_occurrences(file, "one\ntwo", "1\n2", false).unwrap(),
+            ("1\r\n2\r\nthree\r\n".to_string(), 1)
+        );
+        assert_eq!(
+            replace_occurrences(file, "two\r\n", "2\r\n", false).unwrap(),
+            ("one\r\n2\r\nthree\r\n".to_string(), 1)
+        );
+        let err = replace_occurrences("a\r\nb\r\na\r\nb", "a\nb", "c", false).unwrap_err();
+        assert!(err.to_string().contains("on lines 1, 3"), "{err}");
+    }
+
+    #[test]
+    fn test_replace_occurrences_file_boundaries() {
+        // At the very start and end of a file without a trailing newline.
+        assert_eq!(
+            replace_occurrences("start\nmiddle\nend", "start", "START", false).unwrap(),
+            ("START\nmiddle\nend".to_string(), 1)
+        );
+        assert_eq!(
+            replace_occurrences("start\nmiddle\nend", "\nend", "", false).unwrap(),
+            ("start\nmiddle".to_string(), 1)
+        );
+        assert_eq!(
+            replace_occurrences("whole", "whole", "file", false).unwrap(),
+            ("file".to_string(), 1)
+        );
+        assert_eq!(
+            replacement_hunk("start\nmiddle\nend", "end", "END", false),
+            Some(("start\nmiddle\nend".to_string(), "start\nmiddle\nEND".to_string(), 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_diff_preview() {
+        let ctx = setup_test_directory().await;
+        let v = serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "str_replace",
+            "old_str": "3: asdf",
+            "new_str": "3: qwerty",
+        });
+        let fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        let mut out = Vec::new();
+        fw.queue_description(&ctx, &mut out).unwrap();
+        let out = String::from_utf8(strip_ansi_escapes::strip(out)).unwrap();
+        assert!(out.contains("- 3   : 3: asdf"), "{out}");
+        assert!(out.contains("+    3: 3: qwerty"), "{out}");
+        assert!(
+            out.contains("  1, 1: 1: Hello world!"),
+            "context should be shown: {out}"
+        );
+        assert!(!fw.diff_is_summarized(&ctx));
+
+        // Large diffs are summarized.
+        let file_text = (0..500).map(|i| format!("line {i}\n")).collect::<String>();
+        let v = serde_json::json!({
+            "path": "/big.txt",
+            "command": "create",
+            "file_text": file_text,
+        });
+        let fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        let mut out = Vec::new();
+        fw.queue_description(&ctx, &mut out).unwrap();
+        let out = String::from_utf8(strip_ansi_escapes::strip(out)).unwrap();
+        assert!(out.contains("line 199"), "{out}");
+        assert!(!out.contains("line 200"), "{out}");
+        assert!(
+            out.contains("… 300 more lines not shown (+500 -0 lines in total)"),
+            "{out}"
+        );
+        assert!(fw.diff_is_summarized(&ctx));
+
+        let mut out = Vec::new();
+        fw.queue_full_diff(&ctx, &mut out).unwrap();
+        let out = String::from_utf8(strip_ansi_escapes::strip(out)).unwrap();
+        assert!(out.contains("line 499"), "{out}");
+        assert!(!out.contains("more lines not shown"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_result_is_diff() {
+        let ctx = setup_test_directory().await;
+        let v = serde_json::json!({
+            "path": TEST_FILE_PATH,
+            "command": "str_replace",
+            "old_str": "2: This is line 2",
+            "new_str": "2: This is the second line",
+        });
+        let output = serde_json::from_value::<FsWrite>(v)
+            .unwrap()
+            .invoke(&ctx, &mut std::io::sink())
+            .await
+            .unwrap();
+        let OutputKind::Text(text) = output.output else {
+            panic!("expected text output");
+        };
+        assert!(
+            text.contains("-2: This is line 2\n+2: This is the second line\n"),
+            "{text}"
+        );
+        assert!(text.contains("@@ -1,4 +1,4 @@"), "{text}");
+        assert!(text.contains("+++ b/"), "{text}");
     }
 }
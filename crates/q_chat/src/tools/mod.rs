@@ -1,7 +1,12 @@
+pub mod clipboard;
+mod diff;
+mod encoding;
 pub mod execute_bash;
 pub mod fs_read;
 pub mod fs_write;
 pub mod gh_issue;
+mod patch;
+pub mod sandbox;
 pub mod use_aws;
 
 use std::collections::HashMap;
@@ -10,19 +15,30 @@ use std::path::{
     Path,
     PathBuf,
 };
+use std::time::{
+    Duration,
+    Instant,
+};
 
 use aws_smithy_types::{
     Document,
     Number as SmithyNumber,
 };
+use clipboard::Clipboard;
 use crossterm::style::Stylize;
 use execute_bash::ExecuteBash;
 use eyre::Result;
 use fig_api_client::model::ToolResultStatus;
 use fig_os_shim::Context;
+use fig_settings::Settings;
 use fs_read::FsRead;
 use fs_write::FsWrite;
+use futures::stream::{
+    self,
+    StreamExt,
+};
 use gh_issue::GhIssue;
+use sandbox::Sandbox;
 use serde::Deserialize;
 use use_aws::UseAws;
 
@@ -41,6 +57,7 @@ pub enum Tool {
     ExecuteBash(ExecuteBash),
     UseAws(UseAws),
     GhIssue(GhIssue),
+    Clipboard(Clipboard),
 }
 
 impl Tool {
@@ -52,6 +69,50 @@ impl Tool {
             Tool::ExecuteBash(_) => "execute_bash",
             Tool::UseAws(_) => "use_aws",
             Tool::GhIssue(_) => "gh_issue",
+            Tool::Clipboard(_) => "clipboard",
+        }
+    }
+
+    /// The arguments that best identify what this tool use did, used when framing its result.
+    pub fn key_args(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Tool::FsRead(fs_read @ FsRead::Line(_)) => {
+                vec![("mode", "Line".to_string()), ("path", fs_read.paths().join(", "))]
+            },
+            Tool::FsRead(FsRead::Directory(fs_directory)) => {
+                vec![("mode", "Directory".to_string()), ("path", fs_directory.path.clone())]
+            },
+            Tool::FsRead(FsRead::Search(fs_search)) => vec![
+                ("mode", "Search".to_string()),
+                ("path", fs_search.path.clone()),
+                ("pattern", fs_search.pattern.clone()),
+            ],
+            Tool::FsWrite(fs_write) => {
+                let (command, path) = match fs_write {
+                    FsWrite::Create { path, .. } => ("create", path),
+                    FsWrite::StrReplace { path, .. } => ("str_replace", path),
+                    FsWrite::Insert { path, .. } => ("insert", path),
+                    FsWrite::Append { path, .. } => ("append", path),
+                    FsWrite::Patch { path, .. } => ("patch", path),
+                };
+                vec![("command", command.to_string()), ("path", path.clone())]
+            },
+            Tool::ExecuteBash(execute_bash) => {
+                let mut args = vec![("command", execute_bash.command.clone())];
+                args.extend(execute_bash.cwd.clone().map(|cwd| ("cwd", cwd)));
+                if !execute_bash.env.is_empty() {
+                    args.push(("env", execute_bash.env.keys().cloned().collect::<Vec<_>>().join(", ")));
+                }
+                args
+            },
+            Tool::UseAws(use_aws) => vec![
+                ("service_name", use_aws.service_name.clone()),
+                ("operation_name", use_aws.operation_name.clone()),
+                ("region", use_aws.region.clone()),
+            ],
+            Tool::GhIssue(gh_issue) => vec![("title", gh_issue.title.clone())],
+            Tool::Clipboard(Clipboard::Set { .. }) => vec![("command", "set".to_string())],
+            Tool::Clipboard(Clipboard::Get) => vec![("command", "get".to_string())],
         }
     }
 
@@ -59,21 +120,39 @@ impl Tool {
     pub fn requires_acceptance(&self, _ctx: &Context) -> bool {
         match self {
             Tool::FsRead(_) => false,
-            Tool::FsWrite(_) => true,
+            Tool::FsWrite(fs_write) => !fs_write.dry_run(),
             Tool::ExecuteBash(execute_bash) => execute_bash.requires_acceptance(),
             Tool::UseAws(use_aws) => use_aws.requires_acceptance(),
             Tool::GhIssue(_) => false,
+            Tool::Clipboard(_) => true,
+        }
+    }
+
+    /// Whether the tool must be accepted by the user even if it is trusted, because it reaches
+    /// outside of the workspace in a way that might not be obvious, or could do lasting damage.
+    pub fn requires_explicit_acceptance(&self, ctx: &Context) -> bool {
+        match self {
+            Tool::FsRead(fs_read) => fs_read.escaping_symlink_target(ctx).is_some(),
+            Tool::ExecuteBash(execute_bash) => execute_bash.destructive_reason().is_some(),
+            // Dry runs outside of the workspace still show what the target contains.
+            Tool::FsWrite(fs_write) => {
+                fs_write.outside_workspace_target(ctx).is_some() || (!fs_write.dry_run() && fs_write.force())
+            },
+            _ => false,
         }
     }
 
     /// Invokes the tool asynchronously
     pub async fn invoke(&self, context: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
+        // Checked again here in case a symlink changed after the tool was validated.
+        Sandbox::load(context).check(context, self)?;
         match self {
             Tool::FsRead(fs_read) => fs_read.invoke(context, updates).await,
             Tool::FsWrite(fs_write) => fs_write.invoke(context, updates).await,
-            Tool::ExecuteBash(execute_bash) => execute_bash.invoke(updates).await,
+            Tool::ExecuteBash(execute_bash) => execute_bash.invoke(context, updates).await,
             Tool::UseAws(use_aws) => use_aws.invoke(context, updates).await,
             Tool::GhIssue(gh_issue) => gh_issue.invoke(updates).await,
+            Tool::Clipboard(clipboard) => clipboard.invoke(updates).await,
         }
     }
 
@@ -82,9 +161,10 @@ impl Tool {
         match self {
             Tool::FsRead(fs_read) => fs_read.queue_description(ctx, updates).await,
             Tool::FsWrite(fs_write) => fs_write.queue_description(ctx, updates),
-            Tool::ExecuteBash(execute_bash) => execute_bash.queue_description(updates),
+            Tool::ExecuteBash(execute_bash) => execute_bash.queue_description(ctx, updates),
             Tool::UseAws(use_aws) => use_aws.queue_description(updates),
             Tool::GhIssue(gh_issue) => gh_issue.queue_description(updates),
+            Tool::Clipboard(clipboard) => clipboard.queue_description(ctx, updates),
         }
     }
 
@@ -96,6 +176,7 @@ impl Tool {
             Tool::ExecuteBash(execute_bash) => execute_bash.validate(ctx).await,
             Tool::UseAws(use_aws) => use_aws.validate(ctx).await,
             Tool::GhIssue(gh_issue) => gh_issue.validate(ctx).await,
+            Tool::Clipboard(clipboard) => clipboard.validate(ctx).await,
         }
     }
 }
@@ -104,6 +185,25 @@ impl TryFrom<AssistantToolUse> for Tool {
     type Error = ToolUseResult;
 
     fn try_from(value: AssistantToolUse) -> std::result::Result<Self, Self::Error> {
+        Self::from_tool_use(value, &unavailable_tools(&Context::new(), &Settings::new()))
+    }
+}
+
+impl Tool {
+    /// Parses a tool use, refusing calls to any of the `unavailable` tools.
+    pub fn from_tool_use(
+        value: AssistantToolUse,
+        unavailable: &[UnavailableTool],
+    ) -> std::result::Result<Self, ToolUseResult> {
+        // Never execute a tool that was only registered as a stub.
+        if let Some(tool) = unavailable.iter().find(|t| t.name == value.name) {
+            return Err(ToolUseResult {
+                tool_use_id: value.id,
+                content: vec![ToolUseResultBlock::Text(tool.description())],
+                status: ToolResultStatus::Error,
+            });
+        }
+
         let map_err = |parse_error| ToolUseResult {
             tool_use_id: value.id.clone(),
             content: vec![ToolUseResultBlock::Text(format!(
@@ -118,6 +218,7 @@ impl TryFrom<AssistantToolUse> for Tool {
             "execute_bash" => Self::ExecuteBash(serde_json::from_value::<ExecuteBash>(value.args).map_err(map_err)?),
             "use_aws" => Self::UseAws(serde_json::from_value::<UseAws>(value.args).map_err(map_err)?),
             "report_issue" => Self::GhIssue(serde_json::from_value::<GhIssue>(value.args).map_err(map_err)?),
+            "clipboard" => Self::Clipboard(serde_json::from_value::<Clipboard>(value.args).map_err(map_err)?),
             unknown => {
                 return Err(ToolUseResult {
                     tool_use_id: value.id,
@@ -131,6 +232,107 @@ impl TryFrom<AssistantToolUse> for Tool {
     }
 }
 
+/// Setting for whether built-in tools the platform can't support are replaced by a stub, rather
+/// than omitted. On by default.
+pub const STUB_UNAVAILABLE_TOOLS_SETTING: &str = "chat.stubUnavailableTools";
+/// Setting holding the names of built-in tools disabled by policy.
+pub const DISABLED_TOOLS_SETTING: &str = "chat.disabledTools";
+/// Setting for whether tools in [DISABLED_TOOLS_SETTING] are replaced by a stub, rather than
+/// omitted. Off by default.
+pub const STUB_DISABLED_TOOLS_SETTING: &str = "chat.stubDisabledTools";
+
+/// Names of the built-in tools, as in `tool_index.json`.
+const BUILT_IN_TOOLS: &[&str] = &[
+    "fs_read",
+    "fs_write",
+    "execute_bash",
+    "use_aws",
+    "report_issue",
+    "clipboard",
+];
+
+/// Why a built-in tool can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailability {
+    /// The platform lacks something the tool needs.
+    Platform,
+    /// The tool is listed in [DISABLED_TOOLS_SETTING].
+    Policy,
+}
+
+/// A built-in tool that can't be used, along with what the model should do instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnavailableTool {
+    pub name: &'static str,
+    pub reason: Unavailability,
+    pub guidance: &'static str,
+    /// Whether the model is shown a stub spec for the tool, rather than no spec at all.
+    pub stub: bool,
+}
+
+impl UnavailableTool {
+    fn description(&self) -> String {
+        let reason = match self.reason {
+            Unavailability::Platform => "is unavailable on this platform",
+            Unavailability::Policy => "has been disabled by policy",
+        };
+        format!(
+            "The {} tool {reason} and calling it will always fail. {}",
+            self.name, self.guidance
+        )
+    }
+
+    /// A spec that keeps the tool visible to the model, but only to explain that it can't be used.
+    pub fn stub_spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: self.name.to_string(),
+            description: self.description(),
+            input_schema: InputSchema(serde_json::json!({ "type": "object", "properties": {} })),
+        }
+    }
+}
+
+/// Built-in tools that can't be used, either because the platform can't support them or because
+/// they are disabled in `settings`.
+pub fn unavailable_tools(ctx: &Context, settings: &Settings) -> Vec<UnavailableTool> {
+    let mut unavailable = Vec::new();
+    // `execute_bash` runs commands in PowerShell or cmd on Windows, so the only platform gap is a
+    // headless Linux machine without a clipboard.
+    if !clipboard::has_clipboard(ctx) {
+        unavailable.push(UnavailableTool {
+            name: "clipboard",
+            reason: Unavailability::Platform,
+            guidance: "Show the text to the user so that they can copy it themselves.",
+            stub: settings.get_bool_or(STUB_UNAVAILABLE_TOOLS_SETTING, true),
+        });
+    }
+
+    let disabled: Vec<String> = settings.get(DISABLED_TOOLS_SETTING).ok().flatten().unwrap_or_default();
+    let stub = settings.get_bool_or(STUB_DISABLED_TOOLS_SETTING, false);
+    for name in BUILT_IN_TOOLS.iter().filter(|name| disabled.iter().any(|d| d == *name)) {
+        if unavailable.iter().all(|t| t.name != *name) {
+            unavailable.push(UnavailableTool {
+                name,
+                reason: Unavailability::Policy,
+                guidance: "Don't try to work around it. If the task can't be done without it, tell the user.",
+                stub,
+            });
+        }
+    }
+    unavailable
+}
+
+/// Replaces the specs of unavailable tools with a stub explaining why, or removes them entirely.
+pub fn apply_unavailable_tools(tools: &mut HashMap<String, ToolSpec>, unavailable: &[UnavailableTool]) {
+    for tool in unavailable {
+        if tool.stub {
+            tools.insert(tool.name.to_string(), tool.stub_spec());
+        } else {
+            tools.remove(tool.name);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ToolPermission {
     pub trusted: bool,
@@ -222,12 +424,185 @@ pub struct QueuedTool {
     pub name: String,
     pub accepted: bool,
     pub tool: Tool,
+    /// The arguments as sent by the model.
+    pub args: serde_json::Value,
+}
+
+/// Testing helper, an accepted call to the tool `name`.
+#[cfg(test)]
+pub fn queued(id: &str, name: &str, args: serde_json::Value) -> QueuedTool {
+    QueuedTool {
+        id: id.to_string(),
+        name: name.to_string(),
+        accepted: true,
+        tool: Tool::from_tool_use(
+            AssistantToolUse {
+                id: id.to_string(),
+                name: name.to_string(),
+                args: args.clone(),
+            },
+            &[],
+        )
+        .unwrap(),
+        args,
+    }
+}
+
+/// Setting holding the most tools from a single response that may run at the same time. `1`
+/// disables concurrent execution.
+pub const MAX_CONCURRENT_TOOLS_SETTING: &str = "chat.maxConcurrentTools";
+/// Setting holding the names of tools, in addition to `fs_read`, that may run concurrently
+/// alongside other calls from the same response. `fs_write` is never run concurrently.
+pub const CONCURRENT_TOOLS_SETTING: &str = "chat.concurrentTools";
+
+const DEFAULT_MAX_CONCURRENT_TOOLS: i64 = 4;
+
+/// Decides which tool uses from a single response can be run at the same time. Tools with side
+/// effects always run one at a time unless explicitly opted in.
+#[derive(Debug, Clone)]
+pub struct ToolConcurrency {
+    limit: usize,
+    opted_in: Vec<String>,
+}
+
+impl ToolConcurrency {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            limit: settings
+                .get_int_or(MAX_CONCURRENT_TOOLS_SETTING, DEFAULT_MAX_CONCURRENT_TOOLS)
+                .max(1) as usize,
+            opted_in: settings
+                .get::<Vec<String>>(CONCURRENT_TOOLS_SETTING)
+                .ok()
+                .flatten()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn allows(&self, tool: &QueuedTool) -> bool {
+        // Interactive commands take over the terminal, so they always run alone. Writes are checked
+        // against the file as it is just before they run, and backed up, so they do too.
+        let runs_alone = matches!(&tool.tool, Tool::ExecuteBash(execute_bash) if execute_bash.interactive)
+            || matches!(tool.tool, Tool::FsWrite(_));
+        self.limit > 1 && !runs_alone && (matches!(tool.tool, Tool::FsRead(_)) || self.opted_in.contains(&tool.name))
+    }
+}
+
+/// Result of a tool invoked by [invoke_concurrently], along with the output it wrote and how long
+/// it took.
+pub type ConcurrentResult = (Result<InvokeOutput>, Vec<u8>, Duration);
+
+/// Invokes `tools` with at most `limit` running at a time, returning their results in the same
+/// order as `tools`. Output written by each tool is buffered so that it isn't interleaved.
+pub async fn invoke_concurrently(ctx: &Context, tools: &[&QueuedTool], limit: usize) -> Vec<ConcurrentResult> {
+    stream::iter(tools.iter().map(|tool| async move {
+        let mut output = Vec::new();
+        let start = Instant::now();
+        let result = tool.tool.invoke(ctx, &mut output).await;
+        (result, output, start.elapsed())
+    }))
+    .buffered(limit.max(1))
+    .collect()
+    .await
 }
 
 /// The schema specification describing a tool's fields.
 #[derive(Debug, Clone, Deserialize)]
 pub struct InputSchema(pub serde_json::Value);
 
+impl InputSchema {
+    /// Checks `args` against the schema, returning a description of each missing or mistyped
+    /// field. Only `type`, `properties`, `required`, `enum` and `items` are checked, which covers
+    /// every keyword used by the built-in tool schemas.
+    pub fn validate_args(&self, args: &serde_json::Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        validate_value(&self.0, args, "", &mut errors);
+        errors
+    }
+}
+
+fn validate_value(schema: &serde_json::Value, value: &serde_json::Value, path: &str, errors: &mut Vec<String>) {
+    let name = if path.is_empty() { "arguments" } else { path };
+
+    if let Some(expected) = schema.get("type").and_then(|t| t.as_str()) {
+        let matches = match expected {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !matches {
+            errors.push(format!(
+                "`{name}` should be of type {expected}, got {}",
+                json_type(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !allowed.contains(value) {
+            let allowed = allowed.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+            errors.push(format!("`{name}` should be one of {allowed}, got {value}"));
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        for field in schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f.as_str())
+        {
+            if !object.contains_key(field) {
+                errors.push(format!("missing required field `{}`", join_path(path, field)));
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+            for (field, field_value) in object {
+                if let Some(field_schema) = properties.get(field) {
+                    validate_value(field_schema, field_value, &join_path(path, field), errors);
+                }
+            }
+        }
+    }
+
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            validate_value(items, item, &format!("{name}[{i}]"), errors);
+        }
+    }
+}
+
+fn join_path(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{path}.{field}")
+    }
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(n) if n.is_f64() => "number",
+        serde_json::Value::Number(_) => "integer",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
 /// The output received from invoking a [Tool].
 #[derive(Debug, Default)]
 pub struct InvokeOutput {
@@ -304,7 +679,7 @@ pub fn document_to_serde_value(value: Document) -> serde_json::Value {
 ///
 /// Required since path arguments are defined by the model.
 #[allow(dead_code)]
-fn sanitize_path_tool_arg(ctx: &Context, path: impl AsRef<Path>) -> PathBuf {
+pub fn sanitize_path_tool_arg(ctx: &Context, path: impl AsRef<Path>) -> PathBuf {
     let mut res = PathBuf::new();
     // Expand `~` only if it is the first part.
     let mut path = path.as_ref().components();
@@ -323,6 +698,56 @@ fn sanitize_path_tool_arg(ctx: &Context, path: impl AsRef<Path>) -> PathBuf {
     ctx.fs().chroot_path(res)
 }
 
+/// Setting holding directories, in addition to the current working directory, that tools treat as
+/// part of the user's workspace.
+pub const WORKSPACE_ROOTS_SETTING: &str = "chat.workspaceRoots";
+
+/// The canonicalized directories making up the user's workspace.
+pub fn workspace_roots(ctx: &Context) -> Vec<PathBuf> {
+    let extra_roots = fig_settings::settings::get::<Vec<String>>(WORKSPACE_ROOTS_SETTING)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    ctx.env()
+        .current_dir()
+        .map(|cwd| ctx.fs().chroot_path(cwd))
+        .into_iter()
+        .chain(extra_roots.iter().map(|root| sanitize_path_tool_arg(ctx, root)))
+        .filter_map(|root| root.canonicalize().ok())
+        .collect()
+}
+
+/// Returns where `path` resolves to if it is, or passes through, a symlink.
+pub fn resolve_symlinks(path: &Path) -> Option<PathBuf> {
+    let resolved = path.canonicalize().ok()?;
+    path.ancestors()
+        .any(|p| p.symlink_metadata().is_ok_and(|md| md.is_symlink()))
+        .then_some(resolved)
+}
+
+/// Canonicalizes `path` even if it doesn't exist yet, by canonicalizing its closest existing
+/// ancestor and normalizing the rest.
+pub fn canonicalize_lenient(path: &Path) -> Option<PathBuf> {
+    let (ancestor, mut canonical) = path
+        .ancestors()
+        .find_map(|ancestor| Some((ancestor, ancestor.canonicalize().ok()?)))?;
+    for component in path.strip_prefix(ancestor).ok()?.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                canonical.pop();
+            },
+            std::path::Component::Normal(part) => canonical.push(part),
+            _ => (),
+        }
+    }
+    Some(canonical)
+}
+
+/// Whether the canonical `path` lies within any of `roots`.
+pub fn is_within_roots(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
 /// Converts `path` to a relative path according to the current working directory `cwd`.
 fn absolute_to_relative(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<PathBuf> {
     let cwd = cwd.as_ref().canonicalize()?;
@@ -353,7 +778,7 @@ fn absolute_to_relative(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> Result
 }
 
 /// Small helper for formatting the path as a relative path, if able.
-fn format_path(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> String {
+pub fn format_path(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> String {
     absolute_to_relative(cwd, path.as_ref())
         .map(|p| p.to_string_lossy().to_string())
         // If we have three consecutive ".." then it should probably just stay as an absolute path.
@@ -379,6 +804,193 @@ mod tests {
 
     use super::*;
 
+    const TEST_UNAVAILABLE: &[UnavailableTool] = &[UnavailableTool {
+        name: "execute_bash",
+        reason: Unavailability::Platform,
+        guidance: "Ask the user to run the command.",
+        stub: true,
+    }];
+
+    #[test]
+    fn test_unavailable_tool_stub() {
+        let mut tools: HashMap<String, ToolSpec> =
+            serde_json::from_str(include_str!("tool_index.json")).expect("tool index is valid");
+        apply_unavailable_tools(&mut tools, TEST_UNAVAILABLE);
+        let spec = tools.get("execute_bash").unwrap();
+        assert!(spec.description.contains("unavailable on this platform"));
+        assert!(spec.description.contains("Ask the user to run the command."));
+        assert!(spec.input_schema.0["properties"].as_object().unwrap().is_empty());
+
+        apply_unavailable_tools(&mut tools, &[UnavailableTool {
+            stub: false,
+            ..TEST_UNAVAILABLE[0]
+        }]);
+        assert!(!tools.contains_key("execute_bash"));
+        assert!(tools.contains_key("fs_read"));
+    }
+
+    #[test]
+    fn test_unavailable_tools() {
+        let headless = Context::builder().with_os(fig_os_shim::Os::Linux).build_fake();
+        let desktop = Context::builder()
+            .with_os(fig_os_shim::Os::Linux)
+            .with_env_var("WAYLAND_DISPLAY", "wayland-0")
+            .build_fake();
+        let names = |tools: Vec<UnavailableTool>| {
+            tools
+                .into_iter()
+                .map(|t| (t.name, t.reason, t.stub))
+                .collect::<Vec<_>>()
+        };
+
+        // Platform gaps are stubbed and policy disables omitted by default.
+        let settings =
+            Settings::from_slice(&[(DISABLED_TOOLS_SETTING, serde_json::json!(["execute_bash", "unknown"]))]);
+        assert_eq!(names(unavailable_tools(&headless, &settings)), vec![
+            ("clipboard", Unavailability::Platform, true),
+            ("execute_bash", Unavailability::Policy, false),
+        ]);
+        assert_eq!(names(unavailable_tools(&desktop, &settings)), vec![(
+            "execute_bash",
+            Unavailability::Policy,
+            false
+        )]);
+
+        let settings = Settings::from_slice(&[
+            (DISABLED_TOOLS_SETTING, serde_json::json!(["clipboard", "use_aws"])),
+            (STUB_UNAVAILABLE_TOOLS_SETTING, false.into()),
+            (STUB_DISABLED_TOOLS_SETTING, true.into()),
+        ]);
+        assert_eq!(names(unavailable_tools(&headless, &settings)), vec![
+            ("clipboard", Unavailability::Platform, false),
+            ("use_aws", Unavailability::Policy, true),
+        ]);
+    }
+
+    #[test]
+    fn test_disabled_tool_is_never_executed() {
+        let ctx = Context::builder().with_os(fig_os_shim::Os::Linux).build_fake();
+        let settings = Settings::from_slice(&[(DISABLED_TOOLS_SETTING, serde_json::json!(["fs_write"]))]);
+        let err = Tool::from_tool_use(
+            AssistantToolUse {
+                id: "1".to_string(),
+                name: "fs_write".to_string(),
+                args: serde_json::json!({ "command": "create", "path": "/file.txt", "file_text": "hello" }),
+            },
+            &unavailable_tools(&ctx, &settings),
+        )
+        .unwrap_err();
+        assert!(matches!(err.status, ToolResultStatus::Error));
+        assert!(matches!(&err.content[0], ToolUseResultBlock::Text(t) if t.contains("disabled by policy")));
+    }
+
+    #[test]
+    fn test_unavailable_tool_is_never_executed() {
+        let tool_use = AssistantToolUse {
+            id: "1".to_string(),
+            name: "execute_bash".to_string(),
+            args: serde_json::json!({ "command": "echo hello" }),
+        };
+        let err = Tool::from_tool_use(tool_use.clone(), TEST_UNAVAILABLE).unwrap_err();
+        assert!(matches!(err.status, ToolResultStatus::Error));
+        assert!(
+            matches!(&err.content[0], ToolUseResultBlock::Text(t) if t.contains("Ask the user to run the command."))
+        );
+
+        assert!(matches!(Tool::from_tool_use(tool_use, &[]), Ok(Tool::ExecuteBash(_))));
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let tools: HashMap<String, ToolSpec> =
+            serde_json::from_str(include_str!("tool_index.json")).expect("tool index is valid");
+        let fs_read = &tools.get("fs_read").unwrap().input_schema;
+
+        assert!(
+            fs_read
+                .validate_args(&serde_json::json!({ "path": "/tmp", "mode": "Line", "start_line": -5 }))
+                .is_empty()
+        );
+        assert_eq!(
+            fs_read.validate_args(&serde_json::json!({ "mode": "Lines", "start_line": "5" })),
+            vec![
+                r#"`mode` should be one of "Line", "Directory", "Search", got "Lines""#,
+                "`start_line` should be of type integer, got string",
+            ]
+        );
+        assert_eq!(fs_read.validate_args(&serde_json::json!({ "path": "/tmp" })), vec![
+            "missing required field `mode`"
+        ]);
+        assert_eq!(fs_read.validate_args(&serde_json::json!("/tmp")), vec![
+            "`arguments` should be of type object, got string"
+        ]);
+
+        let schema = InputSchema(serde_json::json!({
+            "type": "object",
+            "properties": {
+                "paths": { "type": "array", "items": { "type": "string" } },
+            },
+        }));
+        assert_eq!(schema.validate_args(&serde_json::json!({ "paths": ["a", 1] })), vec![
+            "`paths[1]` should be of type string, got integer"
+        ]);
+    }
+
+    #[test]
+    fn test_tool_concurrency() {
+        let read = queued("1", "fs_read", serde_json::json!({ "path": "/a", "mode": "Line" }));
+        let bash = queued("2", "execute_bash", serde_json::json!({ "command": "ls" }));
+        let write = queued(
+            "3",
+            "fs_write",
+            serde_json::json!({ "path": "/a", "command": "create", "file_text": "a" }),
+        );
+
+        let concurrency = ToolConcurrency::from_settings(&Settings::new_fake());
+        assert!(concurrency.allows(&read));
+        assert!(!concurrency.allows(&bash));
+
+        let concurrency = ToolConcurrency::from_settings(&Settings::from_slice(&[(
+            CONCURRENT_TOOLS_SETTING,
+            serde_json::json!(["execute_bash", "fs_write"]),
+        )]));
+        assert!(concurrency.allows(&bash));
+        assert!(!concurrency.allows(&write));
+
+        let concurrency =
+            ToolConcurrency::from_settings(&Settings::from_slice(&[(MAX_CONCURRENT_TOOLS_SETTING, 1.into())]));
+        assert!(!concurrency.allows(&read));
+    }
+
+    #[tokio::test]
+    async fn test_invoke_concurrently_preserves_order() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut tools = Vec::new();
+        for i in 0..6 {
+            let path = format!("/file_{i}.txt");
+            ctx.fs().write(&path, format!("contents {i}")).await.unwrap();
+            tools.push(queued(
+                &i.to_string(),
+                "fs_read",
+                serde_json::json!({ "path": path, "mode": "Line" }),
+            ));
+        }
+        tools.push(queued(
+            "missing",
+            "fs_read",
+            serde_json::json!({ "path": "/missing.txt", "mode": "Line" }),
+        ));
+
+        let tools = tools.iter().collect::<Vec<_>>();
+        let results = invoke_concurrently(&ctx, &tools, 3).await;
+        assert_eq!(results.len(), 7);
+        for (i, (result, _, _)) in results.iter().take(6).enumerate() {
+            let output = result.as_ref().unwrap();
+            assert!(matches!(&output.output, OutputKind::Text(text) if text == &format!("contents {i}")));
+        }
+        assert!(results[6].0.is_err());
+    }
+
     #[tokio::test]
     async fn test_tilde_path_expansion() {
         let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
@@ -403,6 +1015,24 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_canonicalize_lenient() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("a")).unwrap();
+
+        assert_eq!(canonicalize_lenient(&root.join("a")), Some(root.join("a")));
+        assert_eq!(
+            canonicalize_lenient(&root.join("a/new/dirs")),
+            Some(root.join("a/new/dirs"))
+        );
+        assert_eq!(canonicalize_lenient(&root.join("a/new/../../b")), Some(root.join("b")));
+        assert_eq!(
+            canonicalize_lenient(&root.join("new/../..")),
+            root.parent().map(Path::to_path_buf)
+        );
+    }
+
     #[tokio::test]
     async fn test_format_path() {
         async fn assert_paths(cwd: &str, path: &str, expected: &str) {
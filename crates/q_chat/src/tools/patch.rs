@@ -0,0 +1,353 @@
+//! Applying unified diffs to a single file, for fs_write's `patch` command.
+
+use eyre::{
+    Result,
+    bail,
+};
+
+/// How many context lines may be dropped from either end of a hunk when it doesn't match as is.
+pub const MAX_FUZZ: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// 1-indexed line the hunk starts at in the original file, from its `@@` header.
+    old_start: usize,
+    lines: Vec<Line>,
+}
+
+impl Hunk {
+    /// The lines the hunk expects to find, with `fuzz` context lines dropped from either end.
+    fn old_lines(&self, fuzz: usize) -> Vec<&str> {
+        self.trimmed(fuzz)
+            .iter()
+            .filter_map(|line| match line {
+                Line::Context(text) | Line::Remove(text) => Some(text.as_str()),
+                Line::Add(_) => None,
+            })
+            .collect()
+    }
+
+    /// Number of context lines before the first change.
+    fn leading_context(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|line| matches!(line, Line::Context(_)))
+            .count()
+    }
+
+    /// The hunk's lines with up to `fuzz` context lines dropped from either end.
+    fn trimmed(&self, fuzz: usize) -> &[Line] {
+        let leading = self.leading_context().min(fuzz);
+        let trailing = self
+            .lines
+            .iter()
+            .rev()
+            .take_while(|line| matches!(line, Line::Context(_)))
+            .count()
+            .min(fuzz);
+        if leading + trailing >= self.lines.len() {
+            return &[];
+        }
+        &self.lines[leading..self.lines.len() - trailing]
+    }
+}
+
+/// The result of applying a patch.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Applied {
+    pub content: String,
+    pub hunks: usize,
+    /// Hunks that applied somewhere other than where their header said, as `(hunk, offset)` with
+    /// 1-indexed hunk numbers and the offset in lines.
+    pub offsets: Vec<(usize, isize)>,
+    /// Hunks that only applied with context lines dropped, as `(hunk, fuzz)`.
+    pub fuzzed: Vec<(usize, usize)>,
+}
+
+impl Applied {
+    /// One line summary of how the patch applied, for the tool result.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Applied {} hunk{}", self.hunks, if self.hunks == 1 { "" } else { "s" });
+        let notes = self
+            .offsets
+            .iter()
+            .map(|(hunk, offset)| format!("hunk {hunk} at an offset of {offset} lines"))
+            .chain(
+                self.fuzzed
+                    .iter()
+                    .map(|(hunk, fuzz)| format!("hunk {hunk} with fuzz {fuzz}")),
+            )
+            .collect::<Vec<_>>();
+        if !notes.is_empty() {
+            summary.push_str(&format!(" ({})", notes.join(", ")));
+        }
+        summary
+    }
+}
+
+/// Parses a unified diff of a single file into its hunks.
+pub fn parse(diff: &str) -> Result<Vec<Hunk>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut lines = diff.lines().peekable();
+    while let Some(line) = lines.next() {
+        let is_file_header = line.starts_with("--- ") && lines.peek().is_some_and(|next| next.starts_with("+++ "));
+        if is_file_header || line.starts_with("diff --git ") {
+            if !hunks.is_empty() {
+                bail!("the diff changes more than one file, only a single file can be patched per call");
+            }
+            if is_file_header {
+                lines.next();
+            }
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("@@") {
+            hunks.push(Hunk {
+                old_start: parse_hunk_header(header)?,
+                lines: Vec::new(),
+            });
+            continue;
+        }
+        // Everything before the first hunk is a header, such as `index 83db48f..bf269f4`.
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        match line.chars().next() {
+            Some(' ') => hunk.lines.push(Line::Context(line[1..].to_string())),
+            Some('-') => hunk.lines.push(Line::Remove(line[1..].to_string())),
+            Some('+') => hunk.lines.push(Line::Add(line[1..].to_string())),
+            // "\ No newline at end of file"
+            Some('\\') => (),
+            // Editors often strip the space from empty context lines.
+            None => hunk.lines.push(Line::Context(String::new())),
+            Some(_) => bail!("unexpected line in hunk {}: {line}", hunks.len()),
+        }
+    }
+
+    if hunks.is_empty() {
+        bail!("the diff has no hunks, each hunk must start with a header like `@@ -1,3 +1,4 @@`");
+    }
+    if let Some(i) = hunks.iter().position(|hunk| hunk.lines.is_empty()) {
+        bail!("hunk {} is empty", i + 1);
+    }
+    Ok(hunks)
+}
+
+/// Returns the old start line from the rest of a hunk header, e.g. ` -12,7 +12,8 @@`.
+fn parse_hunk_header(header: &str) -> Result<usize> {
+    let old = header
+        .split_whitespace()
+        .find_map(|part| part.strip_prefix('-'))
+        .ok_or_else(|| eyre::eyre!("invalid hunk header: @@{header}"))?;
+    old.split(',')
+        .next()
+        .unwrap_or(old)
+        .parse()
+        .map_err(|err| eyre::eyre!("invalid hunk header: @@{header}: {err}"))
+}
+
+/// Applies `hunks` to `file`, in order. Each hunk is matched against the file near the line its
+/// header gives, and with up to [MAX_FUZZ] of its outer context lines dropped if it doesn't match
+/// as is. The file's line endings and trailing newline are kept.
+pub fn apply(file: &str, hunks: &[Hunk]) -> Result<Applied> {
+    let line_ending = if file.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines = file.lines().map(str::to_string).collect::<Vec<_>>();
+    let mut applied = Applied {
+        content: String::new(),
+        hunks: hunks.len(),
+        offsets: Vec::new(),
+        fuzzed: Vec::new(),
+    };
+    // Lines added or removed by the hunks applied so far, to shift where later hunks are expected.
+    let mut delta: isize = 0;
+    // Hunks apply in order, so a hunk can't match before the end of the previous one.
+    let mut min_pos = 0;
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let number = i + 1;
+        let expected = ((hunk.old_start.max(1) - 1) as isize + delta).max(0) as usize;
+        let leading_context = hunk.leading_context();
+        let Some((pos, fuzz)) = (0..=MAX_FUZZ).find_map(|fuzz| {
+            find_lines(
+                &lines,
+                &hunk.old_lines(fuzz),
+                expected + leading_context.min(fuzz),
+                min_pos,
+            )
+            .map(|pos| (pos, fuzz))
+        }) else {
+            bail!(
+                "hunk {number} failed at line {}: {}",
+                expected + 1,
+                mismatch(&lines, &hunk.old_lines(0), expected)
+            );
+        };
+
+        let trimmed = hunk.trimmed(fuzz);
+        let offset = pos as isize - (expected + leading_context.min(fuzz)) as isize;
+        if offset != 0 {
+            applied.offsets.push((number, offset));
+        }
+        if fuzz > 0 {
+            applied.fuzzed.push((number, fuzz));
+        }
+
+        // Context lines keep the file's version, in case they only matched loosely.
+        let mut cursor = pos;
+        let mut replacement = Vec::new();
+        for line in trimmed {
+            match line {
+                Line::Context(_) => {
+                    replacement.push(lines[cursor].clone());
+                    cursor += 1;
+                },
+                Line::Remove(_) => cursor += 1,
+                Line::Add(text) => replacement.push(text.clone()),
+            }
+        }
+        let removed = cursor - pos;
+        delta += replacement.len() as isize - removed as isize;
+        min_pos = pos + replacement.len();
+        lines.splice(pos..cursor, replacement);
+    }
+
+    let mut content = lines.join(line_ending);
+    if !lines.is_empty() && (file.is_empty() || file.ends_with('\n')) {
+        content.push_str(line_ending);
+    }
+    applied.content = content;
+    Ok(applied)
+}
+
+/// Finds where `needle` occurs in `lines` at or after `min_pos`, searching outwards from
+/// `expected`. Lines match if they only differ by trailing whitespace.
+fn find_lines(lines: &[String], needle: &[&str], expected: usize, min_pos: usize) -> Option<usize> {
+    let matches_at = |pos: usize| {
+        pos >= min_pos
+            && pos + needle.len() <= lines.len()
+            && lines[pos..pos + needle.len()]
+                .iter()
+                .zip(needle)
+                .all(|(line, expected)| line.trim_end() == expected.trim_end())
+    };
+    if needle.is_empty() {
+        return Some(expected.clamp(min_pos, lines.len()));
+    }
+    (0..=lines.len()).find_map(|distance| {
+        [expected.checked_add(distance), expected.checked_sub(distance)]
+            .into_iter()
+            .flatten()
+            .find(|pos| matches_at(*pos))
+    })
+}
+
+/// Describes why `needle` doesn't match `lines` at `pos`.
+fn mismatch(lines: &[String], needle: &[&str], pos: usize) -> String {
+    for (i, expected) in needle.iter().enumerate() {
+        match lines.get(pos + i) {
+            Some(actual) if actual.trim_end() == expected.trim_end() => (),
+            Some(actual) => {
+                return format!(
+                    "context mismatch, expected line {} to be `{expected}` but it is `{actual}`",
+                    pos + i + 1
+                );
+            },
+            None => return "context mismatch, the hunk goes past the end of the file".to_string(),
+        }
+    }
+    "context mismatch".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+    fn patch(file: &str, diff: &str) -> Result<Applied> {
+        apply(file, &parse(diff)?)
+    }
+
+    #[test]
+    fn test_apply_multiple_hunks() {
+        let diff = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+@@ -8,3 +8,4 @@
+ eight
+ nine
++nine and a half
+ ten
+";
+        let applied = patch(FILE, diff).unwrap();
+        assert_eq!(
+            applied.content,
+            "one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nnine and a half\nten\n"
+        );
+        assert_eq!(applied.hunks, 2);
+        assert!(applied.offsets.is_empty());
+        assert_eq!(applied.summary(), "Applied 2 hunks");
+    }
+
+    #[test]
+    fn test_apply_with_offset_and_fuzz() {
+        // The header is off by two lines.
+        let applied = patch(FILE, "@@ -2,3 +2,3 @@\n four\n-five\n+FIVE\n six\n").unwrap();
+        assert!(applied.content.contains("four\nFIVE\nsix"));
+        assert_eq!(applied.offsets, vec![(1, 2)]);
+
+        // The first context line doesn't match, but the hunk applies with it dropped.
+        let applied = patch(FILE, "@@ -4,3 +4,3 @@\n FOUR?\n-five\n+FIVE\n six\n").unwrap();
+        assert!(applied.content.contains("four\nFIVE\nsix"));
+        assert_eq!(applied.fuzzed, vec![(1, 1)]);
+        assert_eq!(applied.summary(), "Applied 1 hunk (hunk 1 with fuzz 1)");
+    }
+
+    #[test]
+    fn test_apply_failure() {
+        let diff = "@@ -1,2 +1,2 @@\n one\n-two\n+2\n@@ -6,3 +6,3 @@\n six\n-sevem\n+7\n eight\n";
+        let err = patch(FILE, diff).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "hunk 2 failed at line 6: context mismatch, expected line 7 to be `sevem` but it is `seven`"
+        );
+
+        // Removed lines that look like file headers are still removed.
+        let applied = patch(
+            "-- a comment\nselect 1;\n",
+            "@@ -1,2 +1,1 @@\n--- a comment\n select 1;\n",
+        )
+        .unwrap();
+        assert_eq!(applied.content, "select 1;\n");
+
+        assert!(patch(FILE, "just some text").is_err());
+        let two_files = "--- a/a\n+++ b/a\n@@ -1 +1 @@\n-one\n+1\n--- a/b\n+++ b/b\n@@ -1 +1 @@\n-x\n+y\n";
+        assert!(
+            patch(FILE, two_files)
+                .unwrap_err()
+                .to_string()
+                .contains("more than one file")
+        );
+    }
+
+    #[test]
+    fn test_apply_keeps_line_endings() {
+        let applied = patch("a\r\nb\r\nc", "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n").unwrap();
+        assert_eq!(applied.content, "a\r\nB\r\nc");
+
+        // Adding to an empty file.
+        let applied = patch("", "@@ -0,0 +1,2 @@\n+new\n+file\n").unwrap();
+        assert_eq!(applied.content, "new\nfile\n");
+    }
+}
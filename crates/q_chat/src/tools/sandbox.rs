@@ -0,0 +1,204 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use eyre::{
+    Result,
+    bail,
+};
+use fig_os_shim::Context;
+use tracing::warn;
+
+use super::fs_read::{
+    absolute_path,
+    glob_targets,
+    is_glob,
+};
+use super::{
+    Tool,
+    canonicalize_lenient,
+    is_within_roots,
+    sanitize_path_tool_arg,
+};
+
+/// Setting holding the only directories that fs_read and fs_write may access. Unset or empty
+/// leaves them unrestricted.
+pub const ALLOWED_PATHS_SETTING: &str = "chat.allowedPaths";
+
+/// The directories that the filesystem tools are confined to, regardless of whether a call was
+/// approved or trusted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// Canonical allowed directories, or `None` if the tools aren't restricted.
+    roots: Option<Vec<PathBuf>>,
+}
+
+impl Sandbox {
+    /// A sandbox of the directories in `roots`, which are absolute or start with `~`. No roots
+    /// means no restriction.
+    pub fn new(ctx: &Context, roots: &[String]) -> Self {
+        if roots.is_empty() {
+            return Self::default();
+        }
+        let roots = roots
+            .iter()
+            .filter_map(|root| {
+                let path = sanitize_path_tool_arg(ctx, root);
+                let canonical = path.is_absolute().then(|| canonicalize_lenient(&path)).flatten();
+                if canonical.is_none() {
+                    warn!(
+                        ?root,
+                        "Ignoring {ALLOWED_PATHS_SETTING} entry that isn't an absolute path"
+                    );
+                }
+                canonical
+            })
+            .collect();
+        Self { roots: Some(roots) }
+    }
+
+    /// Reads [ALLOWED_PATHS_SETTING], so that edits to the setting apply to the next tool call.
+    pub fn load(ctx: &Context) -> Self {
+        let roots = fig_settings::settings::get::<Vec<String>>(ALLOWED_PATHS_SETTING)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Self::new(ctx, &roots)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.roots.is_some()
+    }
+
+    /// The allowed directories, for showing to the user.
+    pub fn describe(&self) -> String {
+        match &self.roots {
+            Some(roots) if roots.is_empty() => "(none)".to_string(),
+            Some(roots) => roots
+                .iter()
+                .map(|root| root.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "unrestricted".to_string(),
+        }
+    }
+
+    /// Whether the canonical `path` is within the sandbox.
+    pub fn allows(&self, path: &Path) -> bool {
+        self.roots.as_ref().is_none_or(|roots| is_within_roots(path, roots))
+    }
+
+    /// Returns an error if `tool` reads or writes anything outside of the sandbox, after resolving
+    /// symlinks.
+    pub fn check(&self, ctx: &Context, tool: &Tool) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let paths = match tool {
+            Tool::FsRead(fs_read) => fs_read.paths(),
+            Tool::FsWrite(fs_write) => vec![fs_write.path()],
+            _ => return Ok(()),
+        };
+        for path in paths {
+            if is_glob(ctx, path) {
+                let mut targets = glob_targets(ctx, path)?.into_iter();
+                if let Some(base) = targets.next() {
+                    self.check_path(path, &base)?;
+                }
+                for matched in targets {
+                    self.check_path(&matched.to_string_lossy(), &matched)?;
+                }
+            } else {
+                self.check_path(path, &absolute_path(ctx, path)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_path(&self, display_path: &str, path: &Path) -> Result<()> {
+        let canonical = canonicalize_lenient(path);
+        if canonical.as_deref().is_some_and(|canonical| self.allows(canonical)) {
+            return Ok(());
+        }
+        let resolved = match &canonical {
+            Some(canonical) if canonical != path => format!(" (which resolves to '{}')", canonical.display()),
+            _ => String::new(),
+        };
+        bail!(
+            "'{display_path}'{resolved} is outside of the directories that fs_read and fs_write are allowed to access: {}. This is enforced by the {ALLOWED_PATHS_SETTING} setting and can't be approved, so don't retry it or work around it. If the path is needed, ask the user to add it to {ALLOWED_PATHS_SETTING}.",
+            self.describe()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::AssistantToolUse;
+
+    fn tool(name: &str, args: serde_json::Value) -> Tool {
+        Tool::try_from(AssistantToolUse {
+            id: "1".to_string(),
+            name: name.to_string(),
+            args,
+        })
+        .unwrap()
+    }
+
+    fn read(path: &str) -> Tool {
+        tool("fs_read", serde_json::json!({ "mode": "Line", "path": path }))
+    }
+
+    fn write(path: &str) -> Tool {
+        tool(
+            "fs_write",
+            serde_json::json!({ "command": "create", "path": path, "file_text": "text" }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sandbox() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().create_dir_all("/work/src").await.unwrap();
+        ctx.fs().create_dir_all("/private").await.unwrap();
+        ctx.fs().write("/work/src/main.rs", "fn main() {}").await.unwrap();
+        ctx.fs().write("/private/secret.txt", "secret").await.unwrap();
+        ctx.fs().symlink("/private/secret.txt", "/work/link.txt").await.unwrap();
+
+        let unrestricted = Sandbox::new(&ctx, &[]);
+        assert!(!unrestricted.is_enabled());
+        unrestricted.check(&ctx, &read("/private/secret.txt")).unwrap();
+
+        let sandbox = Sandbox::new(&ctx, &["/work".to_string()]);
+        sandbox.check(&ctx, &read("/work/src/main.rs")).unwrap();
+        sandbox.check(&ctx, &write("/work/new/file.rs")).unwrap();
+        sandbox.check(&ctx, &read("/work/src/*.rs")).unwrap();
+
+        let err = sandbox
+            .check(&ctx, &read("/private/secret.txt"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is outside of the directories"), "{err}");
+        assert!(err.contains(ALLOWED_PATHS_SETTING), "{err}");
+        assert!(sandbox.check(&ctx, &write("/work/../private/new.txt")).is_err());
+        assert!(sandbox.check(&ctx, &read("/*/secret.txt")).is_err());
+
+        // Symlinks are resolved before checking.
+        let err = sandbox.check(&ctx, &read("/work/link.txt")).unwrap_err().to_string();
+        assert!(err.contains("which resolves to"), "{err}");
+        assert!(sandbox.check(&ctx, &write("/work/link.txt")).is_err());
+        assert!(sandbox.check(&ctx, &read("/work/*.txt")).is_err());
+
+        // Other tools aren't affected.
+        sandbox
+            .check(
+                &ctx,
+                &tool(
+                    "execute_bash",
+                    serde_json::json!({ "command": "cat /private/secret.txt" }),
+                ),
+            )
+            .unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use fig_settings::Settings;
+
+/// Setting holding the number of consecutive read-only tool calls to print in full before the
+/// rest of the run is collapsed into a summary line. Unset or `0` disables collapsing.
+pub const COLLAPSE_THRESHOLD_SETTING: &str = "chat.transcript.collapseReadOnlyAfter";
+
+/// Number of targets named in a summary line before the rest are counted.
+const SUMMARY_TARGETS: usize = 3;
+
+/// A tool call made during the current turn, as far as the terminal transcript is concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCallEvent {
+    /// The path or other argument that best describes what was accessed.
+    pub target: String,
+    /// Whether the tool ran without approval and without mutating anything.
+    pub read_only: bool,
+    pub succeeded: bool,
+}
+
+impl ToolCallEvent {
+    fn collapsible(&self) -> bool {
+        self.read_only && self.succeeded
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptEntry {
+    /// The event at this index is printed in full.
+    Shown(usize),
+    /// The events in this range are hidden behind a single summary line.
+    Collapsed(std::ops::Range<usize>),
+}
+
+/// Groups `events` into what should be printed. Within each run of consecutive successful
+/// read-only calls, the first `threshold` are shown and the remainder collapsed. Mutating calls
+/// and errors are always shown and end the current run.
+pub fn collapse_events(events: &[ToolCallEvent], threshold: usize) -> Vec<TranscriptEntry> {
+    let mut entries = Vec::new();
+    let mut run_len = 0;
+
+    for (i, event) in events.iter().enumerate() {
+        if !event.collapsible() {
+            run_len = 0;
+            entries.push(TranscriptEntry::Shown(i));
+            continue;
+        }
+
+        run_len += 1;
+        if threshold == 0 || run_len <= threshold {
+            entries.push(TranscriptEntry::Shown(i));
+        } else if let Some(TranscriptEntry::Collapsed(range)) = entries.last_mut() {
+            range.end = i + 1;
+        } else {
+            entries.push(TranscriptEntry::Collapsed(i..i + 1));
+        }
+    }
+
+    entries
+}
+
+/// Formats the summary line for a collapsed run, e.g.
+/// `explored 14 files: src/a.rs, src/b.rs, src/c.rs (+11 more)`.
+pub fn collapsed_summary(events: &[ToolCallEvent]) -> String {
+    let mut targets: Vec<&str> = Vec::new();
+    for event in events {
+        if !targets.contains(&event.target.as_str()) {
+            targets.push(&event.target);
+        }
+    }
+
+    let shown = targets
+        .iter()
+        .take(SUMMARY_TARGETS)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut summary = format!(
+        "explored {} {}: {shown}",
+        targets.len(),
+        if targets.len() == 1 { "file" } else { "files" }
+    );
+    if targets.len() > SUMMARY_TARGETS {
+        summary.push_str(&format!(" (+{} more)", targets.len() - SUMMARY_TARGETS));
+    }
+    summary
+}
+
+/// Tracks the tool calls printed during the current turn so that long runs of read-only calls
+/// can be collapsed in the terminal. The conversation sent to the model is unaffected.
+#[derive(Debug, Default)]
+pub struct TurnTranscript {
+    threshold: usize,
+    events: Vec<ToolCallEvent>,
+    /// Output of the calls in the current collapsed run, kept so it can be shown with `/expand`.
+    pending_output: Vec<u8>,
+    /// Output of the most recently summarized run.
+    last_collapsed: Vec<u8>,
+}
+
+impl TurnTranscript {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let threshold = settings.get_int_or(COLLAPSE_THRESHOLD_SETTING, 0);
+        Self {
+            threshold: threshold.max(0) as usize,
+            ..Default::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.threshold > 0
+    }
+
+    /// Whether the next read-only call would be collapsed if it succeeds.
+    pub fn would_collapse(&self) -> bool {
+        let mut events = self.events.clone();
+        events.push(ToolCallEvent {
+            target: String::new(),
+            read_only: true,
+            succeeded: true,
+        });
+        matches!(
+            collapse_events(&events, self.threshold).last(),
+            Some(TranscriptEntry::Collapsed(_))
+        )
+    }
+
+    /// Records a call that was printed in full.
+    pub fn push_shown(&mut self, event: ToolCallEvent) {
+        self.events.push(event);
+    }
+
+    /// Records a call that was hidden, along with the output it would have printed.
+    pub fn push_collapsed(&mut self, event: ToolCallEvent, output: &[u8]) {
+        self.events.push(event);
+        self.pending_output.extend_from_slice(output);
+    }
+
+    /// Ends the current collapsed run, if any, returning the summary line to print.
+    pub fn take_summary(&mut self) -> Option<String> {
+        let range = match collapse_events(&self.events, self.threshold).pop() {
+            Some(TranscriptEntry::Collapsed(range)) => range,
+            _ => return None,
+        };
+        let summary = collapsed_summary(&self.events[range]);
+        self.last_collapsed = std::mem::take(&mut self.pending_output);
+        // Start a fresh run so later read-only calls are shown again before collapsing.
+        self.events.clear();
+        Some(summary)
+    }
+
+    /// Output hidden by the most recent summary line.
+    pub fn last_collapsed(&self) -> &[u8] {
+        &self.last_collapsed
+    }
+
+    /// Forgets the calls made so far, e.g. when the user sends a new prompt.
+    pub fn end_turn(&mut self) {
+        self.events.clear();
+        self.pending_output.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(target: &str) -> ToolCallEvent {
+        ToolCallEvent {
+            target: target.to_string(),
+            read_only: true,
+            succeeded: true,
+        }
+    }
+
+    fn write(target: &str) -> ToolCallEvent {
+        ToolCallEvent {
+            target: target.to_string(),
+            read_only: false,
+            succeeded: true,
+        }
+    }
+
+    fn failed_read(target: &str) -> ToolCallEvent {
+        ToolCallEvent {
+            target: target.to_string(),
+            read_only: true,
+            succeeded: false,
+        }
+    }
+
+    /// Renders entries as one line each, e.g. `a` for a shown call and `[explored ...]` for a
+    /// collapsed run.
+    fn render(events: &[ToolCallEvent], threshold: usize) -> String {
+        collapse_events(events, threshold)
+            .into_iter()
+            .map(|entry| match entry {
+                TranscriptEntry::Shown(i) => events[i].target.clone(),
+                TranscriptEntry::Collapsed(range) => format!("[{}]", collapsed_summary(&events[range])),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn exploration() -> Vec<ToolCallEvent> {
+        vec![
+            read("a"),
+            read("b"),
+            read("c"),
+            read("d"),
+            read("e"),
+            read("f"),
+            write("g"),
+            read("h"),
+            read("i"),
+            failed_read("j"),
+            read("k"),
+        ]
+    }
+
+    #[test]
+    fn test_collapse_disabled() {
+        assert_eq!(render(&exploration(), 0), "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk");
+    }
+
+    #[test]
+    fn test_collapse_threshold_one() {
+        assert_eq!(
+            render(&exploration(), 1),
+            "a\n[explored 5 files: b, c, d (+2 more)]\ng\nh\n[explored 1 file: i]\nj\nk"
+        );
+    }
+
+    #[test]
+    fn test_collapse_threshold_two() {
+        assert_eq!(
+            render(&exploration(), 2),
+            "a\nb\n[explored 4 files: c, d, e (+1 more)]\ng\nh\ni\nj\nk"
+        );
+    }
+
+    #[test]
+    fn test_collapse_threshold_above_run_length() {
+        assert_eq!(render(&exploration(), 6), "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk");
+    }
+
+    #[test]
+    fn test_summary_counts_distinct_targets() {
+        assert_eq!(
+            collapsed_summary(&[read("a"), read("a"), read("b")]),
+            "explored 2 files: a, b"
+        );
+    }
+
+    #[test]
+    fn test_turn_transcript() {
+        let mut transcript = TurnTranscript {
+            threshold: 1,
+            ..Default::default()
+        };
+        assert!(!transcript.would_collapse());
+        transcript.push_shown(read("a"));
+        assert!(transcript.would_collapse());
+        transcript.push_collapsed(read("b"), b"reading b\n");
+        transcript.push_collapsed(read("c"), b"reading c\n");
+        assert_eq!(transcript.take_summary().as_deref(), Some("explored 2 files: b, c"));
+        assert_eq!(transcript.last_collapsed(), b"reading b\nreading c\n");
+
+        // A new run is started after a summary.
+        assert!(transcript.take_summary().is_none());
+        assert!(!transcript.would_collapse());
+    }
+}
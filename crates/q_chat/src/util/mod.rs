@@ -3,6 +3,7 @@ pub mod issue;
 use std::io::Write;
 use std::time::Duration;
 
+use fig_os_shim::Env;
 use fig_util::system_info::in_cloudshell;
 
 use super::ChatError;
@@ -47,6 +48,18 @@ pub fn animate_output(output: &mut impl Write, bytes: &[u8]) -> Result<(), ChatE
     Ok(())
 }
 
+/// Whether spinners and other cursor movement can be drawn. Returns false when the output
+/// isn't a TTY, or when `NO_COLOR` or `TERM=dumb` asks for plain output.
+pub fn should_animate(env: &Env, is_terminal: bool) -> bool {
+    if !is_terminal {
+        return false;
+    }
+    if env.get("NO_COLOR").is_ok_and(|v| !v.is_empty()) {
+        return false;
+    }
+    env.get("TERM").map_or(true, |term| term != "dumb")
+}
+
 /// Play the terminal bell notification sound
 pub fn play_notification_bell(requires_confirmation: bool) {
     // Don't play bell for tools that don't require confirmation
@@ -109,4 +122,14 @@ mod tests {
         assert_eq!(truncate_safe("Hello World", 11), "Hello World");
         assert_eq!(truncate_safe("Hello World", 15), "Hello World");
     }
+
+    #[test]
+    fn test_should_animate() {
+        assert!(should_animate(&Env::from_slice(&[("TERM", "xterm-256color")]), true));
+        assert!(should_animate(&Env::from_slice(&[]), true));
+        assert!(!should_animate(&Env::from_slice(&[("TERM", "xterm-256color")]), false));
+        assert!(!should_animate(&Env::from_slice(&[("TERM", "dumb")]), true));
+        assert!(!should_animate(&Env::from_slice(&[("NO_COLOR", "1")]), true));
+        assert!(should_animate(&Env::from_slice(&[("NO_COLOR", "")]), true));
+    }
 }
@@ -0,0 +1,380 @@
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use fig_settings::Settings;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{
+    error,
+    warn,
+};
+
+/// Setting holding the number of seconds without progress, while work is in flight, before the
+/// watchdog reports a stalled session. `0` disables the watchdog.
+pub const WATCHDOG_TIMEOUT_SETTING: &str = "chat.watchdogTimeoutSeconds";
+
+const DEFAULT_WATCHDOG_TIMEOUT_SECS: i64 = 300;
+
+/// Bumped whenever the layout of [WatchdogSnapshot] changes.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The parts of the chat session that report progress to the watchdog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    /// The [crate::ChatState] loop.
+    ChatLoop,
+    /// Tool invocations.
+    ToolExecution,
+    /// Receiving and printing the model's response.
+    Renderer,
+}
+
+impl Component {
+    const ALL: [Component; 3] = [Component::ChatLoop, Component::ToolExecution, Component::Renderer];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Progress checkpoints shared between the chat loop and the watchdog.
+#[derive(Debug)]
+pub struct Heartbeats {
+    start: Instant,
+    /// Milliseconds since `start` of the last heartbeat of each [Component].
+    beats: [AtomicU64; 3],
+    checkpoints: Mutex<[&'static str; 3]>,
+    /// Index of the [Component] that heartbeated most recently.
+    last_component: AtomicUsize,
+    in_flight: AtomicBool,
+    in_flight_ids: Mutex<Vec<String>>,
+    /// Number of live [PauseGuard]s.
+    paused: AtomicUsize,
+}
+
+impl Default for Heartbeats {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            beats: Default::default(),
+            checkpoints: Mutex::new([""; 3]),
+            last_component: AtomicUsize::new(Component::ChatLoop.index()),
+            in_flight: AtomicBool::new(false),
+            in_flight_ids: Mutex::new(Vec::new()),
+            paused: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Heartbeats {
+    /// Records that `component` made progress, having just reached `checkpoint`.
+    pub fn beat(&self, component: Component, checkpoint: &'static str) {
+        self.beats[component.index()].store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+        if let Ok(mut checkpoints) = self.checkpoints.lock() {
+            checkpoints[component.index()] = checkpoint;
+        }
+        self.last_component.store(component.index(), Ordering::Relaxed);
+    }
+
+    /// Sets whether the session is waiting on something other than the user.
+    pub fn set_in_flight(&self, in_flight: bool) {
+        self.in_flight.store(in_flight, Ordering::Relaxed);
+    }
+
+    /// Sets the ids of work currently in flight, e.g. tool use ids.
+    pub fn set_in_flight_ids(&self, ids: Vec<String>) {
+        if let Ok(mut in_flight_ids) = self.in_flight_ids.lock() {
+            *in_flight_ids = ids;
+        }
+    }
+
+    /// Stops the session from being reported as stalled until the guard is dropped. Used while a
+    /// tool runs, since tools don't heartbeat, can legitimately take longer than the timeout, such
+    /// as a build or a command waiting on the user, and enforce their own time limits.
+    pub fn pause(&self) -> PauseGuard<'_> {
+        self.paused.fetch_add(1, Ordering::Relaxed);
+        PauseGuard(self)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn snapshot(&self) -> WatchdogSnapshot {
+        let now = self.start.elapsed().as_millis() as u64;
+        let checkpoints = self.checkpoints.lock().map_or([""; 3], |c| *c);
+        let components = Component::ALL
+            .iter()
+            .map(|component| ComponentState {
+                component: *component,
+                last_checkpoint: checkpoints[component.index()],
+                ms_since_heartbeat: now.saturating_sub(self.beats[component.index()].load(Ordering::Relaxed)),
+            })
+            .collect::<Vec<_>>();
+        let last_component = Component::ALL
+            .get(self.last_component.load(Ordering::Relaxed))
+            .copied()
+            .unwrap_or(Component::ChatLoop);
+
+        WatchdogSnapshot {
+            version: SNAPSHOT_VERSION,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            in_flight_ids: self.in_flight_ids.lock().map(|ids| ids.clone()).unwrap_or_default(),
+            last_component,
+            components,
+        }
+    }
+}
+
+/// Resumes the watchdog when dropped, see [Heartbeats::pause].
+#[derive(Debug)]
+pub struct PauseGuard<'a>(&'a Heartbeats);
+
+impl Drop for PauseGuard<'_> {
+    fn drop(&mut self) {
+        self.0.paused.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentState {
+    pub component: Component,
+    pub last_checkpoint: &'static str,
+    pub ms_since_heartbeat: u64,
+}
+
+/// Diagnostic state written out when the session stalls.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchdogSnapshot {
+    pub version: u32,
+    pub in_flight: bool,
+    pub in_flight_ids: Vec<String>,
+    /// The component that heartbeated most recently.
+    pub last_component: Component,
+    pub components: Vec<ComponentState>,
+}
+
+impl WatchdogSnapshot {
+    /// Whether work is in flight but no component has made progress within `timeout`.
+    pub fn is_stalled(&self, timeout: Duration) -> bool {
+        self.in_flight
+            && self
+                .components
+                .iter()
+                .all(|c| c.ms_since_heartbeat >= timeout.as_millis() as u64)
+    }
+}
+
+/// Aborts the watchdog task when dropped.
+#[derive(Debug)]
+pub struct WatchdogHandle(JoinHandle<()>);
+
+impl Drop for WatchdogHandle {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Reads the watchdog timeout from [WATCHDOG_TIMEOUT_SETTING], returning [None] if disabled.
+pub fn timeout_from_settings(settings: &Settings) -> Option<Duration> {
+    match settings.get_int_or(WATCHDOG_TIMEOUT_SETTING, DEFAULT_WATCHDOG_TIMEOUT_SECS) {
+        secs if secs > 0 => Some(Duration::from_secs(secs as u64)),
+        _ => None,
+    }
+}
+
+/// Spawns a task that writes a diagnostics dump to `dump_dir` and prints a recovery banner to
+/// `banner` each time the session stalls for longer than `timeout`.
+pub fn spawn_watchdog(
+    heartbeats: Arc<Heartbeats>,
+    timeout: Duration,
+    dump_dir: PathBuf,
+    mut banner: impl Write + Send + 'static,
+) -> WatchdogHandle {
+    WatchdogHandle(tokio::spawn(async move {
+        let poll_interval = (timeout / 4).clamp(Duration::from_millis(10), Duration::from_secs(5));
+        let mut reported = false;
+        loop {
+            tokio::time::sleep(poll_interval).await;
+            let snapshot = heartbeats.snapshot();
+            if heartbeats.is_paused() || !snapshot.is_stalled(timeout) {
+                reported = false;
+                continue;
+            }
+            if reported {
+                continue;
+            }
+            reported = true;
+
+            warn!(?snapshot, "chat session appears to be stalled");
+            let dump_path = match write_dump(&dump_dir, &snapshot) {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    error!(?err, "failed to write watchdog diagnostics");
+                    None
+                },
+            };
+            if let Err(err) = print_banner(&mut banner, timeout, dump_path.as_deref()) {
+                error!(?err, "failed to print the watchdog banner");
+            }
+        }
+    }))
+}
+
+fn write_dump(dump_dir: &Path, snapshot: &WatchdogSnapshot) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dump_dir)?;
+    let timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+    let path = dump_dir.join(format!("chat-watchdog-{timestamp}.json"));
+    std::fs::write(&path, serde_json::to_vec_pretty(snapshot)?)?;
+    Ok(path)
+}
+
+fn print_banner(out: &mut impl Write, timeout: Duration, dump_path: Option<&Path>) -> std::io::Result<()> {
+    let mut banner = format!(
+        "\n\x1b[33m⚠ No progress in the last {}s. Press Ctrl+C to cancel a running tool or response.\x1b[0m\n",
+        timeout.as_secs()
+    );
+    if let Some(path) = dump_path {
+        banner.push_str(&format!(
+            "\x1b[33m  Diagnostics written to {} - please attach it when reporting this.\x1b[0m\n",
+            path.display()
+        ));
+    }
+    out.write_all(banner.as_bytes())?;
+    out.flush()
+}
+
+/// Writes directly to the terminal, falling back to stderr, so that the watchdog banner shows even
+/// if the normal output is blocked.
+#[derive(Debug, Default)]
+pub struct TerminalWriter;
+
+impl Write for TerminalWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+            Ok(mut tty) => tty.write_all(buf),
+            Err(_) => std::io::stderr().write_all(buf),
+        }
+        .map(|()| buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_stalled() {
+        let heartbeats = Heartbeats::default();
+        heartbeats.beat(Component::ChatLoop, "execute tools");
+
+        // Nothing is in flight, e.g. waiting on the user.
+        assert!(!heartbeats.snapshot().is_stalled(Duration::ZERO));
+
+        heartbeats.set_in_flight(true);
+        assert!(heartbeats.snapshot().is_stalled(Duration::ZERO));
+        assert!(!heartbeats.snapshot().is_stalled(Duration::from_secs(60)));
+
+        let paused = heartbeats.pause();
+        assert!(heartbeats.is_paused());
+        drop(paused);
+        assert!(!heartbeats.is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_dumps_stalled_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let heartbeats = Arc::new(Heartbeats::default());
+        heartbeats.beat(Component::Renderer, "response stream");
+        heartbeats.beat(Component::ChatLoop, "execute tools");
+        heartbeats.beat(Component::ToolExecution, "invoke start");
+        heartbeats.set_in_flight_ids(vec!["tooluse_1".to_string()]);
+        heartbeats.set_in_flight(true);
+
+        // A stuck renderer: nothing heartbeats from here on.
+        let banner = SharedBuffer::default();
+        let _watchdog = spawn_watchdog(
+            Arc::clone(&heartbeats),
+            Duration::from_millis(50),
+            dir.path().to_path_buf(),
+            banner.clone(),
+        );
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let banner = String::from_utf8(banner.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(banner.matches("No progress in the last 0s").count(), 1, "{banner}");
+
+        let dumps = std::fs::read_dir(dir.path())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(dumps.len(), 1, "the watchdog should fire once per stall");
+        let dump: serde_json::Value = serde_json::from_slice(&std::fs::read(dumps[0].path()).unwrap()).unwrap();
+        assert_eq!(dump["version"], SNAPSHOT_VERSION);
+        assert_eq!(dump["last_component"], "tool_execution");
+        assert_eq!(dump["in_flight_ids"], serde_json::json!(["tooluse_1"]));
+        let components = dump["components"].as_array().unwrap();
+        assert_eq!(components[0]["component"], "chat_loop");
+        assert_eq!(components[0]["last_checkpoint"], "execute tools");
+        assert_eq!(components[1]["last_checkpoint"], "invoke start");
+        assert_eq!(components[2]["last_checkpoint"], "response stream");
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_paused_while_tool_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let heartbeats = Arc::new(Heartbeats::default());
+        heartbeats.set_in_flight(true);
+        let banner = SharedBuffer::default();
+        let _watchdog = spawn_watchdog(
+            Arc::clone(&heartbeats),
+            Duration::from_millis(50),
+            dir.path().to_path_buf(),
+            banner.clone(),
+        );
+
+        // A long running tool.
+        let paused = heartbeats.pause();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(banner.0.lock().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).map_or(0, |dumps| dumps.count()), 0);
+
+        drop(paused);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!banner.0.lock().unwrap().is_empty());
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -451,6 +451,8 @@ mod test {
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                no_audit: false,
+                no_color: false,
             })),
             verbose: 2,
             help_all: false,
@@ -585,6 +587,8 @@ mod test {
                 profile: Some("my-profile".to_string()),
                 trust_all_tools: false,
                 trust_tools: None,
+                no_audit: false,
+                no_color: false,
             })
         );
     }
@@ -600,6 +604,8 @@ mod test {
                 profile: Some("my-profile".to_string()),
                 trust_all_tools: false,
                 trust_tools: None,
+                no_audit: false,
+                no_color: false,
             })
         );
     }
@@ -615,6 +621,8 @@ mod test {
                 profile: Some("my-profile".to_string()),
                 trust_all_tools: false,
                 trust_tools: None,
+                no_audit: false,
+                no_color: false,
             })
         );
     }
@@ -630,6 +638,8 @@ mod test {
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                no_audit: false,
+                no_color: false,
             })
         );
     }
@@ -645,6 +655,8 @@ mod test {
                 profile: None,
                 trust_all_tools: true,
                 trust_tools: None,
+                no_audit: false,
+                no_color: false,
             })
         );
     }
@@ -660,6 +672,8 @@ mod test {
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: Some(vec!["".to_string()]),
+                no_audit: false,
+                no_color: false,
             })
         );
     }
@@ -675,6 +689,8 @@ mod test {
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: Some(vec!["fs_read".to_string(), "fs_write".to_string()]),
+                no_audit: false,
+                no_color: false,
             })
         );
     }